@@ -31,6 +31,21 @@ fn benchmark_chan_data(c: &mut Criterion) {
         });
     }
 
+    {
+        let mut d = ChannelData {
+            data: vec![1, 2, 3],
+            number: ChannelNumber(MIN_CHANNEL_NUMBER + 1),
+            raw: vec![],
+        };
+        c.bench_function("BenchmarkChannelData_Encode1000", |b| {
+            b.iter(|| {
+                for _ in 0..1000 {
+                    d.encode();
+                }
+            })
+        });
+    }
+
     {
         let mut d = ChannelData {
             data: vec![1, 2, 3, 4],
@@ -0,0 +1,118 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::runtime::Runtime;
+use turn::relay::udp::{UdpBatchConn, UdpRelayForwarder};
+use util::Conn;
+
+// DATAGRAMS are sent per iteration, few enough to fit the receive buffer of
+// the peer socket on loopback
+const DATAGRAMS: usize = 256;
+// DATAGRAM_SIZE is that of a typical audio RTP packet
+const DATAGRAM_SIZE: usize = 172;
+
+// recv_all waits until the peer received DATAGRAMS datagrams, or gives up
+// after a second if some were dropped anyway
+async fn recv_all(peer: &UdpSocket) {
+    let mut buf = vec![0u8; 1500];
+    let _ = tokio::time::timeout(Duration::from_secs(1), async {
+        for _ in 0..DATAGRAMS {
+            peer.recv_from(&mut buf).await.unwrap();
+        }
+    })
+    .await;
+}
+
+// benchmark_relay_send compares sending a burst of datagrams to a peer one
+// send_to at a time with sending them through a UdpRelayForwarder
+fn benchmark_relay_send(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group(format!("BenchmarkRelaySend{}", DATAGRAMS));
+    let data = vec![0u8; DATAGRAM_SIZE];
+
+    let (relay, peer) = rt.block_on(async {
+        let relay = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        (relay, peer)
+    });
+    let peer_addr = peer.local_addr().unwrap();
+
+    group.bench_function("SendTo", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                for _ in 0..DATAGRAMS {
+                    relay.send_to(&data, peer_addr).await.unwrap();
+                }
+                recv_all(&peer).await;
+            })
+        })
+    });
+
+    for batch_size in [8, 32] {
+        let forwarder = rt.block_on(async {
+            UdpRelayForwarder::new(Arc::clone(&relay), batch_size, Duration::from_millis(1))
+        });
+
+        group.bench_function(format!("UdpRelayForwarder/Batch{}", batch_size), |b| {
+            b.iter(|| {
+                rt.block_on(async {
+                    for _ in 0..DATAGRAMS {
+                        forwarder.send_to(&data, peer_addr).await.unwrap();
+                    }
+                    recv_all(&peer).await;
+                })
+            })
+        });
+
+        rt.block_on(async {
+            forwarder.close().await.unwrap();
+        });
+    }
+
+    group.finish();
+}
+
+// benchmark_listener_recv compares receiving a burst of datagrams one
+// recv_from at a time with receiving them through a UdpBatchConn
+fn benchmark_listener_recv(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group(format!("BenchmarkListenerRecv{}", DATAGRAMS));
+    let data = vec![0u8; DATAGRAM_SIZE];
+
+    let (listener, peer) = rt.block_on(async {
+        let listener = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        (listener, peer)
+    });
+    let listener_addr = listener.local_addr().unwrap();
+
+    let mut bench_recv = |name: String, conn: Arc<dyn Conn + Send + Sync>| {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                rt.block_on(async {
+                    for _ in 0..DATAGRAMS {
+                        peer.send_to(&data, listener_addr).await.unwrap();
+                    }
+                    let mut buf = vec![0u8; 1500];
+                    for _ in 0..DATAGRAMS {
+                        conn.recv_from(&mut buf).await.unwrap();
+                    }
+                })
+            })
+        });
+    };
+
+    bench_recv("RecvFrom".to_owned(), Arc::clone(&listener) as _);
+    for batch_size in [8, 32] {
+        bench_recv(
+            format!("UdpBatchConn/Batch{}", batch_size),
+            Arc::new(UdpBatchConn::new(Arc::clone(&listener), batch_size)),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_relay_send, benchmark_listener_recv);
+criterion_main!(benches);
@@ -0,0 +1,224 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use stun::agent::TransactionId;
+use stun::attributes::{ATTR_NONCE, ATTR_REALM, ATTR_USERNAME};
+use stun::integrity::MessageIntegrity;
+use stun::message::{Getter, Message, MessageType, CLASS_REQUEST, METHOD_ALLOCATE};
+use stun::textattrs::{Nonce, Realm, Username};
+use tokio::net::UdpSocket;
+use tokio::runtime::Runtime;
+use turn::allocation::allocation_manager::LifetimeStrategy;
+use turn::allocation::channel_bind::ChannelRefreshPolicy;
+use turn::auth::{generate_auth_key, AuthHandler};
+use turn::proto::lifetime::Lifetime;
+use turn::proto::reqtrans::RequestedTransport;
+use turn::proto::{refresh_request, PROTO_UDP};
+use turn::relay::relay_static::RelayAddressGeneratorStatic;
+use turn::server::{config::*, Server};
+use util::vnet::net::Net;
+
+const USERNAME: &str = "user";
+const PASSWORD: &str = "pass";
+const REALM: &str = "webrtc.rs";
+
+// ALLOCATIONS can be lowered with TURN_BENCH_ALLOCATIONS. Every allocation
+// holds a client and a relay socket, so the open file limit must be above
+// twice this.
+const ALLOCATIONS: usize = 10_000;
+
+struct BenchAuthHandler;
+
+impl AuthHandler for BenchAuthHandler {
+    fn auth_handle(
+        &self,
+        username: &str,
+        realm: &str,
+        _src_addr: SocketAddr,
+    ) -> Result<Vec<u8>, turn::Error> {
+        Ok(generate_auth_key(username, realm, PASSWORD))
+    }
+}
+
+struct BenchClient {
+    conn: UdpSocket,
+    nonce: String,
+}
+
+impl BenchClient {
+    // allocate sends an unauthenticated Allocate to get a nonce and then
+    // the authenticated one
+    async fn allocate(server_addr: SocketAddr) -> BenchClient {
+        let conn = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let mut m = Message::new();
+        m.build(&[
+            Box::new(TransactionId::new()),
+            Box::new(MessageType::new(METHOD_ALLOCATE, CLASS_REQUEST)),
+            Box::new(RequestedTransport {
+                protocol: PROTO_UDP,
+            }),
+        ])
+        .unwrap();
+        let resp = roundtrip(&conn, server_addr, &m).await;
+        let client = BenchClient {
+            conn,
+            nonce: nonce_of(&resp),
+        };
+        let m = client.authenticated(MessageType::new(METHOD_ALLOCATE, CLASS_REQUEST));
+        roundtrip(&client.conn, server_addr, &m).await;
+
+        client
+    }
+
+    fn authenticated(&self, typ: MessageType) -> Message {
+        let mut m = Message::new();
+        m.build(&[
+            Box::new(TransactionId::new()),
+            Box::new(typ),
+            Box::new(RequestedTransport {
+                protocol: PROTO_UDP,
+            }),
+            Box::new(Lifetime(Duration::from_secs(600))),
+            Box::new(Username::new(ATTR_USERNAME, USERNAME.to_owned())),
+            Box::new(Realm::new(ATTR_REALM, REALM.to_owned())),
+            Box::new(Nonce::new(ATTR_NONCE, self.nonce.clone())),
+            Box::new(MessageIntegrity::new_long_term_integrity(
+                USERNAME.to_owned(),
+                REALM.to_owned(),
+                PASSWORD.to_owned(),
+            )),
+        ])
+        .unwrap();
+        m
+    }
+}
+
+fn nonce_of(m: &Message) -> String {
+    let mut nonce = Nonce::new(ATTR_NONCE, String::new());
+    nonce.get_from(m).unwrap();
+    nonce.text
+}
+
+async fn roundtrip(conn: &UdpSocket, server_addr: SocketAddr, m: &Message) -> Message {
+    conn.send_to(&m.raw, server_addr).await.unwrap();
+
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = conn.recv_from(&mut buf).await.unwrap();
+    let mut resp = Message {
+        raw: buf[..n].to_vec(),
+        ..Default::default()
+    };
+    resp.decode().unwrap();
+    resp
+}
+
+async fn new_server(inbound_worker_threads: usize) -> (Server, SocketAddr) {
+    let conn = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let server_addr = conn.local_addr().unwrap();
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1").unwrap(),
+                address: "127.0.0.1".to_owned(),
+                net: Arc::new(Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: REALM.to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(BenchAuthHandler),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await
+    .unwrap();
+
+    (server, server_addr)
+}
+
+// benchmark_refresh measures how long it takes until every client holding an
+// allocation had one Refresh answered, with the packets of all clients in
+// flight at once.
+fn benchmark_refresh(c: &mut Criterion) {
+    let allocations = std::env::var("TURN_BENCH_ALLOCATIONS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(ALLOCATIONS);
+
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group(format!("BenchmarkServer/Refresh{}", allocations));
+    group.sample_size(10);
+
+    for inbound_worker_threads in [1, 4] {
+        let (server, clients) = rt.block_on(async {
+            let (server, server_addr) = new_server(inbound_worker_threads).await;
+            let mut clients = Vec::with_capacity(allocations);
+            for _ in 0..allocations {
+                clients.push((
+                    Arc::new(BenchClient::allocate(server_addr).await),
+                    server_addr,
+                ));
+            }
+            (server, clients)
+        });
+
+        group.bench_function(
+            format!("InboundWorkerThreads{}", inbound_worker_threads),
+            |b| {
+                b.iter(|| {
+                    rt.block_on(async {
+                        let mut handles = Vec::with_capacity(clients.len());
+                        for (client, server_addr) in &clients {
+                            let client = Arc::clone(client);
+                            let server_addr = *server_addr;
+                            handles.push(tokio::spawn(async move {
+                                let m = client.authenticated(refresh_request());
+                                roundtrip(&client.conn, server_addr, &m).await;
+                            }));
+                        }
+                        for h in handles {
+                            h.await.unwrap();
+                        }
+                    })
+                })
+            },
+        );
+
+        rt.block_on(async {
+            drop(clients);
+            server.close().await.unwrap();
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_refresh);
+criterion_main!(benches);
@@ -0,0 +1,203 @@
+use turn::client::*;
+use turn::Error;
+
+use clap::{App, AppSettings, Arg};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use stun::agent::TransactionId;
+use stun::message::{Getter, Message, BINDING_REQUEST};
+use stun::xoraddr::XorMappedAddress;
+use tokio::net::UdpSocket;
+use tokio::time::{Duration, Instant};
+use util::Conn;
+
+// Allocates a relay address and echoes messages through it off a peer socket,
+// printing the round-trip time of each. The permission for the peer and the
+// channel binding are created by the client when the relay conn first sends
+// to the peer.
+//
+// RUST_LOG=trace cargo run --color=always --package webrtc-turn --example client -- --server 127.0.0.1:3478 --username user --password pass
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    env_logger::init();
+
+    let mut app = App::new("TURN Client")
+        .version("0.1.0")
+        .author("Rain Liu <yliu@webrtc.rs>")
+        .about("An example of the TURN client API echoing through a relay")
+        .setting(AppSettings::DeriveDisplayOrder)
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(
+            Arg::with_name("FULLHELP")
+                .help("Prints more detailed help information")
+                .long("fullhelp"),
+        )
+        .arg(
+            Arg::with_name("server")
+                .required_unless("FULLHELP")
+                .takes_value(true)
+                .long("server")
+                .help("TURN server address (e.g. \"127.0.0.1:3478\")"),
+        )
+        .arg(
+            Arg::with_name("username")
+                .required_unless("FULLHELP")
+                .takes_value(true)
+                .long("username")
+                .help("Username"),
+        )
+        .arg(
+            Arg::with_name("password")
+                .required_unless("FULLHELP")
+                .takes_value(true)
+                .long("password")
+                .help("Password"),
+        )
+        .arg(
+            Arg::with_name("realm")
+                .default_value("webrtc.rs")
+                .takes_value(true)
+                .long("realm")
+                .help("Realm (defaults to \"webrtc.rs\")"),
+        )
+        .arg(
+            Arg::with_name("count")
+                .default_value("100")
+                .takes_value(true)
+                .long("count")
+                .help("Number of echo messages to send"),
+        );
+
+    let matches = app.clone().get_matches();
+
+    if matches.is_present("FULLHELP") {
+        app.print_long_help().unwrap();
+        std::process::exit(0);
+    }
+
+    let server = matches.value_of("server").unwrap();
+    let username = matches.value_of("username").unwrap();
+    let password = matches.value_of("password").unwrap();
+    let realm = matches.value_of("realm").unwrap();
+    let count: usize = matches.value_of("count").unwrap().parse()?;
+
+    // TURN client won't create a local listening socket by itself.
+    let conn = UdpSocket::bind("0.0.0.0:0").await?;
+
+    let cfg = ClientConfig {
+        stun_serv_addr: server.to_owned(),
+        turn_serv_addr: server.to_owned(),
+        username: username.to_owned(),
+        password: password.to_owned(),
+        realm: realm.to_owned(),
+        software: String::new(),
+        rto_in_ms: 0,
+        conn: Arc::new(conn),
+        vnet: None,
+    };
+
+    let client = Client::new(cfg).await?;
+    client.listen().await?;
+
+    let relay_conn = client.allocate().await?;
+    let relay_addr = relay_conn.local_addr().await?;
+    println!("relayed-address={}", relay_addr);
+
+    // The peer echoes everything it receives back to the sender. Its address
+    // as seen by the TURN server is what the relay conn has to send to.
+    let peer = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let peer_addr = mapped_address(&peer, server).await?;
+    println!("peer-address={}", peer_addr);
+
+    let peer_rx = Arc::clone(&peer);
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 1500];
+        while let Ok((n, from)) = peer_rx.recv_from(&mut buf).await {
+            if peer_rx.send_to(&buf[..n], from).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut buf = vec![0u8; 1500];
+
+    // The first send creates the permission for the peer and starts binding
+    // a channel to it. Wait for its echo so the relay is known to work.
+    relay_conn.send_to(b"hello", peer_addr).await?;
+    let (n, from) = tokio::time::timeout(Duration::from_secs(5), relay_conn.recv_from(&mut buf))
+        .await
+        .map_err(|_| Error::Other("no echo from the peer".to_owned()))??;
+    println!(
+        "{} bytes from {}: {:?}",
+        n,
+        from,
+        String::from_utf8_lossy(&buf[..n])
+    );
+
+    // Give the channel binding time to complete, so the echo messages are
+    // sent as ChannelData instead of Send indications.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let mut rtts = vec![];
+    for seq in 0..count {
+        let msg = format!("echo {}", seq);
+        let sent_at = Instant::now();
+        relay_conn.send_to(msg.as_bytes(), peer_addr).await?;
+
+        // replies to earlier, timed out messages may still arrive, skip them
+        let deadline = sent_at + Duration::from_secs(1);
+        loop {
+            match tokio::time::timeout_at(deadline, relay_conn.recv_from(&mut buf)).await {
+                Ok(result) => {
+                    let (n, _) = result?;
+                    if buf[..n] != *msg.as_bytes() {
+                        continue;
+                    }
+                    let rtt = sent_at.elapsed();
+                    println!("seq={} rtt={:.3}ms", seq, rtt.as_secs_f64() * 1000.0);
+                    rtts.push(rtt);
+                }
+                Err(_) => println!("seq={} timed out", seq),
+            }
+            break;
+        }
+    }
+
+    if !rtts.is_empty() {
+        let total: Duration = rtts.iter().sum();
+        println!(
+            "{}/{} echoed, average rtt={:.3}ms",
+            rtts.len(),
+            count,
+            total.as_secs_f64() * 1000.0 / rtts.len() as f64
+        );
+    }
+
+    relay_conn.close().await?;
+    client.close().await?;
+
+    Ok(())
+}
+
+// mapped_address sends a STUN Binding Request from conn to server and returns
+// the address the server saw it from.
+async fn mapped_address(conn: &UdpSocket, server: &str) -> Result<SocketAddr, Error> {
+    let mut m = Message::new();
+    m.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])?;
+    conn.send_to(&m.raw, server).await?;
+
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = tokio::time::timeout(Duration::from_secs(5), conn.recv_from(&mut buf))
+        .await
+        .map_err(|_| Error::Other("no binding response from the server".to_owned()))??;
+    let mut resp = Message {
+        raw: buf[..n].to_vec(),
+        ..Default::default()
+    };
+    resp.decode()?;
+
+    let mut addr = XorMappedAddress::default();
+    addr.get_from(&resp)?;
+    Ok(SocketAddr::new(addr.ip, addr.port))
+}
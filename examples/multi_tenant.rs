@@ -0,0 +1,193 @@
+use turn::allocation::allocation_manager::LifetimeStrategy;
+use turn::allocation::channel_bind::ChannelRefreshPolicy;
+use turn::auth::r#static::*;
+use turn::auth::*;
+use turn::client::*;
+use turn::relay::relay_static::*;
+use turn::server::{config::*, *};
+use turn::Error;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::signal;
+use tokio::time::Duration;
+use util::vnet::net::*;
+use util::Conn;
+
+// One server hosting two tenants. Each tenant has its own listener with its
+// own realm and allocation limit, and its own users. The auth handler picks
+// the user list by the realm of the request, and the listener rejects
+// requests for any realm but its own, so users of one tenant can't
+// authenticate with the other.
+//
+// RUST_LOG=info cargo run --color=always --package webrtc-turn --example multi_tenant
+
+struct Tenant {
+    realm: &'static str,
+    port: u16,
+    max_allocations: usize,
+    users: &'static [(&'static str, &'static str)],
+}
+
+const TENANTS: [Tenant; 2] = [
+    Tenant {
+        realm: "tenant-a.example.com",
+        port: 3478,
+        max_allocations: 10,
+        users: &[("alice", "alice-pass")],
+    },
+    Tenant {
+        realm: "tenant-b.example.com",
+        port: 3479,
+        max_allocations: 2,
+        users: &[("bob", "bob-pass")],
+    },
+];
+
+// RealmAuthHandler authenticates each request with the StaticAuthHandler of
+// its realm
+struct RealmAuthHandler {
+    realms: HashMap<String, StaticAuthHandler>,
+}
+
+impl AuthHandler for RealmAuthHandler {
+    fn auth_handle(
+        &self,
+        username: &str,
+        realm: &str,
+        src_addr: SocketAddr,
+    ) -> Result<Vec<u8>, Error> {
+        match self.realms.get(realm) {
+            Some(handler) => handler.auth_handle(username, realm, src_addr),
+            None => Err(Error::ErrNoSuchUser),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    env_logger::init();
+
+    let mut realms = HashMap::new();
+    let mut conn_configs = vec![];
+    let mut listener_realms = HashMap::new();
+    for tenant in &TENANTS {
+        let users = tenant
+            .users
+            .iter()
+            .map(|(username, password)| (username.to_string(), password.to_string()))
+            .collect();
+        realms.insert(tenant.realm.to_owned(), StaticAuthHandler::new(users));
+
+        let conn = Arc::new(UdpSocket::bind(("127.0.0.1", tenant.port)).await?);
+        println!("{} listening {}...", tenant.realm, conn.local_addr()?);
+        listener_realms.insert(conn.local_addr()?, tenant.realm);
+
+        conn_configs.push(ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "127.0.0.1".to_owned(),
+                net: Arc::new(Net::new(None)),
+            }),
+            max_connections: Some(tenant.max_allocations),
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: Some(tenant.realm.to_owned()),
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        });
+    }
+
+    let server = Server::new(ServerConfig {
+        conn_configs,
+        realm: String::new(),
+        enforce_realm: true,
+        auth_handler: Arc::new(RealmAuthHandler { realms }),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: Some(Box::new(move |info| {
+            // the listener an allocation was made on tells its tenant
+            let realm = listener_realms
+                .get(&info.five_tuple.dst_addr)
+                .copied()
+                .unwrap_or("unknown realm");
+            println!(
+                "{}: allocation {} created for {} at {}",
+                realm, info.relay_addr, info.username, info.five_tuple.src_addr
+            );
+        })),
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let (a, b) = (&TENANTS[0], &TENANTS[1]);
+    let (alice, alice_password) = a.users[0];
+    try_allocate(a, alice, alice_password).await;
+    try_allocate(b, alice, alice_password).await;
+
+    println!("Waiting for Ctrl-C...");
+    signal::ctrl_c().await.expect("failed to listen for event");
+    println!("\nClosing connection now...");
+    server.close().await?;
+
+    Ok(())
+}
+
+// try_allocate allocates a relay address on the listener of tenant as
+// username and prints the outcome
+async fn try_allocate(tenant: &Tenant, username: &str, password: &str) {
+    let result = async {
+        let server_addr = format!("127.0.0.1:{}", tenant.port);
+        let client = Client::new(ClientConfig {
+            stun_serv_addr: server_addr.clone(),
+            turn_serv_addr: server_addr,
+            username: username.to_owned(),
+            password: password.to_owned(),
+            realm: tenant.realm.to_owned(),
+            software: String::new(),
+            rto_in_ms: 0,
+            conn: Arc::new(UdpSocket::bind("127.0.0.1:0").await?),
+            vnet: None,
+        })
+        .await?;
+        client.listen().await?;
+
+        let result = match client.allocate().await {
+            Ok(relay_conn) => relay_conn.local_addr().await.map_err(Error::from),
+            Err(err) => Err(err),
+        };
+        client.close().await?;
+        result
+    }
+    .await;
+
+    match result {
+        Ok(relay_addr) => println!(
+            "{} authenticated under {}, relayed-address={}",
+            username, tenant.realm, relay_addr
+        ),
+        Err(err) => println!(
+            "{} could not authenticate under {}: {}",
+            username, tenant.realm, err
+        ),
+    }
+}
@@ -1,3 +1,5 @@
+use turn::allocation::allocation_manager::LifetimeStrategy;
+use turn::allocation::channel_bind::ChannelRefreshPolicy;
 use turn::auth::*;
 use turn::relay::relay_static::*;
 use turn::server::{config::*, *};
@@ -121,10 +123,35 @@ async fn main() -> Result<(), Error> {
                 address: "0.0.0.0".to_owned(),
                 net: Arc::new(Net::new(None)),
             }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
         }],
         realm: realm.to_owned(),
+        enforce_realm: true,
         auth_handler: Arc::new(MyAuthHandler::new(cred_map)),
         channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
     })
     .await?;
 
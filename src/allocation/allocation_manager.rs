@@ -4,20 +4,166 @@ mod allocation_manager_test;
 use super::*;
 use crate::error::*;
 use crate::relay::*;
+#[cfg(feature = "state-dump")]
+use crate::server::snapshot::AllocationSnapshot;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use stun::textattrs::Username;
 use util::Conn;
 
+// DRAIN_NOTICE_CHANNEL_NUMBER is the channel an empty ChannelData message is sent
+// on to tell a client that its allocation is about to be removed by drain_user.
+// This is not part of any RFC: the channel lies in the range RFC 8656 reserves
+// (0x5000-0x7FFF), so clients never bind it, and clients unaware of the notice
+// drop it like any ChannelData for an unbound channel.
+pub const DRAIN_NOTICE_CHANNEL_NUMBER: ChannelNumber = ChannelNumber(MAX_CHANNEL_NUMBER);
+
+// LifetimeFn picks the lifetime of an allocation of username from the one the
+// Negotiated strategy would give it, see LifetimeStrategy::Dynamic
+pub type LifetimeFn = Box<dyn Fn(&str, Duration) -> Duration + Send + Sync>;
+
+// LifetimeStrategy decides the lifetime granted to Allocate and Refresh
+// requests. It never applies to a Refresh with a LIFETIME of 0, which deletes
+// the allocation.
+#[derive(Default)]
+pub enum LifetimeStrategy {
+    // Fixed grants every allocation the given lifetime, whatever was requested
+    Fixed(Duration),
+    // Negotiated grants the requested LIFETIME if it is below one hour and the
+    // default of 10 minutes otherwise, or if none was requested
+    #[default]
+    Negotiated,
+    // Dynamic grants what the function returns for the username and the
+    // lifetime Negotiated would grant. It runs for every Allocate and Refresh
+    // request, so it must not block, and should not return zero.
+    Dynamic(LifetimeFn),
+}
+
+impl fmt::Debug for LifetimeStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LifetimeStrategy::Fixed(lifetime) => f.debug_tuple("Fixed").field(lifetime).finish(),
+            LifetimeStrategy::Negotiated => write!(f, "Negotiated"),
+            LifetimeStrategy::Dynamic(_) => write!(f, "Dynamic"),
+        }
+    }
+}
+
+impl LifetimeStrategy {
+    // lifetime returns the lifetime granted to username, where negotiated is
+    // the one the Negotiated strategy grants
+    pub fn lifetime(&self, username: &str, negotiated: Duration) -> Duration {
+        match self {
+            LifetimeStrategy::Fixed(lifetime) => *lifetime,
+            LifetimeStrategy::Negotiated => negotiated,
+            LifetimeStrategy::Dynamic(f) => f(username, negotiated),
+        }
+    }
+}
+
 // ManagerConfig a bag of config params for Manager.
 pub struct ManagerConfig {
     pub relay_addr_generator: Box<dyn RelayAddressGenerator + Send + Sync>,
+
+    // max_connections caps the number of client source addresses holding an
+    // allocation at the same time. None means unlimited.
+    pub max_connections: Option<usize>,
+
+    // relay_keepalive_interval, when set, makes every allocation send a STUN
+    // Binding Request from its relay sockets at this interval so that NATs in
+    // front of the relays keep their mappings open.
+    pub relay_keepalive_interval: Option<Duration>,
+
+    // relay_keepalive_server is where keepalive requests are sent. When None
+    // they are looped back to the relay address itself.
+    pub relay_keepalive_server: Option<SocketAddr>,
+
+    // on_allocation_created is called for every allocation right after it is created
+    pub on_allocation_created: Option<AllocationCallback>,
+
+    // on_allocation_closed is called for every allocation once it is closed,
+    // whether it expired, was deleted or the manager was closed
+    pub on_allocation_closed: Option<AllocationCallback>,
+
+    // forward_icmp_errors, when set, makes allocations tell their client about
+    // the ICMP errors their relay sockets report for datagrams relayed to a
+    // peer, by sending a Data indication with an ICMP attribute instead of DATA.
+    pub forward_icmp_errors: bool,
+
+    // allocation_lifetime_strategy decides the lifetime granted to Allocate
+    // and Refresh requests. Negotiated, the default, grants what was requested.
+    pub allocation_lifetime_strategy: LifetimeStrategy,
+}
+
+// SourceSlots maps the FiveTuple fingerprint of every allocation of a Manager
+// with max_connections, and of every one being created, to its client source
+// address
+type SourceSlots = Arc<std::sync::Mutex<HashMap<String, SourceSlot>>>;
+
+struct SourceSlot {
+    src_addr: SocketAddr,
+    // pending is set while the allocation is being created and not yet in the
+    // allocations map
+    pending: bool,
+}
+
+// SourceSlotGuard releases a pending slot when the allocation couldn't be created
+struct SourceSlotGuard {
+    sources: SourceSlots,
+    fingerprint: String,
+    committed: bool,
+}
+
+impl SourceSlotGuard {
+    // commit keeps the slot for the allocation now in the allocations map
+    fn commit(mut self) {
+        let mut sources = self.sources.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(slot) = sources.get_mut(&self.fingerprint) {
+            slot.pending = false;
+        }
+        self.committed = true;
+    }
+}
+
+impl Drop for SourceSlotGuard {
+    fn drop(&mut self) {
+        if !self.committed {
+            let mut sources = self.sources.lock().unwrap_or_else(|e| e.into_inner());
+            sources.remove(&self.fingerprint);
+        }
+    }
+}
+
+// ManagerStats are the running totals of a Manager since it was created. Bytes
+// relayed in are the data peers sent to clients, bytes relayed out the data
+// clients sent to peers, both without TURN framing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ManagerStats {
+    pub total_created: u64,
+    pub total_closed: u64,
+    pub current_count: usize,
+    pub total_bytes_relayed_in: u64,
+    pub total_bytes_relayed_out: u64,
+    pub total_permissions_created: u64,
+    pub total_channel_binds: u64,
 }
 
 // Manager is used to hold active allocations
 pub struct Manager {
     allocations: AllocationMap,
+    relay_addrs: RelayAddrMap,
+    counters: Arc<AllocationCounters>,
     reservations: Arc<Mutex<HashMap<String, u16>>>,
     relay_addr_generator: Box<dyn RelayAddressGenerator + Send + Sync>,
+    max_connections: Option<usize>,
+    sources: SourceSlots,
+    relay_keepalive_interval: Option<Duration>,
+    relay_keepalive_server: Option<SocketAddr>,
+    on_allocation_created: Option<AllocationCallback>,
+    on_allocation_closed: Option<AllocationCallback>,
+    forward_icmp_errors: bool,
+    allocation_lifetime_strategy: LifetimeStrategy,
 }
 
 impl Manager {
@@ -25,19 +171,60 @@ impl Manager {
     pub fn new(config: ManagerConfig) -> Self {
         Manager {
             allocations: Arc::new(Mutex::new(HashMap::new())),
+            relay_addrs: Arc::new(Mutex::new(HashMap::new())),
+            counters: Arc::new(AllocationCounters::default()),
             reservations: Arc::new(Mutex::new(HashMap::new())),
             relay_addr_generator: config.relay_addr_generator,
+            max_connections: config.max_connections,
+            sources: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            relay_keepalive_interval: config.relay_keepalive_interval,
+            relay_keepalive_server: config.relay_keepalive_server,
+            on_allocation_created: config.on_allocation_created,
+            on_allocation_closed: config.on_allocation_closed,
+            forward_icmp_errors: config.forward_icmp_errors,
+            allocation_lifetime_strategy: config.allocation_lifetime_strategy,
         }
     }
 
-    // Close closes the manager and closes all allocations it manages
-    pub async fn close(&self) -> Result<()> {
-        let allocations = self.allocations.lock().await;
-        for a in allocations.values() {
-            let mut a = a.lock().await;
-            a.close().await?;
+    // Close closes the manager and closes all allocations it manages. The
+    // allocations are closed concurrently and it waits up to timeout for all
+    // their sockets to be closed, warning about the ones that aren't by then.
+    pub async fn close(&self, timeout: Duration) -> Result<()> {
+        let closing: Vec<_> = {
+            let allocations = self.allocations.lock().await;
+            allocations
+                .iter()
+                .map(|(fingerprint, a)| {
+                    let a = Arc::clone(a);
+                    let handle = tokio::spawn(async move {
+                        let mut a = a.lock().await;
+                        a.close().await
+                    });
+                    (fingerprint.clone(), handle)
+                })
+                .collect()
+        };
+
+        let deadline = Instant::now() + timeout;
+        let mut result = Ok(());
+        for (fingerprint, handle) in closing {
+            match tokio::time::timeout_at(deadline, handle).await {
+                Ok(Ok(Ok(()))) => {}
+                Ok(Ok(Err(err))) => {
+                    log::error!("Failed to close allocation {}: {}", fingerprint, err);
+                    if result.is_ok() {
+                        result = Err(err);
+                    }
+                }
+                Ok(Err(err)) => {
+                    log::error!("Failed to close allocation {}: {}", fingerprint, err);
+                }
+                Err(_) => {
+                    log::warn!("allocation {} not closed within {:?}", fingerprint, timeout);
+                }
+            }
         }
-        Ok(())
+        result
     }
 
     // get_allocation fetches the allocation matching the passed FiveTuple
@@ -53,6 +240,57 @@ impl Manager {
         turn_socket: Arc<dyn Conn + Send + Sync>,
         requested_port: u16,
         lifetime: Duration,
+        username: Username,
+    ) -> Result<Arc<Mutex<Allocation>>> {
+        self.create_allocation_internal(
+            five_tuple,
+            turn_socket,
+            requested_port,
+            lifetime,
+            username,
+            None,
+            false,
+        )
+        .await
+    }
+
+    // create_dual_allocation creates a new allocation with an IPv4 relayed
+    // address plus an additional IPv6 one, as requested by ADDITIONAL-ADDRESS-FAMILY.
+    // If no IPv6 relay can be bound, the allocation only has the IPv4 relayed
+    // address and its additional_relay_addr is None, see RFC 8656 Section 7.2.
+    pub async fn create_dual_allocation(
+        &self,
+        five_tuple: FiveTuple,
+        turn_socket: Arc<dyn Conn + Send + Sync>,
+        requested_port: u16,
+        lifetime: Duration,
+        username: Username,
+    ) -> Result<Arc<Mutex<Allocation>>> {
+        self.create_allocation_internal(
+            five_tuple,
+            turn_socket,
+            requested_port,
+            lifetime,
+            username,
+            None,
+            true,
+        )
+        .await
+    }
+
+    // create_allocation_internal creates a new allocation tagged with app_id,
+    // with an additional IPv6 relayed address if additional_ipv6 is set and one
+    // can be bound
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn create_allocation_internal(
+        &self,
+        five_tuple: FiveTuple,
+        turn_socket: Arc<dyn Conn + Send + Sync>,
+        requested_port: u16,
+        lifetime: Duration,
+        username: Username,
+        app_id: Option<String>,
+        additional_ipv6: bool,
     ) -> Result<Arc<Mutex<Allocation>>> {
         if lifetime == Duration::from_secs(0) {
             return Err(Error::ErrLifetimeZero);
@@ -62,30 +300,389 @@ impl Manager {
             return Err(Error::ErrDupeFiveTuple);
         }
 
+        // the slot is taken under the allocations lock and held until the
+        // allocation is inserted, so concurrent Allocates can't exceed the limit
+        let source_slot = match self.max_connections {
+            Some(max_connections) => {
+                let allocations = self.allocations.lock().await;
+                Some(self.reserve_source(&allocations, &five_tuple, max_connections)?)
+            }
+            None => None,
+        };
+
         let (relay_socket, relay_addr) = self
             .relay_addr_generator
-            .allocate_conn(true, requested_port)
+            .allocate_conn_for_user(true, requested_port, &username.text)
             .await?;
-        let mut a = Allocation::new(turn_socket, relay_socket, relay_addr, five_tuple.clone());
+        let additional_relay = if additional_ipv6 {
+            match self
+                .relay_addr_generator
+                .allocate_conn_for_user(false, 0, &username.text)
+                .await
+            {
+                Ok((socket, addr)) if addr.is_ipv6() => Some((socket, addr)),
+                Ok((socket, addr)) => {
+                    log::debug!("additional relay {} is no IPv6 address", addr);
+                    let _ = socket.close().await;
+                    None
+                }
+                Err(err) => {
+                    log::debug!("failed to allocate additional IPv6 relay: {}", err);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut a = Allocation::new(
+            turn_socket,
+            relay_socket,
+            relay_addr,
+            five_tuple.clone(),
+            username,
+        );
         a.allocations = Some(Arc::clone(&self.allocations));
+        a.relay_addrs = Some(Arc::clone(&self.relay_addrs));
+        a.counters = Arc::clone(&self.counters);
+        a.app_id = app_id;
+        a.on_closed = self.on_allocation_closed.clone();
+        a.forward_icmp_errors = self.forward_icmp_errors;
+        if let Some((socket, addr)) = additional_relay {
+            log::debug!("listening on additional relay addr: {:?}", addr);
+            a.additional_relay_socket = Some(socket);
+            a.additional_relay_addr = Some(addr);
+        }
 
-        log::debug!("listening on relay addr: {:?}", a.relay_addr);
+        log::debug!(
+            "listening on relay addr: {:?} for user {} (app id {})",
+            a.relay_addr,
+            a.username.text,
+            a.app_id.as_deref().unwrap_or("none")
+        );
         a.start(lifetime).await;
         a.packet_handler().await;
+        if let Some(interval) = self.relay_keepalive_interval {
+            a.start_keepalive(interval, self.relay_keepalive_server);
+        }
 
+        let relay_addrs = [Some(a.relay_addr), a.additional_relay_addr];
+        let info = a.info();
         let a = Arc::new(Mutex::new(a));
         {
             let mut allocations = self.allocations.lock().await;
             allocations.insert(five_tuple.fingerprint(), Arc::clone(&a));
+            if let Some(source_slot) = source_slot {
+                source_slot.commit();
+            }
+        }
+        {
+            let mut relay_addr_map = self.relay_addrs.lock().await;
+            for relay_addr in relay_addrs.iter().flatten() {
+                relay_addr_map.insert(*relay_addr, five_tuple.fingerprint());
+            }
+        }
+
+        self.counters
+            .allocations_created
+            .fetch_add(1, Ordering::Relaxed);
+        if let Some(on_allocation_created) = &self.on_allocation_created {
+            on_allocation_created(info);
         }
 
         Ok(a)
     }
 
+    // reserve_source takes a slot for the source address of five_tuple, failing
+    // if max_connections other source addresses hold an allocation or are
+    // creating one. allocations must be the locked allocations map.
+    fn reserve_source(
+        &self,
+        allocations: &HashMap<String, Arc<Mutex<Allocation>>>,
+        five_tuple: &FiveTuple,
+        max_connections: usize,
+    ) -> Result<SourceSlotGuard> {
+        let fingerprint = five_tuple.fingerprint();
+        let mut sources = self.sources.lock().unwrap_or_else(|e| e.into_inner());
+
+        // allocations leave the map in several places, drop their slots here
+        sources.retain(|fp, slot| slot.pending || allocations.contains_key(fp));
+        if sources.contains_key(&fingerprint) {
+            return Err(Error::ErrDupeFiveTuple);
+        }
+
+        let src_addr = five_tuple.src_addr;
+        if !sources.values().any(|slot| slot.src_addr == src_addr) {
+            let active: HashSet<SocketAddr> = sources.values().map(|slot| slot.src_addr).collect();
+            if active.len() >= max_connections {
+                return Err(Error::ErrMaxConnectionsReached);
+            }
+        }
+
+        sources.insert(
+            fingerprint.clone(),
+            SourceSlot {
+                src_addr,
+                pending: true,
+            },
+        );
+        Ok(SourceSlotGuard {
+            sources: Arc::clone(&self.sources),
+            fingerprint,
+            committed: false,
+        })
+    }
+
+    // permission_count returns the number of active permissions across all allocations
+    pub fn permission_count(&self) -> usize {
+        self.counters.permissions.load(Ordering::Relaxed)
+    }
+
+    // channel_bind_count returns the number of active channel bindings across all allocations
+    pub fn channel_bind_count(&self) -> usize {
+        self.counters.channel_bindings.load(Ordering::Relaxed)
+    }
+
+    // statistics returns the counters of the manager. Unlike the allocation
+    // lookups it takes no locks.
+    pub fn statistics(&self) -> ManagerStats {
+        let created = self.counters.allocations_created.load(Ordering::Relaxed);
+        let closed = self.counters.allocations_closed.load(Ordering::Relaxed);
+        ManagerStats {
+            total_created: created,
+            total_closed: closed,
+            current_count: created.saturating_sub(closed) as usize,
+            total_bytes_relayed_in: self.counters.bytes_relayed_in.load(Ordering::Relaxed),
+            total_bytes_relayed_out: self.counters.bytes_relayed_out.load(Ordering::Relaxed),
+            total_permissions_created: self.counters.permissions_created.load(Ordering::Relaxed),
+            total_channel_binds: self.counters.channel_binds_created.load(Ordering::Relaxed),
+        }
+    }
+
+    // allocations returns a snapshot of all allocations
+    pub(crate) async fn allocations(&self) -> Vec<Arc<Mutex<Allocation>>> {
+        let allocations = self.allocations.lock().await;
+        allocations.values().map(Arc::clone).collect()
+    }
+
+    // export_snapshots returns the state of every allocation, see Allocation::serialize
+    #[cfg(feature = "state-dump")]
+    pub async fn export_snapshots(&self) -> Vec<AllocationSnapshot> {
+        let mut snapshots = vec![];
+        for a in self.allocations().await {
+            snapshots.push(a.lock().await.serialize().await);
+        }
+        snapshots
+    }
+
+    // import_snapshot recreates the allocation of snap for the listener conn
+    // turn_socket, with its remaining lifetime, permissions and channel
+    // bindings. The relay address generator of the manager must bind the
+    // relayed address of the snapshot again, since the client and its peers
+    // already use it. Restored permissions and channel bindings start a new
+    // lifetime of their default length.
+    #[cfg(feature = "state-dump")]
+    pub async fn import_snapshot(
+        &self,
+        snap: &AllocationSnapshot,
+        turn_socket: Arc<dyn Conn + Send + Sync>,
+    ) -> Result<()> {
+        let lifetime = snap
+            .expires_at
+            .duration_since(std::time::SystemTime::now())
+            .map_err(|_| Error::ErrSnapshotExpired)?;
+
+        let five_tuple = FiveTuple {
+            protocol: PROTO_UDP,
+            src_addr: snap.src_addr,
+            dst_addr: turn_socket.local_addr().await?,
+        };
+        let a = self
+            .create_allocation_internal(
+                five_tuple.clone(),
+                turn_socket,
+                snap.relay_addr.port(),
+                lifetime,
+                Username::new(stun::attributes::ATTR_USERNAME, snap.username.clone()),
+                snap.app_id.clone(),
+                false,
+            )
+            .await?;
+
+        let a = a.lock().await;
+        if a.relay_addr != snap.relay_addr {
+            log::warn!(
+                "failed to restore allocation of {}: relay addr {} instead of {}",
+                snap.username,
+                a.relay_addr,
+                snap.relay_addr
+            );
+            drop(a);
+            self.delete_allocation(&five_tuple).await;
+            return Err(Error::ErrSnapshotRelayAddrUnavailable);
+        }
+
+        for ip in &snap.permissions {
+            a.add_permission(Permission::new(SocketAddr::new(*ip, 0)))
+                .await;
+        }
+        for (number, peer) in &snap.channels {
+            a.add_channel_bind(
+                ChannelBind::new(ChannelNumber(*number), *peer),
+                crate::proto::lifetime::DEFAULT_LIFETIME,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    // refresh_all_allocations sets the lifetime of every allocation to lifetime
+    pub async fn refresh_all_allocations(&self, lifetime: Duration) {
+        for a in self.allocations().await {
+            let a = a.lock().await;
+            a.refresh(lifetime).await;
+        }
+    }
+
+    // cap_allocation_lifetimes shortens the remaining lifetime of every allocation
+    // to at most max_lifetime. Allocations expiring sooner are left alone.
+    pub async fn cap_allocation_lifetimes(&self, max_lifetime: Duration) {
+        for a in self.allocations().await {
+            let a = a.lock().await;
+            if a.remaining_lifetime().await > max_lifetime {
+                a.refresh(max_lifetime).await;
+            }
+        }
+    }
+
+    // get_allocation_by_relay_addr fetches the allocation owning the passed relay address
+    pub async fn get_allocation_by_relay_addr(
+        &self,
+        relay_addr: &SocketAddr,
+    ) -> Option<Arc<Mutex<Allocation>>> {
+        let fingerprint = {
+            let relay_addrs = self.relay_addrs.lock().await;
+            relay_addrs.get(relay_addr).cloned()?
+        };
+        let allocations = self.allocations.lock().await;
+        allocations.get(&fingerprint).map(Arc::clone)
+    }
+
     // delete_allocation removes an allocation
     pub async fn delete_allocation(&self, five_tuple: &FiveTuple) {
-        let fingerprint = five_tuple.fingerprint();
+        self.delete_allocation_by_fingerprint(five_tuple.fingerprint())
+            .await;
+    }
+
+    // delete_allocation_by_relay_addr removes the allocation owning the passed relay address
+    pub async fn delete_allocation_by_relay_addr(&self, relay_addr: &SocketAddr) {
+        let fingerprint = {
+            let relay_addrs = self.relay_addrs.lock().await;
+            relay_addrs.get(relay_addr).cloned()
+        };
+        if let Some(fingerprint) = fingerprint {
+            self.delete_allocation_by_fingerprint(fingerprint).await;
+        }
+    }
 
+    // allocation_by_username_prefix returns the allocations of all users whose
+    // name starts with prefix, e.g. all users of one tenant when usernames are
+    // "<tenant>_<user>". It scans every allocation, an index such as a trie
+    // could be added should that become too slow.
+    pub async fn allocation_by_username_prefix(&self, prefix: &str) -> Vec<AllocationInfo> {
+        let mut infos = vec![];
+        for a in self.allocations().await {
+            let a = a.lock().await;
+            if a.username.text.starts_with(prefix) {
+                infos.push(a.info());
+            }
+        }
+        infos
+    }
+
+    // delete_allocations_by_username removes all allocations of username and
+    // returns how many there were
+    pub async fn delete_allocations_by_username(&self, username: &str) -> usize {
+        let mut fingerprints = vec![];
+        for a in self.allocations().await {
+            let a = a.lock().await;
+            if a.username.text == username {
+                fingerprints.push(a.five_tuple.fingerprint());
+            }
+        }
+
+        let count = fingerprints.len();
+        for fingerprint in fingerprints {
+            self.delete_allocation_by_fingerprint(fingerprint).await;
+        }
+        count
+    }
+
+    // drain_user evicts all allocations of username politely: each client first
+    // gets an empty ChannelData message on DRAIN_NOTICE_CHANNEL_NUMBER, and the
+    // allocation is deleted after grace_period, giving the client a chance to
+    // move elsewhere first. This notice is non-standard, clients that don't
+    // know it just lose their allocation after grace_period. It returns the
+    // number of allocations being drained.
+    pub async fn drain_user(&self, username: &str, grace_period: Duration) -> usize {
+        let mut drained = vec![];
+        for a in self.allocations().await {
+            let fingerprint = {
+                let a = a.lock().await;
+                if a.username.text != username {
+                    continue;
+                }
+
+                if let Err(err) = a.send_drain_notice().await {
+                    log::warn!(
+                        "failed to send drain notice to {}: {}",
+                        a.five_tuple.src_addr,
+                        err
+                    );
+                }
+                a.five_tuple.fingerprint()
+            };
+            drained.push((fingerprint, a));
+        }
+
+        let count = drained.len();
+        let allocations = Arc::clone(&self.allocations);
+        tokio::spawn(async move {
+            tokio::time::sleep(grace_period).await;
+
+            // closing takes more locks, closes sockets and runs
+            // on_allocation_closed, so the allocations are taken out of the map
+            // first and closed once its lock is released
+            let expired: Vec<_> = {
+                let mut allocations = allocations.lock().await;
+                drained
+                    .into_iter()
+                    .filter(|(fingerprint, a)| {
+                        // the allocation may have expired and been recreated meanwhile
+                        if !matches!(allocations.get(fingerprint), Some(current) if Arc::ptr_eq(current, a))
+                        {
+                            return false;
+                        }
+                        allocations.remove(fingerprint);
+                        true
+                    })
+                    .map(|(_, a)| a)
+                    .collect()
+            };
+
+            for a in expired {
+                let mut a = a.lock().await;
+                if let Err(err) = a.close().await {
+                    log::error!("Failed to close allocation: {}", err);
+                }
+            }
+        });
+
+        count
+    }
+
+    async fn delete_allocation_by_fingerprint(&self, fingerprint: String) {
         let mut allocations = self.allocations.lock().await;
         let allocation = allocations.remove(&fingerprint);
         if let Some(a) = allocation {
@@ -122,9 +719,40 @@ impl Manager {
         reservations.get(reservation_token).copied()
     }
 
-    // get_random_even_port returns a random un-allocated udp4 port
+    // allocation_lifetime returns the lifetime granted to an allocation of
+    // username by the allocation lifetime strategy, where negotiated is the
+    // one derived from the LIFETIME of the request
+    pub fn allocation_lifetime(&self, username: &str, negotiated: Duration) -> Duration {
+        self.allocation_lifetime_strategy
+            .lifetime(username, negotiated)
+    }
+
+    // supports_dont_fragment tells whether the relay address generator can send
+    // datagrams with the DF bit set
+    pub fn supports_dont_fragment(&self) -> bool {
+        self.relay_addr_generator.supports_dont_fragment()
+    }
+
+    // send_to_dont_fragment sends buf from relay_socket to target with the DF bit set
+    pub async fn send_to_dont_fragment(
+        &self,
+        relay_socket: &(dyn Conn + Send + Sync),
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Result<usize> {
+        self.relay_addr_generator
+            .send_to_dont_fragment(relay_socket, buf, target)
+            .await
+    }
+
+    // get_random_even_port returns a random even IPv4 port that was free when
+    // it was probed
     pub async fn get_random_even_port(&self) -> Result<u16> {
-        let (_, addr) = self.relay_addr_generator.allocate_conn(true, 0).await?;
-        Ok(addr.port())
+        loop {
+            let (_, addr) = self.relay_addr_generator.allocate_conn(true, 0).await?;
+            if addr.port() % 2 == 0 {
+                return Ok(addr.port());
+            }
+        }
     }
 }
@@ -1,10 +1,16 @@
 use super::*;
 use crate::error::Result;
+use crate::relay::relay_dynamic::*;
 use crate::relay::relay_none::*;
+use crate::relay::relay_per_user::*;
+use crate::relay::relay_static::*;
 
+use crate::proto::data::Data;
 use crate::proto::lifetime::DEFAULT_LIFETIME;
-use std::net::Ipv4Addr;
+use async_trait::async_trait;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
+use stun::attributes::{ATTR_DATA, ATTR_USERNAME, ATTR_XOR_PEER_ADDRESS};
 use tokio::net::UdpSocket;
 use util::vnet::net::*;
 
@@ -14,6 +20,13 @@ fn new_test_manager() -> Manager {
             address: "0.0.0.0".to_owned(),
             net: Arc::new(Net::new(None)),
         }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
     };
     Manager::new(config)
 }
@@ -62,6 +75,7 @@ async fn test_packet_handler() -> Result<()> {
             Arc::new(turn_socket),
             0,
             DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
         )
         .await?;
 
@@ -146,7 +160,158 @@ async fn test_packet_handler() -> Result<()> {
     );
 
     // listeners close
-    m.close().await?;
+    m.close(Duration::from_secs(1)).await?;
+
+    Ok(())
+}
+
+// test_forward_icmp_port_unreachable relays a datagram to a closed local port
+// and expects the ICMP port unreachable the kernel gets for it as a Data
+// indication
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_forward_icmp_port_unreachable() -> Result<()> {
+    use crate::proto::icmp::Icmp;
+
+    let turn_socket = UdpSocket::bind("127.0.0.1:0").await?;
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let m = Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+            relay_address: IpAddr::from_str("127.0.0.1")?,
+            address: "127.0.0.1".to_owned(),
+            net: Arc::new(Net::new(None)),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: true,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    });
+    let a = m
+        .create_allocation(
+            FiveTuple {
+                src_addr: client.local_addr()?,
+                dst_addr: turn_socket.local_addr()?,
+                ..Default::default()
+            },
+            Arc::new(turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+
+    let peer = {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        socket.local_addr()?
+    };
+    {
+        let a = a.lock().await;
+        a.add_permission(Permission::new(peer)).await;
+        a.relay_socket.send_to(b"hello", peer).await?;
+    }
+
+    let mut buf = vec![0u8; RTP_MTU];
+    let (n, _) = tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buf))
+        .await
+        .expect("ICMP Data indication should arrive")?;
+    let mut msg = Message::new();
+    msg.write(&buf[..n])?;
+    assert_eq!(msg.typ, MessageType::new(METHOD_DATA, CLASS_INDICATION));
+    assert!(!msg.contains(ATTR_DATA));
+
+    let mut peer_addr = PeerAddress::default();
+    peer_addr.get_from(&msg)?;
+    assert_eq!(SocketAddr::new(peer_addr.ip, peer_addr.port), peer);
+
+    let mut icmp = Icmp::default();
+    icmp.get_from(&msg)?;
+    assert_eq!(icmp, Icmp::port_unreachable(&peer));
+
+    m.close(Duration::from_secs(1)).await?;
+
+    Ok(())
+}
+
+// test_data_indication_encoding checks the Data indication sent for a peer
+// with a permission byte by byte against RFC 5389 Section 6 and 15.2 and
+// RFC 5766 Section 10.3, 14.3 and 14.4
+#[tokio::test]
+async fn test_data_indication_encoding() -> Result<()> {
+    let turn_socket = UdpSocket::bind("127.0.0.1:0").await?;
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let m = new_test_manager();
+    let a = m
+        .create_allocation(
+            FiveTuple {
+                src_addr: client.local_addr()?,
+                dst_addr: turn_socket.local_addr()?,
+                ..Default::default()
+            },
+            Arc::new(turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+
+    let peer = UdpSocket::bind("127.0.0.1:0").await?;
+    let peer_addr = peer.local_addr()?;
+    let relay_port = {
+        let a = a.lock().await;
+        a.add_permission(Permission::new(peer_addr)).await;
+        a.relay_socket.local_addr().await?.port()
+    };
+
+    let payload = b"hello";
+    peer.send_to(payload, SocketAddr::from(([127, 0, 0, 1], relay_port)))
+        .await?;
+
+    let mut buf = vec![0u8; RTP_MTU];
+    let (n, _) = client.recv_from(&mut buf).await?;
+    let raw = &buf[..n];
+
+    // header, 20 bytes
+    assert_eq!(
+        raw.len(),
+        20 + 12 + 4 + 8,
+        "header, XOR-PEER-ADDRESS, padded DATA"
+    );
+    assert_eq!(
+        &raw[0..2],
+        &[0x00, 0x17],
+        "message type should be Data indication"
+    );
+    assert_eq!(
+        u16::from_be_bytes([raw[2], raw[3]]) as usize,
+        raw.len() - 20,
+        "message length should exclude the header"
+    );
+    assert_eq!(&raw[4..8], &MAGIC_COOKIE.to_be_bytes(), "magic cookie");
+
+    // XOR-PEER-ADDRESS: type, length 8, reserved, family IPv4, X-Port, X-Address
+    let attr = &raw[20..32];
+    assert_eq!(&attr[0..2], &ATTR_XOR_PEER_ADDRESS.value().to_be_bytes());
+    assert_eq!(&attr[2..4], &[0x00, 0x08]);
+    assert_eq!(attr[4], 0x00, "reserved");
+    assert_eq!(attr[5], 0x01, "family should be IPv4");
+    let x_port = peer_addr.port() ^ (MAGIC_COOKIE >> 16) as u16;
+    assert_eq!(&attr[6..8], &x_port.to_be_bytes(), "X-Port");
+    let x_address = u32::from_be_bytes([127, 0, 0, 1]) ^ MAGIC_COOKIE;
+    assert_eq!(&attr[8..12], &x_address.to_be_bytes(), "X-Address");
+
+    // DATA: type, unpadded length, value, zero padding to 4 bytes
+    let attr = &raw[32..];
+    assert_eq!(&attr[0..2], &ATTR_DATA.value().to_be_bytes());
+    assert_eq!(&attr[2..4], &(payload.len() as u16).to_be_bytes());
+    assert_eq!(&attr[4..9], payload);
+    assert_eq!(&attr[9..12], &[0, 0, 0], "padding");
+
+    m.close(Duration::from_secs(1)).await?;
 
     Ok(())
 }
@@ -161,57 +326,740 @@ async fn test_create_allocation_duplicate_five_tuple() -> Result<()> {
     let m = new_test_manager();
 
     let five_tuple = random_five_tuple();
-
-    let _ = m
+
+    let _ = m
+        .create_allocation(
+            five_tuple.clone(),
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+
+    let result = m
+        .create_allocation(
+            five_tuple,
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await;
+    assert!(result.is_err(), "expected error, but got ok");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_create_allocation_max_connections() -> Result<()> {
+    //env_logger::init();
+
+    // turn server initialization
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let m = Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(RelayAddressGeneratorNone {
+            address: "0.0.0.0".to_owned(),
+            net: Arc::new(Net::new(None)),
+        }),
+        max_connections: Some(1),
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    });
+
+    let five_tuple = random_five_tuple();
+
+    let _ = m
+        .create_allocation(
+            five_tuple.clone(),
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+
+    let result = m
+        .create_allocation(
+            random_five_tuple(),
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await;
+    assert_eq!(
+        result.err(),
+        Some(Error::ErrMaxConnectionsReached),
+        "expected max connections error"
+    );
+
+    // once the existing allocation is gone, a new address is accepted again
+    m.delete_allocation(&five_tuple).await;
+    let result = m
+        .create_allocation(
+            random_five_tuple(),
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await;
+    assert!(result.is_ok(), "expected ok, but got error");
+
+    Ok(())
+}
+
+// YieldingRelayAddressGenerator binds relays on the loopback address like a
+// generator that has to wait for the socket, so concurrent Allocates interleave
+struct YieldingRelayAddressGenerator;
+
+#[async_trait]
+impl RelayAddressGenerator for YieldingRelayAddressGenerator {
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn allocate_conn(
+        &self,
+        _use_ipv4: bool,
+        requested_port: u16,
+    ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr)> {
+        tokio::task::yield_now().await;
+        let conn =
+            UdpSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), requested_port)).await?;
+        let relay_addr = conn.local_addr()?;
+        Ok((Arc::new(conn), relay_addr))
+    }
+}
+
+fn new_max_connections_manager(max_connections: usize) -> Manager {
+    Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(YieldingRelayAddressGenerator),
+        max_connections: Some(max_connections),
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    })
+}
+
+#[tokio::test]
+async fn test_create_allocation_max_connections_concurrent() -> Result<()> {
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let m = new_max_connections_manager(1);
+
+    // both pass the limit check before either is inserted unless the check
+    // holds a slot until the insert
+    let create = |five_tuple: FiveTuple| {
+        m.create_allocation(
+            five_tuple,
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+    };
+    let (first, second) = tokio::join!(create(random_five_tuple()), create(random_five_tuple()));
+    let errors: Vec<_> = vec![first.err(), second.err()]
+        .into_iter()
+        .flatten()
+        .collect();
+    assert_eq!(errors, vec![Error::ErrMaxConnectionsReached]);
+    assert_eq!(m.allocations().await.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_create_allocation_max_connections_per_source() -> Result<()> {
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let m = new_max_connections_manager(1);
+
+    // the limit counts source addresses, not allocations
+    let five_tuple = random_five_tuple();
+    for dst_port in [3478, 3479] {
+        m.create_allocation(
+            FiveTuple {
+                dst_addr: SocketAddr::new(five_tuple.dst_addr.ip(), dst_port),
+                ..five_tuple.clone()
+            },
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+    }
+
+    let result = m
+        .create_allocation(
+            random_five_tuple(),
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await;
+    assert_eq!(result.err(), Some(Error::ErrMaxConnectionsReached));
+
+    Ok(())
+}
+
+// DualStackRelayAddressGenerator binds relays on the loopback address of the requested family
+struct DualStackRelayAddressGenerator;
+
+#[async_trait]
+impl RelayAddressGenerator for DualStackRelayAddressGenerator {
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn allocate_conn(
+        &self,
+        use_ipv4: bool,
+        requested_port: u16,
+    ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr)> {
+        let ip: IpAddr = if use_ipv4 {
+            Ipv4Addr::LOCALHOST.into()
+        } else {
+            Ipv6Addr::LOCALHOST.into()
+        };
+        let conn = UdpSocket::bind(SocketAddr::new(ip, requested_port)).await?;
+        let relay_addr = conn.local_addr()?;
+        Ok((Arc::new(conn), relay_addr))
+    }
+}
+
+#[tokio::test]
+async fn test_create_dual_allocation() -> Result<()> {
+    // turn server initialization
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let m = Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(DualStackRelayAddressGenerator),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    });
+
+    let a = m
+        .create_dual_allocation(
+            random_five_tuple(),
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+
+    let a = a.lock().await;
+    assert!(a.relay_addr.is_ipv4(), "relay addr should be IPv4");
+    let additional_relay_addr = a
+        .additional_relay_addr
+        .ok_or_else(|| Error::Other("no additional relay addr".to_owned()))?;
+    assert!(
+        additional_relay_addr.is_ipv6(),
+        "additional relay addr should be IPv6"
+    );
+
+    let peer = SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 5000);
+    assert_eq!(
+        a.relay_socket_for(&peer).local_addr().await?,
+        additional_relay_addr,
+        "IPv6 peers should use the additional relay"
+    );
+    assert_eq!(a.info().additional_relay_addr, Some(additional_relay_addr));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_create_dual_allocation_unavailable() -> Result<()> {
+    // turn server initialization
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    // this generator can only bind IPv4 relays
+    let m = new_test_manager();
+
+    // the allocation still gets its IPv4 relayed address, RFC 8656 Section 7.2
+    let five_tuple = random_five_tuple();
+    let a = m
+        .create_dual_allocation(
+            five_tuple.clone(),
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+    let info = a.lock().await.info();
+    assert!(info.relay_addr.is_ipv4());
+    assert_eq!(info.additional_relay_addr, None);
+    assert!(m.get_allocation(&five_tuple).await.is_some());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_relay_keepalive() -> Result<()> {
+    // stun server initialization
+    let stun_server = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+
+    let m = Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(RelayAddressGeneratorNone {
+            address: "127.0.0.1".to_owned(),
+            net: Arc::new(Net::new(None)),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: Some(Duration::from_millis(50)),
+        relay_keepalive_server: Some(stun_server.local_addr()?),
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    });
+
+    let five_tuple = random_five_tuple();
+    let a = m
+        .create_allocation(
+            five_tuple.clone(),
+            turn_socket,
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+    let relay_addr = a.lock().await.relay_addr;
+
+    let mut buffer = vec![0u8; RTP_MTU];
+    for _ in 0..2 {
+        let (n, from) =
+            tokio::time::timeout(Duration::from_secs(1), stun_server.recv_from(&mut buffer))
+                .await
+                .expect("keepalive should arrive")?;
+        assert_eq!(from, relay_addr, "keepalive should be sent from the relay");
+
+        let mut msg = Message::new();
+        msg.unmarshal_binary(&buffer[..n])?;
+        assert_eq!(
+            msg.typ, BINDING_REQUEST,
+            "keepalive should be a binding request"
+        );
+    }
+
+    m.delete_allocation(&five_tuple).await;
+
+    // drain anything already in flight, then expect silence
+    while tokio::time::timeout(
+        Duration::from_millis(100),
+        stun_server.recv_from(&mut buffer),
+    )
+    .await
+    .is_ok()
+    {}
+    assert!(
+        tokio::time::timeout(
+            Duration::from_millis(200),
+            stun_server.recv_from(&mut buffer)
+        )
+        .await
+        .is_err(),
+        "keepalive should stop once the allocation is deleted"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_create_allocation_dynamic_relay_address() -> Result<()> {
+    // turn server initialization
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let m = Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(RelayAddressGeneratorDynamic {
+            generator: Box::new(|username| match username {
+                "alice" => Some(IpAddr::from_str("10.0.0.1").unwrap()),
+                "bob" => Some(IpAddr::from_str("10.0.0.2").unwrap()),
+                _ => None,
+            }),
+            address: "0.0.0.0".to_owned(),
+            net: Arc::new(Net::new(None)),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    });
+
+    for (username, expected) in [("alice", "10.0.0.1"), ("bob", "10.0.0.2")] {
+        let a = m
+            .create_allocation(
+                random_five_tuple(),
+                Arc::clone(&turn_socket),
+                0,
+                DEFAULT_LIFETIME,
+                Username::new(ATTR_USERNAME, username.to_owned()),
+            )
+            .await?;
+        assert_eq!(
+            a.lock().await.relay_addr.ip(),
+            IpAddr::from_str(expected)?,
+            "relay address for {} should come from the generator",
+            username
+        );
+    }
+
+    let result = m
+        .create_allocation(
+            random_five_tuple(),
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "mallory".to_owned()),
+        )
+        .await;
+    assert_eq!(
+        result.err(),
+        Some(Error::ErrNoRelayAddressForUser),
+        "unknown users should get no relay"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_delete_allocation() -> Result<()> {
+    //env_logger::init();
+
+    // turn server initialization
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let m = new_test_manager();
+
+    let five_tuple = random_five_tuple();
+
+    let _ = m
+        .create_allocation(
+            five_tuple.clone(),
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+
+    assert!(
+        m.get_allocation(&five_tuple).await.is_some(),
+        "Failed to get allocation right after creation"
+    );
+
+    m.delete_allocation(&five_tuple).await;
+
+    assert!(
+        m.get_allocation(&five_tuple).await.is_none(),
+        "Get allocation with {} should be nil after delete",
+        five_tuple
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_delete_allocation_by_relay_addr() -> Result<()> {
+    // turn server initialization
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let m = new_test_manager();
+
+    let five_tuple = random_five_tuple();
+    let a = m
+        .create_allocation(
+            five_tuple.clone(),
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+    let relay_addr = a.lock().await.relay_addr;
+
+    assert!(
+        m.get_allocation_by_relay_addr(&relay_addr).await.is_some(),
+        "Failed to get allocation by relay addr right after creation"
+    );
+
+    m.delete_allocation_by_relay_addr(&relay_addr).await;
+
+    assert!(
+        m.get_allocation(&five_tuple).await.is_none(),
+        "Get allocation with {} should be nil after delete",
+        five_tuple
+    );
+    assert!(
+        m.relay_addrs.lock().await.is_empty(),
+        "relay addr index should be empty after delete"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_delete_allocations_by_username() -> Result<()> {
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let m = new_test_manager();
+
+    let mut five_tuples = vec![];
+    for username in ["alice", "alice", "bob"] {
+        let five_tuple = random_five_tuple();
+        m.create_allocation(
+            five_tuple.clone(),
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, username.to_owned()),
+        )
+        .await?;
+        five_tuples.push(five_tuple);
+    }
+
+    assert_eq!(m.delete_allocations_by_username("alice").await, 2);
+    assert!(m.get_allocation(&five_tuples[0]).await.is_none());
+    assert!(m.get_allocation(&five_tuples[1]).await.is_none());
+    assert!(
+        m.get_allocation(&five_tuples[2]).await.is_some(),
+        "allocations of other users should be kept"
+    );
+    assert_eq!(m.delete_allocations_by_username("alice").await, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_allocation_by_username_prefix() -> Result<()> {
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let m = new_test_manager();
+
+    for username in ["tenant1_alice", "tenant1_bob", "tenant2_alice"] {
+        m.create_allocation(
+            random_five_tuple(),
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, username.to_owned()),
+        )
+        .await?;
+    }
+
+    let mut usernames: Vec<String> = m
+        .allocation_by_username_prefix("tenant1_")
+        .await
+        .into_iter()
+        .map(|info| info.username)
+        .collect();
+    usernames.sort();
+    assert_eq!(usernames, vec!["tenant1_alice", "tenant1_bob"]);
+
+    assert_eq!(m.allocation_by_username_prefix("").await.len(), 3);
+    assert!(m.allocation_by_username_prefix("tenant3_").await.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_refresh_all_allocations() -> Result<()> {
+    // turn server initialization
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let m = new_test_manager();
+
+    let mut allocations = vec![];
+    for _ in 0..3 {
+        let a = m
+            .create_allocation(
+                random_five_tuple(),
+                Arc::clone(&turn_socket),
+                0,
+                DEFAULT_LIFETIME,
+                Username::new(ATTR_USERNAME, "user".to_owned()),
+            )
+            .await?;
+        assert!(
+            a.lock().await.remaining_lifetime().await <= DEFAULT_LIFETIME,
+            "should start with the requested lifetime"
+        );
+        allocations.push(a);
+    }
+
+    let new_lifetime = Duration::from_secs(2 * 3600);
+    m.refresh_all_allocations(new_lifetime).await;
+
+    for a in &allocations {
+        let remaining = a.lock().await.remaining_lifetime().await;
+        assert!(
+            remaining > DEFAULT_LIFETIME && remaining <= new_lifetime,
+            "remaining lifetime {:?} should reflect the new expiry",
+            remaining
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_permission_and_channel_bind_count() -> Result<()> {
+    // turn server initialization
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let m = new_test_manager();
+
+    let five_tuple = random_five_tuple();
+    let a = m
         .create_allocation(
             five_tuple.clone(),
             Arc::clone(&turn_socket),
             0,
             DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
         )
         .await?;
 
-    let result = m
-        .create_allocation(five_tuple, Arc::clone(&turn_socket), 0, DEFAULT_LIFETIME)
-        .await;
-    assert!(result.is_err(), "expected error, but got ok");
+    let peer1 = SocketAddr::from_str("1.2.3.4:5000")?;
+    let peer2 = SocketAddr::from_str("5.6.7.8:5000")?;
+    {
+        let a = a.lock().await;
+        a.add_permission(Permission::new(peer1)).await;
+        // refreshing an existing permission must not count twice
+        a.add_permission(Permission::new(peer1)).await;
+        a.add_channel_bind(
+            ChannelBind::new(ChannelNumber(MIN_CHANNEL_NUMBER), peer2),
+            DEFAULT_LIFETIME,
+        )
+        .await?;
+    }
+    assert_eq!(m.permission_count(), 2, "channel bind adds a permission");
+    assert_eq!(m.channel_bind_count(), 1);
+
+    {
+        let a = a.lock().await;
+        assert!(a.remove_permission(&peer1).await);
+        assert!(
+            a.remove_channel_bind(ChannelNumber(MIN_CHANNEL_NUMBER))
+                .await
+        );
+    }
+    assert_eq!(m.permission_count(), 1);
+    assert_eq!(m.channel_bind_count(), 0);
+
+    m.delete_allocation(&five_tuple).await;
+    assert_eq!(m.permission_count(), 0, "closing releases permissions");
+    assert_eq!(m.channel_bind_count(), 0);
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_delete_allocation() -> Result<()> {
-    //env_logger::init();
-
-    // turn server initialization
-    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+async fn test_manager_statistics() -> Result<()> {
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let peer = UdpSocket::bind("127.0.0.1:0").await?;
 
     let m = new_test_manager();
+    assert_eq!(m.statistics(), ManagerStats::default());
 
-    let five_tuple = random_five_tuple();
-
-    let _ = m
+    let five_tuple = FiveTuple {
+        src_addr: client.local_addr()?,
+        dst_addr: turn_socket.local_addr().await?,
+        ..Default::default()
+    };
+    let a = m
         .create_allocation(
-            five_tuple.clone(),
+            five_tuple,
             Arc::clone(&turn_socket),
             0,
             DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+    let other_five_tuple = random_five_tuple();
+    m.create_allocation(
+        other_five_tuple.clone(),
+        Arc::clone(&turn_socket),
+        0,
+        DEFAULT_LIFETIME,
+        Username::new(ATTR_USERNAME, "user".to_owned()),
+    )
+    .await?;
+
+    let relay_port = {
+        let a = a.lock().await;
+        a.add_permission(Permission::new(peer.local_addr()?)).await;
+        a.add_channel_bind(
+            ChannelBind::new(
+                ChannelNumber(MIN_CHANNEL_NUMBER),
+                SocketAddr::from_str("1.2.3.4:5000")?,
+            ),
+            DEFAULT_LIFETIME,
         )
         .await?;
+        a.relay_addr.port()
+    };
 
-    assert!(
-        m.get_allocation(&five_tuple).await.is_some(),
-        "Failed to get allocation right after creation"
-    );
+    peer.send_to(
+        &[0u8; 10],
+        SocketAddr::from_str(&format!("127.0.0.1:{}", relay_port))?,
+    )
+    .await?;
+    let mut buf = vec![0u8; RTP_MTU];
+    tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buf))
+        .await
+        .expect("data should be relayed in time")?;
+    // the receiver counts right after sending
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while m.statistics().total_bytes_relayed_in == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .expect("relayed bytes should be counted in time");
 
-    m.delete_allocation(&five_tuple).await;
+    m.delete_allocation(&other_five_tuple).await;
 
-    assert!(
-        m.get_allocation(&five_tuple).await.is_none(),
-        "Get allocation with {} should be nil after delete",
-        five_tuple
+    assert_eq!(
+        m.statistics(),
+        ManagerStats {
+            total_created: 2,
+            total_closed: 1,
+            current_count: 1,
+            total_bytes_relayed_in: 10,
+            total_bytes_relayed_out: 0,
+            // the channel bind added a permission of its own
+            total_permissions_created: 2,
+            total_channel_binds: 1,
+        }
     );
 
+    m.close(Duration::from_secs(1)).await?;
+    assert_eq!(m.statistics().current_count, 0);
+
     Ok(())
 }
 
@@ -231,7 +1079,13 @@ async fn test_allocation_timeout() -> Result<()> {
         let five_tuple = random_five_tuple();
 
         let a = m
-            .create_allocation(five_tuple, Arc::clone(&turn_socket), 0, lifetime)
+            .create_allocation(
+                five_tuple,
+                Arc::clone(&turn_socket),
+                0,
+                lifetime,
+                Username::new(ATTR_USERNAME, "user".to_owned()),
+            )
             .await?;
 
         allocations.push(a);
@@ -243,7 +1097,7 @@ async fn test_allocation_timeout() -> Result<()> {
         count += 1;
 
         if count >= 10 {
-            assert!(false, "Allocations didn't timeout");
+            panic!("Allocations didn't timeout");
         }
 
         tokio::time::sleep(lifetime + Duration::from_millis(100)).await;
@@ -263,6 +1117,35 @@ async fn test_allocation_timeout() -> Result<()> {
     }
 }
 
+#[tokio::test]
+async fn test_allocation_expired_watch() -> Result<()> {
+    // turn server initialization
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let m = new_test_manager();
+
+    let lifetime = Duration::from_millis(100);
+    let a = m
+        .create_allocation(
+            random_five_tuple(),
+            Arc::clone(&turn_socket),
+            0,
+            lifetime,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+    let mut expired = a.lock().await.expired();
+    assert!(!*expired.borrow(), "allocation should not start expired");
+
+    tokio::time::timeout(Duration::from_secs(1), expired.changed())
+        .await
+        .expect("allocation should expire")
+        .expect("watch sender should be alive");
+    assert!(*expired.borrow(), "allocation should be expired");
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_manager_close() -> Result<()> {
     // env_logger::init();
@@ -280,6 +1163,7 @@ async fn test_manager_close() -> Result<()> {
             Arc::clone(&turn_socket),
             0,
             Duration::from_millis(100),
+            Username::new(ATTR_USERNAME, "user".to_owned()),
         )
         .await?;
     allocations.push(a1);
@@ -290,6 +1174,7 @@ async fn test_manager_close() -> Result<()> {
             Arc::clone(&turn_socket),
             0,
             Duration::from_millis(200),
+            Username::new(ATTR_USERNAME, "user".to_owned()),
         )
         .await?;
     allocations.push(a2);
@@ -298,7 +1183,7 @@ async fn test_manager_close() -> Result<()> {
 
     log::trace!("Mgr is going to be closed...");
 
-    m.close().await?;
+    m.close(Duration::from_secs(1)).await?;
 
     for allocation in allocations {
         let mut a = allocation.lock().await;
@@ -310,3 +1195,487 @@ async fn test_manager_close() -> Result<()> {
 
     Ok(())
 }
+
+// SlowCloseConn is a relay socket whose close takes close_delay
+struct SlowCloseConn {
+    conn: UdpSocket,
+    close_delay: Duration,
+    closed: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl Conn for SlowCloseConn {
+    async fn connect(&self, addr: SocketAddr) -> std::result::Result<(), util::Error> {
+        Conn::connect(&self.conn, addr).await
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> std::result::Result<usize, util::Error> {
+        Conn::recv(&self.conn, buf).await
+    }
+
+    async fn recv_from(
+        &self,
+        buf: &mut [u8],
+    ) -> std::result::Result<(usize, SocketAddr), util::Error> {
+        Conn::recv_from(&self.conn, buf).await
+    }
+
+    async fn send(&self, buf: &[u8]) -> std::result::Result<usize, util::Error> {
+        Conn::send(&self.conn, buf).await
+    }
+
+    async fn send_to(
+        &self,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> std::result::Result<usize, util::Error> {
+        Conn::send_to(&self.conn, buf, target).await
+    }
+
+    async fn local_addr(&self) -> std::result::Result<SocketAddr, util::Error> {
+        Conn::local_addr(&self.conn).await
+    }
+
+    async fn remote_addr(&self) -> Option<SocketAddr> {
+        Conn::remote_addr(&self.conn).await
+    }
+
+    async fn close(&self) -> std::result::Result<(), util::Error> {
+        tokio::time::sleep(self.close_delay).await;
+        self.closed.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+// SlowCloseRelayAddressGenerator hands out SlowCloseConn relays and counts
+// how many of them finished closing
+struct SlowCloseRelayAddressGenerator {
+    close_delay: Duration,
+    closed: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl RelayAddressGenerator for SlowCloseRelayAddressGenerator {
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn allocate_conn(
+        &self,
+        _use_ipv4: bool,
+        requested_port: u16,
+    ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr)> {
+        let conn = UdpSocket::bind(("127.0.0.1", requested_port)).await?;
+        let relay_addr = conn.local_addr()?;
+        Ok((
+            Arc::new(SlowCloseConn {
+                conn,
+                close_delay: self.close_delay,
+                closed: Arc::clone(&self.closed),
+            }),
+            relay_addr,
+        ))
+    }
+}
+
+async fn new_slow_close_manager(close_delay: Duration) -> Result<(Manager, Arc<AtomicUsize>)> {
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let closed = Arc::new(AtomicUsize::new(0));
+
+    let m = Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(SlowCloseRelayAddressGenerator {
+            close_delay,
+            closed: Arc::clone(&closed),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    });
+
+    for _ in 0..2 {
+        m.create_allocation(
+            random_five_tuple(),
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+    }
+
+    Ok((m, closed))
+}
+
+#[tokio::test]
+async fn test_manager_close_waits_for_relay_sockets() -> Result<()> {
+    let (m, closed) = new_slow_close_manager(Duration::from_millis(100)).await?;
+
+    let start = Instant::now();
+    m.close(Duration::from_secs(5)).await?;
+    assert_eq!(
+        closed.load(Ordering::SeqCst),
+        2,
+        "all relay sockets should be closed"
+    );
+    assert!(
+        start.elapsed() < Duration::from_millis(200),
+        "allocations should be closed concurrently"
+    );
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_manager_close_timeout() -> Result<()> {
+    let (m, closed) = new_slow_close_manager(Duration::from_secs(60)).await?;
+
+    let start = Instant::now();
+    m.close(Duration::from_millis(100)).await?;
+    assert!(
+        start.elapsed() < Duration::from_secs(60),
+        "should give up after the timeout"
+    );
+    assert_eq!(closed.load(Ordering::SeqCst), 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_drain_user() -> Result<()> {
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let m = new_test_manager();
+
+    let drained_five_tuple = FiveTuple {
+        src_addr: client.local_addr()?,
+        dst_addr: turn_socket.local_addr().await?,
+        ..Default::default()
+    };
+    m.create_allocation(
+        drained_five_tuple.clone(),
+        Arc::clone(&turn_socket),
+        0,
+        DEFAULT_LIFETIME,
+        Username::new(ATTR_USERNAME, "user".to_owned()),
+    )
+    .await?;
+
+    let other_five_tuple = random_five_tuple();
+    m.create_allocation(
+        other_five_tuple.clone(),
+        Arc::clone(&turn_socket),
+        0,
+        DEFAULT_LIFETIME,
+        Username::new(ATTR_USERNAME, "other".to_owned()),
+    )
+    .await?;
+
+    let grace_period = Duration::from_millis(100);
+    assert_eq!(m.drain_user("user", grace_period).await, 1);
+
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = client.recv_from(&mut buf).await?;
+    let mut notice = ChannelData {
+        raw: buf[..n].to_vec(),
+        ..Default::default()
+    };
+    notice.decode()?;
+    assert_eq!(notice.number, DRAIN_NOTICE_CHANNEL_NUMBER);
+    assert!(notice.data.is_empty(), "drain notice should be empty");
+
+    assert!(
+        m.get_allocation(&drained_five_tuple).await.is_some(),
+        "allocation should survive the grace period"
+    );
+
+    tokio::time::sleep(grace_period * 2).await;
+
+    assert!(
+        m.get_allocation(&drained_five_tuple).await.is_none(),
+        "allocation should be deleted after the grace period"
+    );
+    assert!(
+        m.get_allocation(&other_five_tuple).await.is_some(),
+        "allocations of other users should be kept"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_allocation_callbacks() -> Result<()> {
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let created = Arc::new(std::sync::Mutex::new(vec![]));
+    let closed = Arc::new(std::sync::Mutex::new(vec![]));
+
+    let m = {
+        let created = Arc::clone(&created);
+        let closed = Arc::clone(&closed);
+        Manager::new(ManagerConfig {
+            relay_addr_generator: Box::new(RelayAddressGeneratorNone {
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            on_allocation_created: Some(Arc::new(move |info| created.lock().unwrap().push(info))),
+            on_allocation_closed: Some(Arc::new(move |info| closed.lock().unwrap().push(info))),
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+        })
+    };
+
+    let five_tuple = random_five_tuple();
+    let a = m
+        .create_allocation(
+            five_tuple.clone(),
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+    let info = a.lock().await.info();
+    assert_eq!(info.five_tuple, five_tuple);
+    assert_eq!(info.username, "user");
+
+    assert_eq!(*created.lock().unwrap(), vec![info.clone()]);
+    assert!(closed.lock().unwrap().is_empty());
+
+    m.delete_allocation(&five_tuple).await;
+
+    assert_eq!(*created.lock().unwrap(), vec![info.clone()]);
+    assert_eq!(*closed.lock().unwrap(), vec![info]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_relay_addr_generator_per_user() -> Result<()> {
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let premium_ip = IpAddr::from_str("192.0.2.1")?;
+    let standard_ip = IpAddr::from_str("192.0.2.2")?;
+    let new_generator = |relay_address| -> Arc<dyn RelayAddressGenerator + Send + Sync> {
+        Arc::new(RelayAddressGeneratorStatic {
+            relay_address,
+            address: "0.0.0.0".to_owned(),
+            net: Arc::new(Net::new(None)),
+        })
+    };
+    let premium = new_generator(premium_ip);
+    let standard = new_generator(standard_ip);
+
+    let m = Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(RelayAddressGeneratorPerUser {
+            factory: Box::new(move |username| {
+                if username.starts_with("premium_") {
+                    Arc::clone(&premium)
+                } else {
+                    Arc::clone(&standard)
+                }
+            }),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    });
+
+    for (username, expected_ip) in [("premium_alice", premium_ip), ("bob", standard_ip)] {
+        let a = m
+            .create_allocation(
+                random_five_tuple(),
+                Arc::clone(&turn_socket),
+                0,
+                DEFAULT_LIFETIME,
+                Username::new(ATTR_USERNAME, username.to_owned()),
+            )
+            .await?;
+        assert_eq!(
+            a.lock().await.relay_addr.ip(),
+            expected_ip,
+            "{} should get a relay from its tier",
+            username
+        );
+    }
+
+    m.close(Duration::from_secs(1)).await?;
+
+    Ok(())
+}
+
+#[test]
+fn test_allocation_lifetime_strategy() {
+    let requested = Duration::from_secs(300);
+
+    let fixed = LifetimeStrategy::Fixed(Duration::from_secs(60));
+    assert_eq!(fixed.lifetime("alice", requested), Duration::from_secs(60));
+    assert_eq!(
+        fixed.lifetime("alice", DEFAULT_LIFETIME),
+        Duration::from_secs(60)
+    );
+
+    let negotiated = LifetimeStrategy::default();
+    assert_eq!(negotiated.lifetime("alice", requested), requested);
+    assert_eq!(
+        negotiated.lifetime("alice", DEFAULT_LIFETIME),
+        DEFAULT_LIFETIME
+    );
+
+    let dynamic = LifetimeStrategy::Dynamic(Box::new(|username, negotiated| {
+        if username.starts_with("premium_") {
+            negotiated
+        } else {
+            negotiated.min(Duration::from_secs(120))
+        }
+    }));
+    assert_eq!(dynamic.lifetime("premium_alice", requested), requested);
+    assert_eq!(dynamic.lifetime("bob", requested), Duration::from_secs(120));
+    assert_eq!(
+        dynamic.lifetime("bob", Duration::from_secs(30)),
+        Duration::from_secs(30)
+    );
+
+    assert_eq!(format!("{:?}", fixed), "Fixed(60s)");
+    assert_eq!(format!("{:?}", dynamic), "Dynamic");
+}
+
+// AdvertisedRelayAddressGenerator binds relays on an ephemeral loopback port
+// and advertises them at the requested port of ip, like a server behind a
+// port-preserving NAT. A restarted server can't rebind relays of its old
+// process in a test, since their sockets are never released.
+#[cfg(feature = "state-dump")]
+struct AdvertisedRelayAddressGenerator {
+    ip: IpAddr,
+    next_port: AtomicUsize,
+}
+
+#[cfg(feature = "state-dump")]
+#[async_trait]
+impl RelayAddressGenerator for AdvertisedRelayAddressGenerator {
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn allocate_conn(
+        &self,
+        _use_ipv4: bool,
+        requested_port: u16,
+    ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr)> {
+        let conn = UdpSocket::bind("127.0.0.1:0").await?;
+        let port = if requested_port != 0 {
+            requested_port
+        } else {
+            self.next_port.fetch_add(1, Ordering::SeqCst) as u16
+        };
+        Ok((Arc::new(conn), SocketAddr::new(self.ip, port)))
+    }
+}
+
+#[cfg(feature = "state-dump")]
+fn new_advertised_manager() -> Manager {
+    Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(AdvertisedRelayAddressGenerator {
+            ip: IpAddr::from_str("192.0.2.1").unwrap(),
+            next_port: AtomicUsize::new(50000),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    })
+}
+
+#[cfg(feature = "state-dump")]
+#[tokio::test]
+async fn test_export_import_snapshots() -> Result<()> {
+    let turn_socket: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let five_tuple = FiveTuple {
+        src_addr: SocketAddr::from_str("127.0.0.1:5000")?,
+        dst_addr: turn_socket.local_addr().await?,
+        ..Default::default()
+    };
+    let peer = SocketAddr::from_str("127.0.0.1:6000")?;
+
+    let m = new_advertised_manager();
+    let a = m
+        .create_allocation(
+            five_tuple.clone(),
+            Arc::clone(&turn_socket),
+            0,
+            DEFAULT_LIFETIME,
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+    {
+        let a = a.lock().await;
+        a.add_channel_bind(
+            ChannelBind::new(ChannelNumber(0x4000), peer),
+            DEFAULT_LIFETIME,
+        )
+        .await?;
+    }
+
+    let snapshots = m.export_snapshots().await;
+    assert_eq!(snapshots.len(), 1);
+    let snap = &snapshots[0];
+    assert_eq!(snap.username, "user");
+    assert_eq!(snap.relay_addr, SocketAddr::from_str("192.0.2.1:50000")?);
+    assert_eq!(snap.src_addr, five_tuple.src_addr);
+    assert_eq!(snap.permissions, vec![peer.ip()]);
+    assert_eq!(snap.channels, vec![(0x4000, peer)]);
+
+    let restored = new_advertised_manager();
+    restored
+        .import_snapshot(snap, Arc::clone(&turn_socket))
+        .await?;
+    let a = restored
+        .get_allocation(&five_tuple)
+        .await
+        .expect("allocation should be restored");
+    let a = a.lock().await;
+    assert_eq!(a.relay_addr, snap.relay_addr);
+    assert_eq!(a.username.text, "user");
+    assert!(a.has_permission(&peer).await);
+    assert_eq!(a.get_channel_addr(&ChannelNumber(0x4000)).await, Some(peer));
+    let remaining = a.remaining_lifetime().await;
+    assert!(remaining > Duration::from_secs(0) && remaining <= DEFAULT_LIFETIME);
+    drop(a);
+
+    // an expired snapshot is not restored
+    let mut expired = snap.clone();
+    expired.src_addr = SocketAddr::from_str("127.0.0.1:5001")?;
+    expired.expires_at = std::time::SystemTime::now() - Duration::from_secs(1);
+    assert_eq!(
+        restored
+            .import_snapshot(&expired, Arc::clone(&turn_socket))
+            .await,
+        Err(Error::ErrSnapshotExpired)
+    );
+
+    // neither is one whose relayed address can't be bound again
+    let mut moved = snap.clone();
+    moved.src_addr = SocketAddr::from_str("127.0.0.1:5002")?;
+    moved.relay_addr = SocketAddr::from_str("192.0.2.2:50000")?;
+    assert_eq!(
+        restored.import_snapshot(&moved, turn_socket).await,
+        Err(Error::ErrSnapshotRelayAddrUnavailable)
+    );
+    assert_eq!(restored.allocations().await.len(), 1);
+
+    Ok(())
+}
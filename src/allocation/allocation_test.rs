@@ -2,6 +2,7 @@ use super::*;
 
 use crate::proto::lifetime::DEFAULT_LIFETIME;
 use std::str::FromStr;
+use stun::attributes::{ATTR_DATA, ATTR_USERNAME};
 use tokio::net::UdpSocket;
 
 #[tokio::test]
@@ -9,7 +10,13 @@ async fn test_has_permission() -> Result<()> {
     let turn_socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
     let relay_socket = Arc::clone(&turn_socket);
     let relay_addr = relay_socket.local_addr()?;
-    let a = Allocation::new(turn_socket, relay_socket, relay_addr, FiveTuple::default());
+    let a = Allocation::new(
+        turn_socket,
+        relay_socket,
+        relay_addr,
+        FiveTuple::default(),
+        Username::new(ATTR_USERNAME, "user".to_owned()),
+    );
 
     let addr1 = SocketAddr::from_str("127.0.0.1:3478")?;
     let addr2 = SocketAddr::from_str("127.0.0.1:3479")?;
@@ -40,7 +47,13 @@ async fn test_add_permission() -> Result<()> {
     let turn_socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
     let relay_socket = Arc::clone(&turn_socket);
     let relay_addr = relay_socket.local_addr()?;
-    let a = Allocation::new(turn_socket, relay_socket, relay_addr, FiveTuple::default());
+    let a = Allocation::new(
+        turn_socket,
+        relay_socket,
+        relay_addr,
+        FiveTuple::default(),
+        Username::new(ATTR_USERNAME, "user".to_owned()),
+    );
 
     let addr = SocketAddr::from_str("127.0.0.1:3478")?;
     let p = Permission::new(addr);
@@ -57,7 +70,13 @@ async fn test_remove_permission() -> Result<()> {
     let turn_socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
     let relay_socket = Arc::clone(&turn_socket);
     let relay_addr = relay_socket.local_addr()?;
-    let a = Allocation::new(turn_socket, relay_socket, relay_addr, FiveTuple::default());
+    let a = Allocation::new(
+        turn_socket,
+        relay_socket,
+        relay_addr,
+        FiveTuple::default(),
+        Username::new(ATTR_USERNAME, "user".to_owned()),
+    );
 
     let addr = SocketAddr::from_str("127.0.0.1:3478")?;
 
@@ -83,7 +102,13 @@ async fn test_add_channel_bind() -> Result<()> {
     let turn_socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
     let relay_socket = Arc::clone(&turn_socket);
     let relay_addr = relay_socket.local_addr()?;
-    let a = Allocation::new(turn_socket, relay_socket, relay_addr, FiveTuple::default());
+    let a = Allocation::new(
+        turn_socket,
+        relay_socket,
+        relay_addr,
+        FiveTuple::default(),
+        Username::new(ATTR_USERNAME, "user".to_owned()),
+    );
 
     let addr = SocketAddr::from_str("127.0.0.1:3478")?;
     let c = ChannelBind::new(ChannelNumber(MIN_CHANNEL_NUMBER), addr);
@@ -110,7 +135,13 @@ async fn test_get_channel_by_number() -> Result<()> {
     let turn_socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
     let relay_socket = Arc::clone(&turn_socket);
     let relay_addr = relay_socket.local_addr()?;
-    let a = Allocation::new(turn_socket, relay_socket, relay_addr, FiveTuple::default());
+    let a = Allocation::new(
+        turn_socket,
+        relay_socket,
+        relay_addr,
+        FiveTuple::default(),
+        Username::new(ATTR_USERNAME, "user".to_owned()),
+    );
 
     let addr = SocketAddr::from_str("127.0.0.1:3478")?;
     let c = ChannelBind::new(ChannelNumber(MIN_CHANNEL_NUMBER), addr);
@@ -139,7 +170,13 @@ async fn test_get_channel_by_addr() -> Result<()> {
     let turn_socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
     let relay_socket = Arc::clone(&turn_socket);
     let relay_addr = relay_socket.local_addr()?;
-    let a = Allocation::new(turn_socket, relay_socket, relay_addr, FiveTuple::default());
+    let a = Allocation::new(
+        turn_socket,
+        relay_socket,
+        relay_addr,
+        FiveTuple::default(),
+        Username::new(ATTR_USERNAME, "user".to_owned()),
+    );
 
     let addr = SocketAddr::from_str("127.0.0.1:3478")?;
     let addr2 = SocketAddr::from_str("127.0.0.1:3479")?;
@@ -164,7 +201,13 @@ async fn test_remove_channel_bind() -> Result<()> {
     let turn_socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
     let relay_socket = Arc::clone(&turn_socket);
     let relay_addr = relay_socket.local_addr()?;
-    let a = Allocation::new(turn_socket, relay_socket, relay_addr, FiveTuple::default());
+    let a = Allocation::new(
+        turn_socket,
+        relay_socket,
+        relay_addr,
+        FiveTuple::default(),
+        Username::new(ATTR_USERNAME, "user".to_owned()),
+    );
 
     let addr = SocketAddr::from_str("127.0.0.1:3478")?;
     let number = ChannelNumber(MIN_CHANNEL_NUMBER);
@@ -194,7 +237,13 @@ async fn test_allocation_refresh() -> Result<()> {
     let turn_socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
     let relay_socket = Arc::clone(&turn_socket);
     let relay_addr = relay_socket.local_addr()?;
-    let mut a = Allocation::new(turn_socket, relay_socket, relay_addr, FiveTuple::default());
+    let mut a = Allocation::new(
+        turn_socket,
+        relay_socket,
+        relay_addr,
+        FiveTuple::default(),
+        Username::new(ATTR_USERNAME, "user".to_owned()),
+    );
 
     a.start(DEFAULT_LIFETIME).await;
     a.refresh(Duration::from_secs(0)).await;
@@ -209,7 +258,13 @@ async fn test_allocation_close() -> Result<()> {
     let turn_socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
     let relay_socket = Arc::clone(&turn_socket);
     let relay_addr = relay_socket.local_addr()?;
-    let mut a = Allocation::new(turn_socket, relay_socket, relay_addr, FiveTuple::default());
+    let mut a = Allocation::new(
+        turn_socket,
+        relay_socket,
+        relay_addr,
+        FiveTuple::default(),
+        Username::new(ATTR_USERNAME, "user".to_owned()),
+    );
 
     // add mock lifetimeTimer
     a.start(DEFAULT_LIFETIME).await;
@@ -228,3 +283,123 @@ async fn test_allocation_close() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_peer_addresses_and_channel_bindings() -> Result<()> {
+    let turn_socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let relay_socket = Arc::clone(&turn_socket);
+    let relay_addr = relay_socket.local_addr()?;
+    let a = Allocation::new(
+        turn_socket,
+        relay_socket,
+        relay_addr,
+        FiveTuple::default(),
+        Username::new(ATTR_USERNAME, "user".to_owned()),
+    );
+
+    assert!(a.peer_addresses().await.is_empty());
+    assert!(a.channel_bindings().await.is_empty());
+
+    let permission_addr = SocketAddr::from_str("127.0.0.1:3478")?;
+    a.add_permission(Permission::new(permission_addr)).await;
+
+    let channel_addr = SocketAddr::from_str("127.0.0.2:3479")?;
+    a.add_channel_bind(
+        ChannelBind::new(ChannelNumber(MIN_CHANNEL_NUMBER), channel_addr),
+        DEFAULT_LIFETIME,
+    )
+    .await?;
+
+    let mut peers = a.peer_addresses().await;
+    peers.sort();
+    assert_eq!(peers, vec![permission_addr.ip(), channel_addr.ip()]);
+    assert_eq!(
+        a.channel_bindings().await,
+        vec![(MIN_CHANNEL_NUMBER, channel_addr)]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_relay_addr_for() -> Result<()> {
+    let turn_socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let relay_socket = Arc::clone(&turn_socket);
+    let relay_addr = relay_socket.local_addr()?;
+    let mut a = Allocation::new(
+        turn_socket,
+        relay_socket,
+        relay_addr,
+        FiveTuple::default(),
+        Username::new(ATTR_USERNAME, "user".to_owned()),
+    );
+
+    let peer4 = SocketAddr::from_str("127.0.0.1:3478")?;
+    let peer6 = SocketAddr::from_str("[::1]:3478")?;
+    assert_eq!(a.relay_addr_for(&peer4), relay_addr);
+    assert_eq!(a.relay_addr_for(&peer6), relay_addr);
+
+    let additional_relay_addr = SocketAddr::from_str("[::1]:5000")?;
+    a.additional_relay_addr = Some(additional_relay_addr);
+    assert_eq!(a.relay_addr_for(&peer4), relay_addr);
+    assert_eq!(a.relay_addr_for(&peer6), additional_relay_addr);
+
+    let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+    assert!(Error::from(util::Error::from(refused)).is_connection_refused());
+    let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+    assert!(!Error::from(reset).is_connection_refused());
+    assert!(!Error::ErrShortWrite.is_connection_refused());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_forward_icmp_error() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let turn_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let relay_socket = Arc::clone(&turn_socket);
+    let relay_addr = relay_socket.local_addr()?;
+    let mut a = Allocation::new(
+        turn_socket,
+        relay_socket,
+        relay_addr,
+        FiveTuple {
+            src_addr: client.local_addr()?,
+            ..Default::default()
+        },
+        Username::new(ATTR_USERNAME, "user".to_owned()),
+    );
+
+    let peer = SocketAddr::from_str("127.0.0.1:3478")?;
+    let refused: Error = std::io::Error::from(std::io::ErrorKind::ConnectionRefused).into();
+
+    // nothing is sent unless enabled, nor for other errors
+    a.forward_icmp_error(&peer, &refused).await;
+    a.forward_icmp_errors = true;
+    a.forward_icmp_error(&peer, &Error::ErrShortWrite).await;
+    a.forward_icmp_error(&peer, &refused).await;
+
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = client.recv_from(&mut buf).await?;
+    let mut msg = Message::new();
+    msg.write(&buf[..n])?;
+    assert_eq!(msg.typ, MessageType::new(METHOD_DATA, CLASS_INDICATION));
+    assert!(
+        !msg.contains(ATTR_DATA),
+        "ICMP Data indication must not carry DATA"
+    );
+
+    let mut peer_addr = PeerAddress::default();
+    peer_addr.get_from(&msg)?;
+    assert_eq!(SocketAddr::new(peer_addr.ip, peer_addr.port), peer);
+
+    let mut icmp = Icmp::default();
+    icmp.get_from(&msg)?;
+    assert_eq!(icmp, Icmp::port_unreachable(&peer));
+
+    // the disabled and non-ICMP calls above must not have sent anything
+    let pending = tokio::time::timeout(Duration::from_millis(50), client.recv_from(&mut buf)).await;
+    assert!(pending.is_err(), "only one Data indication expected");
+
+    Ok(())
+}
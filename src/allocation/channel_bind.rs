@@ -8,6 +8,31 @@ use std::sync::{atomic::AtomicBool, atomic::Ordering, Arc};
 use tokio::sync::Mutex;
 use tokio::time::{Duration, Instant};
 
+// how long before expiry a ChannelBind that hasn't been refreshed is reported
+const CHANNEL_BIND_EXPIRY_WARNING: Duration = Duration::from_secs(60);
+
+// ChannelRefreshPolicy decides what a ChannelBind request for a channel that
+// is already bound to the same peer does
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ChannelRefreshPolicy {
+    // Reset restarts the binding's lifetime, as RFC 5766 Section 11.3 describes
+    #[default]
+    Reset,
+    // Extend adds the duration to the time the binding has left
+    Extend(Duration),
+    // Reject refuses the request with 400 (Bad Request), so a binding can't be
+    // kept alive beyond its first lifetime
+    Reject,
+}
+
+// TimerUpdate changes the expiry of a running ChannelBind
+#[derive(Debug, Clone, Copy)]
+enum TimerUpdate {
+    Reset(Duration),
+    Extend(Duration),
+}
+
 // ChannelBind represents a TURN Channel
 // https://tools.ietf.org/html/rfc5766#section-2.5
 #[derive(Clone)]
@@ -15,7 +40,8 @@ pub struct ChannelBind {
     pub(crate) peer: SocketAddr,
     pub(crate) number: ChannelNumber,
     pub(crate) channel_bindings: Option<Arc<Mutex<HashMap<ChannelNumber, ChannelBind>>>>,
-    reset_tx: Option<mpsc::Sender<Duration>>,
+    pub(crate) counters: Option<Arc<AllocationCounters>>,
+    reset_tx: Option<mpsc::Sender<TimerUpdate>>,
     timer_expired: Arc<AtomicBool>,
 }
 
@@ -26,6 +52,7 @@ impl ChannelBind {
             number,
             peer,
             channel_bindings: None,
+            counters: None,
             reset_tx: None,
             timer_expired: Arc::new(AtomicBool::new(false)),
         }
@@ -36,28 +63,56 @@ impl ChannelBind {
         self.reset_tx = Some(reset_tx);
 
         let channel_bindings = self.channel_bindings.clone();
+        let counters = self.counters.clone();
         let number = self.number;
+        let peer = self.peer;
         let timer_expired = Arc::clone(&self.timer_expired);
 
         tokio::spawn(async move {
             let timer = tokio::time::sleep(lifetime);
             tokio::pin!(timer);
+            let warning_timer =
+                tokio::time::sleep(lifetime.saturating_sub(CHANNEL_BIND_EXPIRY_WARNING));
+            tokio::pin!(warning_timer);
+            let mut warned = false;
             let mut done = false;
 
             while !done {
                 tokio::select! {
+                    _ = &mut warning_timer, if !warned => {
+                        // Stalled media is often a client that stopped refreshing its channels.
+                        log::debug!(
+                            "ChannelBind {} to {} expires in less than {:?} and hasn't been refreshed",
+                            number,
+                            peer,
+                            CHANNEL_BIND_EXPIRY_WARNING
+                        );
+                        warned = true;
+                    },
                     _ = &mut timer => {
                         if let Some(cbs) = &channel_bindings{
                             let mut cb = cbs.lock().await;
                             if cb.remove(&number).is_none() {
                                 log::error!("Failed to remove ChannelBind for {}", number);
+                            } else if let Some(counters) = &counters {
+                                counters.channel_bindings.fetch_sub(1, Ordering::Relaxed);
                             }
                         }
                         done = true;
                     },
                     result = reset_rx.recv() => {
-                        if let Some(d) = result {
-                            timer.as_mut().reset(Instant::now() + d);
+                        if let Some(update) = result {
+                            let deadline = match update {
+                                TimerUpdate::Reset(d) => Instant::now() + d,
+                                TimerUpdate::Extend(d) => timer.deadline() + d,
+                            };
+                            timer.as_mut().reset(deadline);
+                            warning_timer.as_mut().reset(
+                                deadline
+                                    .checked_sub(CHANNEL_BIND_EXPIRY_WARNING)
+                                    .unwrap_or_else(Instant::now),
+                            );
+                            warned = false;
                         } else {
                             done = true;
                         }
@@ -77,7 +132,14 @@ impl ChannelBind {
 
     pub(crate) async fn refresh(&self, lifetime: Duration) {
         if let Some(tx) = &self.reset_tx {
-            let _ = tx.send(lifetime).await;
+            let _ = tx.send(TimerUpdate::Reset(lifetime)).await;
+        }
+    }
+
+    // extend adds d to the time left until the ChannelBind expires
+    pub(crate) async fn extend(&self, d: Duration) {
+        if let Some(tx) = &self.reset_tx {
+            let _ = tx.send(TimerUpdate::Extend(d)).await;
         }
     }
 }
@@ -1,7 +1,10 @@
 use super::*;
 use crate::allocation::*;
 use crate::error::Result;
+use crate::proto::lifetime::DEFAULT_LIFETIME;
 
+use stun::attributes::ATTR_USERNAME;
+use stun::textattrs::Username;
 use tokio::net::UdpSocket;
 
 use std::net::Ipv4Addr;
@@ -10,7 +13,13 @@ async fn create_channel_bind(lifetime: Duration) -> Result<Allocation> {
     let turn_socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
     let relay_socket = Arc::clone(&turn_socket);
     let relay_addr = relay_socket.local_addr()?;
-    let a = Allocation::new(turn_socket, relay_socket, relay_addr, FiveTuple::default());
+    let a = Allocation::new(
+        turn_socket,
+        relay_socket,
+        relay_addr,
+        FiveTuple::default(),
+        Username::new(ATTR_USERNAME, "user".to_owned()),
+    );
 
     let addr = SocketAddr::new(Ipv4Addr::new(0, 0, 0, 0).into(), 0);
     let c = ChannelBind::new(ChannelNumber(MIN_CHANNEL_NUMBER), addr);
@@ -28,7 +37,7 @@ async fn test_channel_bind() -> Result<()> {
     if let Some(addr) = result {
         assert_eq!(addr.ip().to_string(), "0.0.0.0");
     } else {
-        assert!(false, "expected some, but got none");
+        panic!("expected some, but got none");
     }
 
     Ok(())
@@ -65,3 +74,54 @@ async fn test_channel_bind_reset() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test(start_paused = true)]
+async fn test_channel_bind_refresh_policy_extend() -> Result<()> {
+    let a = create_channel_bind(Duration::from_secs(100)).await?;
+    let number = ChannelNumber(MIN_CHANNEL_NUMBER);
+    let peer = a.get_channel_addr(&number).await.unwrap();
+
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    a.add_channel_bind_with_policy(
+        ChannelBind::new(number, peer),
+        Duration::from_secs(100),
+        ChannelRefreshPolicy::Extend(Duration::from_secs(100)),
+    )
+    .await?;
+
+    // a reset would have expired at 160s, the extension lasts until 200s
+    tokio::time::sleep(Duration::from_secs(110)).await;
+    assert!(a.get_channel_addr(&number).await.is_some());
+
+    tokio::time::sleep(Duration::from_secs(40)).await;
+    assert!(a.get_channel_addr(&number).await.is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_channel_bind_refresh_policy_reject() -> Result<()> {
+    let a = create_channel_bind(DEFAULT_LIFETIME).await?;
+    let number = ChannelNumber(MIN_CHANNEL_NUMBER);
+    let peer = a.get_channel_addr(&number).await.unwrap();
+
+    let result = a
+        .add_channel_bind_with_policy(
+            ChannelBind::new(number, peer),
+            DEFAULT_LIFETIME,
+            ChannelRefreshPolicy::Reject,
+        )
+        .await;
+    assert_eq!(result, Err(Error::ErrChannelBindRefreshRejected));
+
+    // only re-binds are rejected, new channels are still bound
+    let other_peer = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 2).into(), 5000);
+    a.add_channel_bind_with_policy(
+        ChannelBind::new(ChannelNumber(MIN_CHANNEL_NUMBER + 1), other_peer),
+        DEFAULT_LIFETIME,
+        ChannelRefreshPolicy::Reject,
+    )
+    .await?;
+
+    Ok(())
+}
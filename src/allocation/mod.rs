@@ -1,3 +1,27 @@
+//! Allocations and their bookkeeping, see RFC 5766 Section 5.
+//!
+//! An [`Allocation`] is created by the [`Manager`](allocation_manager::Manager)
+//! of a listener when an Allocate request succeeds and is keyed by the 5-tuple
+//! of the client. It holds:
+//!
+//! * the relay socket(s) the [`RelayAddressGenerator`](crate::relay::RelayAddressGenerator)
+//!   created for it, whose relay receiver tasks forward peer data to the client;
+//! * the [`Permission`]s installed by CreatePermission
+//!   and ChannelBind requests, each expiring after 5 minutes unless refreshed;
+//! * the [`ChannelBind`]s of ChannelBind requests,
+//!   expiring after the server channel bind timeout;
+//! * its own lifetime timer, reset by Refresh requests. When it fires, or the
+//!   relay socket fails, the allocation removes itself from the manager.
+//!
+//! ```text
+//!  Manager (one per listener)
+//!    +-- Allocation (one per client 5-tuple)
+//!          +-- relay socket(s) -> relay receiver task -> listener conn
+//!          +-- permissions      (peer IP -> Permission)
+//!          +-- channel bindings (channel number -> ChannelBind)
+//!          +-- lifetime timer
+//! ```
+
 #[cfg(test)]
 mod allocation_test;
 
@@ -7,27 +31,69 @@ pub mod five_tuple;
 pub mod permission;
 
 use crate::error::*;
-use crate::proto::{chandata::*, channum::*, data::*, peeraddr::*, *};
+use crate::proto::{chandata::*, channum::*, icmp::*, peeraddr::*, *};
+use crate::relay::receiver::RelayReceiver;
+#[cfg(feature = "state-dump")]
+use crate::server::snapshot::AllocationSnapshot;
 use channel_bind::*;
 use five_tuple::*;
 use permission::*;
 
 use stun::agent::*;
+use stun::fingerprint::*;
 use stun::message::*;
+use stun::textattrs::Username;
 
 use util::Conn;
 
 use std::collections::HashMap;
 use std::marker::{Send, Sync};
-use std::net::SocketAddr;
-use std::sync::{atomic::AtomicBool, atomic::Ordering, Arc};
-use tokio::sync::{mpsc, Mutex};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch, Mutex};
 use tokio::time::{Duration, Instant};
 
-const RTP_MTU: usize = 1500;
+pub(crate) const RTP_MTU: usize = 1500;
 
 pub type AllocationMap = Arc<Mutex<HashMap<String, Arc<Mutex<Allocation>>>>>;
 
+// AllocationCounters tracks the number of live permissions and channel bindings
+// across all allocations sharing it, along with running totals for
+// Manager::statistics
+#[derive(Default)]
+pub(crate) struct AllocationCounters {
+    pub(crate) permissions: AtomicUsize,
+    pub(crate) channel_bindings: AtomicUsize,
+    pub(crate) allocations_created: AtomicU64,
+    pub(crate) allocations_closed: AtomicU64,
+    // bytes_relayed_in counts the data peers sent to clients, bytes_relayed_out
+    // the data clients sent to peers, without TURN framing
+    pub(crate) bytes_relayed_in: AtomicU64,
+    pub(crate) bytes_relayed_out: AtomicU64,
+    pub(crate) permissions_created: AtomicU64,
+    pub(crate) channel_binds_created: AtomicU64,
+}
+
+// RelayAddrMap maps relay addresses to the fingerprint of the FiveTuple owning them
+pub type RelayAddrMap = Arc<Mutex<HashMap<SocketAddr, String>>>;
+
+// AllocationInfo describes an allocation to the server's allocation callbacks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllocationInfo {
+    pub five_tuple: FiveTuple,
+    pub username: String,
+    pub relay_addr: SocketAddr,
+    // additional_relay_addr is the IPv6 relayed address allocated on top of
+    // relay_addr for ADDITIONAL-ADDRESS-FAMILY, if any
+    pub additional_relay_addr: Option<SocketAddr>,
+    // app_id is the APP-ID the client tagged the allocation with, if any
+    pub app_id: Option<String>,
+}
+
+// AllocationCallback is called with the AllocationInfo of an allocation when it is created or closed
+pub type AllocationCallback = Arc<dyn Fn(AllocationInfo) + Send + Sync>;
+
 // Allocation is tied to a FiveTuple and relays traffic
 // use create_allocation and get_allocation to operate
 pub struct Allocation {
@@ -35,19 +101,58 @@ pub struct Allocation {
     turn_socket: Arc<dyn Conn + Send + Sync>,
     pub(crate) relay_addr: SocketAddr,
     pub(crate) relay_socket: Arc<dyn Conn + Send + Sync>,
-    five_tuple: FiveTuple,
-    permissions: Arc<Mutex<HashMap<String, Permission>>>,
-    channel_bindings: Arc<Mutex<HashMap<ChannelNumber, ChannelBind>>>,
+    pub(crate) additional_relay_addr: Option<SocketAddr>,
+    pub(crate) additional_relay_socket: Option<Arc<dyn Conn + Send + Sync>>,
+    pub(crate) five_tuple: FiveTuple,
+    pub(crate) username: Username,
+    pub(crate) app_id: Option<String>,
+    pub(crate) permissions: Arc<Mutex<HashMap<String, Permission>>>,
+    pub(crate) channel_bindings: Arc<Mutex<HashMap<ChannelNumber, ChannelBind>>>,
     pub(crate) allocations: Option<AllocationMap>,
+    pub(crate) relay_addrs: Option<RelayAddrMap>,
+    pub(crate) counters: Arc<AllocationCounters>,
+    pub(crate) on_closed: Option<AllocationCallback>,
+    pub(crate) forward_icmp_errors: bool,
     reset_tx: Option<mpsc::Sender<Duration>>,
     timer_expired: Arc<AtomicBool>,
+    expires_at: Mutex<Instant>,
+    expired_tx: Arc<watch::Sender<bool>>,
+    expired_rx: watch::Receiver<bool>,
+    keepalive_stop_tx: Option<mpsc::Sender<()>>,
     closed: bool, // Option<mpsc::Receiver<()>>,
 }
 
-fn addr2ipfingerprint(addr: &SocketAddr) -> String {
+pub(crate) fn addr2ipfingerprint(addr: &SocketAddr) -> String {
     addr.ip().to_string()
 }
 
+// send_icmp_indication sends a Data indication with an ICMP attribute in place
+// of DATA to client, RFC 8656 Section 11.5.
+pub(crate) async fn send_icmp_indication(
+    turn_socket: &(dyn Conn + Send + Sync),
+    client: SocketAddr,
+    peer: &SocketAddr,
+    icmp: Icmp,
+) {
+    let mut msg = Message::new();
+    if let Err(err) = msg.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_DATA, CLASS_INDICATION)),
+        Box::new(PeerAddress {
+            ip: peer.ip(),
+            port: peer.port(),
+        }),
+        Box::new(icmp),
+    ]) {
+        log::error!("Failed to build ICMP DataIndication for {} {}", peer, err);
+        return;
+    }
+
+    if let Err(err) = turn_socket.send_to(&msg.raw, client).await {
+        log::error!("Failed to send ICMP DataIndication for {} {}", peer, err);
+    }
+}
+
 impl Allocation {
     // creates a new instance of NewAllocation.
     pub fn new(
@@ -55,22 +160,141 @@ impl Allocation {
         relay_socket: Arc<dyn Conn + Send + Sync>,
         relay_addr: SocketAddr,
         five_tuple: FiveTuple,
+        username: Username,
     ) -> Self {
+        let (expired_tx, expired_rx) = watch::channel(false);
         Allocation {
             protocol: PROTO_UDP,
             turn_socket,
             relay_addr,
             relay_socket,
+            additional_relay_addr: None,
+            additional_relay_socket: None,
             five_tuple,
+            username,
+            app_id: None,
             permissions: Arc::new(Mutex::new(HashMap::new())),
             channel_bindings: Arc::new(Mutex::new(HashMap::new())),
             allocations: None,
+            relay_addrs: None,
+            counters: Arc::new(AllocationCounters::default()),
+            on_closed: None,
+            forward_icmp_errors: false,
             reset_tx: None,
             timer_expired: Arc::new(AtomicBool::new(false)),
+            expires_at: Mutex::new(Instant::now()),
+            expired_tx: Arc::new(expired_tx),
+            expired_rx,
+            keepalive_stop_tx: None,
             closed: false,
         }
     }
 
+    // info returns the AllocationInfo describing this allocation
+    pub fn info(&self) -> AllocationInfo {
+        AllocationInfo {
+            five_tuple: self.five_tuple.clone(),
+            username: self.username.text.clone(),
+            relay_addr: self.relay_addr,
+            additional_relay_addr: self.additional_relay_addr,
+            app_id: self.app_id.clone(),
+        }
+    }
+
+    // relay_socket_for returns the relay socket matching the address family of peer.
+    // IPv6 peers are reached through the additional relayed address when there is one.
+    pub(crate) fn relay_socket_for(&self, peer: &SocketAddr) -> &Arc<dyn Conn + Send + Sync> {
+        match &self.additional_relay_socket {
+            Some(socket) if peer.is_ipv6() => socket,
+            _ => &self.relay_socket,
+        }
+    }
+
+    // relay_addr_for returns the relayed address traffic to peer is sent from
+    pub(crate) fn relay_addr_for(&self, peer: &SocketAddr) -> SocketAddr {
+        match self.additional_relay_addr {
+            Some(addr) if peer.is_ipv6() => addr,
+            _ => self.relay_addr,
+        }
+    }
+
+    // log_relay_send_error logs that relaying to peer failed, along with the
+    // allocation and channel it was sent through. Refused connections are
+    // routine for UDP peers that went away, so they are only logged at debug.
+    pub(crate) fn log_relay_send_error(
+        &self,
+        peer: &SocketAddr,
+        channel: Option<ChannelNumber>,
+        err: &Error,
+    ) {
+        let channel = channel.map_or_else(|| "none".to_owned(), |c| c.to_string());
+        let level = if err.is_connection_refused() {
+            log::Level::Debug
+        } else {
+            log::Level::Warn
+        };
+        log::log!(
+            level,
+            "failed to relay from {} to {} for user {} (channel {}): {}",
+            self.relay_addr_for(peer),
+            peer,
+            self.username.text,
+            channel,
+            err
+        );
+    }
+
+    // forward_icmp_error tells the client that relaying to peer failed with
+    // err when that is an error an ICMP message would report: a refused
+    // connection, which is how the kernel reports an ICMP port unreachable on
+    // a connected socket, or ErrPacketTooBig for a datagram sent with
+    // DONT-FRAGMENT. It does nothing unless forward_icmp_errors is set. The
+    // relay receiver forwards the ICMP errors a RelaySocket reads from its
+    // error queue.
+    pub(crate) async fn forward_icmp_error(&self, peer: &SocketAddr, err: &Error) {
+        if !self.forward_icmp_errors {
+            return;
+        }
+        let icmp = if err.is_connection_refused() {
+            Icmp::port_unreachable(peer)
+        } else if matches!(err, Error::ErrPacketTooBig) {
+            Icmp::packet_too_big(peer)
+        } else {
+            return;
+        };
+
+        send_icmp_indication(
+            self.turn_socket.as_ref(),
+            self.five_tuple.src_addr,
+            peer,
+            icmp,
+        )
+        .await;
+    }
+
+    // relays_family reports whether the allocation has a relayed address of the
+    // same family as peer, so it can reach it at all.
+    pub(crate) fn relays_family(&self, peer: &SocketAddr) -> bool {
+        self.relay_addr.is_ipv4() == peer.is_ipv4()
+            || self
+                .additional_relay_addr
+                .is_some_and(|addr| addr.is_ipv4() == peer.is_ipv4())
+    }
+
+    // send_drain_notice sends an empty ChannelData message on
+    // DRAIN_NOTICE_CHANNEL_NUMBER to the client, see Manager::drain_user.
+    pub(crate) async fn send_drain_notice(&self) -> Result<()> {
+        let mut notice = ChannelData {
+            number: allocation_manager::DRAIN_NOTICE_CHANNEL_NUMBER,
+            ..Default::default()
+        };
+        notice.encode();
+        self.turn_socket
+            .send_to(&notice.raw, self.five_tuple.src_addr)
+            .await?;
+        Ok(())
+    }
+
     // has_permission gets the Permission from the allocation
     pub async fn has_permission(&self, addr: &SocketAddr) -> bool {
         let permissions = self.permissions.lock().await;
@@ -90,23 +314,45 @@ impl Allocation {
         }
 
         p.permissions = Some(Arc::clone(&self.permissions));
+        p.counters = Some(Arc::clone(&self.counters));
         p.start(PERMISSION_TIMEOUT).await;
 
         {
             let mut permissions = self.permissions.lock().await;
-            permissions.insert(fingerprint, p);
+            if permissions.insert(fingerprint, p).is_none() {
+                self.counters.permissions.fetch_add(1, Ordering::Relaxed);
+                self.counters
+                    .permissions_created
+                    .fetch_add(1, Ordering::Relaxed);
+            }
         }
     }
 
     // remove_permission removes the net.Addr's fingerprint from the allocation's permissions
     pub async fn remove_permission(&self, addr: &SocketAddr) -> bool {
         let mut permissions = self.permissions.lock().await;
-        permissions.remove(&addr2ipfingerprint(addr)).is_some()
+        let removed = permissions.remove(&addr2ipfingerprint(addr)).is_some();
+        if removed {
+            self.counters.permissions.fetch_sub(1, Ordering::Relaxed);
+        }
+        removed
     }
 
     // add_channel_bind adds a new ChannelBind to the allocation, it also updates the
     // permissions needed for this ChannelBind
-    pub async fn add_channel_bind(&self, mut c: ChannelBind, lifetime: Duration) -> Result<()> {
+    pub async fn add_channel_bind(&self, c: ChannelBind, lifetime: Duration) -> Result<()> {
+        self.add_channel_bind_with_policy(c, lifetime, ChannelRefreshPolicy::Reset)
+            .await
+    }
+
+    // add_channel_bind_with_policy is add_channel_bind, with policy deciding what
+    // happens when the channel is already bound to the peer
+    pub async fn add_channel_bind_with_policy(
+        &self,
+        mut c: ChannelBind,
+        lifetime: Duration,
+        policy: ChannelRefreshPolicy,
+    ) -> Result<()> {
         {
             if let Some(addr) = self.get_channel_addr(&c.number).await {
                 if addr != c.peer {
@@ -124,7 +370,13 @@ impl Allocation {
         {
             let channel_bindings = self.channel_bindings.lock().await;
             if let Some(cb) = channel_bindings.get(&c.number) {
-                cb.refresh(lifetime).await;
+                match policy {
+                    ChannelRefreshPolicy::Reset => cb.refresh(lifetime).await,
+                    ChannelRefreshPolicy::Extend(d) => cb.extend(d).await,
+                    ChannelRefreshPolicy::Reject => {
+                        return Err(Error::ErrChannelBindRefreshRejected)
+                    }
+                }
 
                 // Channel binds also refresh permissions.
                 self.add_permission(Permission::new(cb.peer)).await;
@@ -137,11 +389,19 @@ impl Allocation {
 
         // Add or refresh this channel.
         c.channel_bindings = Some(Arc::clone(&self.channel_bindings));
+        c.counters = Some(Arc::clone(&self.counters));
         c.start(lifetime).await;
 
         {
             let mut channel_bindings = self.channel_bindings.lock().await;
-            channel_bindings.insert(c.number, c);
+            if channel_bindings.insert(c.number, c).is_none() {
+                self.counters
+                    .channel_bindings
+                    .fetch_add(1, Ordering::Relaxed);
+                self.counters
+                    .channel_binds_created
+                    .fetch_add(1, Ordering::Relaxed);
+            }
         }
 
         // Channel binds also refresh permissions.
@@ -153,7 +413,13 @@ impl Allocation {
     // remove_channel_bind removes the ChannelBind from this allocation by id
     pub async fn remove_channel_bind(&self, number: ChannelNumber) -> bool {
         let mut channel_bindings = self.channel_bindings.lock().await;
-        channel_bindings.remove(&number).is_some()
+        let removed = channel_bindings.remove(&number).is_some();
+        if removed {
+            self.counters
+                .channel_bindings
+                .fetch_sub(1, Ordering::Relaxed);
+        }
+        removed
     }
 
     // get_channel_addr gets the ChannelBind's addr
@@ -173,6 +439,36 @@ impl Allocation {
         None
     }
 
+    // peer_addresses returns the peer IPs the allocation currently has a permission for
+    pub async fn peer_addresses(&self) -> Vec<IpAddr> {
+        let permissions = self.permissions.lock().await;
+        permissions.values().map(|p| p.addr.ip()).collect()
+    }
+
+    // channel_bindings returns the bound channel numbers with their peers
+    pub async fn channel_bindings(&self) -> Vec<(u16, SocketAddr)> {
+        let channel_bindings = self.channel_bindings.lock().await;
+        channel_bindings
+            .values()
+            .map(|cb| (cb.number.0, cb.peer))
+            .collect()
+    }
+
+    // serialize returns the state of the allocation, for Manager::import_snapshot
+    // to recreate it, e.g. after a restart
+    #[cfg(feature = "state-dump")]
+    pub async fn serialize(&self) -> AllocationSnapshot {
+        AllocationSnapshot {
+            username: self.username.text.clone(),
+            app_id: self.app_id.clone(),
+            relay_addr: self.relay_addr,
+            src_addr: self.five_tuple.src_addr,
+            permissions: self.peer_addresses().await,
+            channels: self.channel_bindings().await,
+            expires_at: std::time::SystemTime::now() + self.remaining_lifetime().await,
+        }
+    }
+
     // Close closes the allocation
     pub async fn close(&mut self) -> Result<()> {
         if self.closed {
@@ -180,13 +476,21 @@ impl Allocation {
         }
 
         self.closed = true;
+        self.counters
+            .allocations_closed
+            .fetch_add(1, Ordering::Relaxed);
         self.stop();
+        self.keepalive_stop_tx.take();
 
         {
             let mut permissions = self.permissions.lock().await;
             for p in permissions.values_mut() {
                 p.stop();
             }
+            self.counters
+                .permissions
+                .fetch_sub(permissions.len(), Ordering::Relaxed);
+            permissions.clear();
         }
 
         {
@@ -194,23 +498,53 @@ impl Allocation {
             for c in channel_bindings.values_mut() {
                 c.stop();
             }
+            self.counters
+                .channel_bindings
+                .fetch_sub(channel_bindings.len(), Ordering::Relaxed);
+            channel_bindings.clear();
+        }
+
+        if let Some(relay_addrs) = &self.relay_addrs {
+            let mut relay_addrs = relay_addrs.lock().await;
+            relay_addrs.remove(&self.relay_addr);
+            if let Some(additional_relay_addr) = &self.additional_relay_addr {
+                relay_addrs.remove(additional_relay_addr);
+            }
         }
 
         log::trace!("allocation with {} closed!", self.five_tuple);
 
-        let _ = self.turn_socket.close().await;
+        // turn_socket is the listener conn, shared with the server's read loop,
+        // which closes it on exit; closing it here would cut a TCP client off
+        // before it gets the response to its Refresh.
         let _ = self.relay_socket.close().await;
+        if let Some(additional_relay_socket) = &self.additional_relay_socket {
+            let _ = additional_relay_socket.close().await;
+        }
+
+        if let Some(on_closed) = &self.on_closed {
+            on_closed(self.info());
+        }
 
         Ok(())
     }
 
+    // expired returns a watch that flips to true once the allocation's lifetime
+    // runs out and it is closed. It fires at most once, and never fires for
+    // allocations that are deleted or refreshed to zero by the client.
+    pub fn expired(&self) -> watch::Receiver<bool> {
+        self.expired_rx.clone()
+    }
+
     pub async fn start(&mut self, lifetime: Duration) {
         let (reset_tx, mut reset_rx) = mpsc::channel(1);
         self.reset_tx = Some(reset_tx);
+        *self.expires_at.lock().await = Instant::now() + lifetime;
 
         let allocations = self.allocations.clone();
         let five_tuple = self.five_tuple.clone();
         let timer_expired = Arc::clone(&self.timer_expired);
+        let expired_tx = Arc::clone(&self.expired_tx);
 
         tokio::spawn(async move {
             let timer = tokio::time::sleep(lifetime);
@@ -227,6 +561,7 @@ impl Allocation {
                                 let _ = a.close().await;
                             }
                         }
+                        expired_tx.send_replace(true);
                         done = true;
                     },
                     result = reset_rx.recv() => {
@@ -243,6 +578,53 @@ impl Allocation {
         });
     }
 
+    // start_keepalive periodically sends a STUN Binding Request from every relay
+    // socket to keep NAT mappings in front of them alive. Requests go to server,
+    // or back to the relay address itself when no server is given.
+    pub(crate) fn start_keepalive(&mut self, interval: Duration, server: Option<SocketAddr>) {
+        let (stop_tx, mut stop_rx) = mpsc::channel::<()>(1);
+        self.keepalive_stop_tx = Some(stop_tx);
+
+        let mut relays = vec![(Arc::clone(&self.relay_socket), self.relay_addr)];
+        if let (Some(relay_socket), Some(relay_addr)) =
+            (&self.additional_relay_socket, self.additional_relay_addr)
+        {
+            relays.push((Arc::clone(relay_socket), relay_addr));
+        }
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {},
+                    _ = stop_rx.recv() => break,
+                }
+
+                for (relay_socket, relay_addr) in &relays {
+                    let dst = server.unwrap_or(*relay_addr);
+                    let mut msg = Message::new();
+                    if let Err(err) = msg.build(&[
+                        Box::new(TransactionId::new()),
+                        Box::new(BINDING_REQUEST),
+                        Box::new(FINGERPRINT),
+                    ]) {
+                        log::error!("Failed to build keepalive request: {}", err);
+                        continue;
+                    }
+
+                    if let Err(err) = relay_socket.send_to(&msg.raw, dst).await {
+                        log::debug!(
+                            "stop keepalive for relay {} on send error: {}",
+                            relay_addr,
+                            err
+                        );
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
     pub fn stop(&mut self) -> bool {
         let expired = self.reset_tx.is_none() || self.timer_expired.load(Ordering::SeqCst);
         self.reset_tx.take();
@@ -252,10 +634,17 @@ impl Allocation {
     // Refresh updates the allocations lifetime
     pub async fn refresh(&self, lifetime: Duration) {
         if let Some(tx) = &self.reset_tx {
+            *self.expires_at.lock().await = Instant::now() + lifetime;
             let _ = tx.send(lifetime).await;
         }
     }
 
+    // remaining_lifetime returns how long until the allocation expires
+    pub async fn remaining_lifetime(&self) -> Duration {
+        let expires_at = self.expires_at.lock().await;
+        expires_at.saturating_duration_since(Instant::now())
+    }
+
     //  https://tools.ietf.org/html/rfc5766#section-10.3
     //  When the server receives a UDP datagram at a currently allocated
     //  relayed transport address, the server looks up the allocation
@@ -276,123 +665,31 @@ impl Allocation {
     //  transport address of the received UDP datagram.  The Data indication
     //  is then sent on the 5-tuple associated with the allocation.
     async fn packet_handler(&self) {
-        let five_tuple = self.five_tuple.clone();
-        let relay_addr = self.relay_addr;
-        let relay_socket = Arc::clone(&self.relay_socket);
-        let turn_socket = Arc::clone(&self.turn_socket);
-        let allocations = self.allocations.clone();
-        let channel_bindings = Arc::clone(&self.channel_bindings);
-        let permissions = Arc::clone(&self.permissions);
+        self.spawn_packet_handler(Arc::clone(&self.relay_socket), self.relay_addr);
 
-        tokio::spawn(async move {
-            let mut buffer = vec![0u8; RTP_MTU];
+        if let (Some(relay_socket), Some(relay_addr)) =
+            (&self.additional_relay_socket, self.additional_relay_addr)
+        {
+            self.spawn_packet_handler(Arc::clone(relay_socket), relay_addr);
+        }
+    }
 
-            loop {
-                let (n, src_addr) = match relay_socket.recv_from(&mut buffer).await {
-                    Ok((n, src_addr)) => (n, src_addr),
-                    Err(_) => {
-                        if let Some(allocs) = &allocations {
-                            let mut alls = allocs.lock().await;
-                            alls.remove(&five_tuple.fingerprint());
-                        }
-                        break;
-                    }
-                };
-
-                log::debug!(
-                    "relay socket {:?} received {} bytes from {}",
-                    relay_socket.local_addr().await,
-                    n,
-                    src_addr
-                );
-
-                let cb_number = {
-                    let mut cb_number = None;
-                    let cbs = channel_bindings.lock().await;
-                    for cb in cbs.values() {
-                        if cb.peer == src_addr {
-                            cb_number = Some(cb.number);
-                            break;
-                        }
-                    }
-                    cb_number
-                };
-
-                if let Some(number) = cb_number {
-                    let mut channel_data = ChannelData {
-                        data: buffer[..n].to_vec(),
-                        number,
-                        raw: vec![],
-                    };
-                    channel_data.encode();
-
-                    if let Err(err) = turn_socket
-                        .send_to(&channel_data.raw, five_tuple.src_addr)
-                        .await
-                    {
-                        log::error!(
-                            "Failed to send ChannelData from allocation {} {}",
-                            src_addr,
-                            err
-                        );
-                    }
-                } else {
-                    let exist = {
-                        let ps = permissions.lock().await;
-                        ps.get(&addr2ipfingerprint(&src_addr)).is_some()
-                    };
-
-                    if exist {
-                        let msg = {
-                            let peer_address_attr = PeerAddress {
-                                ip: src_addr.ip(),
-                                port: src_addr.port(),
-                            };
-                            let data_attr = Data(buffer[..n].to_vec());
-
-                            let mut msg = Message::new();
-                            if let Err(err) = msg.build(&[
-                                Box::new(TransactionId::new()),
-                                Box::new(MessageType::new(METHOD_DATA, CLASS_INDICATION)),
-                                Box::new(peer_address_attr),
-                                Box::new(data_attr),
-                            ]) {
-                                log::error!(
-                                    "Failed to send DataIndication from allocation {} {}",
-                                    src_addr,
-                                    err
-                                );
-                                None
-                            } else {
-                                Some(msg)
-                            }
-                        };
-
-                        if let Some(msg) = msg {
-                            log::debug!(
-                                "relaying message from {} to client at {}",
-                                src_addr,
-                                five_tuple.src_addr
-                            );
-                            if let Err(err) =
-                                turn_socket.send_to(&msg.raw, five_tuple.src_addr).await
-                            {
-                                log::error!(
-                                    "Failed to send DataIndication from allocation {} {}",
-                                    src_addr,
-                                    err
-                                );
-                            }
-                        }
-                    } else {
-                        log::info!(
-                            "No Permission or Channel exists for {} on allocation {}",
-                            src_addr,
-                            relay_addr
-                        );
-                    }
-                }
-            }
-        });
+    fn spawn_packet_handler(
+        &self,
+        relay_socket: Arc<dyn Conn + Send + Sync>,
+        relay_addr: SocketAddr,
+    ) {
+        let receiver = RelayReceiver {
+            relay_socket,
+            relay_addr,
+            five_tuple: self.five_tuple.clone(),
+            turn_socket: Arc::clone(&self.turn_socket),
+            allocations: self.allocations.clone(),
+            channel_bindings: Arc::clone(&self.channel_bindings),
+            permissions: Arc::clone(&self.permissions),
+            counters: Arc::clone(&self.counters),
+            forward_icmp_errors: self.forward_icmp_errors,
+        };
+        tokio::spawn(receiver.run());
     }
 }
@@ -12,6 +12,7 @@ pub(crate) const PERMISSION_TIMEOUT: Duration = Duration::from_secs(5 * 60);
 pub struct Permission {
     pub(crate) addr: SocketAddr,
     pub(crate) permissions: Option<Arc<Mutex<HashMap<String, Permission>>>>,
+    pub(crate) counters: Option<Arc<AllocationCounters>>,
     reset_tx: Option<mpsc::Sender<Duration>>,
     timer_expired: Arc<AtomicBool>,
 }
@@ -22,6 +23,7 @@ impl Permission {
         Permission {
             addr,
             permissions: None,
+            counters: None,
             reset_tx: None,
             timer_expired: Arc::new(AtomicBool::new(false)),
         }
@@ -32,6 +34,7 @@ impl Permission {
         self.reset_tx = Some(reset_tx);
 
         let permissions = self.permissions.clone();
+        let counters = self.counters.clone();
         let addr = self.addr;
         let timer_expired = Arc::clone(&self.timer_expired);
 
@@ -45,7 +48,11 @@ impl Permission {
                     _ = &mut timer => {
                         if let Some(perms) = &permissions{
                             let mut p = perms.lock().await;
-                            p.remove(&addr2ipfingerprint(&addr));
+                            if p.remove(&addr2ipfingerprint(&addr)).is_some() {
+                                if let Some(counters) = &counters {
+                                    counters.permissions.fetch_sub(1, Ordering::Relaxed);
+                                }
+                            }
                         }
                         done = true;
                     },
@@ -0,0 +1,113 @@
+#[cfg(test)]
+mod cached_test;
+
+use super::*;
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+// CACHE_PRUNE_THRESHOLD is the number of cached users above which expired
+// entries are dropped on insert, so the cache doesn't keep every username
+// ever seen.
+const CACHE_PRUNE_THRESHOLD: usize = 1024;
+
+struct CachedKey {
+    realm: String,
+    key: Vec<u8>,
+    cached_at: Instant,
+}
+
+// CachingAuthHandler wraps an AuthHandler and remembers the keys it returns
+// for ttl, so the server, which consults the auth handler for every
+// authenticated request, calls a slow auth backend only once per user and
+// ttl. A cached key is used for the realm it was returned for only. Failures
+// are not cached.
+//
+// The inner handler is skipped on a hit, so it must not depend on the client
+// address or ORIGIN, and a key revoked in the backend stays valid until ttl
+// runs out unless it is invalidated.
+pub struct CachingAuthHandler {
+    inner: Arc<dyn AuthHandler + Send + Sync>,
+    ttl: Duration,
+    // the trait is synchronous, so this is a std lock. It is never held
+    // across a call to the inner handler.
+    cache: RwLock<HashMap<String, CachedKey>>,
+}
+
+impl CachingAuthHandler {
+    // new wraps inner, caching the keys it returns for ttl
+    pub fn new(inner: Arc<dyn AuthHandler + Send + Sync>, ttl: Duration) -> Self {
+        CachingAuthHandler {
+            inner,
+            ttl,
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    // invalidate drops the cached key of username, e.g. after its password
+    // changed, so the next request calls the inner handler again
+    pub fn invalidate(&self, username: &str) {
+        let mut cache = self.cache.write().unwrap_or_else(|e| e.into_inner());
+        cache.remove(username);
+    }
+
+    // len returns the number of cached keys, expired or not
+    pub fn len(&self) -> usize {
+        let cache = self.cache.read().unwrap_or_else(|e| e.into_inner());
+        cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, username: &str, realm: &str) -> Option<Vec<u8>> {
+        let cache = self.cache.read().unwrap_or_else(|e| e.into_inner());
+        cache
+            .get(username)
+            .filter(|c| c.realm == realm && c.cached_at.elapsed() < self.ttl)
+            .map(|c| c.key.clone())
+    }
+
+    fn insert(&self, username: &str, realm: &str, key: Vec<u8>) {
+        let mut cache = self.cache.write().unwrap_or_else(|e| e.into_inner());
+        if cache.len() >= CACHE_PRUNE_THRESHOLD {
+            let ttl = self.ttl;
+            cache.retain(|_, c| c.cached_at.elapsed() < ttl);
+        }
+        cache.insert(
+            username.to_owned(),
+            CachedKey {
+                realm: realm.to_owned(),
+                key,
+                cached_at: Instant::now(),
+            },
+        );
+    }
+}
+
+impl AuthHandler for CachingAuthHandler {
+    fn auth_handle(&self, username: &str, realm: &str, src_addr: SocketAddr) -> Result<Vec<u8>> {
+        self.auth_handle_with_origin(username, realm, src_addr, None)
+    }
+
+    fn auth_handle_with_origin(
+        &self,
+        username: &str,
+        realm: &str,
+        src_addr: SocketAddr,
+        origin: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        if let Some(key) = self.get(username, realm) {
+            log::trace!("using cached key of {}", username);
+            return Ok(key);
+        }
+
+        let key = self
+            .inner
+            .auth_handle_with_origin(username, realm, src_addr, origin)?;
+        self.insert(username, realm, key.clone());
+        Ok(key)
+    }
+}
@@ -0,0 +1,101 @@
+use super::*;
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAuthHandler {
+    calls: AtomicUsize,
+}
+
+impl AuthHandler for CountingAuthHandler {
+    fn auth_handle(&self, username: &str, realm: &str, _src_addr: SocketAddr) -> Result<Vec<u8>> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        if username == "unknown" {
+            return Err(Error::ErrNoSuchUser);
+        }
+        Ok(generate_auth_key(username, realm, "pass"))
+    }
+}
+
+fn new_handler(ttl: Duration) -> (Arc<CountingAuthHandler>, CachingAuthHandler) {
+    let inner = Arc::new(CountingAuthHandler {
+        calls: AtomicUsize::new(0),
+    });
+    let handler = CachingAuthHandler::new(
+        Arc::clone(&inner) as Arc<dyn AuthHandler + Send + Sync>,
+        ttl,
+    );
+    (inner, handler)
+}
+
+fn addr() -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 5000)
+}
+
+#[test]
+fn test_caching_auth_handler_hit() -> Result<()> {
+    let (inner, handler) = new_handler(Duration::from_secs(60));
+
+    let key = handler.auth_handle("user", "webrtc.rs", addr())?;
+    assert_eq!(key, generate_auth_key("user", "webrtc.rs", "pass"));
+    assert_eq!(handler.auth_handle("user", "webrtc.rs", addr())?, key);
+    assert_eq!(
+        inner.calls.load(Ordering::SeqCst),
+        1,
+        "cache hit should skip the inner handler"
+    );
+
+    // another realm needs another key
+    let key = handler.auth_handle("user", "other.realm", addr())?;
+    assert_eq!(key, generate_auth_key("user", "other.realm", "pass"));
+    assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_caching_auth_handler_ttl() -> Result<()> {
+    let (inner, handler) = new_handler(Duration::from_millis(20));
+
+    handler.auth_handle("user", "webrtc.rs", addr())?;
+    std::thread::sleep(Duration::from_millis(30));
+    handler.auth_handle("user", "webrtc.rs", addr())?;
+    assert_eq!(
+        inner.calls.load(Ordering::SeqCst),
+        2,
+        "expired key should be fetched again"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_caching_auth_handler_invalidate() -> Result<()> {
+    let (inner, handler) = new_handler(Duration::from_secs(60));
+
+    handler.auth_handle("user", "webrtc.rs", addr())?;
+    handler.auth_handle("other", "webrtc.rs", addr())?;
+    assert_eq!(handler.len(), 2);
+
+    handler.invalidate("user");
+    assert_eq!(handler.len(), 1);
+    handler.auth_handle("user", "webrtc.rs", addr())?;
+    handler.auth_handle("other", "webrtc.rs", addr())?;
+    assert_eq!(inner.calls.load(Ordering::SeqCst), 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_caching_auth_handler_errors_not_cached() {
+    let (inner, handler) = new_handler(Duration::from_secs(60));
+
+    for _ in 0..2 {
+        assert_eq!(
+            handler.auth_handle("unknown", "webrtc.rs", addr()),
+            Err(Error::ErrNoSuchUser)
+        );
+    }
+    assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+    assert!(handler.is_empty());
+}
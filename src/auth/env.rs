@@ -0,0 +1,103 @@
+#[cfg(test)]
+mod env_test;
+
+use super::*;
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+// EnvAuthHandler authenticates users with credentials read from environment variables.
+//
+// For a prefix of "TURN" it picks up:
+//   * TURN_USERNAME_<ID> / TURN_PASSWORD_<ID> pairs sharing the same <ID>
+//   * TURN_CREDENTIALS holding "user:password,user2:password2"
+//
+// The environment is read once on creation; call reload to pick up changes.
+pub struct EnvAuthHandler {
+    prefix: String,
+    credentials: RwLock<HashMap<String, String>>,
+}
+
+impl EnvAuthHandler {
+    // new creates an EnvAuthHandler and loads credentials for the given prefix
+    pub fn new(prefix: &str) -> Self {
+        let h = EnvAuthHandler {
+            prefix: prefix.to_owned(),
+            credentials: RwLock::new(HashMap::new()),
+        };
+        h.reload();
+        h
+    }
+
+    // reload re-reads the environment, replacing all previously loaded credentials
+    pub fn reload(&self) {
+        let credentials = credentials_from_vars(&self.prefix, std::env::vars());
+        log::debug!(
+            "loaded {} credentials from {}_* environment variables",
+            credentials.len(),
+            self.prefix
+        );
+
+        let mut c = self.credentials.write().unwrap();
+        *c = credentials;
+    }
+}
+
+impl AuthHandler for EnvAuthHandler {
+    fn auth_handle(&self, username: &str, realm: &str, src_addr: SocketAddr) -> Result<Vec<u8>> {
+        log::trace!(
+            "Authentication username={} realm={} src_addr={}",
+            username,
+            realm,
+            src_addr
+        );
+
+        let credentials = self.credentials.read().unwrap();
+        if let Some(password) = credentials.get(username) {
+            Ok(generate_auth_key(username, realm, password))
+        } else {
+            Err(Error::ErrNoSuchUser)
+        }
+    }
+}
+
+// credentials_from_vars builds the username -> password map out of (key, value) pairs
+fn credentials_from_vars(
+    prefix: &str,
+    vars: impl Iterator<Item = (String, String)>,
+) -> HashMap<String, String> {
+    let username_prefix = format!("{}_USERNAME_", prefix);
+    let password_prefix = format!("{}_PASSWORD_", prefix);
+    let credentials_key = format!("{}_CREDENTIALS", prefix);
+
+    let mut usernames = HashMap::new();
+    let mut passwords = HashMap::new();
+    let mut credentials = HashMap::new();
+
+    for (key, value) in vars {
+        if let Some(id) = key.strip_prefix(&username_prefix) {
+            usernames.insert(id.to_owned(), value);
+        } else if let Some(id) = key.strip_prefix(&password_prefix) {
+            passwords.insert(id.to_owned(), value);
+        } else if key == credentials_key {
+            for cred in value.split(',') {
+                let cred: Vec<&str> = cred.splitn(2, ':').collect();
+                if cred.len() == 2 && !cred[0].is_empty() {
+                    credentials.insert(cred[0].to_owned(), cred[1].to_owned());
+                } else {
+                    log::warn!("ignoring malformed entry in {}", credentials_key);
+                }
+            }
+        }
+    }
+
+    for (id, username) in usernames {
+        if let Some(password) = passwords.remove(&id) {
+            credentials.insert(username, password);
+        } else {
+            log::warn!("{}{} has no matching password", username_prefix, id);
+        }
+    }
+
+    credentials
+}
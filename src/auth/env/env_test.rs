@@ -0,0 +1,62 @@
+use super::*;
+
+use std::net::{IpAddr, Ipv4Addr};
+
+#[test]
+fn test_credentials_from_vars() {
+    let vars = vec![
+        ("TURN_USERNAME_1".to_owned(), "alice".to_owned()),
+        ("TURN_PASSWORD_1".to_owned(), "alice-pass".to_owned()),
+        ("TURN_USERNAME_BOB".to_owned(), "bob".to_owned()),
+        ("TURN_PASSWORD_BOB".to_owned(), "bob-pass".to_owned()),
+        ("TURN_USERNAME_2".to_owned(), "no-password".to_owned()),
+        (
+            "TURN_CREDENTIALS".to_owned(),
+            "carol:carol-pass,dave:dave:pass,malformed".to_owned(),
+        ),
+        ("OTHER_USERNAME_1".to_owned(), "mallory".to_owned()),
+        ("OTHER_PASSWORD_1".to_owned(), "mallory-pass".to_owned()),
+    ];
+
+    let credentials = credentials_from_vars("TURN", vars.into_iter());
+
+    let mut expected = HashMap::new();
+    expected.insert("alice".to_owned(), "alice-pass".to_owned());
+    expected.insert("bob".to_owned(), "bob-pass".to_owned());
+    expected.insert("carol".to_owned(), "carol-pass".to_owned());
+    expected.insert("dave".to_owned(), "dave:pass".to_owned());
+    assert_eq!(credentials, expected, "should match");
+}
+
+#[test]
+fn test_env_auth_handler_reload() -> Result<()> {
+    let src_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 5000);
+
+    std::env::set_var("ENV_AUTH_TEST_CREDENTIALS", "user:pass");
+    let handler = EnvAuthHandler::new("ENV_AUTH_TEST");
+
+    let key = handler.auth_handle("user", "webrtc.rs", src_addr)?;
+    assert_eq!(
+        key,
+        generate_auth_key("user", "webrtc.rs", "pass"),
+        "should match"
+    );
+
+    std::env::set_var("ENV_AUTH_TEST_CREDENTIALS", "user2:pass2");
+    handler.reload();
+
+    assert!(
+        handler.auth_handle("user", "webrtc.rs", src_addr).is_err(),
+        "user should be gone after reload"
+    );
+    let key = handler.auth_handle("user2", "webrtc.rs", src_addr)?;
+    assert_eq!(
+        key,
+        generate_auth_key("user2", "webrtc.rs", "pass2"),
+        "should match"
+    );
+
+    std::env::remove_var("ENV_AUTH_TEST_CREDENTIALS");
+
+    Ok(())
+}
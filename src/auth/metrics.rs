@@ -0,0 +1,89 @@
+#[cfg(test)]
+mod metrics_test;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::time::Duration;
+
+// AUTH_LATENCY_BUCKETS are the upper bounds of the latency histogram buckets.
+// Calls slower than the last bound are counted in an extra overflow bucket.
+pub const AUTH_LATENCY_BUCKETS: [Duration; 7] = [
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+];
+
+// AuthMetrics counts the calls the server makes to its AuthHandler, how many
+// of them failed and how long they took, e.g. to spot a struggling remote
+// auth backend. It is shared by all listeners of a server.
+#[derive(Debug, Default)]
+pub struct AuthMetrics {
+    calls: AtomicU64,
+    failures: AtomicU64,
+    total_latency_us: AtomicU64,
+    latency_buckets: [AtomicU64; AUTH_LATENCY_BUCKETS.len() + 1],
+}
+
+impl AuthMetrics {
+    // record counts one call to the auth handler that took latency
+    pub fn record(&self, latency: Duration, success: bool) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        if !success {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        self.total_latency_us
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+
+        let bucket = AUTH_LATENCY_BUCKETS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(AUTH_LATENCY_BUCKETS.len());
+        self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // snapshot returns the current values of all metrics
+    pub fn snapshot(&self) -> AuthMetricsSnapshot {
+        AuthMetricsSnapshot {
+            calls: self.calls.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            total_latency: Duration::from_micros(self.total_latency_us.load(Ordering::Relaxed)),
+            latency_buckets: self
+                .latency_buckets
+                .iter()
+                .map(|bucket| bucket.load(Ordering::Relaxed))
+                .collect(),
+        }
+    }
+}
+
+// AuthMetricsSnapshot holds the values of AuthMetrics at one point in time.
+// latency_buckets[i] counts the calls that took at most AUTH_LATENCY_BUCKETS[i]
+// and longer than the previous bound, the last entry counts all slower calls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AuthMetricsSnapshot {
+    pub calls: u64,
+    pub failures: u64,
+    pub total_latency: Duration,
+    pub latency_buckets: Vec<u64>,
+}
+
+impl AuthMetricsSnapshot {
+    // failure_rate returns the share of calls that failed, 0 without any calls
+    pub fn failure_rate(&self) -> f64 {
+        if self.calls == 0 {
+            0.0
+        } else {
+            self.failures as f64 / self.calls as f64
+        }
+    }
+
+    // average_latency returns the mean latency of all calls, 0 without any calls
+    pub fn average_latency(&self) -> Duration {
+        let total_latency_us = self.total_latency.as_micros() as u64;
+        Duration::from_micros(total_latency_us.checked_div(self.calls).unwrap_or(0))
+    }
+}
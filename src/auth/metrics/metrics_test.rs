@@ -0,0 +1,27 @@
+use super::*;
+
+#[test]
+fn test_auth_metrics() {
+    let metrics = AuthMetrics::default();
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.calls, 0);
+    assert_eq!(snapshot.failure_rate(), 0.0);
+    assert_eq!(snapshot.average_latency(), Duration::from_secs(0));
+
+    metrics.record(Duration::from_micros(500), true);
+    metrics.record(Duration::from_millis(1), true);
+    metrics.record(Duration::from_millis(30), false);
+    metrics.record(Duration::from_secs(3), false);
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.calls, 4);
+    assert_eq!(snapshot.failures, 2);
+    assert_eq!(snapshot.failure_rate(), 0.5);
+    assert_eq!(
+        snapshot.total_latency,
+        Duration::from_micros(3_031_500),
+        "should sum all latencies"
+    );
+    assert_eq!(snapshot.latency_buckets, vec![2, 0, 0, 1, 0, 0, 0, 1]);
+}
@@ -1,16 +1,43 @@
 #[cfg(test)]
 mod auth_test;
 
+pub mod cached;
+pub mod env;
+pub mod metrics;
+pub mod multi;
+#[cfg(feature = "oauth2")]
+pub mod oauth2;
+pub mod rate_limited;
+pub mod r#static;
+
 use crate::error::*;
+use crate::proto::credential::LongTermCredential;
 
 use std::net::SocketAddr;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use md5::{Digest, Md5};
 use ring::hmac;
 
 pub trait AuthHandler {
     fn auth_handle(&self, username: &str, realm: &str, src_addr: SocketAddr) -> Result<Vec<u8>>;
+
+    // auth_handle_with_origin is what the server calls. It also gets the ORIGIN
+    // sent by WebRTC clients, if any, for origin-based access control, and falls
+    // back to auth_handle by default.
+    fn auth_handle_with_origin(
+        &self,
+        username: &str,
+        realm: &str,
+        src_addr: SocketAddr,
+        _origin: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        self.auth_handle(username, realm, src_addr)
+    }
+
+    // type_name names the handler, e.g. in Server::export_config
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
 }
 
 // generate_long_term_credentials can be used to create credentials valid for [duration] time
@@ -35,11 +62,7 @@ fn long_term_credentials(username: &str, shared_secret: &str) -> String {
 
 // generate_auth_key is a convenience function to easily generate keys in the format used by AuthHandler
 pub fn generate_auth_key(username: &str, realm: &str, password: &str) -> Vec<u8> {
-    let s = format!("{}:{}:{}", username, realm, password);
-
-    let mut h = Md5::new();
-    h.update(s.as_bytes());
-    h.finalize().as_slice().to_vec()
+    LongTermCredential::compute_key(username, realm, password).to_vec()
 }
 
 pub struct LongTermAuthHandler {
@@ -0,0 +1,65 @@
+#[cfg(test)]
+mod multi_test;
+
+use super::*;
+
+use std::sync::Arc;
+
+// MultiAuthHandler tries a chain of handlers in order and uses the key of the
+// first one that accepts the user, e.g. to accept both static passwords and
+// JWTs while credentials are being migrated. The server still checks the
+// MESSAGE-INTEGRITY against that key, so a handler earlier in the chain that
+// knows the username shadows the handlers after it.
+pub struct MultiAuthHandler {
+    handlers: Vec<Arc<dyn AuthHandler + Send + Sync>>,
+}
+
+impl MultiAuthHandler {
+    // new creates a MultiAuthHandler trying handlers in the given order
+    pub fn new(handlers: Vec<Arc<dyn AuthHandler + Send + Sync>>) -> Self {
+        MultiAuthHandler { handlers }
+    }
+}
+
+impl AuthHandler for MultiAuthHandler {
+    fn auth_handle(&self, username: &str, realm: &str, src_addr: SocketAddr) -> Result<Vec<u8>> {
+        self.auth_handle_with_origin(username, realm, src_addr, None)
+    }
+
+    // auth_handle_with_origin fails with the error of the first handler when
+    // all of them reject the user, so the server answers as that handler would.
+    fn auth_handle_with_origin(
+        &self,
+        username: &str,
+        realm: &str,
+        src_addr: SocketAddr,
+        origin: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        let mut first_err = None;
+        for (i, handler) in self.handlers.iter().enumerate() {
+            match handler.auth_handle_with_origin(username, realm, src_addr, origin) {
+                Ok(key) => {
+                    log::debug!(
+                        "user {} authenticated by handler {} ({})",
+                        username,
+                        i,
+                        handler.type_name()
+                    );
+                    return Ok(key);
+                }
+                Err(err) => {
+                    log::trace!(
+                        "handler {} ({}) rejected user {}: {}",
+                        i,
+                        handler.type_name(),
+                        username,
+                        err
+                    );
+                    first_err.get_or_insert(err);
+                }
+            }
+        }
+
+        Err(first_err.unwrap_or(Error::ErrNoSuchUser))
+    }
+}
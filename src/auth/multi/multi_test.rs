@@ -0,0 +1,43 @@
+use super::*;
+use crate::auth::r#static::StaticAuthHandler;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+
+fn static_handler(username: &str, password: &str) -> Arc<dyn AuthHandler + Send + Sync> {
+    let mut credentials = HashMap::new();
+    credentials.insert(username.to_owned(), password.to_owned());
+    Arc::new(StaticAuthHandler::new(credentials))
+}
+
+#[test]
+fn test_multi_auth_handler() -> Result<()> {
+    let src_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 5000);
+
+    let handler = MultiAuthHandler::new(vec![
+        static_handler("old", "old_pass"),
+        static_handler("new", "new_pass"),
+        static_handler("old", "shadowed"),
+    ]);
+
+    assert_eq!(
+        handler.auth_handle("old", "webrtc.rs", src_addr)?,
+        generate_auth_key("old", "webrtc.rs", "old_pass"),
+        "first handler should win"
+    );
+    assert_eq!(
+        handler.auth_handle("new", "webrtc.rs", src_addr)?,
+        generate_auth_key("new", "webrtc.rs", "new_pass"),
+        "later handler should be tried"
+    );
+    assert_eq!(
+        handler.auth_handle("unknown", "webrtc.rs", src_addr),
+        Err(Error::ErrNoSuchUser),
+        "user unknown to all handlers should be rejected"
+    );
+
+    let empty = MultiAuthHandler::new(vec![]);
+    assert!(empty.auth_handle("old", "webrtc.rs", src_addr).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,104 @@
+#[cfg(test)]
+mod oauth2_test;
+
+use super::*;
+
+use ring::signature;
+
+use serde::Deserialize;
+
+// OAuth2Key is the public key access tokens are signed with.
+pub enum OAuth2Key {
+    // Rsa is a DER encoded RSAPublicKey (PKCS#1), for RS256 tokens
+    Rsa(Vec<u8>),
+    // Ec is an uncompressed P-256 point, for ES256 tokens
+    Ec(Vec<u8>),
+}
+
+#[derive(Deserialize)]
+struct TokenHeader {
+    alg: String,
+}
+
+#[derive(Deserialize)]
+struct TokenClaims {
+    typ: String,
+    svc: String,
+    exp: u64,
+    key: String,
+}
+
+// OAuth2AuthHandler authenticates clients holding an OAuth 2.0 access token,
+// following the model of THIRD-PARTY-AUTHORIZATION (RFC 7635): the
+// authorization server hands the client a token together with a session key,
+// and the TURN server validates the token without contacting it.
+//
+// The token is a JWT sent as USERNAME. It must be signed with key (RS256 or
+// ES256), and carry `typ` "access_token", `svc` "turn", a future `exp` and
+// `key`, the base64 encoded session key the client computes MESSAGE-INTEGRITY
+// with. Requests with invalid or expired tokens are answered with 401.
+//
+// USERNAME is limited to 513 bytes, which RS256 tokens rarely fit in, so
+// ES256 is the better choice.
+pub struct OAuth2AuthHandler {
+    key: OAuth2Key,
+}
+
+impl OAuth2AuthHandler {
+    // new creates an OAuth2AuthHandler accepting tokens signed with key
+    pub fn new(key: OAuth2Key) -> Self {
+        OAuth2AuthHandler { key }
+    }
+
+    // validate checks token and returns the session key it carries
+    fn validate(&self, token: &str) -> Result<Vec<u8>> {
+        let mut parts = token.split('.');
+        let (header, claims, sig) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(claims), Some(sig), None) => (header, claims, sig),
+            _ => return Err(Error::ErrInvalidAccessToken),
+        };
+
+        let header: TokenHeader = decode_part(header)?;
+        let alg: &'static dyn signature::VerificationAlgorithm =
+            match (&self.key, header.alg.as_str()) {
+                (OAuth2Key::Rsa(_), "RS256") => &signature::RSA_PKCS1_2048_8192_SHA256,
+                (OAuth2Key::Ec(_), "ES256") => &signature::ECDSA_P256_SHA256_FIXED,
+                _ => return Err(Error::ErrInvalidAccessToken),
+            };
+        let public_key = match &self.key {
+            OAuth2Key::Rsa(key) | OAuth2Key::Ec(key) => key,
+        };
+
+        let sig = base64::decode_config(sig, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| Error::ErrInvalidAccessToken)?;
+        let signed = &token[..token.rfind('.').unwrap_or(0)];
+        signature::UnparsedPublicKey::new(alg, public_key)
+            .verify(signed.as_bytes(), &sig)
+            .map_err(|_| Error::ErrInvalidAccessToken)?;
+
+        let claims: TokenClaims = decode_part(claims)?;
+        if claims.typ != "access_token" || claims.svc != "turn" {
+            return Err(Error::ErrInvalidAccessToken);
+        }
+        if claims.exp <= SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() {
+            return Err(Error::ErrAccessTokenExpired);
+        }
+
+        base64::decode(&claims.key).map_err(|_| Error::ErrInvalidAccessToken)
+    }
+}
+
+fn decode_part<T: serde::de::DeserializeOwned>(part: &str) -> Result<T> {
+    let json = base64::decode_config(part, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| Error::ErrInvalidAccessToken)?;
+    serde_json::from_slice(&json).map_err(|_| Error::ErrInvalidAccessToken)
+}
+
+impl AuthHandler for OAuth2AuthHandler {
+    fn auth_handle(&self, username: &str, _realm: &str, src_addr: SocketAddr) -> Result<Vec<u8>> {
+        self.validate(username).map_err(|err| {
+            log::debug!("rejected access token from {}: {}", src_addr, err);
+            err
+        })
+    }
+}
@@ -0,0 +1,118 @@
+use super::*;
+
+use std::str::FromStr;
+
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+
+const SESSION_KEY: &[u8] = b"session-key";
+
+fn new_key_pair() -> EcdsaKeyPair {
+    let rng = SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+    EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap()
+}
+
+fn sign_token(key_pair: &EcdsaKeyPair, alg: &str, claims: &str) -> String {
+    let header = base64::encode_config(
+        format!("{{\"alg\":\"{}\",\"typ\":\"JWT\"}}", alg),
+        base64::URL_SAFE_NO_PAD,
+    );
+    let claims = base64::encode_config(claims, base64::URL_SAFE_NO_PAD);
+    let signed = format!("{}.{}", header, claims);
+    let sig = key_pair
+        .sign(&SystemRandom::new(), signed.as_bytes())
+        .unwrap();
+    format!(
+        "{}.{}",
+        signed,
+        base64::encode_config(sig.as_ref(), base64::URL_SAFE_NO_PAD)
+    )
+}
+
+fn claims(typ: &str, svc: &str, exp: u64) -> String {
+    format!(
+        "{{\"typ\":\"{}\",\"svc\":\"{}\",\"exp\":{},\"key\":\"{}\"}}",
+        typ,
+        svc,
+        exp,
+        base64::encode(SESSION_KEY)
+    )
+}
+
+fn in_an_hour() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 3600
+}
+
+#[test]
+fn test_oauth2_auth_handler() -> Result<()> {
+    let key_pair = new_key_pair();
+    let handler = OAuth2AuthHandler::new(OAuth2Key::Ec(key_pair.public_key().as_ref().to_vec()));
+    let src_addr = SocketAddr::from_str("127.0.0.1:5000")?;
+
+    let token = sign_token(
+        &key_pair,
+        "ES256",
+        &claims("access_token", "turn", in_an_hour()),
+    );
+    assert!(token.len() <= 513, "token should fit in USERNAME");
+    assert_eq!(
+        handler.auth_handle(&token, "webrtc.rs", src_addr)?,
+        SESSION_KEY.to_vec()
+    );
+
+    let expired = sign_token(&key_pair, "ES256", &claims("access_token", "turn", 1));
+    assert_eq!(
+        handler.auth_handle(&expired, "webrtc.rs", src_addr),
+        Err(Error::ErrAccessTokenExpired)
+    );
+
+    for (name, token) in [
+        (
+            "wrong typ",
+            sign_token(
+                &key_pair,
+                "ES256",
+                &claims("refresh_token", "turn", in_an_hour()),
+            ),
+        ),
+        (
+            "wrong svc",
+            sign_token(
+                &key_pair,
+                "ES256",
+                &claims("access_token", "stun", in_an_hour()),
+            ),
+        ),
+        (
+            "wrong alg",
+            sign_token(
+                &key_pair,
+                "RS256",
+                &claims("access_token", "turn", in_an_hour()),
+            ),
+        ),
+        (
+            "foreign key",
+            sign_token(
+                &new_key_pair(),
+                "ES256",
+                &claims("access_token", "turn", in_an_hour()),
+            ),
+        ),
+        ("not a JWT", "user".to_owned()),
+    ] {
+        assert_eq!(
+            handler.auth_handle(&token, "webrtc.rs", src_addr),
+            Err(Error::ErrInvalidAccessToken),
+            "{} should be rejected",
+            name
+        );
+    }
+
+    Ok(())
+}
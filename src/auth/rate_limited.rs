@@ -0,0 +1,148 @@
+#[cfg(test)]
+mod rate_limited_test;
+
+use super::*;
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// BUCKET_PRUNE_THRESHOLD is the number of buckets above which buckets that
+// refilled completely are dropped, so the maps don't grow with every username
+// and address ever seen.
+const BUCKET_PRUNE_THRESHOLD: usize = 1024;
+
+// TokenBucket holds up to limit tokens and refills limit tokens per window
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, limit: u32, window: Duration, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at);
+        let refilled = elapsed.as_secs_f64() / window.as_secs_f64() * f64::from(limit);
+        self.tokens = (self.tokens + refilled).min(f64::from(limit));
+        self.updated_at = now;
+    }
+}
+
+struct Buckets<K> {
+    limit: u32,
+    window: Duration,
+    buckets: HashMap<K, TokenBucket>,
+}
+
+impl<K: Eq + Hash> Buckets<K> {
+    fn new(limit: u32, window: Duration) -> Self {
+        Buckets {
+            limit,
+            window,
+            buckets: HashMap::new(),
+        }
+    }
+
+    // bucket returns the refilled bucket of key, creating a full one if needed
+    fn bucket(&mut self, key: K, now: Instant) -> &mut TokenBucket {
+        if self.buckets.len() >= BUCKET_PRUNE_THRESHOLD {
+            let (limit, window) = (self.limit, self.window);
+            self.buckets.retain(|_, b| {
+                b.refill(limit, window, now);
+                b.tokens < f64::from(limit)
+            });
+        }
+
+        let limit = f64::from(self.limit);
+        let bucket = self.buckets.entry(key).or_insert(TokenBucket {
+            tokens: limit,
+            updated_at: now,
+        });
+        bucket.refill(self.limit, self.window, now);
+        bucket
+    }
+}
+
+struct RateLimits {
+    per_user: Buckets<String>,
+    per_ip: Buckets<IpAddr>,
+}
+
+// RateLimitedAuthHandler wraps an AuthHandler and limits how often it is
+// called per username and per client IP, e.g. so brute-force attempts can't
+// overwhelm a slow auth backend. Each limit is a token bucket allowing the
+// given number of calls per window, with bursts of up to that many calls.
+// Calls over either limit fail without calling the inner handler, which the
+// server answers with 401 (Unauthorized).
+//
+// The server consults the auth handler for every authenticated request, not
+// only for Allocate, so the limits must leave room for the Refresh,
+// CreatePermission and ChannelBind requests of legitimate clients.
+pub struct RateLimitedAuthHandler {
+    inner: Arc<dyn AuthHandler + Send + Sync>,
+    limits: Mutex<RateLimits>,
+}
+
+impl RateLimitedAuthHandler {
+    // new wraps inner, allowing per_user_limit calls per username and
+    // per_ip_limit calls per client IP every window
+    pub fn new(
+        inner: Arc<dyn AuthHandler + Send + Sync>,
+        per_user_limit: u32,
+        per_ip_limit: u32,
+        window: Duration,
+    ) -> Self {
+        RateLimitedAuthHandler {
+            inner,
+            limits: Mutex::new(RateLimits {
+                per_user: Buckets::new(per_user_limit, window),
+                per_ip: Buckets::new(per_ip_limit, window),
+            }),
+        }
+    }
+
+    // take takes a token from the buckets of username and ip, or none if
+    // either of them is empty
+    fn take(&self, username: &str, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut limits = self.limits.lock().unwrap_or_else(|e| e.into_inner());
+
+        if limits.per_ip.bucket(ip, now).tokens < 1.0 {
+            return false;
+        }
+        let user_bucket = limits.per_user.bucket(username.to_owned(), now);
+        if user_bucket.tokens < 1.0 {
+            return false;
+        }
+        user_bucket.tokens -= 1.0;
+        limits.per_ip.bucket(ip, now).tokens -= 1.0;
+        true
+    }
+}
+
+impl AuthHandler for RateLimitedAuthHandler {
+    fn auth_handle(&self, username: &str, realm: &str, src_addr: SocketAddr) -> Result<Vec<u8>> {
+        self.auth_handle_with_origin(username, realm, src_addr, None)
+    }
+
+    fn auth_handle_with_origin(
+        &self,
+        username: &str,
+        realm: &str,
+        src_addr: SocketAddr,
+        origin: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        if !self.take(username, src_addr.ip()) {
+            log::debug!(
+                "rate limited authentication of {} from {}",
+                username,
+                src_addr
+            );
+            return Err(Error::ErrAuthRateLimited);
+        }
+
+        self.inner
+            .auth_handle_with_origin(username, realm, src_addr, origin)
+    }
+}
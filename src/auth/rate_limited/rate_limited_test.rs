@@ -0,0 +1,97 @@
+use super::*;
+use crate::auth::r#static::StaticAuthHandler;
+
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAuthHandler {
+    calls: AtomicUsize,
+}
+
+impl AuthHandler for CountingAuthHandler {
+    fn auth_handle(&self, username: &str, realm: &str, _src_addr: SocketAddr) -> Result<Vec<u8>> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        Ok(generate_auth_key(username, realm, "pass"))
+    }
+}
+
+fn addr(last_octet: u8) -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, last_octet)), 5000)
+}
+
+#[test]
+fn test_rate_limited_auth_handler_per_user() -> Result<()> {
+    let inner = Arc::new(CountingAuthHandler {
+        calls: AtomicUsize::new(0),
+    });
+    let handler = RateLimitedAuthHandler::new(
+        Arc::clone(&inner) as Arc<dyn AuthHandler + Send + Sync>,
+        2,
+        100,
+        Duration::from_secs(60),
+    );
+
+    handler.auth_handle("user", "webrtc.rs", addr(1))?;
+    handler.auth_handle("user", "webrtc.rs", addr(2))?;
+    assert_eq!(
+        handler.auth_handle("user", "webrtc.rs", addr(3)),
+        Err(Error::ErrAuthRateLimited),
+        "third attempt for user should be limited"
+    );
+    assert_eq!(
+        inner.calls.load(Ordering::SeqCst),
+        2,
+        "limited attempt should not reach the inner handler"
+    );
+
+    handler.auth_handle("other", "webrtc.rs", addr(3))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_rate_limited_auth_handler_per_ip() -> Result<()> {
+    let mut credentials = HashMap::new();
+    credentials.insert("user".to_owned(), "pass".to_owned());
+    let handler = RateLimitedAuthHandler::new(
+        Arc::new(StaticAuthHandler::new(credentials)),
+        100,
+        2,
+        Duration::from_secs(60),
+    );
+
+    // failed attempts count too
+    assert_eq!(
+        handler.auth_handle("guess1", "webrtc.rs", addr(1)),
+        Err(Error::ErrNoSuchUser)
+    );
+    assert_eq!(
+        handler.auth_handle("guess2", "webrtc.rs", addr(1)),
+        Err(Error::ErrNoSuchUser)
+    );
+    assert_eq!(
+        handler.auth_handle("user", "webrtc.rs", addr(1)),
+        Err(Error::ErrAuthRateLimited),
+        "third attempt from the address should be limited"
+    );
+
+    handler.auth_handle("user", "webrtc.rs", addr(2))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_rate_limited_auth_handler_refill() -> Result<()> {
+    let inner = Arc::new(CountingAuthHandler {
+        calls: AtomicUsize::new(0),
+    });
+    let handler = RateLimitedAuthHandler::new(inner, 1, 1, Duration::from_millis(50));
+
+    handler.auth_handle("user", "webrtc.rs", addr(1))?;
+    assert!(handler.auth_handle("user", "webrtc.rs", addr(1)).is_err());
+
+    std::thread::sleep(Duration::from_millis(60));
+    handler.auth_handle("user", "webrtc.rs", addr(1))?;
+
+    Ok(())
+}
@@ -0,0 +1,52 @@
+#[cfg(test)]
+mod static_test;
+
+use super::*;
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+// StaticAuthHandler authenticates users against a fixed username -> password map.
+// Users can be added or removed while the server is running.
+pub struct StaticAuthHandler {
+    credentials: RwLock<HashMap<String, String>>,
+}
+
+impl StaticAuthHandler {
+    // new creates a StaticAuthHandler from a username -> password map
+    pub fn new(credentials: HashMap<String, String>) -> Self {
+        StaticAuthHandler {
+            credentials: RwLock::new(credentials),
+        }
+    }
+
+    // add_user adds a user, replacing the password if the user already exists
+    pub fn add_user(&self, username: &str, password: &str) {
+        let mut credentials = self.credentials.write().unwrap();
+        credentials.insert(username.to_owned(), password.to_owned());
+    }
+
+    // remove_user removes a user, returning whether it existed
+    pub fn remove_user(&self, username: &str) -> bool {
+        let mut credentials = self.credentials.write().unwrap();
+        credentials.remove(username).is_some()
+    }
+}
+
+impl AuthHandler for StaticAuthHandler {
+    fn auth_handle(&self, username: &str, realm: &str, src_addr: SocketAddr) -> Result<Vec<u8>> {
+        log::trace!(
+            "Authentication username={} realm={} src_addr={}",
+            username,
+            realm,
+            src_addr
+        );
+
+        let credentials = self.credentials.read().unwrap();
+        if let Some(password) = credentials.get(username) {
+            Ok(generate_auth_key(username, realm, password))
+        } else {
+            Err(Error::ErrNoSuchUser)
+        }
+    }
+}
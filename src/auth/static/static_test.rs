@@ -0,0 +1,42 @@
+use super::*;
+
+use std::net::{IpAddr, Ipv4Addr};
+
+#[test]
+fn test_static_auth_handler() -> Result<()> {
+    let src_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 5000);
+
+    let mut credentials = HashMap::new();
+    credentials.insert("user".to_owned(), "pass".to_owned());
+    let handler = StaticAuthHandler::new(credentials);
+
+    let key = handler.auth_handle("user", "webrtc.rs", src_addr)?;
+    assert_eq!(
+        key,
+        generate_auth_key("user", "webrtc.rs", "pass"),
+        "should match"
+    );
+
+    assert_eq!(
+        handler.auth_handle("user2", "webrtc.rs", src_addr),
+        Err(Error::ErrNoSuchUser),
+        "unknown user should be rejected"
+    );
+
+    handler.add_user("user2", "pass2");
+    let key = handler.auth_handle("user2", "webrtc.rs", src_addr)?;
+    assert_eq!(
+        key,
+        generate_auth_key("user2", "webrtc.rs", "pass2"),
+        "should match"
+    );
+
+    assert!(handler.remove_user("user"), "user should exist");
+    assert!(!handler.remove_user("user"), "user should be gone");
+    assert!(
+        handler.auth_handle("user", "webrtc.rs", src_addr).is_err(),
+        "removed user should be rejected"
+    );
+
+    Ok(())
+}
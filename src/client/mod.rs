@@ -4,12 +4,14 @@ mod client_test;
 pub mod binding;
 pub mod periodic_timer;
 pub mod permission;
+pub mod pool;
 pub mod relay_conn;
 pub mod transaction;
 
 use crate::error::*;
 use crate::proto::{
-    chandata::*, data::*, lifetime::*, peeraddr::*, relayaddr::*, reqtrans::*, PROTO_UDP,
+    chandata::*, data::*, lifetime::*, nonce::get_nonce, peeraddr::*, relayaddr::*, reqtrans::*,
+    PROTO_UDP,
 };
 use binding::*;
 use relay_conn::*;
@@ -103,7 +105,7 @@ impl RelayConnObserver for ClientInternal {
         to: &str,
         ignore_result: bool,
     ) -> Result<TransactionResult> {
-        let tr_key = base64::encode(&msg.transaction_id.0);
+        let tr_key = base64::encode(msg.transaction_id.0);
 
         let mut tr = Transaction::new(TransactionConfig {
             key: tr_key.clone(),
@@ -341,7 +343,7 @@ impl ClientInternal {
         // - stun.ClassSuccessResponse
         // - stun.ClassErrorResponse
 
-        let tr_key = base64::encode(&msg.transaction_id.0);
+        let tr_key = base64::encode(msg.transaction_id.0);
 
         let mut tm = tr_map.lock().await;
         if tm.find(&tr_key).is_none() {
@@ -507,7 +509,7 @@ impl ClientInternal {
         let res = tr_res.msg;
 
         // Anonymous allocate failed, trying to authenticate.
-        let nonce = Nonce::get_from_as(&res, ATTR_NONCE)?;
+        let nonce = get_nonce(&res)?;
         self.realm = Realm::get_from_as(&res, ATTR_REALM)?;
 
         self.integrity = MessageIntegrity::new_long_term_integrity(
@@ -542,7 +544,7 @@ impl ClientInternal {
             if result.is_err() {
                 return Err(Error::Other(format!("{}", res.typ)));
             } else {
-                return Err(Error::Other(format!("{} (error {})", res.typ, code)));
+                return Err(error_response(&res, code));
             }
         }
 
@@ -619,3 +621,12 @@ impl Client {
         ci.send_binding_request().await
     }
 }
+
+// error_response turns the ERROR-CODE of the error response res into an Error
+pub(crate) fn error_response(res: &Message, code: ErrorCodeAttribute) -> Error {
+    Error::ErrErrorResponse {
+        message_type: res.typ.to_string(),
+        code: code.code.0,
+        reason: String::from_utf8_lossy(&code.reason).into_owned(),
+    }
+}
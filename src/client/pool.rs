@@ -0,0 +1,240 @@
+#[cfg(test)]
+mod pool_test;
+
+use super::*;
+
+use std::ops::Deref;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{Duration, Instant};
+
+// ClientPoolConfig is a bag of config parameters for ClientPool. Every pooled
+// allocation gets a Client of its own, bound to a new socket on local_addr.
+pub struct ClientPoolConfig {
+    pub stun_serv_addr: String, // STUN server address (e.g. "stun.abc.com:3478")
+    pub turn_serv_addr: String, // TURN server addrees (e.g. "turn.abc.com:3478")
+    pub username: String,
+    pub password: String,
+    pub realm: String,
+    pub software: String,
+    pub rto_in_ms: u16,
+    pub local_addr: String, // address the client sockets are bound to (e.g. "0.0.0.0:0")
+    // max_age is how long an allocation is lent out again. Allocations older
+    // than that are closed instead of returned to the pool, before they are
+    // near expiry. Zero means no limit.
+    pub max_age: Duration,
+    pub vnet: Option<Arc<Net>>,
+}
+
+struct PoolEntry {
+    client: Client,
+    conn: Arc<dyn Conn + Send + Sync>,
+    created_at: Instant,
+}
+
+impl PoolEntry {
+    async fn close(self) {
+        if let Err(err) = self.conn.close().await {
+            log::debug!("failed to close pooled allocation: {}", err);
+        }
+        let _ = self.client.close().await;
+    }
+}
+
+struct ClientPoolInternal {
+    config: ClientPoolConfig,
+    net: Arc<Net>,
+    idle: std::sync::Mutex<Vec<PoolEntry>>,
+    permits: Arc<Semaphore>,
+}
+
+impl ClientPoolInternal {
+    async fn create(&self) -> Result<PoolEntry> {
+        let local_addr = SocketAddr::from_str(&self.config.local_addr)?;
+        let conn = self.net.bind(local_addr).await?;
+
+        let client = Client::new(ClientConfig {
+            stun_serv_addr: self.config.stun_serv_addr.clone(),
+            turn_serv_addr: self.config.turn_serv_addr.clone(),
+            username: self.config.username.clone(),
+            password: self.config.password.clone(),
+            realm: self.config.realm.clone(),
+            software: self.config.software.clone(),
+            rto_in_ms: self.config.rto_in_ms,
+            conn,
+            vnet: Some(Arc::clone(&self.net)),
+        })
+        .await?;
+        client.listen().await?;
+
+        let conn = match client.allocate().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                let _ = client.close().await;
+                return Err(err);
+            }
+        };
+
+        Ok(PoolEntry {
+            client,
+            conn: Arc::new(conn),
+            created_at: Instant::now(),
+        })
+    }
+
+    fn is_expired(&self, entry: &PoolEntry) -> bool {
+        self.config.max_age != Duration::from_secs(0)
+            && entry.created_at.elapsed() >= self.config.max_age
+    }
+
+    fn take_idle(&self) -> Vec<PoolEntry> {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        idle.drain(..).collect()
+    }
+}
+
+// ClientPool keeps TURN allocations ready to be lent out, so a caller doesn't
+// pay for a new allocation, which takes several round trips to the server, on
+// every peer connection. At most max_size allocations are lent out at a time.
+#[derive(Clone)]
+pub struct ClientPool {
+    internal: Arc<ClientPoolInternal>,
+}
+
+impl ClientPool {
+    // new creates a pool for at most max_size allocations and pre-warms it with
+    // min_idle of them
+    pub async fn new(config: ClientPoolConfig, min_idle: usize, max_size: usize) -> Result<Self> {
+        let net = match &config.vnet {
+            Some(vnet) => Arc::clone(vnet),
+            None => Arc::new(Net::new(None)),
+        };
+
+        let pool = ClientPool {
+            internal: Arc::new(ClientPoolInternal {
+                config,
+                net,
+                idle: std::sync::Mutex::new(vec![]),
+                permits: Arc::new(Semaphore::new(max_size)),
+            }),
+        };
+
+        for _ in 0..min_idle.min(max_size) {
+            match pool.internal.create().await {
+                Ok(entry) => pool.push_idle(entry),
+                Err(err) => {
+                    pool.close().await;
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(pool)
+    }
+
+    // idle_count returns the number of allocations ready to be lent out
+    pub fn idle_count(&self) -> usize {
+        let idle = self.internal.idle.lock().unwrap_or_else(|e| e.into_inner());
+        idle.len()
+    }
+
+    // acquire lends out an allocation, creating one if none is idle. It waits
+    // while max_size allocations are lent out.
+    pub async fn acquire(&self) -> Result<PooledAllocation> {
+        let permit = Arc::clone(&self.internal.permits)
+            .acquire_owned()
+            .await
+            .map_err(|_| Error::ErrClientPoolClosed)?;
+
+        loop {
+            let entry = {
+                let mut idle = self.internal.idle.lock().unwrap_or_else(|e| e.into_inner());
+                idle.pop()
+            };
+            match entry {
+                Some(entry) if self.internal.is_expired(&entry) => entry.close().await,
+                Some(entry) => {
+                    return Ok(PooledAllocation {
+                        entry: Some(entry),
+                        pool: Arc::clone(&self.internal),
+                        _permit: permit,
+                    })
+                }
+                None => break,
+            }
+        }
+
+        let entry = self.internal.create().await?;
+        Ok(PooledAllocation {
+            entry: Some(entry),
+            pool: Arc::clone(&self.internal),
+            _permit: permit,
+        })
+    }
+
+    // close closes all idle allocations and fails all later acquire calls.
+    // Allocations lent out are closed when they are dropped.
+    pub async fn close(&self) {
+        self.internal.permits.close();
+        for entry in self.internal.take_idle() {
+            entry.close().await;
+        }
+    }
+
+    fn push_idle(&self, entry: PoolEntry) {
+        let mut idle = self.internal.idle.lock().unwrap_or_else(|e| e.into_inner());
+        idle.push(entry);
+    }
+}
+
+// PooledAllocation is an allocation lent out by a ClientPool. It derefs to
+// the relayed Conn. Dropping it returns the allocation to the pool, unless it
+// is older than ClientPoolConfig::max_age or the pool is closed.
+pub struct PooledAllocation {
+    entry: Option<PoolEntry>,
+    pool: Arc<ClientPoolInternal>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledAllocation {
+    // conn returns the relayed Conn of the allocation
+    pub fn conn(&self) -> &Arc<dyn Conn + Send + Sync> {
+        // entry is only taken in drop or discard
+        &self.entry.as_ref().unwrap().conn
+    }
+
+    // discard closes the allocation instead of returning it to the pool, e.g.
+    // after it failed
+    pub async fn discard(mut self) {
+        if let Some(entry) = self.entry.take() {
+            entry.close().await;
+        }
+    }
+}
+
+impl Deref for PooledAllocation {
+    type Target = dyn Conn + Send + Sync;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn().as_ref()
+    }
+}
+
+impl Drop for PooledAllocation {
+    fn drop(&mut self) {
+        let entry = match self.entry.take() {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        if !self.pool.permits.is_closed() && !self.pool.is_expired(&entry) {
+            let mut idle = self.pool.idle.lock().unwrap_or_else(|e| e.into_inner());
+            idle.push(entry);
+            return;
+        }
+
+        // closing talks to the server, which can't be awaited here
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(entry.close());
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use super::*;
+use crate::allocation::allocation_manager::LifetimeStrategy;
+use crate::allocation::channel_bind::ChannelRefreshPolicy;
+use crate::auth::*;
+use crate::relay::relay_static::*;
+use crate::server::{config::*, *};
+
+use std::net::IpAddr;
+use tokio::net::UdpSocket;
+
+struct TestAuthHandler;
+impl AuthHandler for TestAuthHandler {
+    fn auth_handle(&self, username: &str, realm: &str, _src_addr: SocketAddr) -> Result<Vec<u8>> {
+        Ok(generate_auth_key(username, realm, "pass"))
+    }
+}
+
+async fn new_test_server() -> Result<(Server, u16)> {
+    let conn = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let server_port = conn.local_addr()?.port();
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler {}),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    Ok((server, server_port))
+}
+
+fn new_pool_config(server_port: u16, max_age: Duration) -> ClientPoolConfig {
+    ClientPoolConfig {
+        stun_serv_addr: format!("127.0.0.1:{}", server_port),
+        turn_serv_addr: format!("127.0.0.1:{}", server_port),
+        username: "foo".to_owned(),
+        password: "pass".to_owned(),
+        realm: String::new(),
+        software: String::new(),
+        rto_in_ms: 0,
+        local_addr: "127.0.0.1:0".to_owned(),
+        max_age,
+        vnet: None,
+    }
+}
+
+#[tokio::test]
+async fn test_client_pool_acquire() -> Result<()> {
+    let (server, server_port) = new_test_server().await?;
+
+    let pool = ClientPool::new(new_pool_config(server_port, Duration::from_secs(0)), 2, 3).await?;
+    assert_eq!(pool.idle_count(), 2, "should pre-warm min_idle allocations");
+    assert_eq!(server.list_listeners().await[0].allocation_count, 2);
+
+    let a1 = pool.acquire().await?;
+    let relayed_addr = a1.local_addr().await?;
+    assert_eq!(pool.idle_count(), 1);
+    drop(a1);
+    assert_eq!(pool.idle_count(), 2, "should return the allocation");
+
+    let a1 = pool.acquire().await?;
+    let a2 = pool.acquire().await?;
+    let a3 = pool.acquire().await?;
+    assert_eq!(pool.idle_count(), 0);
+    assert_eq!(server.list_listeners().await[0].allocation_count, 3);
+    let mut relayed_addrs = vec![];
+    for a in [&a1, &a2, &a3] {
+        relayed_addrs.push(a.local_addr().await?);
+    }
+    assert!(
+        relayed_addrs.contains(&relayed_addr),
+        "should reuse the returned allocation"
+    );
+
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), pool.acquire())
+            .await
+            .is_err(),
+        "should wait while max_size allocations are lent out"
+    );
+
+    drop(a1);
+    let a4 = pool.acquire().await?;
+    assert_eq!(server.list_listeners().await[0].allocation_count, 3);
+
+    drop(a4);
+    drop(a2);
+    drop(a3);
+    pool.close().await;
+    assert_eq!(pool.idle_count(), 0);
+    assert!(matches!(
+        pool.acquire().await,
+        Err(Error::ErrClientPoolClosed)
+    ));
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_pool_max_age() -> Result<()> {
+    let (server, server_port) = new_test_server().await?;
+
+    let pool = ClientPool::new(
+        new_pool_config(server_port, Duration::from_millis(200)),
+        1,
+        1,
+    )
+    .await?;
+
+    let a = pool.acquire().await?;
+    let relayed_addr = a.local_addr().await?;
+    drop(a);
+    assert_eq!(pool.idle_count(), 1);
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    let a = pool.acquire().await?;
+    assert_ne!(
+        a.local_addr().await?,
+        relayed_addr,
+        "should replace the expired allocation"
+    );
+    assert_eq!(
+        pool.idle_count(),
+        0,
+        "should discard the expired allocation"
+    );
+    drop(a);
+
+    pool.close().await;
+    server.close().await?;
+
+    Ok(())
+}
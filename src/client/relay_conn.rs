@@ -3,6 +3,7 @@ mod relay_conn_test;
 
 // client implements the API for a TURN client
 use super::binding::*;
+use super::error_response;
 use super::periodic_timer::*;
 use super::permission::*;
 use super::transaction::*;
@@ -10,7 +11,6 @@ use crate::proto;
 use crate::Error;
 
 use stun::agent::*;
-use stun::attributes::*;
 use stun::error_code::*;
 use stun::fingerprint::*;
 use stun::integrity::*;
@@ -109,11 +109,11 @@ impl<T: 'static + RelayConnObserver + Send + Sync> RelayConn<T> {
 #[async_trait]
 impl<T: RelayConnObserver + Send + Sync> Conn for RelayConn<T> {
     async fn connect(&self, _addr: SocketAddr) -> Result<(), util::Error> {
-        Err(io::Error::new(io::ErrorKind::Other, "Not applicable").into())
+        Err(io::Error::other("Not applicable").into())
     }
 
     async fn recv(&self, _buf: &mut [u8]) -> Result<usize, util::Error> {
-        Err(io::Error::new(io::ErrorKind::Other, "Not applicable").into())
+        Err(io::Error::other("Not applicable").into())
     }
 
     // ReadFrom reads a packet from the connection,
@@ -150,7 +150,7 @@ impl<T: RelayConnObserver + Send + Sync> Conn for RelayConn<T> {
     }
 
     async fn send(&self, _buf: &[u8]) -> Result<usize, util::Error> {
-        Err(io::Error::new(io::ErrorKind::Other, "Not applicable").into())
+        Err(io::Error::other("Not applicable").into())
     }
 
     // write_to writes a packet with payload p to addr.
@@ -162,7 +162,7 @@ impl<T: RelayConnObserver + Send + Sync> Conn for RelayConn<T> {
         let mut relay_conn = self.relay_conn.lock().await;
         match relay_conn.send_to(p, addr).await {
             Ok(n) => Ok(n),
-            Err(err) => Err(io::Error::new(io::ErrorKind::Other, err.to_string()).into()),
+            Err(err) => Err(io::Error::other(err.to_string()).into()),
         }
     }
 
@@ -228,9 +228,7 @@ impl<T: RelayConnObserver + Send + Sync> RelayConnInternal<T> {
                 }
             }
         }
-        if let Err(err) = result {
-            return Err(err);
-        }
+        result?;
 
         let number = {
             let (bind_st, bind_at, bind_number, bind_addr) = {
@@ -433,7 +431,7 @@ impl<T: RelayConnObserver + Send + Sync> RelayConnInternal<T> {
                 self.set_nonce_from_msg(&res);
                 return Err(Error::ErrTryAgain);
             } else {
-                return Err(Error::Other(format!("{} (error {})", res.typ, code)));
+                return Err(error_response(&res, code));
             }
         }
 
@@ -442,12 +440,12 @@ impl<T: RelayConnObserver + Send + Sync> RelayConnInternal<T> {
 
     pub fn set_nonce_from_msg(&mut self, msg: &Message) {
         // Update nonce
-        match Nonce::get_from_as(msg, ATTR_NONCE) {
+        match proto::nonce::get_nonce(msg) {
             Ok(nonce) => {
                 self.nonce = nonce;
                 log::debug!("refresh allocation: 438, got new nonce.");
             }
-            Err(_) => log::warn!("refresh allocation: 438 but no nonce."),
+            Err(_) => log::warn!("refresh allocation: 438 but no valid nonce."),
         }
     }
 
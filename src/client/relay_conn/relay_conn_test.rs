@@ -1,5 +1,6 @@
 use super::*;
 use crate::error::Result;
+use stun::attributes::*;
 
 use std::net::Ipv4Addr;
 
@@ -77,7 +78,7 @@ async fn test_relay_conn() -> Result<()> {
     {
         assert!(Error::ErrUnexpectedResponse != err);
     } else {
-        assert!(false, "should fail");
+        panic!("should fail");
     }
 
     Ok(())
@@ -20,8 +20,12 @@ pub enum Error {
     ErrListenerUnset,
     #[error("turn: RelayAddressGenerator has invalid ListeningAddress")]
     ErrListeningAddressInvalid,
+    #[error("turn: RelayAddressGenerator has no RelayAddress for this user")]
+    ErrNoRelayAddressForUser,
     #[error("turn: RelayAddressGenerator in RelayConfig is unset")]
     ErrRelayAddressGeneratorUnset,
+    #[error("turn: idle_timeout is only supported on conns with a remote address")]
+    ErrIdleTimeoutWithoutRemoteAddr,
     #[error("turn: max retries exceeded")]
     ErrMaxRetriesExceeded,
     #[error("turn: MaxPort must be not 0")]
@@ -50,6 +54,8 @@ pub enum Error {
     ErrOneAllocateOnly,
     #[error("already allocated")]
     ErrAlreadyAllocated,
+    #[error("client pool is closed")]
+    ErrClientPoolClosed,
     #[error("non-STUN message from STUN server")]
     ErrNonStunmessage,
     #[error("failed to decode STUN message")]
@@ -98,6 +104,8 @@ pub enum Error {
     ErrLeveledLoggerMustBeSet,
     #[error("you cannot use the same channel number with different peer")]
     ErrSameChannelDifferentPeer,
+    #[error("refreshing a channel binding is not allowed")]
+    ErrChannelBindRefreshRejected,
     #[error("allocations must not be created with nil FivTuple")]
     ErrNilFiveTuple,
     #[error("allocations must not be created with nil FiveTuple.src_addr")]
@@ -108,8 +116,22 @@ pub enum Error {
     ErrNilTurnSocket,
     #[error("allocations must not be created with a lifetime of 0")]
     ErrLifetimeZero,
+    #[error("allocations must not be created with a lifetime below the minimum")]
+    ErrLifetimeTooShort,
     #[error("allocation attempt created with duplicate FiveTuple")]
     ErrDupeFiveTuple,
+    #[error("allocation snapshot has expired")]
+    ErrSnapshotExpired,
+    #[error("relayed address of allocation snapshot is not available")]
+    ErrSnapshotRelayAddrUnavailable,
+    #[error("turn: max connections reached for listener")]
+    ErrMaxConnectionsReached,
+    #[error("turn: additional address family is not available")]
+    ErrAdditionalAddressFamilyUnavailable,
+    #[error("Request must not contain REQUESTED-ADDRESS-FAMILY and ADDITIONAL-ADDRESS-FAMILY")]
+    ErrRequestWithRequestedAndAdditionalFamily,
+    #[error("ADDITIONAL-ADDRESS-FAMILY must be IPv6")]
+    ErrAdditionalAddressFamilyMustBeIpv6,
     #[error("failed to cast net.Addr to *net.UDPAddr")]
     ErrFailedToCastUdpaddr,
     #[error("failed to generate nonce")]
@@ -118,10 +140,28 @@ pub enum Error {
     ErrFailedToSendError,
     #[error("duplicated Nonce generated, discarding request")]
     ErrDuplicatedNonce,
+    #[error("failed to generate nonce")]
+    ErrNonceGeneration,
+    #[error("turn: invalid nonce")]
+    ErrInvalidNonce,
     #[error("no such user exists")]
     ErrNoSuchUser,
+    #[error("too many authentication attempts")]
+    ErrAuthRateLimited,
+    #[error("turn: invalid OAuth 2.0 access token")]
+    ErrInvalidAccessToken,
+    #[error("turn: OAuth 2.0 access token expired")]
+    ErrAccessTokenExpired,
     #[error("unexpected class")]
     ErrUnexpectedClass,
+    #[error("{message_type} (error {code}: {reason})")]
+    ErrErrorResponse {
+        message_type: String,
+        code: u16,
+        reason: String,
+    },
+    #[error("unknown error code {0}")]
+    ErrUnknownErrorCode(u16),
     #[error("unexpected method")]
     ErrUnexpectedMethod,
     #[error("failed to handle")]
@@ -140,8 +180,12 @@ pub enum Error {
     ErrRequestedTransportMustBeUdp,
     #[error("no support for DONT-FRAGMENT")]
     ErrNoDontFragmentSupport,
+    #[error("packet too big to be sent without fragmentation")]
+    ErrPacketTooBig,
     #[error("Request must not contain RESERVATION-TOKEN and EVEN-PORT")]
     ErrRequestWithReservationTokenAndEvenPort,
+    #[error("no reservation for RESERVATION-TOKEN")]
+    ErrReservationNotFound,
     #[error("no allocation found")]
     ErrNoAllocationFound,
     #[error("unable to handle send-indication, no permission added")]
@@ -177,6 +221,19 @@ impl PartialEq for IoError {
     }
 }
 
+impl Error {
+    // is_connection_refused reports whether the error is a refused connection,
+    // which for UDP sockets is the ICMP port unreachable of an earlier datagram.
+    pub fn is_connection_refused(&self) -> bool {
+        let kind = match self {
+            Error::Io(e) => e.0.kind(),
+            Error::Util(util::Error::Io(e)) => e.0.kind(),
+            _ => return false,
+        };
+        kind == io::ErrorKind::ConnectionRefused
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Self {
         Error::Io(IoError(e))
@@ -0,0 +1,71 @@
+#[cfg(test)]
+mod addfamily_test;
+
+use super::reqfamily::{REQUESTED_FAMILY_IPV4, REQUESTED_FAMILY_IPV6};
+
+use stun::attributes::*;
+use stun::checks::*;
+use stun::message::*;
+
+use std::fmt;
+
+// ATTR_ADDITIONAL_ADDRESS_FAMILY is the ADDITIONAL-ADDRESS-FAMILY attribute type.
+pub const ATTR_ADDITIONAL_ADDRESS_FAMILY: AttrType = AttrType(0x8000);
+
+// Values for AdditionalAddressFamily, identical to the REQUESTED-ADDRESS-FAMILY ones.
+pub const ADDITIONAL_FAMILY_IPV4: AdditionalAddressFamily =
+    AdditionalAddressFamily(REQUESTED_FAMILY_IPV4.0);
+pub const ADDITIONAL_FAMILY_IPV6: AdditionalAddressFamily =
+    AdditionalAddressFamily(REQUESTED_FAMILY_IPV6.0);
+
+// AdditionalAddressFamily represents the ADDITIONAL-ADDRESS-FAMILY attribute.
+//
+// It is used by the client in an Allocate request to ask for an IPv6
+// relayed transport address in addition to the IPv4 one. The only value
+// the server accepts is IPv6.
+//
+// RFC 8656 Section 18.11
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct AdditionalAddressFamily(pub u8);
+
+impl fmt::Display for AdditionalAddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match *self {
+            ADDITIONAL_FAMILY_IPV4 => "IPv4",
+            ADDITIONAL_FAMILY_IPV6 => "IPv6",
+            _ => "unknown",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+const ADDITIONAL_FAMILY_SIZE: usize = 4;
+
+impl Setter for AdditionalAddressFamily {
+    // AddTo adds ADDITIONAL-ADDRESS-FAMILY to message.
+    fn add_to(&self, m: &mut Message) -> Result<(), stun::Error> {
+        let mut v = vec![0; ADDITIONAL_FAMILY_SIZE];
+        v[0] = self.0;
+        // b[1:4] is RFFU = 0.
+        m.add(ATTR_ADDITIONAL_ADDRESS_FAMILY, &v);
+        Ok(())
+    }
+}
+
+impl Getter for AdditionalAddressFamily {
+    // GetFrom decodes ADDITIONAL-ADDRESS-FAMILY from message.
+    fn get_from(&mut self, m: &Message) -> Result<(), stun::Error> {
+        let v = m.get(ATTR_ADDITIONAL_ADDRESS_FAMILY)?;
+        check_size(
+            ATTR_ADDITIONAL_ADDRESS_FAMILY,
+            v.len(),
+            ADDITIONAL_FAMILY_SIZE,
+        )?;
+
+        if v[0] != ADDITIONAL_FAMILY_IPV4.0 && v[0] != ADDITIONAL_FAMILY_IPV6.0 {
+            return Err(stun::Error::Other("ErrInvalidAdditionalFamilyValue".into()));
+        }
+        self.0 = v[0];
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use super::*;
+
+#[test]
+fn test_additional_address_family_string() {
+    assert_eq!(ADDITIONAL_FAMILY_IPV4.to_string(), "IPv4", "should match");
+    assert_eq!(ADDITIONAL_FAMILY_IPV6.to_string(), "IPv6", "should match");
+    assert_eq!(
+        AdditionalAddressFamily(0x04).to_string(),
+        "unknown",
+        "should be unknown"
+    );
+}
+
+#[test]
+fn test_additional_address_family_add_to() -> Result<(), stun::Error> {
+    let mut m = Message::new();
+    let r = ADDITIONAL_FAMILY_IPV6;
+    r.add_to(&mut m)?;
+    m.write_header();
+
+    //"GetFrom"
+    {
+        let mut decoded = Message::new();
+        decoded.write(&m.raw)?;
+        let mut req = AdditionalAddressFamily::default();
+        req.get_from(&decoded)?;
+        assert_eq!(req, r, "Decoded {}, expected {}", req, r);
+
+        //"HandleErr"
+        {
+            let mut m = Message::new();
+            let mut handle = AdditionalAddressFamily::default();
+            if let Err(err) = handle.get_from(&m) {
+                assert_eq!(
+                    stun::Error::ErrAttributeNotFound,
+                    err,
+                    "{} should be not found",
+                    err
+                );
+            } else {
+                panic!("expected error, but got ok");
+            }
+            m.add(ATTR_ADDITIONAL_ADDRESS_FAMILY, &[1, 2, 3]);
+            if let Err(err) = handle.get_from(&m) {
+                assert!(
+                    is_attr_size_invalid(&err),
+                    "IsAttrSizeInvalid should be true"
+                );
+            } else {
+                panic!("expected error, but got ok");
+            }
+            m.reset();
+            m.add(ATTR_ADDITIONAL_ADDRESS_FAMILY, &[5, 0, 0, 0]);
+            assert!(
+                handle.get_from(&m).is_err(),
+                "should error on invalid value"
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,100 @@
+#[cfg(test)]
+mod addrerror_test;
+
+use super::reqfamily::*;
+
+use stun::attributes::*;
+use stun::checks::*;
+use stun::error_code::ErrorCode;
+use stun::message::*;
+
+use std::fmt;
+
+// ATTR_ADDRESS_ERROR_CODE is the ADDRESS-ERROR-CODE attribute type,
+// RFC 8656 Section 18.12.
+pub const ATTR_ADDRESS_ERROR_CODE: AttrType = AttrType(0x8001);
+
+// AddressErrorCode represents the ADDRESS-ERROR-CODE attribute. It reports
+// that a request only partially succeeded and why it failed for family.
+//
+// RFC 6156 Section 4.2, RFC 8656 Section 18.12
+#[derive(Default, PartialEq, Eq)]
+pub struct AddressErrorCode {
+    pub family: RequestedAddressFamily,
+    pub code: ErrorCode,
+    pub reason: Vec<u8>,
+}
+
+impl AddressErrorCode {
+    // new creates ADDRESS-ERROR-CODE for family carrying code and its reason phrase.
+    pub fn new(family: RequestedAddressFamily, code: super::error_code::ErrorCode) -> Self {
+        AddressErrorCode {
+            family,
+            code: code.into(),
+            reason: code.reason_phrase().as_bytes().to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for AddressErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} {}",
+            self.family,
+            self.code.0,
+            String::from_utf8_lossy(&self.reason)
+        )
+    }
+}
+
+// constants for ADDRESS-ERROR-CODE encoding.
+const ADDRESS_ERROR_CODE_FAMILY_BYTE: usize = 0;
+const ADDRESS_ERROR_CODE_CLASS_BYTE: usize = 2;
+const ADDRESS_ERROR_CODE_NUMBER_BYTE: usize = 3;
+const ADDRESS_ERROR_CODE_REASON_START: usize = 4;
+const ADDRESS_ERROR_CODE_REASON_MAX_B: usize = 763;
+const ADDRESS_ERROR_CODE_MODULO: u16 = 100;
+
+impl Setter for AddressErrorCode {
+    // AddTo adds ADDRESS-ERROR-CODE to message.
+    fn add_to(&self, m: &mut Message) -> Result<(), stun::Error> {
+        check_overflow(
+            ATTR_ADDRESS_ERROR_CODE,
+            self.reason.len() + ADDRESS_ERROR_CODE_REASON_START,
+            ADDRESS_ERROR_CODE_REASON_MAX_B + ADDRESS_ERROR_CODE_REASON_START,
+        )?;
+
+        let mut v = Vec::with_capacity(ADDRESS_ERROR_CODE_REASON_START + self.reason.len());
+        v.push(self.family.0);
+        // b[1] is reserved and MUST be zero, as are the upper bits of the class byte.
+        v.push(0);
+        v.push((self.code.0 / ADDRESS_ERROR_CODE_MODULO) as u8);
+        v.push((self.code.0 % ADDRESS_ERROR_CODE_MODULO) as u8);
+        v.extend_from_slice(&self.reason);
+        m.add(ATTR_ADDRESS_ERROR_CODE, &v);
+        Ok(())
+    }
+}
+
+impl Getter for AddressErrorCode {
+    // GetFrom decodes ADDRESS-ERROR-CODE from message.
+    fn get_from(&mut self, m: &Message) -> Result<(), stun::Error> {
+        let v = m.get(ATTR_ADDRESS_ERROR_CODE)?;
+        if v.len() < ADDRESS_ERROR_CODE_REASON_START {
+            return Err(stun::Error::ErrUnexpectedEof);
+        }
+
+        let family = v[ADDRESS_ERROR_CODE_FAMILY_BYTE];
+        if family != REQUESTED_FAMILY_IPV4.0 && family != REQUESTED_FAMILY_IPV6.0 {
+            return Err(stun::Error::Other("ErrInvalidRequestedFamilyValue".into()));
+        }
+
+        let class = (v[ADDRESS_ERROR_CODE_CLASS_BYTE] & 0x07) as u16;
+        let number = v[ADDRESS_ERROR_CODE_NUMBER_BYTE] as u16;
+        self.family = RequestedAddressFamily(family);
+        self.code = ErrorCode(class * ADDRESS_ERROR_CODE_MODULO + number);
+        self.reason = v[ADDRESS_ERROR_CODE_REASON_START..].to_vec();
+        Ok(())
+    }
+}
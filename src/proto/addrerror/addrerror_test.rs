@@ -0,0 +1,66 @@
+use super::*;
+
+#[test]
+fn test_address_error_code_string() {
+    let a = AddressErrorCode::new(
+        REQUESTED_FAMILY_IPV6,
+        crate::proto::error_code::ErrorCode::PeerAddressFamilyMismatch,
+    );
+    assert_eq!(a.to_string(), "IPv6: 443 Peer Address Family Mismatch");
+}
+
+#[test]
+fn test_address_error_code_add_to() -> Result<(), stun::Error> {
+    let mut m = Message::new();
+    let a = AddressErrorCode::new(
+        REQUESTED_FAMILY_IPV6,
+        crate::proto::error_code::ErrorCode::PeerAddressFamilyMismatch,
+    );
+    a.add_to(&mut m)?;
+    m.write_header();
+
+    //"GetFrom"
+    {
+        let mut decoded = Message::new();
+        decoded.write(&m.raw)?;
+        let mut got = AddressErrorCode::default();
+        got.get_from(&decoded)?;
+        assert!(got == a, "Decoded {}, expected {}", got, a);
+
+        //"HandleErr"
+        {
+            let m = Message::new();
+            let mut handle = AddressErrorCode::default();
+            if let Err(err) = handle.get_from(&m) {
+                assert_eq!(
+                    stun::Error::ErrAttributeNotFound,
+                    err,
+                    "{} should be not found",
+                    err
+                );
+            } else {
+                panic!("expected error, but got ok");
+            }
+
+            let mut m = Message::new();
+            m.add(ATTR_ADDRESS_ERROR_CODE, &[1, 0, 4]);
+            if let Err(err) = handle.get_from(&m) {
+                assert_eq!(
+                    stun::Error::ErrUnexpectedEof,
+                    err,
+                    "IsErrUnexpectedEof should be true"
+                );
+            } else {
+                panic!("expected error, but got ok");
+            }
+
+            let mut m = Message::new();
+            m.add(ATTR_ADDRESS_ERROR_CODE, &[3, 0, 4, 43]);
+            if handle.get_from(&m).is_ok() {
+                panic!("should error on invalid family");
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,48 @@
+#[cfg(test)]
+mod appid_test;
+
+use std::fmt;
+use stun::attributes::*;
+use stun::checks::*;
+use stun::message::*;
+
+// ATTR_APP_ID is the APP-ID attribute type. It is not registered with IANA
+// and lies in the comprehension-optional range, so servers that don't know
+// it ignore it.
+pub const ATTR_APP_ID: AttrType = AttrType(0xFF01);
+
+// APP_ID_MAX_LEN is the longest APP-ID accepted, in bytes.
+pub const APP_ID_MAX_LEN: usize = 128;
+
+// AppId represents APP-ID attribute.
+//
+// Clients add it to an Allocate request to tag the allocation with the
+// application it is used for, e.g. for per-application accounting. It is a
+// UTF-8 string of at most APP_ID_MAX_LEN bytes.
+#[derive(Default, Debug, PartialEq, Eq, Clone)]
+pub struct AppId(pub String);
+
+impl fmt::Display for AppId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Setter for AppId {
+    // AddTo adds APP-ID to message.
+    fn add_to(&self, m: &mut Message) -> Result<(), stun::Error> {
+        check_overflow(ATTR_APP_ID, self.0.len(), APP_ID_MAX_LEN)?;
+        m.add(ATTR_APP_ID, self.0.as_bytes());
+        Ok(())
+    }
+}
+
+impl Getter for AppId {
+    // GetFrom decodes APP-ID from message.
+    fn get_from(&mut self, m: &Message) -> Result<(), stun::Error> {
+        let v = m.get(ATTR_APP_ID)?;
+        check_overflow(ATTR_APP_ID, v.len(), APP_ID_MAX_LEN)?;
+        self.0 = String::from_utf8(v).map_err(|err| stun::Error::Other(err.to_string()))?;
+        Ok(())
+    }
+}
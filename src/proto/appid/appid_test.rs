@@ -0,0 +1,56 @@
+use super::*;
+
+#[test]
+fn test_app_id_add_to() -> Result<(), stun::Error> {
+    let mut m = Message::new();
+    let a = AppId("meetings".to_owned());
+    a.add_to(&mut m)?;
+    m.write_header();
+
+    //"GetFrom"
+    {
+        let mut decoded = Message::new();
+        decoded.write(&m.raw)?;
+        let mut got = AppId::default();
+        got.get_from(&decoded)?;
+        assert_eq!(got, a, "Decoded {}, expected {}", got, a);
+
+        //"HandleErr"
+        {
+            let mut m = Message::new();
+            let mut handle = AppId::default();
+            if let Err(err) = handle.get_from(&m) {
+                assert_eq!(
+                    stun::Error::ErrAttributeNotFound,
+                    err,
+                    "{} should be not found",
+                    err
+                );
+            } else {
+                panic!("expected error, but got ok");
+            }
+
+            m.add(ATTR_APP_ID, &[0xff, 0xfe]);
+            assert!(
+                handle.get_from(&m).is_err(),
+                "should error on invalid UTF-8"
+            );
+
+            let mut m = Message::new();
+            m.add(ATTR_APP_ID, &[b'a'; APP_ID_MAX_LEN + 1]);
+            if let Err(err) = handle.get_from(&m) {
+                assert!(
+                    is_attr_size_overflow(&err),
+                    "IsAttrSizeOverflow should be true"
+                );
+            } else {
+                panic!("expected error, but got ok");
+            }
+        }
+    }
+
+    let too_long = AppId("a".repeat(APP_ID_MAX_LEN + 1));
+    assert!(too_long.add_to(&mut Message::new()).is_err());
+
+    Ok(())
+}
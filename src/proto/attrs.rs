@@ -0,0 +1,112 @@
+#[cfg(test)]
+mod attrs_test;
+
+use super::error_code::ErrorCode;
+use super::lifetime::Lifetime;
+use super::relayaddr::RelayedAddress;
+use crate::error::*;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use stun::agent::TransactionId;
+use stun::error_code::ErrorCodeAttribute;
+use stun::fingerprint::FINGERPRINT;
+use stun::integrity::MessageIntegrity;
+use stun::message::*;
+use stun::xoraddr::XorMappedAddress;
+
+// ResponseBuilder builds a STUN message from typed attributes. Attributes are
+// encoded in the order they are added, except for MESSAGE-INTEGRITY and
+// FINGERPRINT, which are always encoded last and in that order, as RFC 5389
+// Section 15.4 and 15.5 require, however they were added.
+pub struct ResponseBuilder {
+    transaction_id: TransactionId,
+    msg_type: MessageType,
+    attrs: Vec<Box<dyn Setter>>,
+    integrity: Option<MessageIntegrity>,
+    fingerprint: bool,
+}
+
+impl ResponseBuilder {
+    // new starts a message of msg_type, usually answering the request with
+    // transaction_id
+    pub fn new(transaction_id: TransactionId, msg_type: MessageType) -> Self {
+        ResponseBuilder {
+            transaction_id,
+            msg_type,
+            attrs: vec![],
+            integrity: None,
+            fingerprint: false,
+        }
+    }
+
+    // with_lifetime adds LIFETIME
+    pub fn with_lifetime(self, lifetime: Duration) -> Self {
+        self.with_attr(Box::new(Lifetime(lifetime)))
+    }
+
+    // with_xor_mapped_address adds XOR-MAPPED-ADDRESS
+    pub fn with_xor_mapped_address(self, addr: SocketAddr) -> Self {
+        self.with_attr(Box::new(XorMappedAddress {
+            ip: addr.ip(),
+            port: addr.port(),
+        }))
+    }
+
+    // with_relay_address adds XOR-RELAYED-ADDRESS
+    pub fn with_relay_address(self, addr: SocketAddr) -> Self {
+        self.with_attr(Box::new(RelayedAddress {
+            ip: addr.ip(),
+            port: addr.port(),
+        }))
+    }
+
+    // with_error_code adds ERROR-CODE with reason as the reason phrase. An
+    // empty reason uses the standard phrase of code.
+    pub fn with_error_code(self, code: ErrorCode, reason: &str) -> Self {
+        let reason = if reason.is_empty() {
+            code.reason_phrase()
+        } else {
+            reason
+        };
+        self.with_attr(Box::new(ErrorCodeAttribute {
+            code: code.into(),
+            reason: reason.as_bytes().to_vec(),
+        }))
+    }
+
+    // with_attr adds any other attribute
+    pub fn with_attr(mut self, attr: Box<dyn Setter>) -> Self {
+        self.attrs.push(attr);
+        self
+    }
+
+    // with_message_integrity adds MESSAGE-INTEGRITY, see ResponseBuilder
+    pub fn with_message_integrity(mut self, integrity: MessageIntegrity) -> Self {
+        self.integrity = Some(integrity);
+        self
+    }
+
+    // with_fingerprint adds FINGERPRINT, see ResponseBuilder
+    pub fn with_fingerprint(mut self) -> Self {
+        self.fingerprint = true;
+        self
+    }
+
+    // build encodes the message. Its wire format is in Message::raw.
+    pub fn build(self) -> Result<Message> {
+        let mut attrs: Vec<Box<dyn Setter>> =
+            vec![Box::new(self.transaction_id), Box::new(self.msg_type)];
+        attrs.extend(self.attrs);
+        if let Some(integrity) = self.integrity {
+            attrs.push(Box::new(integrity));
+        }
+        if self.fingerprint {
+            attrs.push(Box::new(FINGERPRINT));
+        }
+
+        let mut msg = Message::new();
+        msg.build(&attrs)?;
+        Ok(msg)
+    }
+}
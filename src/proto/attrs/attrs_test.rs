@@ -0,0 +1,89 @@
+use super::*;
+
+use stun::attributes::*;
+use stun::fingerprint::FingerprintAttr;
+use stun::textattrs::Username;
+
+#[test]
+fn test_response_builder() -> Result<()> {
+    let transaction_id = TransactionId::new();
+    let mapped = SocketAddr::from(([1, 2, 3, 4], 5000));
+    let relayed = SocketAddr::from(([5, 6, 7, 8], 6000));
+    let integrity = MessageIntegrity::new_short_term_integrity("pass".to_owned());
+
+    let msg = ResponseBuilder::new(
+        transaction_id,
+        MessageType::new(METHOD_ALLOCATE, CLASS_SUCCESS_RESPONSE),
+    )
+    .with_fingerprint()
+    .with_message_integrity(integrity.clone())
+    .with_relay_address(relayed)
+    .with_lifetime(Duration::from_secs(600))
+    .with_xor_mapped_address(mapped)
+    .build()?;
+
+    let mut decoded = Message::new();
+    decoded.write(&msg.raw)?;
+    assert_eq!(decoded.transaction_id, transaction_id);
+    assert_eq!(
+        decoded.typ,
+        MessageType::new(METHOD_ALLOCATE, CLASS_SUCCESS_RESPONSE)
+    );
+
+    let types: Vec<AttrType> = decoded.attributes.0.iter().map(|a| a.typ).collect();
+    assert_eq!(
+        types,
+        vec![
+            ATTR_XOR_RELAYED_ADDRESS,
+            ATTR_LIFETIME,
+            ATTR_XORMAPPED_ADDRESS,
+            ATTR_MESSAGE_INTEGRITY,
+            ATTR_FINGERPRINT,
+        ],
+        "MESSAGE-INTEGRITY and FINGERPRINT should come last"
+    );
+
+    let mut relay_addr = RelayedAddress::default();
+    relay_addr.get_from(&decoded)?;
+    assert_eq!(SocketAddr::new(relay_addr.ip, relay_addr.port), relayed);
+    let mut lifetime = Lifetime::default();
+    lifetime.get_from(&decoded)?;
+    assert_eq!(lifetime.0, Duration::from_secs(600));
+    let mut mapped_addr = XorMappedAddress::default();
+    mapped_addr.get_from(&decoded)?;
+    assert_eq!(SocketAddr::new(mapped_addr.ip, mapped_addr.port), mapped);
+    integrity.check(&mut decoded)?;
+    FingerprintAttr.check(&decoded)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_response_builder_error_code() -> Result<()> {
+    let msg = ResponseBuilder::new(
+        TransactionId::new(),
+        MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE),
+    )
+    .with_error_code(ErrorCode::AllocationQuotaReached, "")
+    .with_attr(Box::new(Username::new(ATTR_USERNAME, "user".to_owned())))
+    .build()?;
+
+    let mut code = ErrorCodeAttribute::default();
+    code.get_from(&msg)?;
+    assert_eq!(code.code.0, 486);
+    assert_eq!(code.reason, b"Allocation Quota Reached");
+
+    let msg = ResponseBuilder::new(
+        TransactionId::new(),
+        MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE),
+    )
+    .with_error_code(ErrorCode::BadRequest, "missing REQUESTED-TRANSPORT")
+    .build()?;
+
+    let mut code = ErrorCodeAttribute::default();
+    code.get_from(&msg)?;
+    assert_eq!(code.code.0, 400);
+    assert_eq!(code.reason, b"missing REQUESTED-TRANSPORT");
+
+    Ok(())
+}
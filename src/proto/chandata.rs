@@ -4,9 +4,11 @@ mod chandata_test;
 use super::channum::*;
 use crate::error::*;
 
+use stun::message::MESSAGE_HEADER_SIZE;
+
 const PADDING: usize = 4;
 
-fn nearest_padded_value_length(l: usize) -> usize {
+pub(crate) fn nearest_padded_value_length(l: usize) -> usize {
     let mut n = PADDING * (l / PADDING);
     if n < l {
         n += PADDING;
@@ -16,7 +18,8 @@ fn nearest_padded_value_length(l: usize) -> usize {
 
 const CHANNEL_DATA_LENGTH_SIZE: usize = 2;
 const CHANNEL_DATA_NUMBER_SIZE: usize = CHANNEL_DATA_LENGTH_SIZE;
-const CHANNEL_DATA_HEADER_SIZE: usize = CHANNEL_DATA_LENGTH_SIZE + CHANNEL_DATA_NUMBER_SIZE;
+pub(crate) const CHANNEL_DATA_HEADER_SIZE: usize =
+    CHANNEL_DATA_LENGTH_SIZE + CHANNEL_DATA_NUMBER_SIZE;
 
 // ChannelData represents The ChannelData Message.
 //
@@ -40,8 +43,7 @@ impl ChannelData {
     //
     // Similar to stun.Message.grow method.
     fn grow(&mut self, v: usize) {
-        let n = self.raw.len() + v;
-        self.raw.extend_from_slice(&vec![0; n - self.raw.len()]);
+        self.raw.resize(self.raw.len() + v, 0);
     }
 
     // Reset resets Length, Data and Raw length.
@@ -50,16 +52,15 @@ impl ChannelData {
         self.data.clear();
     }
 
-    // Encode encodes ChannelData Message to Raw.
+    // Encode encodes ChannelData Message to Raw. Raw keeps its capacity, so
+    // re-encoding into the same ChannelData doesn't allocate.
     pub fn encode(&mut self) {
+        let padded = nearest_padded_value_length(CHANNEL_DATA_HEADER_SIZE + self.data.len());
         self.raw.clear();
+        self.raw.reserve(padded);
         self.write_header();
         self.raw.extend_from_slice(&self.data);
-        let padded = nearest_padded_value_length(self.raw.len());
-        let bytes_to_add = padded - self.raw.len();
-        if bytes_to_add > 0 {
-            self.raw.extend_from_slice(&vec![0; bytes_to_add]);
-        }
+        self.raw.resize(padded, 0);
     }
 
     // Decode decodes The ChannelData Message from Raw.
@@ -116,3 +117,87 @@ impl ChannelData {
         num.valid()
     }
 }
+
+// frame_length returns the length of the message starting with header, excluding
+// any trailing padding, and the length of the whole frame on the wire.
+fn frame_length(header: &[u8]) -> (usize, usize) {
+    let l = u16::from_be_bytes([header[2], header[3]]) as usize;
+    if header[0] & 0xc0 == 0x40 {
+        // ChannelData: 2 bytes channel number, 2 bytes length, padded data
+        let n = CHANNEL_DATA_HEADER_SIZE + l;
+        (n, nearest_padded_value_length(n))
+    } else {
+        // STUN: 20 bytes header, attributes of the given length
+        let n = MESSAGE_HEADER_SIZE + l;
+        (n, n)
+    }
+}
+
+// TcpChannelDataDecoder reassembles ChannelData messages from a TCP byte
+// stream, where one read may hold part of a message or several of them.
+// Over TCP every ChannelData message is padded to a multiple of 4 bytes,
+// see RFC 5766 Section 11.5, and the padding is dropped here. TcpFramer
+// reads through it.
+#[derive(Default, Debug)]
+pub struct TcpChannelDataDecoder {
+    buf: Vec<u8>,
+}
+
+impl TcpChannelDataDecoder {
+    pub fn new() -> Self {
+        TcpChannelDataDecoder::default()
+    }
+
+    // push appends bytes read from the stream.
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    // buffered_len returns the number of bytes pushed but not decoded yet.
+    pub fn buffered_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    // decode_frame returns the next complete frame, a ChannelData message
+    // without its padding or a STUN message, or None while more bytes are
+    // needed. TURN over TCP interleaves both on one connection.
+    pub fn decode_frame(&mut self) -> Option<Vec<u8>> {
+        if self.buf.len() < CHANNEL_DATA_HEADER_SIZE {
+            return None;
+        }
+
+        let (n, frame_len) = frame_length(&self.buf[..CHANNEL_DATA_HEADER_SIZE]);
+        if self.buf.len() < frame_len {
+            return None;
+        }
+
+        let mut raw: Vec<u8> = self.buf.drain(..frame_len).collect();
+        raw.truncate(n);
+        Some(raw)
+    }
+
+    // decode returns the next complete ChannelData message, or None while
+    // more bytes are needed. It fails on a frame that isn't ChannelData, after
+    // which the stream can't be resynchronized.
+    pub fn decode(&mut self) -> Result<Option<ChannelData>> {
+        if self.buf.len() < CHANNEL_DATA_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let number = ChannelNumber(u16::from_be_bytes([self.buf[0], self.buf[1]]));
+        if !number.valid() {
+            return Err(Error::ErrInvalidChannelNumber);
+        }
+
+        let raw = match self.decode_frame() {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+        let mut c = ChannelData {
+            raw,
+            ..Default::default()
+        };
+        c.decode()?;
+        Ok(Some(c))
+    }
+}
@@ -125,7 +125,40 @@ fn test_channel_data_decode() -> Result<()> {
                 name, want_err, err
             );
         } else {
-            assert!(false, "expected error, but got ok");
+            panic!("expected error, but got ok");
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_channel_data_decode_number_range() -> Result<()> {
+    let tests = vec![
+        (0x0000u16, Err(Error::ErrInvalidChannelNumber)),
+        (0x3FFF, Err(Error::ErrInvalidChannelNumber)),
+        (0x4000, Ok(())),
+        (0x7FFF, Ok(())),
+        (0x8000, Err(Error::ErrInvalidChannelNumber)),
+    ];
+
+    for (number, want) in tests {
+        let mut raw = number.to_be_bytes().to_vec();
+        raw.extend_from_slice(&[0, 4, 1, 2, 3, 4]);
+        let mut m = ChannelData {
+            raw: raw.clone(),
+            ..Default::default()
+        };
+        assert_eq!(m.decode(), want, "channel number {:#06x}", number);
+        assert_eq!(
+            ChannelData::is_channel_data(&raw),
+            want.is_ok(),
+            "channel number {:#06x}",
+            number
+        );
+        if want.is_ok() {
+            assert_eq!(m.number, ChannelNumber(number));
+            assert_eq!(m.data, vec![1, 2, 3, 4]);
         }
     }
 
@@ -186,8 +219,10 @@ fn test_chrome_channel_data() -> Result<()> {
     // All hex streams decoded to raw binary format and stored in data slice.
     // Decoding packets to messages.
     for packet in data {
-        let mut m = ChannelData::default();
-        m.raw = packet;
+        let mut m = ChannelData {
+            raw: packet,
+            ..Default::default()
+        };
         m.decode()?;
         let mut encoded = ChannelData {
             data: m.data.clone(),
@@ -195,8 +230,10 @@ fn test_chrome_channel_data() -> Result<()> {
             ..Default::default()
         };
         encoded.encode();
-        let mut decoded = ChannelData::default();
-        decoded.raw = encoded.raw.clone();
+        let mut decoded = ChannelData {
+            raw: encoded.raw.clone(),
+            ..Default::default()
+        };
         decoded.decode()?;
         assert_eq!(decoded, m, "should be equal");
 
@@ -206,3 +243,90 @@ fn test_chrome_channel_data() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_tcp_channel_data_decoder() -> Result<()> {
+    let mut messages = vec![];
+    for (i, data) in vec![vec![1, 2, 3], vec![], vec![4, 5, 6, 7, 8]]
+        .into_iter()
+        .enumerate()
+    {
+        let mut c = ChannelData {
+            data,
+            number: ChannelNumber(MIN_CHANNEL_NUMBER + i as u16),
+            ..Default::default()
+        };
+        c.encode();
+        messages.push(c);
+    }
+    let stream: Vec<u8> = messages.iter().flat_map(|c| c.raw.clone()).collect();
+    assert_eq!(stream.len(), 8 + 4 + 12, "messages should be padded");
+
+    // all messages in one read
+    let mut decoder = TcpChannelDataDecoder::new();
+    decoder.push(&stream);
+    for expected in &messages {
+        assert_eq!(decoder.decode()?.as_ref(), Some(expected));
+    }
+    assert_eq!(decoder.decode()?, None);
+    assert_eq!(decoder.buffered_len(), 0);
+
+    // one byte per read
+    let mut decoder = TcpChannelDataDecoder::new();
+    let mut decoded = vec![];
+    for b in &stream {
+        decoder.push(&[*b]);
+        if let Some(c) = decoder.decode()? {
+            decoded.push(c);
+        }
+    }
+    assert_eq!(decoded, messages);
+
+    // a message split right before its padding
+    let mut decoder = TcpChannelDataDecoder::new();
+    decoder.push(&stream[..7]);
+    assert_eq!(decoder.decode()?, None, "should wait for the padding");
+    decoder.push(&stream[7..]);
+    assert_eq!(decoder.decode()?.as_ref(), Some(&messages[0]));
+
+    let mut decoder = TcpChannelDataDecoder::new();
+    decoder.push(&[0x00, 0x01, 0x00, 0x00]);
+    assert_eq!(decoder.decode(), Err(Error::ErrInvalidChannelNumber));
+
+    Ok(())
+}
+
+#[test]
+fn test_tcp_channel_data_decoder_frames() -> Result<()> {
+    let mut c = ChannelData {
+        data: vec![1, 2, 3],
+        number: ChannelNumber(MIN_CHANNEL_NUMBER),
+        ..Default::default()
+    };
+    c.encode();
+    let mut m = stun::message::Message::new();
+    m.build(&[
+        Box::new(stun::agent::TransactionId::new()),
+        Box::new(stun::message::BINDING_REQUEST),
+    ])?;
+
+    // STUN and padded ChannelData interleaved, as on a TURN TCP connection
+    let mut decoder = TcpChannelDataDecoder::new();
+    decoder.push(&c.raw);
+    decoder.push(&m.raw[..10]);
+    assert_eq!(
+        decoder.decode_frame(),
+        Some(c.raw[..7].to_vec()),
+        "padding should be dropped"
+    );
+    assert_eq!(
+        decoder.decode_frame(),
+        None,
+        "should wait for the STUN message"
+    );
+    decoder.push(&m.raw[10..]);
+    assert_eq!(decoder.decode_frame(), Some(m.raw.clone()));
+    assert_eq!(decoder.buffered_len(), 0);
+
+    Ok(())
+}
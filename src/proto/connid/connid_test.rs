@@ -0,0 +1,73 @@
+use super::*;
+use crate::proto::peeraddr::PeerAddress;
+use std::net::{IpAddr, Ipv4Addr};
+use stun::agent::TransactionId;
+
+#[test]
+fn test_connection_id_add_to() -> Result<(), stun::Error> {
+    let mut m = Message::new();
+    ConnectionId(0x01020304).add_to(&mut m)?;
+    m.write_header();
+
+    assert_eq!(m.get(ATTR_CONNECTION_ID)?, vec![1, 2, 3, 4]);
+
+    let mut decoded = Message::new();
+    decoded.write(&m.raw)?;
+
+    let mut id = ConnectionId::default();
+    id.get_from(&decoded)?;
+    assert_eq!(id, ConnectionId(0x01020304));
+
+    Ok(())
+}
+
+#[test]
+fn test_connection_id_get_from_bad_size() -> Result<(), stun::Error> {
+    let mut m = Message::new();
+    m.add(ATTR_CONNECTION_ID, &[1, 2, 3]);
+
+    let mut id = ConnectionId::default();
+    let result = id.get_from(&m);
+    assert!(is_attr_size_invalid(&result.unwrap_err()));
+
+    let mut id = ConnectionId::default();
+    assert_eq!(
+        id.get_from(&Message::new()),
+        Err(stun::Error::ErrAttributeNotFound)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_connection_attempt_indication() -> Result<(), stun::Error> {
+    let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(
+            METHOD_CONNECTION_ATTEMPT,
+            CLASS_INDICATION,
+        )),
+        Box::new(ConnectionId(7)),
+        Box::new(PeerAddress { ip, port: 5000 }),
+    ])?;
+
+    let mut decoded = Message::new();
+    decoded.write(&m.raw)?;
+    assert_eq!(
+        decoded.typ,
+        MessageType::new(METHOD_CONNECTION_ATTEMPT, CLASS_INDICATION)
+    );
+
+    let mut id = ConnectionId::default();
+    id.get_from(&decoded)?;
+    assert_eq!(id, ConnectionId(7));
+
+    let mut got = PeerAddress::default();
+    got.get_from(&decoded)?;
+    assert_eq!(got, PeerAddress { ip, port: 5000 });
+
+    Ok(())
+}
@@ -0,0 +1,33 @@
+#[cfg(test)]
+mod credential_test;
+
+use md5::{Digest, Md5};
+use stun::integrity::MessageIntegrity;
+use stun::message::*;
+
+// LongTermCredential holds the computations of the STUN long-term credential
+// mechanism, for AuthHandler implementations and other code that checks
+// MESSAGE-INTEGRITY itself.
+//
+// RFC 5389 Section 10.2 and 15.4
+pub struct LongTermCredential;
+
+impl LongTermCredential {
+    // compute_key returns the key MESSAGE-INTEGRITY is computed with, that is
+    // MD5(username ":" realm ":" password). AuthHandler::auth_handle returns
+    // this key. All three values are used as sent, without SASLprep.
+    pub fn compute_key(username: &str, realm: &str, password: &str) -> [u8; 16] {
+        let mut h = Md5::new();
+        h.update(format!("{}:{}:{}", username, realm, password).as_bytes());
+        h.finalize().into()
+    }
+
+    // verify_integrity reports whether msg carries a MESSAGE-INTEGRITY that is
+    // the HMAC-SHA1 with key of msg up to that attribute. It is false if msg
+    // has no MESSAGE-INTEGRITY.
+    pub fn verify_integrity(msg: &Message, key: &[u8]) -> bool {
+        MessageIntegrity(key.to_vec())
+            .check(&mut msg.clone())
+            .is_ok()
+    }
+}
@@ -0,0 +1,63 @@
+use super::*;
+use stun::agent::TransactionId;
+use stun::attributes::*;
+use stun::fingerprint::FINGERPRINT;
+use stun::textattrs::{Nonce, Realm, Username};
+
+#[test]
+fn test_compute_key() {
+    // MD5("user:webrtc.rs:pass")
+    let key = LongTermCredential::compute_key("user", "webrtc.rs", "pass");
+    assert_eq!(
+        key,
+        [
+            0xfc, 0x3c, 0xbc, 0x9d, 0xfe, 0xc3, 0x97, 0xa9, 0x56, 0x49, 0xff, 0x06, 0xb9, 0xd3,
+            0xa9, 0xf0,
+        ]
+    );
+    assert_eq!(
+        key.to_vec(),
+        crate::auth::generate_auth_key("user", "webrtc.rs", "pass")
+    );
+}
+
+fn signed_request(key: &[u8]) -> Result<Message, stun::Error> {
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(BINDING_REQUEST),
+        Box::new(Username::new(ATTR_USERNAME, "user".to_owned())),
+        Box::new(Realm::new(ATTR_REALM, "webrtc.rs".to_owned())),
+        Box::new(Nonce::new(ATTR_NONCE, "nonce".to_owned())),
+        Box::new(MessageIntegrity(key.to_vec())),
+        Box::new(FINGERPRINT),
+    ])?;
+    Ok(m)
+}
+
+#[test]
+fn test_verify_integrity() -> Result<(), stun::Error> {
+    let key = LongTermCredential::compute_key("user", "webrtc.rs", "pass");
+    let m = signed_request(&key)?;
+
+    let mut decoded = Message::new();
+    decoded.write(&m.raw)?;
+    assert!(LongTermCredential::verify_integrity(&decoded, &key));
+
+    let wrong_key = LongTermCredential::compute_key("user", "webrtc.rs", "wrong");
+    assert!(!LongTermCredential::verify_integrity(&decoded, &wrong_key));
+
+    // a changed attribute before MESSAGE-INTEGRITY invalidates it
+    let mut tampered = Message::new();
+    tampered.write(&m.raw)?;
+    let pos = tampered.raw.windows(4).position(|w| w == b"user").unwrap();
+    tampered.raw[pos] = b'U';
+    tampered.decode()?;
+    assert!(!LongTermCredential::verify_integrity(&tampered, &key));
+
+    let mut unsigned = Message::new();
+    unsigned.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])?;
+    assert!(!LongTermCredential::verify_integrity(&unsigned, &key));
+
+    Ok(())
+}
@@ -5,6 +5,13 @@ use stun::attributes::*;
 use stun::message::*;
 
 // DontFragmentAttr represents DONT-FRAGMENT attribute.
+//
+// This attribute is used by the client to request that the server set
+// the DF (Don't Fragment) bit in the IP header when relaying the
+// application data onward to the peer. It has no value part, so its
+// presence is all that matters.
+//
+// RFC 5766 Section 14.8
 #[derive(Debug, Default, PartialEq, Eq)]
 pub struct DontFragmentAttr;
 
@@ -0,0 +1,108 @@
+#[cfg(test)]
+mod error_code_test;
+
+use crate::error::*;
+
+use std::convert::TryFrom;
+use std::fmt;
+use stun::error_code::ErrorCodeAttribute;
+use stun::message::*;
+
+// ErrorCode is an ERROR-CODE value defined by the STUN and TURN RFCs.
+//
+// It is a Setter, so it can be added to a response directly and comes with
+// its RFC reason phrase.
+//
+// RFC 5389 Section 15.6, RFC 5766 Section 15, RFC 6156 Section 10.2
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[repr(u16)]
+pub enum ErrorCode {
+    TryAlternate = 300,
+    BadRequest = 400,
+    Unauthorized = 401,
+    Forbidden = 403,
+    UnknownAttribute = 420,
+    AllocationMismatch = 437,
+    StaleNonce = 438,
+    AddressFamilyNotSupported = 440,
+    WrongCredentials = 441,
+    UnsupportedTransportProtocol = 442,
+    PeerAddressFamilyMismatch = 443,
+    AllocationQuotaReached = 486,
+    ServerError = 500,
+    InsufficientCapacity = 508,
+}
+
+impl ErrorCode {
+    // code returns the numeric value of the error code
+    pub fn code(&self) -> u16 {
+        *self as u16
+    }
+
+    // reason_phrase returns the reason phrase the RFCs recommend for the code
+    pub fn reason_phrase(&self) -> &'static str {
+        match self {
+            ErrorCode::TryAlternate => "Try Alternate",
+            ErrorCode::BadRequest => "Bad Request",
+            ErrorCode::Unauthorized => "Unauthorized",
+            ErrorCode::Forbidden => "Forbidden",
+            ErrorCode::UnknownAttribute => "Unknown Attribute",
+            ErrorCode::AllocationMismatch => "Allocation Mismatch",
+            ErrorCode::StaleNonce => "Stale Nonce",
+            ErrorCode::AddressFamilyNotSupported => "Address Family not Supported",
+            ErrorCode::WrongCredentials => "Wrong Credentials",
+            ErrorCode::UnsupportedTransportProtocol => "Unsupported Transport Protocol",
+            ErrorCode::PeerAddressFamilyMismatch => "Peer Address Family Mismatch",
+            ErrorCode::AllocationQuotaReached => "Allocation Quota Reached",
+            ErrorCode::ServerError => "Server Error",
+            ErrorCode::InsufficientCapacity => "Insufficient Capacity",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason_phrase())
+    }
+}
+
+impl TryFrom<u16> for ErrorCode {
+    type Error = Error;
+
+    fn try_from(code: u16) -> Result<Self> {
+        Ok(match code {
+            300 => ErrorCode::TryAlternate,
+            400 => ErrorCode::BadRequest,
+            401 => ErrorCode::Unauthorized,
+            403 => ErrorCode::Forbidden,
+            420 => ErrorCode::UnknownAttribute,
+            437 => ErrorCode::AllocationMismatch,
+            438 => ErrorCode::StaleNonce,
+            440 => ErrorCode::AddressFamilyNotSupported,
+            441 => ErrorCode::WrongCredentials,
+            442 => ErrorCode::UnsupportedTransportProtocol,
+            443 => ErrorCode::PeerAddressFamilyMismatch,
+            486 => ErrorCode::AllocationQuotaReached,
+            500 => ErrorCode::ServerError,
+            508 => ErrorCode::InsufficientCapacity,
+            _ => return Err(Error::ErrUnknownErrorCode(code)),
+        })
+    }
+}
+
+impl From<ErrorCode> for stun::error_code::ErrorCode {
+    fn from(code: ErrorCode) -> Self {
+        stun::error_code::ErrorCode(code.code())
+    }
+}
+
+impl Setter for ErrorCode {
+    // AddTo adds ERROR-CODE with the reason phrase to message.
+    fn add_to(&self, m: &mut Message) -> std::result::Result<(), stun::Error> {
+        ErrorCodeAttribute {
+            code: (*self).into(),
+            reason: self.reason_phrase().as_bytes().to_vec(),
+        }
+        .add_to(m)
+    }
+}
@@ -0,0 +1,77 @@
+use super::*;
+
+use stun::error_code::{
+    CODE_ADDR_FAMILY_NOT_SUPPORTED, CODE_ALLOC_MISMATCH, CODE_ALLOC_QUOTA_REACHED,
+    CODE_BAD_REQUEST, CODE_FORBIDDEN, CODE_INSUFFICIENT_CAPACITY, CODE_PEER_ADDR_FAMILY_MISMATCH,
+    CODE_SERVER_ERROR, CODE_STALE_NONCE, CODE_TRY_ALTERNATE, CODE_UNAUTHORIZED,
+    CODE_UNKNOWN_ATTRIBUTE, CODE_UNSUPPORTED_TRANS_PROTO, CODE_WRONG_CREDENTIALS,
+};
+
+#[test]
+fn test_error_code_try_from() -> Result<()> {
+    let tests = vec![
+        (CODE_TRY_ALTERNATE, ErrorCode::TryAlternate),
+        (CODE_BAD_REQUEST, ErrorCode::BadRequest),
+        (CODE_UNAUTHORIZED, ErrorCode::Unauthorized),
+        (CODE_FORBIDDEN, ErrorCode::Forbidden),
+        (CODE_UNKNOWN_ATTRIBUTE, ErrorCode::UnknownAttribute),
+        (CODE_ALLOC_MISMATCH, ErrorCode::AllocationMismatch),
+        (CODE_STALE_NONCE, ErrorCode::StaleNonce),
+        (
+            CODE_ADDR_FAMILY_NOT_SUPPORTED,
+            ErrorCode::AddressFamilyNotSupported,
+        ),
+        (CODE_WRONG_CREDENTIALS, ErrorCode::WrongCredentials),
+        (
+            CODE_UNSUPPORTED_TRANS_PROTO,
+            ErrorCode::UnsupportedTransportProtocol,
+        ),
+        (
+            CODE_PEER_ADDR_FAMILY_MISMATCH,
+            ErrorCode::PeerAddressFamilyMismatch,
+        ),
+        (CODE_ALLOC_QUOTA_REACHED, ErrorCode::AllocationQuotaReached),
+        (CODE_SERVER_ERROR, ErrorCode::ServerError),
+        (CODE_INSUFFICIENT_CAPACITY, ErrorCode::InsufficientCapacity),
+    ];
+
+    for (stun_code, code) in tests {
+        assert_eq!(ErrorCode::try_from(stun_code.0)?, code, "{}", code);
+        assert_eq!(
+            stun::error_code::ErrorCode::from(code).0,
+            stun_code.0,
+            "{}",
+            code
+        );
+    }
+
+    assert_eq!(
+        ErrorCode::try_from(499),
+        Err(Error::ErrUnknownErrorCode(499)),
+        "unknown codes should be rejected"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_error_code_display() {
+    assert_eq!(ErrorCode::StaleNonce.to_string(), "438 Stale Nonce");
+    assert_eq!(
+        ErrorCode::InsufficientCapacity.to_string(),
+        "508 Insufficient Capacity"
+    );
+}
+
+#[test]
+fn test_error_code_add_to() -> Result<()> {
+    let mut m = Message::new();
+    m.build(&[Box::new(ErrorCode::AllocationMismatch)])?;
+
+    let mut attr = ErrorCodeAttribute::default();
+    attr.get_from(&m)?;
+    assert_eq!(attr.code.0, 437, "should match");
+    assert_eq!(attr.reason, b"Allocation Mismatch", "should match");
+
+    Ok(())
+}
@@ -18,7 +18,7 @@ pub struct EvenPort {
     // reserve_port means that the server is requested to reserve
     // the next-higher port number (on the same IP address)
     // for a subsequent allocation.
-    reserve_port: bool,
+    pub reserve_port: bool,
 }
 
 impl fmt::Display for EvenPort {
@@ -32,7 +32,8 @@ impl fmt::Display for EvenPort {
 }
 
 const EVEN_PORT_SIZE: usize = 1;
-const FIRST_BIT_SET: u8 = 0b10000000; //FIXME? (1 << 8) - 1;
+// R bit, the most significant bit of the single byte value
+const FIRST_BIT_SET: u8 = 0b10000000;
 
 impl Setter for EvenPort {
     // AddTo adds EVEN-PORT to message.
@@ -54,9 +55,8 @@ impl Getter for EvenPort {
 
         check_size(ATTR_EVEN_PORT, v.len(), EVEN_PORT_SIZE)?;
 
-        if v[0] & FIRST_BIT_SET > 0 {
-            self.reserve_port = true;
-        }
+        // the other 7 bits are RFFU and ignored
+        self.reserve_port = v[0] & FIRST_BIT_SET != 0;
         Ok(())
     }
 }
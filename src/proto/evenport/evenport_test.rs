@@ -72,10 +72,25 @@ fn test_even_port_add_to() -> Result<(), stun::Error> {
                     "IsAttrSizeInvalid should be true"
                 );
             } else {
-                assert!(false, "expected error, but got ok");
+                panic!("expected error, but got ok");
             }
         }
     }
 
     Ok(())
 }
+
+#[test]
+fn test_even_port_get_from_resets_reserve_port() -> Result<(), stun::Error> {
+    let mut m = Message::new();
+    EvenPort {
+        reserve_port: false,
+    }
+    .add_to(&mut m)?;
+
+    let mut port = EvenPort { reserve_port: true };
+    port.get_from(&m)?;
+    assert!(!port.reserve_port, "R bit unset should clear reserve_port");
+
+    Ok(())
+}
@@ -0,0 +1,124 @@
+#[cfg(test)]
+mod framer_test;
+
+use super::chandata::*;
+
+use async_trait::async_trait;
+use std::io;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use util::Conn;
+
+// READ_CHUNK_SIZE is how much TcpFramer reads from the stream at once
+const READ_CHUNK_SIZE: usize = 4096;
+
+// TcpFramer turns a TCP stream into a packet Conn carrying one STUN message or
+// ChannelData message per recv/send, so TURN-over-TCP can share the UDP code path.
+//
+// Over a stream transport ChannelData messages MUST be padded to a multiple of 4
+// bytes, see RFC 5766 Section 11.5. STUN messages are always 4-byte aligned. Each
+// frame is delimited by the length field of its own header. The stream is read
+// in chunks and split into frames by a TcpChannelDataDecoder.
+pub struct TcpFramer {
+    reader: Mutex<FramedReader>,
+    writer: Mutex<OwnedWriteHalf>,
+    local_addr: SocketAddr,
+    remote_addr: SocketAddr,
+}
+
+struct FramedReader {
+    stream: OwnedReadHalf,
+    decoder: TcpChannelDataDecoder,
+}
+
+impl TcpFramer {
+    // creates a new TcpFramer over stream
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        let local_addr = stream.local_addr()?;
+        let remote_addr = stream.peer_addr()?;
+        let (reader, writer) = stream.into_split();
+        Ok(TcpFramer {
+            reader: Mutex::new(FramedReader {
+                stream: reader,
+                decoder: TcpChannelDataDecoder::new(),
+            }),
+            writer: Mutex::new(writer),
+            local_addr,
+            remote_addr,
+        })
+    }
+}
+
+#[async_trait]
+impl Conn for TcpFramer {
+    async fn connect(&self, _addr: SocketAddr) -> util::Result<()> {
+        Err(io::Error::other("Not applicable").into())
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> util::Result<usize> {
+        let mut reader = self.reader.lock().await;
+
+        let frame = loop {
+            if let Some(frame) = reader.decoder.decode_frame() {
+                break frame;
+            }
+
+            let mut chunk = vec![0u8; READ_CHUNK_SIZE];
+            let n = reader.stream.read(&mut chunk).await?;
+            if n == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            reader.decoder.push(&chunk[..n]);
+        };
+
+        if buf.len() < frame.len() {
+            return Err(util::Error::ErrBufferShort);
+        }
+        buf[..frame.len()].copy_from_slice(&frame);
+        Ok(frame.len())
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> util::Result<(usize, SocketAddr)> {
+        let n = self.recv(buf).await?;
+        Ok((n, self.remote_addr))
+    }
+
+    async fn send(&self, buf: &[u8]) -> util::Result<usize> {
+        let mut writer = self.writer.lock().await;
+        if ChannelData::is_channel_data(buf) {
+            let padding = nearest_padded_value_length(buf.len()) - buf.len();
+            if padding > 0 {
+                let mut frame = Vec::with_capacity(buf.len() + padding);
+                frame.extend_from_slice(buf);
+                frame.resize(buf.len() + padding, 0);
+                writer.write_all(&frame).await?;
+                return Ok(buf.len());
+            }
+        }
+
+        writer.write_all(buf).await?;
+        Ok(buf.len())
+    }
+
+    // send_to ignores target, a TCP connection only has one peer
+    async fn send_to(&self, buf: &[u8], _target: SocketAddr) -> util::Result<usize> {
+        self.send(buf).await
+    }
+
+    async fn local_addr(&self) -> util::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    async fn remote_addr(&self) -> Option<SocketAddr> {
+        Some(self.remote_addr)
+    }
+
+    async fn close(&self) -> util::Result<()> {
+        let mut writer = self.writer.lock().await;
+        writer.shutdown().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use super::*;
+use crate::error::Result;
+use crate::proto::channum::*;
+
+use stun::agent::TransactionId;
+use stun::message::*;
+use tokio::net::TcpListener;
+
+async fn framer_pair() -> Result<(TcpFramer, TcpStream)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let client = TcpStream::connect(listener.local_addr()?).await?;
+    let (server, _) = listener.accept().await?;
+    Ok((TcpFramer::new(server)?, client))
+}
+
+fn channel_data(number: u16, data: &[u8]) -> ChannelData {
+    let mut c = ChannelData {
+        number: ChannelNumber(number),
+        data: data.to_vec(),
+        ..Default::default()
+    };
+    c.encode();
+    c
+}
+
+#[tokio::test]
+async fn test_tcp_framer_recv_back_to_back() -> Result<()> {
+    let (framer, mut client) = framer_pair().await?;
+
+    let mut m = Message::new();
+    m.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])?;
+    let c1 = channel_data(MIN_CHANNEL_NUMBER, &[1, 2, 3]);
+    let c2 = channel_data(MIN_CHANNEL_NUMBER + 1, &[4, 5, 6, 7, 8]);
+
+    // everything arrives in a single segment
+    let mut stream = vec![];
+    stream.extend_from_slice(&m.raw);
+    stream.extend_from_slice(&c1.raw);
+    stream.extend_from_slice(&c2.raw);
+    client.write_all(&stream).await?;
+
+    let mut buf = vec![0u8; 1500];
+
+    let n = framer.recv(&mut buf).await?;
+    assert_eq!(&buf[..n], &m.raw[..], "should read the STUN message");
+
+    let n = framer.recv(&mut buf).await?;
+    assert_eq!(n, 7, "padding should be stripped");
+    let mut got = ChannelData {
+        raw: buf[..n].to_vec(),
+        ..Default::default()
+    };
+    got.decode()?;
+    assert_eq!(got, c1, "should read the first ChannelData");
+
+    let (n, addr) = framer.recv_from(&mut buf).await?;
+    assert_eq!(addr, client.local_addr()?, "should report the peer address");
+    let mut got = ChannelData {
+        raw: buf[..n].to_vec(),
+        ..Default::default()
+    };
+    got.decode()?;
+    assert_eq!(got, c2, "should read the second ChannelData");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tcp_framer_send_pads_channel_data() -> Result<()> {
+    let (framer, mut client) = framer_pair().await?;
+
+    // unpadded ChannelData: 4 bytes header + 3 bytes data
+    let unpadded = [0x40, 0x00, 0x00, 0x03, 1, 2, 3];
+    let n = framer.send(&unpadded).await?;
+    assert_eq!(n, unpadded.len(), "should report the payload length");
+    framer.send(&unpadded).await?;
+
+    let mut buf = vec![0u8; 16];
+    client.read_exact(&mut buf).await?;
+    assert_eq!(
+        buf,
+        vec![0x40, 0x00, 0x00, 0x03, 1, 2, 3, 0, 0x40, 0x00, 0x00, 0x03, 1, 2, 3, 0],
+        "each ChannelData should be padded to 4 bytes"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tcp_framer_short_buffer() -> Result<()> {
+    let (framer, mut client) = framer_pair().await?;
+
+    let c = channel_data(MIN_CHANNEL_NUMBER, &[1, 2, 3, 4, 5, 6, 7, 8]);
+    client.write_all(&c.raw).await?;
+
+    let mut buf = vec![0u8; 4];
+    let result = framer.recv(&mut buf).await;
+    assert_eq!(result, Err(util::Error::ErrBufferShort), "should not fit");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tcp_framer_recv_cancelled_mid_frame() -> Result<()> {
+    let (framer, mut client) = framer_pair().await?;
+
+    let c = channel_data(MIN_CHANNEL_NUMBER, &[1, 2, 3, 4, 5, 6, 7, 8]);
+    client.write_all(&c.raw[..6]).await?;
+
+    // e.g. the read loop dropping recv to handle a command
+    let mut buf = vec![0u8; 1500];
+    let result =
+        tokio::time::timeout(std::time::Duration::from_millis(50), framer.recv(&mut buf)).await;
+    assert!(result.is_err(), "frame should be incomplete");
+
+    client.write_all(&c.raw[6..]).await?;
+    let n = framer.recv(&mut buf).await?;
+    assert_eq!(&buf[..n], &c.raw[..], "bytes read before should be kept");
+
+    Ok(())
+}
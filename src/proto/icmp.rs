@@ -0,0 +1,118 @@
+#[cfg(test)]
+mod icmp_test;
+
+use std::fmt;
+use std::net::SocketAddr;
+use stun::attributes::*;
+use stun::checks::*;
+use stun::message::*;
+
+// ATTR_ICMP is the ICMP attribute type, comprehension-optional.
+//
+// RFC 8656 Section 18.13
+pub const ATTR_ICMP: AttrType = AttrType(0x8004);
+
+// ICMP types and codes of a port unreachable error, RFC 792 and RFC 4443.
+pub const ICMP_TYPE_DEST_UNREACHABLE: u8 = 3;
+pub const ICMP_CODE_PORT_UNREACHABLE: u8 = 3;
+pub const ICMPV6_TYPE_DEST_UNREACHABLE: u8 = 1;
+pub const ICMPV6_CODE_PORT_UNREACHABLE: u8 = 4;
+
+// ICMP types and codes of a datagram too big to be sent with the DF bit,
+// RFC 792 and RFC 4443.
+pub const ICMP_CODE_FRAGMENTATION_NEEDED: u8 = 4;
+pub const ICMPV6_TYPE_PACKET_TOO_BIG: u8 = 2;
+
+// Icmp represents ICMP attribute.
+//
+// The server adds it to a Data indication, in place of the DATA attribute,
+// to tell the client about an ICMP error it got for a datagram relayed to
+// the peer in XOR-PEER-ADDRESS.
+//
+// RFC 8656 Section 18.13
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Icmp {
+    pub icmp_type: u8,
+    pub code: u8,
+    pub error_data: u32,
+}
+
+impl Icmp {
+    // port_unreachable returns the ICMP or ICMPv6 port unreachable error,
+    // depending on the address family of peer.
+    pub fn port_unreachable(peer: &SocketAddr) -> Self {
+        if peer.is_ipv4() {
+            Icmp {
+                icmp_type: ICMP_TYPE_DEST_UNREACHABLE,
+                code: ICMP_CODE_PORT_UNREACHABLE,
+                error_data: 0,
+            }
+        } else {
+            Icmp {
+                icmp_type: ICMPV6_TYPE_DEST_UNREACHABLE,
+                code: ICMPV6_CODE_PORT_UNREACHABLE,
+                error_data: 0,
+            }
+        }
+    }
+
+    // packet_too_big returns the ICMP fragmentation needed or ICMPv6 packet
+    // too big error, depending on the address family of peer, for an MTU the
+    // server doesn't know.
+    pub fn packet_too_big(peer: &SocketAddr) -> Self {
+        if peer.is_ipv4() {
+            Icmp {
+                icmp_type: ICMP_TYPE_DEST_UNREACHABLE,
+                code: ICMP_CODE_FRAGMENTATION_NEEDED,
+                error_data: 0,
+            }
+        } else {
+            Icmp {
+                icmp_type: ICMPV6_TYPE_PACKET_TOO_BIG,
+                code: 0,
+                error_data: 0,
+            }
+        }
+    }
+}
+
+impl fmt::Display for Icmp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "type: {}, code: {}, error data: {}",
+            self.icmp_type, self.code, self.error_data
+        )
+    }
+}
+
+// 16 bits of reserved + 8 bits of type + 8 bits of code + 32 bits of error data.
+const ICMP_SIZE: usize = 8;
+
+impl Setter for Icmp {
+    // AddTo adds ICMP to message.
+    fn add_to(&self, m: &mut Message) -> Result<(), stun::Error> {
+        let mut v = vec![0; ICMP_SIZE];
+        // v[0:2] is reserved and MUST be 0.
+        v[2] = self.icmp_type;
+        v[3] = self.code;
+        v[4..].copy_from_slice(&self.error_data.to_be_bytes());
+        m.add(ATTR_ICMP, &v);
+        Ok(())
+    }
+}
+
+impl Getter for Icmp {
+    // GetFrom decodes ICMP from message.
+    fn get_from(&mut self, m: &Message) -> Result<(), stun::Error> {
+        let v = m.get(ATTR_ICMP)?;
+
+        check_size(ATTR_ICMP, v.len(), ICMP_SIZE)?;
+
+        // v[0:2] is reserved and ignored on reception.
+        self.icmp_type = v[2];
+        self.code = v[3];
+        self.error_data = u32::from_be_bytes([v[4], v[5], v[6], v[7]]);
+        Ok(())
+    }
+}
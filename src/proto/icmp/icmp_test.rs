@@ -0,0 +1,89 @@
+use super::*;
+
+#[test]
+fn test_icmp_string() {
+    let i = Icmp {
+        icmp_type: 3,
+        code: 3,
+        error_data: 0,
+    };
+    assert_eq!(i.to_string(), "type: 3, code: 3, error data: 0");
+}
+
+#[test]
+fn test_icmp_port_unreachable() {
+    let i = Icmp::port_unreachable(&"1.2.3.4:5000".parse().unwrap());
+    assert_eq!(i.icmp_type, ICMP_TYPE_DEST_UNREACHABLE);
+    assert_eq!(i.code, ICMP_CODE_PORT_UNREACHABLE);
+
+    let i = Icmp::port_unreachable(&"[2001:db8::1]:5000".parse().unwrap());
+    assert_eq!(i.icmp_type, ICMPV6_TYPE_DEST_UNREACHABLE);
+    assert_eq!(i.code, ICMPV6_CODE_PORT_UNREACHABLE);
+}
+
+#[test]
+fn test_icmp_packet_too_big() {
+    let i = Icmp::packet_too_big(&"1.2.3.4:5000".parse().unwrap());
+    assert_eq!(i.icmp_type, ICMP_TYPE_DEST_UNREACHABLE);
+    assert_eq!(i.code, ICMP_CODE_FRAGMENTATION_NEEDED);
+
+    let i = Icmp::packet_too_big(&"[2001:db8::1]:5000".parse().unwrap());
+    assert_eq!(i.icmp_type, ICMPV6_TYPE_PACKET_TOO_BIG);
+    assert_eq!(i.code, 0);
+}
+
+#[test]
+fn test_icmp_add_to() -> Result<(), stun::Error> {
+    let mut m = Message::new();
+    let i = Icmp {
+        icmp_type: 3,
+        code: 4,
+        error_data: 1400,
+    };
+    i.add_to(&mut m)?;
+    m.write_header();
+
+    let raw = m.get(ATTR_ICMP)?;
+    assert_eq!(
+        raw,
+        vec![0, 0, 3, 4, 0, 0, 0x05, 0x78],
+        "unexpected encoding"
+    );
+
+    //"GetFrom"
+    {
+        let mut decoded = Message::new();
+        decoded.write(&m.raw)?;
+        let mut got = Icmp::default();
+        got.get_from(&decoded)?;
+        assert_eq!(got, i, "Decoded {}, expected {}", got, i);
+
+        //"HandleErr"
+        {
+            let mut m = Message::new();
+            let mut handle = Icmp::default();
+            if let Err(err) = handle.get_from(&m) {
+                assert_eq!(
+                    stun::Error::ErrAttributeNotFound,
+                    err,
+                    "{} should be not found",
+                    err
+                );
+            } else {
+                panic!("expected error, but got ok");
+            }
+
+            m.add(ATTR_ICMP, &[1, 2, 3]);
+            if let Err(err) = handle.get_from(&m) {
+                assert!(
+                    is_attr_size_invalid(&err),
+                    "IsAttrSizeInvalid should be true"
+                );
+            } else {
+                panic!("expected error, but got ok");
+            }
+        }
+    }
+
+    Ok(())
+}
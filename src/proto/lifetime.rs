@@ -36,8 +36,10 @@ const LIFETIME_SIZE: usize = 4; // 4 bytes, 32 bits
 impl Setter for Lifetime {
     // AddTo adds LIFETIME to message.
     fn add_to(&self, m: &mut Message) -> Result<(), stun::Error> {
+        // lifetimes beyond the 32 bit range are capped instead of wrapping around
+        let seconds = self.0.as_secs().min(u32::MAX as u64) as u32;
         let mut v = vec![0; LIFETIME_SIZE];
-        v.copy_from_slice(&(self.0.as_secs() as u32).to_be_bytes());
+        v.copy_from_slice(&seconds.to_be_bytes());
         m.add(ATTR_LIFETIME, &v);
         Ok(())
     }
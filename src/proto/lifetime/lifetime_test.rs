@@ -8,6 +8,22 @@ fn test_lifetime_string() -> Result<(), stun::Error> {
     Ok(())
 }
 
+#[test]
+fn test_lifetime_add_to_overflow() -> Result<(), stun::Error> {
+    let mut m = Message::new();
+    Lifetime(Duration::from_secs(u32::MAX as u64 + 10)).add_to(&mut m)?;
+
+    let mut life = Lifetime::default();
+    life.get_from(&m)?;
+    assert_eq!(
+        life.0,
+        Duration::from_secs(u32::MAX as u64),
+        "should be capped instead of wrapping around"
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_lifetime_add_to() -> Result<(), stun::Error> {
     let mut m = Message::new();
@@ -36,7 +52,7 @@ fn test_lifetime_add_to() -> Result<(), stun::Error> {
                     err
                 );
             } else {
-                assert!(false, "expected error, but got ok");
+                panic!("expected error, but got ok");
             }
             m.add(ATTR_LIFETIME, &[1, 2, 3]);
 
@@ -46,7 +62,7 @@ fn test_lifetime_add_to() -> Result<(), stun::Error> {
                     "IsAttrSizeInvalid should be true"
                 );
             } else {
-                assert!(false, "expected error, but got ok");
+                panic!("expected error, but got ok");
             }
         }
     }
@@ -1,25 +1,71 @@
+//! TURN protocol types: the attributes added by TURN and its extensions on
+//! top of STUN, ChannelData messages and helpers for their message types.
+//!
+//! | Attribute / type | Module | Specification |
+//! |---|---|---|
+//! | CHANNEL-NUMBER | [`channum`] | [RFC 5766 §14.1](https://tools.ietf.org/html/rfc5766#section-14.1) |
+//! | LIFETIME | [`lifetime`] | [RFC 5766 §14.2](https://tools.ietf.org/html/rfc5766#section-14.2) |
+//! | XOR-PEER-ADDRESS | [`peeraddr`] | [RFC 5766 §14.3](https://tools.ietf.org/html/rfc5766#section-14.3) |
+//! | DATA | [`data`] | [RFC 5766 §14.4](https://tools.ietf.org/html/rfc5766#section-14.4) |
+//! | XOR-RELAYED-ADDRESS | [`relayaddr`] | [RFC 5766 §14.5](https://tools.ietf.org/html/rfc5766#section-14.5) |
+//! | EVEN-PORT | [`evenport`] | [RFC 5766 §14.6](https://tools.ietf.org/html/rfc5766#section-14.6) |
+//! | REQUESTED-TRANSPORT | [`reqtrans`] | [RFC 5766 §14.7](https://tools.ietf.org/html/rfc5766#section-14.7) |
+//! | DONT-FRAGMENT | [`dontfrag`] | [RFC 5766 §14.8](https://tools.ietf.org/html/rfc5766#section-14.8) |
+//! | RESERVATION-TOKEN | [`rsrvtoken`] | [RFC 5766 §14.9](https://tools.ietf.org/html/rfc5766#section-14.9) |
+//! | ERROR-CODE values | [`error_code`] | [RFC 5766 §15](https://tools.ietf.org/html/rfc5766#section-15) |
+//! | ChannelData | [`chandata`] | [RFC 5766 §11.4](https://tools.ietf.org/html/rfc5766#section-11.4) |
+//! | REQUESTED-ADDRESS-FAMILY | [`reqfamily`] | [RFC 6156 §4.1.1](https://tools.ietf.org/html/rfc6156#section-4.1.1) |
+//! | ADDITIONAL-ADDRESS-FAMILY | [`addfamily`] | [RFC 8656 §18.11](https://tools.ietf.org/html/rfc8656#section-18.11) |
+//! | ADDRESS-ERROR-CODE | [`addrerror`] | [RFC 8656 §18.12](https://tools.ietf.org/html/rfc8656#section-18.12) |
+//! | TRANSACTION-TRANSMIT-COUNTER | [`trcounter`] | [RFC 7982 §3.2](https://tools.ietf.org/html/rfc7982#section-3.2) |
+//! | ORIGIN | [`origin`] | [RFC 7635 / draft-ietf-tram-stun-origin](https://tools.ietf.org/html/draft-ietf-tram-stun-origin) |
+//! | CONNECTION-ID | [`connid`] | [RFC 6062 §6.2.1](https://tools.ietf.org/html/rfc6062#section-6.2.1) |
+//! | Long-term credentials | [`credential`] | [RFC 5389 §15.4](https://tools.ietf.org/html/rfc5389#section-15.4) |
+//! | NONCE values | [`nonce`] | [RFC 5389 §10.2](https://tools.ietf.org/html/rfc5389#section-10.2) |
+//!
+//! Some semantics are easy to get wrong:
+//!
+//! * EVEN-PORT carries a single R bit. Only when it is set does the server
+//!   reserve the next-higher port and return a RESERVATION-TOKEN for it.
+//! * RESERVATION-TOKEN is exactly 8 bytes and must not be combined with
+//!   EVEN-PORT in one Allocate request.
+//! * DONT-FRAGMENT has no value. A server that can't set the DF bit rejects
+//!   Allocate requests carrying it as an unknown comprehension-required
+//!   attribute.
+
 #[cfg(test)]
 mod proto_test;
 
+pub mod addfamily;
 pub mod addr;
+pub mod addrerror;
+pub mod appid;
+pub mod attrs;
 pub mod chandata;
 pub mod channum;
+pub mod connid;
+pub mod credential;
 pub mod data;
 pub mod dontfrag;
+pub mod error_code;
 pub mod evenport;
+pub mod framer;
+pub mod icmp;
 pub mod lifetime;
+pub mod msgext;
+pub mod nonce;
+pub mod origin;
 pub mod peeraddr;
 pub mod relayaddr;
 pub mod reqfamily;
 pub mod reqtrans;
 pub mod rsrvtoken;
+pub mod trcounter;
 
 use std::fmt;
 
 use stun::message::*;
 
-// proto implements RFC 5766 Traversal Using Relays around NAT.
-
 // protocol is IANA assigned protocol number.
 #[derive(PartialEq, Eq, Default, Debug, Clone, Copy)]
 pub struct Protocol(pub u8);
@@ -0,0 +1,43 @@
+#[cfg(test)]
+mod msgext_test;
+
+use stun::attributes::RawAttribute;
+use stun::message::*;
+
+// MessageExt adds typed access to the attributes of a STUN message, so a
+// handler can write m.get_attr::<Lifetime>() instead of filling a default
+// attribute through Getter::get_from.
+//
+// get_attr and try_get_attr work for attribute types whose Default value
+// knows its attribute type, which holds for all attributes in this module
+// tree. stun's TextAttribute types (USERNAME, REALM, NONCE, SOFTWARE) take
+// it as a field, so they are still read with TextAttribute::get_from_as.
+pub trait MessageExt {
+    // attributes iterates over the raw attributes of the message, in the order
+    // they were encoded
+    fn attributes(&self) -> std::slice::Iter<'_, RawAttribute>;
+
+    // get_attr decodes the first attribute of type T, or returns None if the
+    // message has none or it is malformed
+    fn get_attr<T: Getter + Default>(&self) -> Option<T>;
+
+    // try_get_attr is get_attr that tells a missing attribute
+    // (ErrAttributeNotFound) from a malformed one
+    fn try_get_attr<T: Getter + Default>(&self) -> Result<T, stun::Error>;
+}
+
+impl MessageExt for Message {
+    fn attributes(&self) -> std::slice::Iter<'_, RawAttribute> {
+        self.attributes.0.iter()
+    }
+
+    fn get_attr<T: Getter + Default>(&self) -> Option<T> {
+        self.try_get_attr().ok()
+    }
+
+    fn try_get_attr<T: Getter + Default>(&self) -> Result<T, stun::Error> {
+        let mut attr = T::default();
+        attr.get_from(self)?;
+        Ok(attr)
+    }
+}
@@ -0,0 +1,51 @@
+use super::*;
+use crate::proto::channum::ChannelNumber;
+use crate::proto::lifetime::Lifetime;
+use crate::proto::reqtrans::RequestedTransport;
+use crate::proto::PROTO_UDP;
+use std::time::Duration;
+use stun::agent::TransactionId;
+use stun::attributes::*;
+use stun::checks::is_attr_size_invalid;
+
+#[test]
+fn test_message_ext() -> Result<(), stun::Error> {
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_ALLOCATE, CLASS_REQUEST)),
+        Box::new(RequestedTransport {
+            protocol: PROTO_UDP,
+        }),
+        Box::new(Lifetime(Duration::from_secs(300))),
+    ])?;
+    m.add(ATTR_CHANNEL_NUMBER, &[0x40]);
+
+    let types: Vec<AttrType> = m.attributes().map(|attr| attr.typ).collect();
+    assert_eq!(
+        types,
+        vec![ATTR_REQUESTED_TRANSPORT, ATTR_LIFETIME, ATTR_CHANNEL_NUMBER]
+    );
+
+    assert_eq!(
+        m.get_attr::<Lifetime>(),
+        Some(Lifetime(Duration::from_secs(300)))
+    );
+    assert_eq!(
+        m.get_attr::<RequestedTransport>().map(|t| t.protocol),
+        Some(PROTO_UDP)
+    );
+
+    // missing and malformed attributes
+    assert_eq!(m.get_attr::<crate::proto::data::Data>(), None);
+    assert_eq!(
+        m.try_get_attr::<crate::proto::data::Data>(),
+        Err(stun::Error::ErrAttributeNotFound)
+    );
+    assert_eq!(m.get_attr::<ChannelNumber>(), None);
+    assert!(is_attr_size_invalid(
+        &m.try_get_attr::<ChannelNumber>().unwrap_err()
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+#[cfg(test)]
+mod nonce_test;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use stun::attributes::ATTR_NONCE;
+use stun::message::Message;
+use stun::textattrs::Nonce;
+
+use crate::error::*;
+
+// NONCE_RANDOM_LEN is the number of random bytes in a nonce, 128 bits.
+pub const NONCE_RANDOM_LEN: usize = 16;
+
+// NONCE_PREFIX_SEPARATOR separates the prefix from the random part of a
+// nonce. It is not part of the URL-safe base64 alphabet, so the random part
+// can never be mistaken for a prefix.
+pub const NONCE_PREFIX_SEPARATOR: char = '.';
+
+// MAX_NONCE_LEN is the maximum number of characters of a nonce. RFC 5389
+// Section 15.8 requires fewer than 128.
+pub const MAX_NONCE_LEN: usize = 127;
+
+// is_qdtext reports whether c may appear in a quoted-string, see the qdtext
+// rule of RFC 3261 Section 25.1. Line folding is not accepted.
+fn is_qdtext(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '!' | '\u{23}'..='\u{5b}' | '\u{5d}'..='\u{7e}') || !c.is_ascii()
+}
+
+// validate_nonce checks that nonce is a valid value of the NONCE attribute: 1
+// to MAX_NONCE_LEN characters of the qdtext set, so no double quote,
+// backslash or control character.
+//
+// https://tools.ietf.org/html/rfc5389#section-15.8
+pub fn validate_nonce(nonce: &str) -> Result<()> {
+    let len = nonce.chars().count();
+    if len == 0 || len > MAX_NONCE_LEN || !nonce.chars().all(is_qdtext) {
+        return Err(Error::ErrInvalidNonce);
+    }
+    Ok(())
+}
+
+// get_nonce reads the NONCE attribute of m and fails with ErrInvalidNonce
+// if its value is no valid nonce.
+pub fn get_nonce(m: &Message) -> Result<Nonce> {
+    let nonce = Nonce::get_from_as(m, ATTR_NONCE)?;
+    validate_nonce(&nonce.text)?;
+    Ok(nonce)
+}
+
+// NonceGenerator generates the values of the NONCE attribute a server sends
+// in 401 (Unauthorized) and 438 (Stale Nonce) responses. Every nonce holds
+// 128 bits from the operating system's CSPRNG encoded as URL-safe base64,
+// so nonces can't be predicted by an attacker.
+//
+// A prefix, e.g. a server identifier, can be prepended to tell nonces issued
+// by different servers of a deployment apart, see NonceGenerator::is_own.
+//
+// https://tools.ietf.org/html/rfc5389#section-10.2
+#[derive(Debug, Clone)]
+pub struct NonceGenerator {
+    prefix: Option<String>,
+    rng: SystemRandom,
+}
+
+impl Default for NonceGenerator {
+    fn default() -> Self {
+        NonceGenerator::new()
+    }
+}
+
+impl NonceGenerator {
+    // new creates a NonceGenerator for nonces without prefix.
+    pub fn new() -> Self {
+        NonceGenerator {
+            prefix: None,
+            rng: SystemRandom::new(),
+        }
+    }
+
+    // with_prefix creates a NonceGenerator for nonces starting with prefix
+    // followed by NONCE_PREFIX_SEPARATOR. An empty prefix is the same as none.
+    pub fn with_prefix(prefix: &str) -> Self {
+        NonceGenerator {
+            prefix: if prefix.is_empty() {
+                None
+            } else {
+                Some(prefix.to_owned())
+            },
+            rng: SystemRandom::new(),
+        }
+    }
+
+    // prefix returns the prefix of generated nonces, if any.
+    pub fn prefix(&self) -> Option<&str> {
+        self.prefix.as_deref()
+    }
+
+    // validate checks that the nonces of this generator are valid, which only
+    // depends on the prefix. It must be short enough and may only contain
+    // qdtext characters, see validate_nonce.
+    pub fn validate(&self) -> Result<()> {
+        self.generate().map(|_| ())
+    }
+
+    // generate returns a new nonce.
+    pub fn generate(&self) -> Result<String> {
+        let mut buf = [0u8; NONCE_RANDOM_LEN];
+        self.rng
+            .fill(&mut buf)
+            .map_err(|_| Error::ErrNonceGeneration)?;
+        let random = base64::encode_config(buf, base64::URL_SAFE_NO_PAD);
+
+        let nonce = match &self.prefix {
+            Some(prefix) => format!("{}{}{}", prefix, NONCE_PREFIX_SEPARATOR, random),
+            None => random,
+        };
+        validate_nonce(&nonce)?;
+        Ok(nonce)
+    }
+
+    // is_own reports whether nonce carries the prefix of this generator. It
+    // always holds for a generator without prefix.
+    pub fn is_own(&self, nonce: &str) -> bool {
+        match &self.prefix {
+            Some(prefix) => nonce
+                .strip_prefix(prefix.as_str())
+                .and_then(|rest| rest.strip_prefix(NONCE_PREFIX_SEPARATOR))
+                .is_some(),
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use super::*;
+
+use std::collections::HashSet;
+use stun::message::Setter;
+
+#[test]
+fn test_nonce_generator_unique() -> Result<()> {
+    let generator = NonceGenerator::new();
+    let mut nonces = HashSet::new();
+    for _ in 0..10_000 {
+        let nonce = generator.generate()?;
+        assert!(nonces.insert(nonce), "nonce should be unique");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_nonce_generator_encoding() -> Result<()> {
+    let nonce = NonceGenerator::new().generate()?;
+    let decoded = base64::decode_config(&nonce, base64::URL_SAFE_NO_PAD)
+        .expect("nonce should be URL-safe base64");
+    assert_eq!(decoded.len(), NONCE_RANDOM_LEN, "should hold 128 bits");
+
+    Ok(())
+}
+
+#[test]
+fn test_nonce_generator_prefix() -> Result<()> {
+    let generator = NonceGenerator::with_prefix("turn-1");
+    assert_eq!(generator.prefix(), Some("turn-1"));
+
+    let nonce = generator.generate()?;
+    assert!(nonce.starts_with("turn-1."), "got {}", nonce);
+    assert!(generator.is_own(&nonce));
+
+    let other = NonceGenerator::with_prefix("turn-10").generate()?;
+    assert!(!generator.is_own(&other), "should not own {}", other);
+    assert!(!generator.is_own(&NonceGenerator::new().generate()?));
+    assert!(NonceGenerator::new().is_own(&nonce));
+
+    assert_eq!(NonceGenerator::with_prefix("").prefix(), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_validate_nonce() -> Result<()> {
+    for nonce in [
+        "f//499k954d6OL34oL9FSTvy64sA".to_owned(),
+        "a".to_owned(),
+        "a".repeat(MAX_NONCE_LEN),
+        "with space\tand tab!#[]~".to_owned(),
+        // characters count, not bytes
+        "ü".repeat(MAX_NONCE_LEN),
+        NonceGenerator::new().generate()?,
+    ] {
+        assert!(
+            validate_nonce(&nonce).is_ok(),
+            "{:?} should be valid",
+            nonce
+        );
+    }
+
+    for nonce in [
+        String::new(),
+        "a".repeat(MAX_NONCE_LEN + 1),
+        "quote\"d".to_owned(),
+        "back\\slash".to_owned(),
+        "line\r\nbreak".to_owned(),
+        "nul\0".to_owned(),
+        "del\x7f".to_owned(),
+    ] {
+        assert_eq!(
+            validate_nonce(&nonce),
+            Err(Error::ErrInvalidNonce),
+            "{:?} should be invalid",
+            nonce
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_get_nonce() -> Result<()> {
+    let mut m = Message::new();
+    assert!(get_nonce(&m).is_err(), "missing nonce should fail");
+
+    Nonce::new(ATTR_NONCE, "nonce".to_owned()).add_to(&mut m)?;
+    assert_eq!(get_nonce(&m)?.text, "nonce");
+
+    let mut m = Message::new();
+    Nonce::new(ATTR_NONCE, "bad\"nonce".to_owned()).add_to(&mut m)?;
+    assert_eq!(get_nonce(&m).err(), Some(Error::ErrInvalidNonce));
+
+    Ok(())
+}
+
+#[test]
+fn test_nonce_generator_validate() {
+    assert!(NonceGenerator::new().validate().is_ok());
+    assert!(NonceGenerator::with_prefix("turn-1").validate().is_ok());
+
+    let invalid = NonceGenerator::with_prefix("turn\"1");
+    assert_eq!(invalid.validate(), Err(Error::ErrInvalidNonce));
+    assert_eq!(invalid.generate(), Err(Error::ErrInvalidNonce));
+
+    // the prefix leaves room for the separator and the random part
+    let random_len = base64::encode_config([0u8; NONCE_RANDOM_LEN], base64::URL_SAFE_NO_PAD).len();
+    let max_prefix_len = MAX_NONCE_LEN - 1 - random_len;
+    assert!(NonceGenerator::with_prefix(&"a".repeat(max_prefix_len))
+        .validate()
+        .is_ok());
+    assert_eq!(
+        NonceGenerator::with_prefix(&"a".repeat(max_prefix_len + 1)).validate(),
+        Err(Error::ErrInvalidNonce)
+    );
+}
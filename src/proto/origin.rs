@@ -0,0 +1,42 @@
+#[cfg(test)]
+mod origin_test;
+
+use stun::attributes::*;
+use stun::checks::*;
+use stun::message::*;
+
+use std::fmt;
+
+const MAX_ORIGIN_B: usize = 763;
+
+// Origin represents the ORIGIN attribute browsers add to the STUN and TURN
+// requests of a page, carrying its web origin as defined in RFC 6454.
+// It is comprehension-optional.
+//
+// https://tools.ietf.org/html/draft-ietf-tram-stun-origin-06
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Origin(pub String);
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Setter for Origin {
+    // AddTo adds ORIGIN to message.
+    fn add_to(&self, m: &mut Message) -> Result<(), stun::Error> {
+        check_overflow(ATTR_ORIGIN, self.0.len(), MAX_ORIGIN_B)?;
+        m.add(ATTR_ORIGIN, self.0.as_bytes());
+        Ok(())
+    }
+}
+
+impl Getter for Origin {
+    // GetFrom decodes ORIGIN from message.
+    fn get_from(&mut self, m: &Message) -> Result<(), stun::Error> {
+        let v = m.get(ATTR_ORIGIN)?;
+        self.0 = String::from_utf8(v)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use super::*;
+
+#[test]
+fn test_origin() -> Result<(), stun::Error> {
+    let mut m = Message::new();
+    let o = Origin("https://webrtc.rs".to_owned());
+    o.add_to(&mut m)?;
+    m.write_header();
+
+    //"GetFrom"
+    {
+        let mut decoded = Message::new();
+        decoded.write(&m.raw)?;
+        let mut got = Origin::default();
+        got.get_from(&decoded)?;
+        assert_eq!(got, o, "Decoded {}, expected {}", got, o);
+
+        //"HandleErr"
+        {
+            let m = Message::new();
+            let mut handle = Origin::default();
+            if let Err(err) = handle.get_from(&m) {
+                assert_eq!(
+                    stun::Error::ErrAttributeNotFound,
+                    err,
+                    "{} should be not found",
+                    err
+                );
+            } else {
+                panic!("expected error, but got ok");
+            }
+        }
+    }
+
+    //"Overflow"
+    {
+        let mut m = Message::new();
+        let o = Origin("x".repeat(MAX_ORIGIN_B + 1));
+        assert!(o.add_to(&mut m).is_err(), "should error on overflow");
+    }
+
+    Ok(())
+}
@@ -60,6 +60,17 @@ impl Getter for PeerAddress {
     }
 }
 
+impl PeerAddress {
+    // get_from_raw decodes the XOR-PEER-ADDRESS value v of m. Unlike get_from,
+    // it works for any of several XOR-PEER-ADDRESS attributes in one message.
+    pub(crate) fn get_from_raw(&mut self, m: &Message, v: &[u8]) -> Result<(), stun::Error> {
+        let mut attr = Message::new();
+        attr.transaction_id = m.transaction_id;
+        attr.add(ATTR_XOR_PEER_ADDRESS, v);
+        self.get_from(&attr)
+    }
+}
+
 // XORPeerAddress implements XOR-PEER-ADDRESS attribute.
 //
 // The XOR-PEER-ADDRESS specifies the address and port of the peer as
@@ -1,6 +1,7 @@
 use super::*;
 
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use stun::agent::TransactionId;
 
 #[test]
 fn test_peer_address() -> Result<(), stun::Error> {
@@ -24,3 +25,40 @@ fn test_peer_address() -> Result<(), stun::Error> {
 
     Ok(())
 }
+
+#[test]
+fn test_peer_address_ipv6_encoding() -> Result<(), stun::Error> {
+    // RFC 5389 Section 15.2: an IPv6 X-Address is XORed with the magic cookie
+    // followed by the transaction ID
+    let ip = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+    let a = PeerAddress {
+        ip: IpAddr::V6(ip),
+        port: 3478,
+    };
+
+    let mut m = Message::new();
+    m.transaction_id = TransactionId([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+    a.add_to(&mut m)?;
+    m.write_header();
+
+    let v = m.get(ATTR_XOR_PEER_ADDRESS)?;
+    assert_eq!(v.len(), 20);
+    assert_eq!(v[1], 0x02, "family should be IPv6");
+    assert_eq!(
+        &v[2..4],
+        &(3478 ^ (MAGIC_COOKIE >> 16) as u16).to_be_bytes(),
+        "X-Port"
+    );
+    let mut key = MAGIC_COOKIE.to_be_bytes().to_vec();
+    key.extend_from_slice(&m.transaction_id.0);
+    let x_address: Vec<u8> = ip.octets().iter().zip(&key).map(|(b, k)| b ^ k).collect();
+    assert_eq!(&v[4..], &x_address[..], "X-Address");
+
+    let mut decoded = Message::new();
+    decoded.write(&m.raw)?;
+    let mut got = PeerAddress::default();
+    got.get_from(&decoded)?;
+    assert_eq!(got, a);
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+#[cfg(test)]
+mod trcounter_test;
+
+use std::fmt;
+use stun::attributes::*;
+use stun::checks::*;
+use stun::message::*;
+
+// ATTR_TRANSACTION_TRANSMIT_COUNTER is the TRANSACTION-TRANSMIT-COUNTER
+// attribute type, comprehension-optional.
+//
+// RFC 7982 Section 3.2
+pub const ATTR_TRANSACTION_TRANSMIT_COUNTER: AttrType = AttrType(0x8025);
+
+// TransactionTransmitCounter represents TRANSACTION-TRANSMIT-COUNTER attribute.
+//
+// The client sets req to the number of times the request has been
+// transmitted, and the server echoes it back, setting resp to the number
+// of responses it has sent for that transaction. Clients use the pair to
+// tell which transmission a response belongs to for RTT estimation.
+//
+// RFC 7982 Section 3.2
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TransactionTransmitCounter {
+    pub req: u8,
+    pub resp: u8,
+}
+
+impl fmt::Display for TransactionTransmitCounter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "req: {}, resp: {}", self.req, self.resp)
+    }
+}
+
+// 16 bits of reserved + 8 bits of req + 8 bits of resp.
+const TRANSACTION_TRANSMIT_COUNTER_SIZE: usize = 4;
+
+impl Setter for TransactionTransmitCounter {
+    // AddTo adds TRANSACTION-TRANSMIT-COUNTER to message.
+    fn add_to(&self, m: &mut Message) -> Result<(), stun::Error> {
+        let mut v = vec![0; TRANSACTION_TRANSMIT_COUNTER_SIZE];
+        // v[0:2] is reserved and MUST be 0.
+        v[2] = self.req;
+        v[3] = self.resp;
+        m.add(ATTR_TRANSACTION_TRANSMIT_COUNTER, &v);
+        Ok(())
+    }
+}
+
+impl Getter for TransactionTransmitCounter {
+    // GetFrom decodes TRANSACTION-TRANSMIT-COUNTER from message.
+    fn get_from(&mut self, m: &Message) -> Result<(), stun::Error> {
+        let v = m.get(ATTR_TRANSACTION_TRANSMIT_COUNTER)?;
+
+        check_size(
+            ATTR_TRANSACTION_TRANSMIT_COUNTER,
+            v.len(),
+            TRANSACTION_TRANSMIT_COUNTER_SIZE,
+        )?;
+
+        // v[0:2] is reserved and ignored on reception.
+        self.req = v[2];
+        self.resp = v[3];
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use super::*;
+
+#[test]
+fn test_transaction_transmit_counter_string() {
+    let c = TransactionTransmitCounter { req: 3, resp: 1 };
+    assert_eq!(c.to_string(), "req: 3, resp: 1", "bad string {}", c);
+}
+
+#[test]
+fn test_transaction_transmit_counter_add_to() -> Result<(), stun::Error> {
+    let mut m = Message::new();
+    let c = TransactionTransmitCounter { req: 2, resp: 1 };
+    c.add_to(&mut m)?;
+    m.write_header();
+
+    let raw = m.get(ATTR_TRANSACTION_TRANSMIT_COUNTER)?;
+    assert_eq!(raw, vec![0, 0, 2, 1], "unexpected encoding");
+
+    //"GetFrom"
+    {
+        let mut decoded = Message::new();
+        decoded.write(&m.raw)?;
+        let mut got = TransactionTransmitCounter::default();
+        got.get_from(&decoded)?;
+        assert_eq!(got, c, "Decoded {}, expected {}", got, c);
+
+        //"HandleErr"
+        {
+            let mut m = Message::new();
+            let mut handle = TransactionTransmitCounter::default();
+            if let Err(err) = handle.get_from(&m) {
+                assert_eq!(
+                    stun::Error::ErrAttributeNotFound,
+                    err,
+                    "{} should be not found",
+                    err
+                );
+            } else {
+                panic!("expected error, got ok");
+            }
+
+            m.add(ATTR_TRANSACTION_TRANSMIT_COUNTER, &[1, 2, 3]);
+            if let Err(err) = handle.get_from(&m) {
+                assert!(
+                    is_attr_size_invalid(&err),
+                    "IsAttrSizeInvalid should be true"
+                );
+            } else {
+                panic!("expected error, got ok");
+            }
+        }
+    }
+
+    Ok(())
+}
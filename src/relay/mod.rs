@@ -1,8 +1,13 @@
+pub(crate) mod receiver;
+pub mod relay_dynamic;
 pub mod relay_none;
+pub mod relay_per_user;
 pub mod relay_range;
 pub mod relay_static;
+pub mod socket;
+pub mod udp;
 
-use crate::error::Result;
+use crate::error::*;
 
 use util::Conn;
 
@@ -17,10 +22,55 @@ pub trait RelayAddressGenerator {
     // validate confirms that the RelayAddressGenerator is properly initialized
     fn validate(&self) -> Result<()>;
 
+    // init is awaited once by Server::new, after validate and before any relay is
+    // allocated. Generators that need to look something up first, e.g. the public
+    // IP from a cloud metadata service, can do it here. The default does nothing.
+    async fn init(&mut self) -> Result<()> {
+        Ok(())
+    }
+
     // Allocate a RelayAddress
     async fn allocate_conn(
         &self,
         use_ipv4: bool,
         requested_port: u16,
     ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr)>;
+
+    // Allocate a RelayAddress on behalf of the authenticated username.
+    // Generators that don't care who is asking can rely on the default,
+    // which ignores username and calls allocate_conn.
+    async fn allocate_conn_for_user(
+        &self,
+        use_ipv4: bool,
+        requested_port: u16,
+        _username: &str,
+    ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr)> {
+        self.allocate_conn(use_ipv4, requested_port).await
+    }
+
+    // supports_dont_fragment tells whether send_to_dont_fragment can set the DF
+    // bit. While it returns false, the default, Allocate requests with a
+    // DONT-FRAGMENT attribute are rejected with 420 (Unknown Attribute).
+    fn supports_dont_fragment(&self) -> bool {
+        false
+    }
+
+    // send_to_dont_fragment sends buf from relay_socket, one of the sockets
+    // allocated by this generator, to target with the DF bit set, as requested
+    // by a Send indication with DONT-FRAGMENT, e.g. for path MTU probing. It
+    // should fail with ErrPacketTooBig when the datagram is rejected for its
+    // size, e.g. on EMSGSIZE or an ICMP Packet Too Big.
+    async fn send_to_dont_fragment(
+        &self,
+        _relay_socket: &(dyn Conn + Send + Sync),
+        _buf: &[u8],
+        _target: SocketAddr,
+    ) -> Result<usize> {
+        Err(Error::ErrNoDontFragmentSupport)
+    }
+
+    // type_name names the generator, e.g. in Server::export_config
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
 }
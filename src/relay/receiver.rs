@@ -0,0 +1,199 @@
+#[cfg(test)]
+mod receiver_test;
+
+use crate::allocation::channel_bind::ChannelBind;
+use crate::allocation::five_tuple::FiveTuple;
+use crate::allocation::permission::Permission;
+use crate::allocation::{
+    addr2ipfingerprint, send_icmp_indication, AllocationCounters, AllocationMap, RTP_MTU,
+};
+use crate::proto::chandata::ChannelData;
+use crate::proto::channum::ChannelNumber;
+use crate::proto::data::Data;
+use crate::proto::peeraddr::PeerAddress;
+use crate::relay::socket::IcmpError;
+
+use stun::agent::TransactionId;
+use stun::message::*;
+use util::Conn;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// RelayReceiver forwards the datagrams peers send to one relay socket of an
+// allocation to its client, see RFC 5766 Section 10.3. It runs as a task of
+// its own per relay socket, so relaying to the client never waits on the
+// handling of TURN requests.
+pub(crate) struct RelayReceiver {
+    pub(crate) relay_socket: Arc<dyn Conn + Send + Sync>,
+    pub(crate) relay_addr: SocketAddr,
+    pub(crate) five_tuple: FiveTuple,
+    pub(crate) turn_socket: Arc<dyn Conn + Send + Sync>,
+    // allocations is where the allocation removes itself from when the relay
+    // socket fails
+    pub(crate) allocations: Option<AllocationMap>,
+    pub(crate) channel_bindings: Arc<Mutex<HashMap<ChannelNumber, ChannelBind>>>,
+    pub(crate) permissions: Arc<Mutex<HashMap<String, Permission>>>,
+    pub(crate) counters: Arc<AllocationCounters>,
+    // forward_icmp_errors, when set, tells the client about the ICMP errors
+    // the relay socket reports, see RelaySocket
+    pub(crate) forward_icmp_errors: bool,
+}
+
+impl RelayReceiver {
+    // run receives from the relay socket until it fails, e.g. because the
+    // allocation closed it
+    pub(crate) async fn run(self) {
+        let mut buffer = vec![0u8; RTP_MTU];
+
+        loop {
+            let (n, src_addr) = match self.relay_socket.recv_from(&mut buffer).await {
+                Ok((n, src_addr)) => (n, src_addr),
+                Err(err) => {
+                    if let Some(err) = IcmpError::from_conn_error(&err) {
+                        self.forward_icmp(err).await;
+                        continue;
+                    }
+                    // the allocation closed the socket and removed itself already
+                    if matches!(err, util::Error::ErrUseClosedNetworkConn) {
+                        break;
+                    }
+                    if let Some(allocs) = &self.allocations {
+                        let mut alls = allocs.lock().await;
+                        alls.remove(&self.five_tuple.fingerprint());
+                    }
+                    break;
+                }
+            };
+
+            log::debug!(
+                "relay socket {:?} received {} bytes from {}",
+                self.relay_socket.local_addr().await,
+                n,
+                src_addr
+            );
+
+            self.forward(&buffer[..n], src_addr).await;
+        }
+    }
+
+    // forward relays data received from src_addr to the client, as ChannelData
+    // if a channel is bound to src_addr and as a Data indication otherwise. It
+    // is dropped without a permission for src_addr.
+    async fn forward(&self, data: &[u8], src_addr: SocketAddr) {
+        if let Some(number) = self.channel_number(&src_addr).await {
+            let mut channel_data = ChannelData {
+                data: data.to_vec(),
+                number,
+                raw: vec![],
+            };
+            channel_data.encode();
+
+            match self
+                .turn_socket
+                .send_to(&channel_data.raw, self.five_tuple.src_addr)
+                .await
+            {
+                Ok(_) => self.count_relayed(data),
+                Err(err) => log::error!(
+                    "Failed to send ChannelData from allocation {} {}",
+                    src_addr,
+                    err
+                ),
+            }
+            return;
+        }
+
+        let exist = {
+            let ps = self.permissions.lock().await;
+            ps.get(&addr2ipfingerprint(&src_addr)).is_some()
+        };
+        if !exist {
+            log::info!(
+                "No Permission or Channel exists for {} on allocation {}",
+                src_addr,
+                self.relay_addr
+            );
+            return;
+        }
+
+        let mut msg = Message::new();
+        if let Err(err) = msg.build(&[
+            Box::new(TransactionId::new()),
+            Box::new(MessageType::new(METHOD_DATA, CLASS_INDICATION)),
+            Box::new(PeerAddress {
+                ip: src_addr.ip(),
+                port: src_addr.port(),
+            }),
+            Box::new(Data(data.to_vec())),
+        ]) {
+            log::error!(
+                "Failed to send DataIndication from allocation {} {}",
+                src_addr,
+                err
+            );
+            return;
+        }
+
+        log::debug!(
+            "relaying message from {} to client at {}",
+            src_addr,
+            self.five_tuple.src_addr
+        );
+        match self
+            .turn_socket
+            .send_to(&msg.raw, self.five_tuple.src_addr)
+            .await
+        {
+            Ok(_) => self.count_relayed(data),
+            Err(err) => log::error!(
+                "Failed to send DataIndication from allocation {} {}",
+                src_addr,
+                err
+            ),
+        }
+    }
+
+    // forward_icmp tells the client about err, if forward_icmp_errors is set
+    // and there is a permission for the peer, RFC 8656 Section 11.5
+    async fn forward_icmp(&self, err: IcmpError) {
+        log::debug!(
+            "relay socket {} got an ICMP error for {}: {}",
+            self.relay_addr,
+            err.peer,
+            err.icmp
+        );
+        if !self.forward_icmp_errors {
+            return;
+        }
+
+        let exist = {
+            let ps = self.permissions.lock().await;
+            ps.get(&addr2ipfingerprint(&err.peer)).is_some()
+        };
+        if exist {
+            send_icmp_indication(
+                self.turn_socket.as_ref(),
+                self.five_tuple.src_addr,
+                &err.peer,
+                err.icmp,
+            )
+            .await;
+        }
+    }
+
+    fn count_relayed(&self, data: &[u8]) {
+        self.counters
+            .bytes_relayed_in
+            .fetch_add(data.len() as u64, Ordering::Relaxed);
+    }
+
+    // channel_number returns the channel bound to peer, if any
+    async fn channel_number(&self, peer: &SocketAddr) -> Option<ChannelNumber> {
+        let cbs = self.channel_bindings.lock().await;
+        cbs.values().find(|cb| cb.peer == *peer).map(|cb| cb.number)
+    }
+}
@@ -0,0 +1,116 @@
+use super::*;
+use crate::error::Result;
+use crate::proto::channum::MIN_CHANNEL_NUMBER;
+
+use tokio::net::UdpSocket;
+use tokio::time::Duration;
+
+struct TestReceiver {
+    client: UdpSocket,
+    peer: UdpSocket,
+    relay_addr: SocketAddr,
+    channel_bindings: Arc<Mutex<HashMap<ChannelNumber, ChannelBind>>>,
+    permissions: Arc<Mutex<HashMap<String, Permission>>>,
+}
+
+async fn spawn_receiver() -> Result<TestReceiver> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let peer = UdpSocket::bind("127.0.0.1:0").await?;
+    let turn_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let relay_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let relay_addr = relay_socket.local_addr()?;
+    let channel_bindings = Arc::new(Mutex::new(HashMap::new()));
+    let permissions = Arc::new(Mutex::new(HashMap::new()));
+
+    let receiver = RelayReceiver {
+        relay_socket,
+        relay_addr,
+        five_tuple: FiveTuple {
+            src_addr: client.local_addr()?,
+            ..Default::default()
+        },
+        turn_socket,
+        allocations: None,
+        channel_bindings: Arc::clone(&channel_bindings),
+        permissions: Arc::clone(&permissions),
+        counters: Arc::new(AllocationCounters::default()),
+        forward_icmp_errors: false,
+    };
+    tokio::spawn(receiver.run());
+
+    Ok(TestReceiver {
+        client,
+        peer,
+        relay_addr,
+        channel_bindings,
+        permissions,
+    })
+}
+
+async fn recv(client: &UdpSocket) -> Option<Vec<u8>> {
+    let mut buf = vec![0u8; RTP_MTU];
+    match tokio::time::timeout(Duration::from_millis(100), client.recv_from(&mut buf)).await {
+        Ok(Ok((n, _))) => Some(buf[..n].to_vec()),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn test_relay_receiver_data_indication() -> Result<()> {
+    let t = spawn_receiver().await?;
+    let peer_addr = t.peer.local_addr()?;
+
+    // dropped without a permission
+    t.peer.send_to(b"dropped", t.relay_addr).await?;
+    assert!(recv(&t.client).await.is_none());
+
+    t.permissions
+        .lock()
+        .await
+        .insert(addr2ipfingerprint(&peer_addr), Permission::new(peer_addr));
+    t.peer.send_to(b"hello", t.relay_addr).await?;
+    let raw = recv(&t.client)
+        .await
+        .expect("should relay a Data indication");
+
+    let mut msg = Message::new();
+    msg.write(&raw)?;
+    assert_eq!(msg.typ, MessageType::new(METHOD_DATA, CLASS_INDICATION));
+    let mut peer_attr = PeerAddress::default();
+    peer_attr.get_from(&msg)?;
+    assert_eq!(SocketAddr::new(peer_attr.ip, peer_attr.port), peer_addr);
+    let mut data = Data::default();
+    data.get_from(&msg)?;
+    assert_eq!(data.0, b"hello");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_relay_receiver_channel_data() -> Result<()> {
+    let t = spawn_receiver().await?;
+    let peer_addr = t.peer.local_addr()?;
+
+    let number = ChannelNumber(MIN_CHANNEL_NUMBER);
+    t.channel_bindings
+        .lock()
+        .await
+        .insert(number, ChannelBind::new(number, peer_addr));
+    t.peer.send_to(b"hello", t.relay_addr).await?;
+    let raw = recv(&t.client).await.expect("should relay ChannelData");
+
+    let mut channel_data = ChannelData {
+        raw,
+        ..Default::default()
+    };
+    channel_data.decode()?;
+    assert_eq!(channel_data.number, number);
+    assert_eq!(channel_data.data, b"hello");
+
+    // a peer without a channel still needs a permission
+    let other = UdpSocket::bind("127.0.0.1:0").await?;
+    other.send_to(b"dropped", t.relay_addr).await?;
+    assert!(recv(&t.client).await.is_none());
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use super::*;
+use crate::error::*;
+
+use async_trait::async_trait;
+use std::net::IpAddr;
+use util::vnet::net::*;
+
+// RelayGeneratorFn picks the relay IP for the given username, or returns None to reject the allocation.
+pub type RelayGeneratorFn = Box<dyn (Fn(&str) -> Option<IpAddr>) + Send + Sync>;
+
+// RelayAddressGeneratorDynamic returns the IP address picked by a user-provided closure each time
+// a relay is created. This can be used when the relay address depends on custom logic, e.g. asking
+// a load balancer or choosing based on who is allocating.
+pub struct RelayAddressGeneratorDynamic {
+    // generator is called with the requesting username and returns the RelayAddress for it
+    pub generator: RelayGeneratorFn,
+
+    // Address is passed to Listen/ListenPacket when creating the Relay
+    pub address: String,
+
+    pub net: Arc<Net>,
+}
+
+#[async_trait]
+impl RelayAddressGenerator for RelayAddressGeneratorDynamic {
+    // validate confirms that the RelayAddressGenerator is properly initialized
+    fn validate(&self) -> Result<()> {
+        if self.address.is_empty() {
+            Err(Error::ErrListeningAddressInvalid)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Allocate a PacketConn (UDP) RelayAddress for an anonymous user
+    async fn allocate_conn(
+        &self,
+        use_ipv4: bool,
+        requested_port: u16,
+    ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr)> {
+        self.allocate_conn_for_user(use_ipv4, requested_port, "")
+            .await
+    }
+
+    // Allocate a PacketConn (UDP) RelayAddress picked by generator for username
+    async fn allocate_conn_for_user(
+        &self,
+        use_ipv4: bool,
+        requested_port: u16,
+        username: &str,
+    ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr)> {
+        let relay_address = (self.generator)(username).ok_or(Error::ErrNoRelayAddressForUser)?;
+
+        let addr = self
+            .net
+            .resolve_addr(use_ipv4, &format!("{}:{}", self.address, requested_port))
+            .await?;
+        let conn = self.net.bind(addr).await?;
+        let mut relay_addr = conn.local_addr().await?;
+        relay_addr.set_ip(relay_address);
+        Ok((conn, relay_addr))
+    }
+}
@@ -0,0 +1,51 @@
+use super::*;
+use crate::error::*;
+
+use async_trait::async_trait;
+
+// RelayGeneratorFactory picks the RelayAddressGenerator for an allocation of the given username
+pub type RelayGeneratorFactory =
+    Box<dyn (Fn(&str) -> Arc<dyn RelayAddressGenerator + Send + Sync>) + Send + Sync>;
+
+// RelayAddressGeneratorPerUser lets the relay address depend on who asks for
+// it, e.g. to give premium users dedicated relay IPs. For every allocation it
+// asks factory for the generator of the authenticated username and delegates
+// to it. Relays allocated without a username, such as the probes for a random
+// even port, are asked for with an empty username.
+//
+// Server::new only calls init on this generator, so the generators returned
+// by factory must be ready to use.
+pub struct RelayAddressGeneratorPerUser {
+    pub factory: RelayGeneratorFactory,
+}
+
+#[async_trait]
+impl RelayAddressGenerator for RelayAddressGeneratorPerUser {
+    // validate confirms that the RelayAddressGenerator is properly initialized
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    // Allocate a RelayAddress from the generator for the empty username
+    async fn allocate_conn(
+        &self,
+        use_ipv4: bool,
+        requested_port: u16,
+    ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr)> {
+        self.allocate_conn_for_user(use_ipv4, requested_port, "")
+            .await
+    }
+
+    // Allocate a RelayAddress from the generator for username
+    async fn allocate_conn_for_user(
+        &self,
+        use_ipv4: bool,
+        requested_port: u16,
+        username: &str,
+    ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr)> {
+        let generator = (self.factory)(username);
+        generator
+            .allocate_conn_for_user(use_ipv4, requested_port, username)
+            .await
+    }
+}
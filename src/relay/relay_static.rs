@@ -38,9 +38,25 @@ impl RelayAddressGenerator for RelayAddressGeneratorStatic {
             .net
             .resolve_addr(use_ipv4, &format!("{}:{}", self.address, requested_port))
             .await?;
-        let conn = self.net.bind(addr).await?;
+        let conn = socket::bind(&self.net, addr).await?;
         let mut relay_addr = conn.local_addr().await?;
         relay_addr.set_ip(self.relay_address);
         return Ok((conn, relay_addr));
     }
+
+    // supports_dont_fragment is true on Linux unless net is virtual, as the
+    // relay sockets are RelaySockets then
+    fn supports_dont_fragment(&self) -> bool {
+        cfg!(target_os = "linux") && !self.net.is_virtual()
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn send_to_dont_fragment(
+        &self,
+        relay_socket: &(dyn Conn + Send + Sync),
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Result<usize> {
+        socket::send_to_dont_fragment(relay_socket, buf, target).await
+    }
 }
@@ -0,0 +1,366 @@
+#[cfg(test)]
+mod socket_test;
+
+use crate::error::*;
+use crate::proto::icmp::Icmp;
+
+use async_trait::async_trait;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+use util::vnet::net::*;
+use util::Conn;
+
+// RelaySocket is the relay Conn the built-in generators hand out on real
+// networks. An unconnected UDP socket doesn't hear about the ICMP errors its
+// datagrams cause, so on Linux RelaySocket turns on IP_RECVERR and reads them
+// from the error queue of the socket: recv_from fails with an IcmpError for
+// each of them and can be called again afterwards. On Linux it can also send
+// with the DF bit set, see send_to_dont_fragment. Elsewhere it is a bare
+// UdpSocket.
+//
+// Unlike a bare UdpSocket, close makes a pending recv_from fail, so the relay
+// receiver of a closed allocation lets go of the socket.
+pub struct RelaySocket {
+    socket: UdpSocket,
+    closed: watch::Sender<bool>,
+    // send_lock keeps other datagrams from being sent while the DF bit is
+    // turned on for one
+    #[cfg(target_os = "linux")]
+    send_lock: std::sync::Mutex<()>,
+    // pmtu_discover is the IP_MTU_DISCOVER of an IPv4 socket, restored after
+    // each datagram sent with the DF bit, and None for an IPv6 socket
+    #[cfg(target_os = "linux")]
+    pmtu_discover: Option<nix::libc::c_int>,
+}
+
+tokio::task_local! {
+    // DONT_FRAGMENT is set while send_to_dont_fragment sends through a Conn,
+    // which can't be handed anything but the datagram and its target
+    static DONT_FRAGMENT: bool;
+}
+
+// IcmpError is the error RelaySocket::recv_from fails with for an ICMP error
+// a datagram sent to peer caused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcmpError {
+    pub peer: SocketAddr,
+    pub icmp: Icmp,
+}
+
+impl IcmpError {
+    // from_conn_error returns the IcmpError err carries, if any
+    pub fn from_conn_error(err: &util::Error) -> Option<IcmpError> {
+        match err {
+            util::Error::Io(err) => err.0.get_ref()?.downcast_ref::<IcmpError>().copied(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for IcmpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ICMP error for {} ({})", self.peer, self.icmp)
+    }
+}
+
+impl std::error::Error for IcmpError {}
+
+impl RelaySocket {
+    // new wraps socket, which must not be connected
+    pub fn new(socket: UdpSocket) -> Result<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            use nix::sys::socket::{setsockopt, sockopt};
+            use std::os::unix::io::AsRawFd;
+
+            let fd = socket.as_raw_fd();
+            let pmtu_discover = if socket.local_addr()?.is_ipv4() {
+                setsockopt(fd, sockopt::Ipv4RecvErr, &true).map_err(io::Error::from)?;
+                Some(ip_mtu_discover(fd)?)
+            } else {
+                setsockopt(fd, sockopt::Ipv6RecvErr, &true).map_err(io::Error::from)?;
+                None
+            };
+
+            let (closed, _) = watch::channel(false);
+            Ok(RelaySocket {
+                socket,
+                closed,
+                send_lock: std::sync::Mutex::new(()),
+                pmtu_discover,
+            })
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let (closed, _) = watch::channel(false);
+            Ok(RelaySocket { socket, closed })
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn recv_from_socket(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        use tokio::io::Interest;
+
+        loop {
+            tokio::select! {
+                result = self.socket.recv_from(buf) => match result {
+                    // the kernel fails the next read for a queued error once
+                    Err(err) if is_queued_error(&err) => match recv_icmp_error(&self.socket) {
+                        Ok(Some(err)) => return Err(io::Error::other(err)),
+                        Ok(None) => continue,
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                        Err(err) => return Err(err),
+                    },
+                    result => return result,
+                },
+                result = self.socket.async_io(Interest::ERROR, || recv_icmp_error(&self.socket)) => {
+                    if let Some(err) = result? {
+                        return Err(io::Error::other(err));
+                    }
+                },
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn recv_from_socket(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.socket.recv_from(buf).await
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn send_to_socket(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let dont_fragment = DONT_FRAGMENT.try_with(|df| *df).unwrap_or(false);
+        let mut retried = false;
+
+        loop {
+            self.socket.writable().await?;
+
+            let result = {
+                let _guard = self.send_lock.lock().unwrap_or_else(|e| e.into_inner());
+                if dont_fragment {
+                    self.set_dont_fragment(true)?;
+                    let result = self.socket.try_send_to(buf, target);
+                    self.set_dont_fragment(false)?;
+                    result
+                } else {
+                    self.socket.try_send_to(buf, target)
+                }
+            };
+
+            match result {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                // a queued ICMP error fails the next send once, without sending
+                Err(err) if is_queued_error(&err) && !retried => retried = true,
+                result => return result,
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn send_to_socket(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        self.socket.send_to(buf, target).await
+    }
+
+    // set_dont_fragment turns the DF bit on or back to what it was
+    #[cfg(target_os = "linux")]
+    fn set_dont_fragment(&self, dont_fragment: bool) -> io::Result<()> {
+        use nix::libc::IP_PMTUDISC_DO;
+        use nix::sys::socket::{setsockopt, sockopt};
+        use std::os::unix::io::AsRawFd;
+
+        let fd = self.socket.as_raw_fd();
+        match self.pmtu_discover {
+            Some(_) if dont_fragment => set_ip_mtu_discover(fd, IP_PMTUDISC_DO),
+            Some(pmtu_discover) => set_ip_mtu_discover(fd, pmtu_discover),
+            None => setsockopt(fd, sockopt::Ipv6DontFrag, &dont_fragment).map_err(io::Error::from),
+        }
+    }
+}
+
+// send_to_dont_fragment sends buf from relay_socket to target with the DF bit
+// set. It fails with ErrPacketTooBig when the datagram is too big for that.
+// relay_socket must be a RelaySocket, any other Conn sends as usual.
+#[cfg(target_os = "linux")]
+pub(crate) async fn send_to_dont_fragment(
+    relay_socket: &(dyn Conn + Send + Sync),
+    buf: &[u8],
+    target: SocketAddr,
+) -> Result<usize> {
+    use nix::libc::EMSGSIZE;
+
+    match DONT_FRAGMENT
+        .scope(true, relay_socket.send_to(buf, target))
+        .await
+    {
+        Err(util::Error::Io(err)) if err.0.raw_os_error() == Some(EMSGSIZE) => {
+            Err(Error::ErrPacketTooBig)
+        }
+        result => Ok(result?),
+    }
+}
+
+// bind binds a relay socket to addr on net, a RelaySocket unless net is
+// virtual
+pub(crate) async fn bind(net: &Net, addr: SocketAddr) -> Result<Arc<dyn Conn + Send + Sync>> {
+    if net.is_virtual() {
+        return Ok(net.bind(addr).await?);
+    }
+    Ok(Arc::new(RelaySocket::new(UdpSocket::bind(addr).await?)?))
+}
+
+// is_queued_error reports whether err is one the kernel reports for an ICMP
+// error it queued, see ip_icmp_error and icmp_err_convert
+#[cfg(target_os = "linux")]
+fn is_queued_error(err: &io::Error) -> bool {
+    use nix::errno::Errno;
+
+    matches!(
+        err.raw_os_error().map(Errno::from_i32),
+        Some(
+            Errno::ECONNREFUSED
+                | Errno::EHOSTUNREACH
+                | Errno::ENETUNREACH
+                | Errno::EHOSTDOWN
+                | Errno::ENONET
+                | Errno::ENOPROTOOPT
+                | Errno::EOPNOTSUPP
+                | Errno::EPROTO
+                | Errno::EACCES
+                | Errno::EMSGSIZE
+        )
+    )
+}
+
+// recv_icmp_error reads the next error of the error queue of socket. Errors
+// that don't come from an ICMP message, e.g. a local EMSGSIZE, are read as
+// None.
+#[cfg(target_os = "linux")]
+fn recv_icmp_error(socket: &UdpSocket) -> io::Result<Option<IcmpError>> {
+    use nix::libc::{sock_extended_err, sockaddr_in6, SO_EE_ORIGIN_ICMP, SO_EE_ORIGIN_ICMP6};
+    use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags, SockaddrStorage};
+    use std::io::IoSliceMut;
+    use std::os::unix::io::AsRawFd;
+
+    let mut cmsg_buffer = nix::cmsg_space!(sock_extended_err, sockaddr_in6);
+    let mut iov = [IoSliceMut::new(&mut [])];
+    let msg = recvmsg::<SockaddrStorage>(
+        socket.as_raw_fd(),
+        &mut iov,
+        Some(&mut cmsg_buffer),
+        MsgFlags::MSG_ERRQUEUE | MsgFlags::MSG_DONTWAIT,
+    )
+    .map_err(io::Error::from)?;
+
+    // the address of an error is where the datagram that caused it went
+    let peer = match msg.address.as_ref().and_then(super::udp::socket_addr) {
+        Some(peer) => peer,
+        None => return Ok(None),
+    };
+    for cmsg in msg.cmsgs() {
+        if let ControlMessageOwned::Ipv4RecvErr(err, _) | ControlMessageOwned::Ipv6RecvErr(err, _) =
+            cmsg
+        {
+            if err.ee_origin != SO_EE_ORIGIN_ICMP && err.ee_origin != SO_EE_ORIGIN_ICMP6 {
+                return Ok(None);
+            }
+            return Ok(Some(IcmpError {
+                peer,
+                icmp: Icmp {
+                    icmp_type: err.ee_type,
+                    code: err.ee_code,
+                    error_data: err.ee_info,
+                },
+            }));
+        }
+    }
+    Ok(None)
+}
+
+// ip_mtu_discover gets IP_MTU_DISCOVER, which nix has no sockopt for
+#[cfg(target_os = "linux")]
+fn ip_mtu_discover(fd: std::os::unix::io::RawFd) -> io::Result<nix::libc::c_int> {
+    use nix::libc::{c_int, getsockopt, socklen_t, IPPROTO_IP, IP_MTU_DISCOVER};
+
+    let mut value: c_int = 0;
+    let mut len = std::mem::size_of::<c_int>() as socklen_t;
+    // SAFETY: value and len outlive the call and len is the size of value
+    let ret = unsafe {
+        getsockopt(
+            fd,
+            IPPROTO_IP,
+            IP_MTU_DISCOVER,
+            &mut value as *mut c_int as *mut _,
+            &mut len,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+// set_ip_mtu_discover sets IP_MTU_DISCOVER, see ip_mtu_discover
+#[cfg(target_os = "linux")]
+fn set_ip_mtu_discover(fd: std::os::unix::io::RawFd, value: nix::libc::c_int) -> io::Result<()> {
+    use nix::libc::{c_int, setsockopt, socklen_t, IPPROTO_IP, IP_MTU_DISCOVER};
+
+    // SAFETY: value outlives the call and the size passed is its size
+    let ret = unsafe {
+        setsockopt(
+            fd,
+            IPPROTO_IP,
+            IP_MTU_DISCOVER,
+            &value as *const c_int as *const _,
+            std::mem::size_of::<c_int>() as socklen_t,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Conn for RelaySocket {
+    async fn connect(&self, addr: SocketAddr) -> util::Result<()> {
+        Ok(self.socket.connect(addr).await?)
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> util::Result<usize> {
+        Ok(self.socket.recv(buf).await?)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> util::Result<(usize, SocketAddr)> {
+        let mut closed = self.closed.subscribe();
+        tokio::select! {
+            _ = closed.wait_for(|closed| *closed) => Err(util::Error::ErrUseClosedNetworkConn),
+            result = self.recv_from_socket(buf) => Ok(result?),
+        }
+    }
+
+    async fn send(&self, buf: &[u8]) -> util::Result<usize> {
+        Ok(self.socket.send(buf).await?)
+    }
+
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> util::Result<usize> {
+        Ok(self.send_to_socket(buf, target).await?)
+    }
+
+    async fn local_addr(&self) -> util::Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    async fn remote_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    async fn close(&self) -> util::Result<()> {
+        self.closed.send_replace(true);
+        Ok(())
+    }
+}
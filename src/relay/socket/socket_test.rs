@@ -0,0 +1,116 @@
+use super::*;
+use crate::proto::icmp::*;
+
+use tokio::time::Duration;
+
+// closed_port returns a local address nothing listens on
+async fn closed_port() -> Result<SocketAddr> {
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    Ok(socket.local_addr()?)
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_relay_socket_icmp_error() -> Result<()> {
+    let relay = RelaySocket::new(UdpSocket::bind("127.0.0.1:0").await?)?;
+    let peer = UdpSocket::bind("127.0.0.1:0").await?;
+    let closed = closed_port().await?;
+
+    relay.send_to(b"hello", closed).await?;
+
+    let mut buf = vec![0u8; 1500];
+    let err = match tokio::time::timeout(Duration::from_secs(1), relay.recv_from(&mut buf)).await {
+        Ok(Err(err)) => err,
+        Ok(Ok((_, from))) => panic!("expected an ICMP error, got a datagram from {}", from),
+        Err(_) => panic!("expected an ICMP error"),
+    };
+    assert_eq!(
+        IcmpError::from_conn_error(&err),
+        Some(IcmpError {
+            peer: closed,
+            icmp: Icmp::port_unreachable(&closed),
+        })
+    );
+
+    // the socket keeps working after the error
+    relay.send_to(b"again", peer.local_addr()?).await?;
+    let (n, _) = peer.recv_from(&mut buf).await?;
+    assert_eq!(&buf[..n], b"again");
+
+    peer.send_to(b"reply", relay.local_addr().await?).await?;
+    let (n, from) = relay.recv_from(&mut buf).await?;
+    assert_eq!(&buf[..n], b"reply");
+    assert_eq!(from, peer.local_addr()?);
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_relay_socket_dont_fragment() -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let relay = RelaySocket::new(UdpSocket::bind("127.0.0.1:0").await?)?;
+    let peer = UdpSocket::bind("127.0.0.1:0").await?;
+    let pmtu_discover = ip_mtu_discover(relay.socket.as_raw_fd())?;
+
+    let n = send_to_dont_fragment(&relay, b"probe", peer.local_addr()?).await?;
+    assert_eq!(n, 5);
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = peer.recv_from(&mut buf).await?;
+    assert_eq!(&buf[..n], b"probe");
+
+    assert_eq!(
+        ip_mtu_discover(relay.socket.as_raw_fd())?,
+        pmtu_discover,
+        "the DF bit should only be set for the probe"
+    );
+
+    Ok(())
+}
+
+// test_relay_socket_dont_fragment_too_big sends a datagram bigger than the
+// MTU of loopback, which IPv6 fragments unless the DF bit is set
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_relay_socket_dont_fragment_too_big() -> Result<()> {
+    let relay = RelaySocket::new(UdpSocket::bind("[::1]:0").await?)?;
+    let peer = UdpSocket::bind("[::1]:0").await?;
+    let data = vec![0u8; 65500];
+
+    let result = send_to_dont_fragment(&relay, &data, peer.local_addr()?).await;
+    assert!(
+        matches!(result, Err(Error::ErrPacketTooBig)),
+        "expected ErrPacketTooBig, got {:?}",
+        result
+    );
+
+    let n = relay.send_to(&data, peer.local_addr()?).await?;
+    assert_eq!(n, data.len());
+    let mut buf = vec![0u8; 65536];
+    let (n, _) = peer.recv_from(&mut buf).await?;
+    assert_eq!(n, data.len());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_relay_socket_close() -> Result<()> {
+    let relay = Arc::new(RelaySocket::new(UdpSocket::bind("127.0.0.1:0").await?)?);
+
+    let reader = Arc::clone(&relay);
+    let pending = tokio::spawn(async move {
+        let mut buf = vec![0u8; 1500];
+        reader.recv_from(&mut buf).await
+    });
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    relay.close().await?;
+    let result = tokio::time::timeout(Duration::from_secs(1), pending)
+        .await
+        .expect("close should end a pending recv_from")
+        .expect("recv_from task panicked");
+    assert!(matches!(result, Err(util::Error::ErrUseClosedNetworkConn)));
+
+    Ok(())
+}
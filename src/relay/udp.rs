@@ -0,0 +1,341 @@
+#[cfg(test)]
+mod udp_test;
+
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{Duration, Instant};
+use util::Conn;
+
+// BATCH_QUEUE_SIZE is how many datagrams wait for the send task before
+// send_to waits too
+const BATCH_QUEUE_SIZE: usize = 1024;
+
+// DEFAULT_RECV_BATCH_SIZE is the number of datagrams UdpBatchConn reads at
+// most per recvmmsg call unless told otherwise
+pub const DEFAULT_RECV_BATCH_SIZE: usize = 32;
+
+// RECV_BUFFER_SIZE fits the largest UDP payload, so the read loop still sees
+// oversized datagrams as such
+const RECV_BUFFER_SIZE: usize = 65536;
+
+type Datagram = (Vec<u8>, SocketAddr);
+
+// UdpRelayForwarder is a relay Conn that sends datagrams to peers in batches.
+// send_to only queues a datagram. A background task sends the queue once
+// batch_size datagrams are waiting or the oldest one has waited batch_timeout,
+// whichever comes first. On Linux a batch is sent with a single sendmmsg call,
+// elsewhere one datagram at a time. Receiving goes to the socket directly.
+//
+// A RelayAddressGenerator can hand it out in place of the bare socket. As
+// send_to returns before the datagram is sent, send errors, e.g. ICMP port
+// unreachable from a peer, are only logged.
+pub struct UdpRelayForwarder {
+    socket: Arc<UdpSocket>,
+    tx: std::sync::Mutex<Option<mpsc::Sender<Datagram>>>,
+}
+
+impl UdpRelayForwarder {
+    // new starts the send task of socket, which must be called from within a
+    // tokio runtime. A batch_size of 0 or 1 sends every datagram on its own.
+    pub fn new(socket: Arc<UdpSocket>, batch_size: usize, batch_timeout: Duration) -> Self {
+        let (tx, rx) = mpsc::channel(BATCH_QUEUE_SIZE);
+        tokio::spawn(UdpRelayForwarder::run(
+            Arc::clone(&socket),
+            rx,
+            batch_size.max(1),
+            batch_timeout,
+        ));
+
+        UdpRelayForwarder {
+            socket,
+            tx: std::sync::Mutex::new(Some(tx)),
+        }
+    }
+
+    // run sends the queued datagrams in batches until the forwarder is closed
+    // and the queue is drained
+    async fn run(
+        socket: Arc<UdpSocket>,
+        mut rx: mpsc::Receiver<Datagram>,
+        batch_size: usize,
+        batch_timeout: Duration,
+    ) {
+        let mut batch = Vec::with_capacity(batch_size);
+
+        while let Some(datagram) = rx.recv().await {
+            batch.push(datagram);
+
+            let deadline = Instant::now() + batch_timeout;
+            while batch.len() < batch_size {
+                match tokio::time::timeout_at(deadline, rx.recv()).await {
+                    Ok(Some(datagram)) => batch.push(datagram),
+                    Ok(None) | Err(_) => break,
+                }
+            }
+
+            send_batch(&socket, &batch).await;
+            batch.clear();
+        }
+    }
+}
+
+// send_batch sends batch with as few sendmmsg calls as the socket buffer
+// allows. A datagram that fails is logged and skipped.
+#[cfg(target_os = "linux")]
+async fn send_batch(socket: &UdpSocket, batch: &[Datagram]) {
+    use nix::sys::socket::{sendmmsg, ControlMessage, MsgFlags, SendMmsgData, SockaddrStorage};
+    use std::io::{self, IoSlice};
+    use std::marker::PhantomData;
+    use std::os::unix::io::AsRawFd;
+    use tokio::io::Interest;
+
+    let mut sent = 0;
+    while sent < batch.len() {
+        if let Err(err) = socket.writable().await {
+            log::debug!(
+                "relay socket failed, dropping {} datagrams: {}",
+                batch.len() - sent,
+                err
+            );
+            return;
+        }
+
+        let result = socket.try_io(Interest::WRITABLE, || {
+            let data: Vec<SendMmsgData<'_, [IoSlice<'_>; 1], [ControlMessage<'_>; 0], _>> = batch
+                [sent..]
+                .iter()
+                .map(|(buf, target)| SendMmsgData {
+                    iov: [IoSlice::new(buf)],
+                    cmsgs: [],
+                    addr: Some(SockaddrStorage::from(*target)),
+                    _lt: PhantomData,
+                })
+                .collect();
+            sendmmsg(socket.as_raw_fd(), &data, MsgFlags::empty()).map_err(io::Error::from)
+        });
+
+        match result {
+            Ok(lens) => sent += lens.len(),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            Err(err) => {
+                log::debug!("failed to relay datagram to {}: {}", batch[sent].1, err);
+                sent += 1;
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn send_batch(socket: &UdpSocket, batch: &[Datagram]) {
+    for (buf, target) in batch {
+        if let Err(err) = socket.send_to(buf, target).await {
+            log::debug!("failed to relay datagram to {}: {}", target, err);
+        }
+    }
+}
+
+#[async_trait]
+impl Conn for UdpRelayForwarder {
+    async fn connect(&self, addr: SocketAddr) -> util::Result<()> {
+        Ok(self.socket.connect(addr).await?)
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> util::Result<usize> {
+        Ok(self.socket.recv(buf).await?)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> util::Result<(usize, SocketAddr)> {
+        Ok(self.socket.recv_from(buf).await?)
+    }
+
+    // send isn't batched, it only goes to a peer set with connect
+    async fn send(&self, buf: &[u8]) -> util::Result<usize> {
+        Ok(self.socket.send(buf).await?)
+    }
+
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> util::Result<usize> {
+        let tx = {
+            let tx = self.tx.lock().unwrap_or_else(|e| e.into_inner());
+            tx.clone().ok_or(util::Error::ErrUseClosedNetworkConn)?
+        };
+        tx.send((buf.to_vec(), target))
+            .await
+            .map_err(|_| util::Error::ErrUseClosedNetworkConn)?;
+        Ok(buf.len())
+    }
+
+    async fn local_addr(&self) -> util::Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    async fn remote_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    // close stops queueing datagrams. Those already queued are still sent.
+    async fn close(&self) -> util::Result<()> {
+        let mut tx = self.tx.lock().unwrap_or_else(|e| e.into_inner());
+        tx.take();
+        Ok(())
+    }
+}
+
+// UdpBatchConn is a listener Conn that reads datagrams in batches. When no
+// datagram is buffered, recv_from reads up to batch_size of them at once with
+// a single recvmmsg call on Linux, and hands them out one per call, so the
+// read loop of the server handles each of them as before. Elsewhere it reads
+// one datagram per call. Sending goes to the socket directly.
+//
+// Use it in place of the bare socket in ConnConfig::conn:
+//
+//     conn: Arc::new(UdpBatchConn::new(socket, DEFAULT_RECV_BATCH_SIZE)),
+pub struct UdpBatchConn {
+    socket: Arc<UdpSocket>,
+    received: Mutex<RecvBatch>,
+}
+
+struct RecvBatch {
+    buffers: Vec<Vec<u8>>,
+    // ready holds the buffer index, length and source of every datagram of the
+    // last batch not handed out yet
+    ready: VecDeque<(usize, usize, SocketAddr)>,
+}
+
+impl UdpBatchConn {
+    // new reads datagrams from socket in batches of at most batch_size. Every
+    // datagram of a batch has a 64 KiB buffer of its own.
+    pub fn new(socket: Arc<UdpSocket>, batch_size: usize) -> Self {
+        UdpBatchConn {
+            socket,
+            received: Mutex::new(RecvBatch {
+                buffers: vec![vec![0u8; RECV_BUFFER_SIZE]; batch_size.max(1)],
+                ready: VecDeque::new(),
+            }),
+        }
+    }
+}
+
+// recv_batch fills batch with the datagrams the socket has queued, waiting
+// for the first one
+#[cfg(target_os = "linux")]
+async fn recv_batch(socket: &UdpSocket, batch: &mut RecvBatch) -> std::io::Result<()> {
+    use nix::sys::socket::{recvmmsg, MsgFlags, RecvMmsgData, SockaddrStorage};
+    use std::io::{self, IoSliceMut};
+    use std::os::unix::io::AsRawFd;
+    use tokio::io::Interest;
+
+    loop {
+        socket.readable().await?;
+
+        let result = socket.try_io(Interest::READABLE, || {
+            let mut data: Vec<RecvMmsgData<'_, [IoSliceMut<'_>; 1]>> = batch
+                .buffers
+                .iter_mut()
+                .map(|buf| RecvMmsgData {
+                    iov: [IoSliceMut::new(buf)],
+                    cmsg_buffer: None,
+                })
+                .collect();
+            let msgs = recvmmsg::<_, SockaddrStorage>(
+                socket.as_raw_fd(),
+                &mut data,
+                MsgFlags::MSG_DONTWAIT,
+                None,
+            )
+            .map_err(io::Error::from)?;
+            Ok(msgs
+                .iter()
+                .map(|msg| (msg.bytes, msg.address.and_then(|addr| socket_addr(&addr))))
+                .collect::<Vec<_>>())
+        });
+
+        match result {
+            Ok(msgs) => {
+                for (i, (n, addr)) in msgs.into_iter().enumerate() {
+                    match addr {
+                        Some(addr) => batch.ready.push_back((i, n, addr)),
+                        None => log::debug!("dropping datagram without an IP source address"),
+                    }
+                }
+                if !batch.ready.is_empty() {
+                    return Ok(());
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(super) fn socket_addr(addr: &nix::sys::socket::SockaddrStorage) -> Option<SocketAddr> {
+    use std::net::{Ipv4Addr, SocketAddrV6};
+
+    if let Some(addr) = addr.as_sockaddr_in() {
+        Some(SocketAddr::new(
+            Ipv4Addr::from(addr.ip()).into(),
+            addr.port(),
+        ))
+    } else {
+        addr.as_sockaddr_in6().map(|addr| {
+            SocketAddrV6::new(addr.ip(), addr.port(), addr.flowinfo(), addr.scope_id()).into()
+        })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn recv_batch(socket: &UdpSocket, batch: &mut RecvBatch) -> std::io::Result<()> {
+    let (n, addr) = socket.recv_from(&mut batch.buffers[0]).await?;
+    batch.ready.push_back((0, n, addr));
+    Ok(())
+}
+
+#[async_trait]
+impl Conn for UdpBatchConn {
+    async fn connect(&self, addr: SocketAddr) -> util::Result<()> {
+        Ok(self.socket.connect(addr).await?)
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> util::Result<usize> {
+        let (n, _) = self.recv_from(buf).await?;
+        Ok(n)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> util::Result<(usize, SocketAddr)> {
+        let mut batch = self.received.lock().await;
+        if batch.ready.is_empty() {
+            recv_batch(&self.socket, &mut batch).await?;
+        }
+
+        // recv_batch returns with at least one datagram ready
+        let (i, n, addr) = batch.ready.pop_front().unwrap();
+        // like recv_from on the socket, a datagram larger than buf is truncated
+        let n = n.min(buf.len());
+        buf[..n].copy_from_slice(&batch.buffers[i][..n]);
+        Ok((n, addr))
+    }
+
+    async fn send(&self, buf: &[u8]) -> util::Result<usize> {
+        Ok(self.socket.send(buf).await?)
+    }
+
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> util::Result<usize> {
+        Ok(self.socket.send_to(buf, target).await?)
+    }
+
+    async fn local_addr(&self) -> util::Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    async fn remote_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    async fn close(&self) -> util::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use super::*;
+use crate::error::Result;
+
+async fn recv_datagram(peer: &UdpSocket) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = tokio::time::timeout(Duration::from_secs(1), peer.recv_from(&mut buf))
+        .await
+        .expect("datagram should be relayed in time")?;
+    Ok(buf[..n].to_vec())
+}
+
+#[tokio::test]
+async fn test_udp_relay_forwarder_batch_size() -> Result<()> {
+    let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let peer = UdpSocket::bind("127.0.0.1:0").await?;
+    let peer_addr = peer.local_addr()?;
+
+    // a full batch is sent long before batch_timeout
+    let forwarder = UdpRelayForwarder::new(Arc::clone(&socket), 4, Duration::from_secs(60));
+    for i in 0..8u8 {
+        assert_eq!(forwarder.send_to(&[i, i], peer_addr).await?, 2);
+    }
+    for i in 0..8u8 {
+        assert_eq!(recv_datagram(&peer).await?, vec![i, i]);
+    }
+
+    assert_eq!(forwarder.local_addr().await?, socket.local_addr()?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_udp_relay_forwarder_batch_timeout() -> Result<()> {
+    let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let peer = UdpSocket::bind("127.0.0.1:0").await?;
+    let peer_addr = peer.local_addr()?;
+
+    let forwarder = UdpRelayForwarder::new(socket, 64, Duration::from_millis(50));
+    let start = Instant::now();
+    forwarder.send_to(&[1, 2, 3], peer_addr).await?;
+    assert_eq!(recv_datagram(&peer).await?, vec![1, 2, 3]);
+    assert!(
+        start.elapsed() >= Duration::from_millis(40),
+        "a partial batch should wait for batch_timeout"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_udp_relay_forwarder_close() -> Result<()> {
+    let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let peer = UdpSocket::bind("127.0.0.1:0").await?;
+    let peer_addr = peer.local_addr()?;
+
+    let forwarder = UdpRelayForwarder::new(Arc::clone(&socket), 64, Duration::from_millis(50));
+    forwarder.send_to(&[1], peer_addr).await?;
+    forwarder.close().await?;
+
+    // queued datagrams are still sent, new ones are refused
+    assert_eq!(recv_datagram(&peer).await?, vec![1]);
+    assert!(forwarder.send_to(&[2], peer_addr).await.is_err());
+
+    // receiving goes to the socket
+    peer.send_to(&[3], socket.local_addr()?).await?;
+    let mut buf = vec![0u8; 16];
+    let (n, from) = forwarder.recv_from(&mut buf).await?;
+    assert_eq!(&buf[..n], &[3]);
+    assert_eq!(from, peer_addr);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_udp_batch_conn_recv_from() -> Result<()> {
+    let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let peer = UdpSocket::bind("127.0.0.1:0").await?;
+    let peer_addr = peer.local_addr()?;
+    let conn = UdpBatchConn::new(Arc::clone(&socket), 4);
+
+    // more datagrams than fit one batch are all handed out, in order
+    for i in 0..10u8 {
+        peer.send_to(&[i, i], socket.local_addr()?).await?;
+    }
+    let mut buf = vec![0u8; 16];
+    for i in 0..10u8 {
+        let (n, from) = tokio::time::timeout(Duration::from_secs(1), conn.recv_from(&mut buf))
+            .await
+            .expect("datagram should be received in time")?;
+        assert_eq!(&buf[..n], &[i, i]);
+        assert_eq!(from, peer_addr);
+    }
+
+    // sending goes to the socket
+    assert_eq!(conn.send_to(&[42], peer_addr).await?, 1);
+    assert_eq!(recv_datagram(&peer).await?, vec![42]);
+    assert_eq!(conn.local_addr().await?, socket.local_addr()?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_udp_batch_conn_truncates() -> Result<()> {
+    let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let peer = UdpSocket::bind("127.0.0.1:0").await?;
+    let conn = UdpBatchConn::new(Arc::clone(&socket), DEFAULT_RECV_BATCH_SIZE);
+
+    // a datagram larger than buf fills it, like recv_from on the socket
+    peer.send_to(&[7u8; 100], socket.local_addr()?).await?;
+    let mut buf = vec![0u8; 10];
+    let (n, _) = conn.recv_from(&mut buf).await?;
+    assert_eq!(n, 10);
+    assert_eq!(buf, vec![7u8; 10]);
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use crate::allocation::five_tuple::FiveTuple;
+use crate::error::*;
+
+use std::fmt;
+use std::net::SocketAddr;
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+// capacity of the command channel of each listener. It is a tokio mpsc
+// channel read_loop awaits next to its conn, so receiving commands never
+// blocks a runtime thread, and a full channel makes senders wait instead of
+// dropping commands.
+pub(crate) const COMMAND_CHANNEL_CAPACITY: usize = 16;
+
+/// Command is a management instruction delivered to every listener of a
+/// running [`Server`](super::Server). Each listener applies it to its own
+/// allocation manager, so a command that matches nothing on one listener is
+/// silently ignored there.
+///
+/// New variants may be added in minor releases.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// DeleteAllocation removes the allocation for the given 5-tuple, closing
+    /// its relay sockets.
+    DeleteAllocation(FiveTuple),
+
+    /// DeleteAllocationByRelayAddr removes the allocation owning the given
+    /// relay address, closing its relay sockets.
+    DeleteAllocationByRelayAddr(SocketAddr),
+
+    /// DeleteUserAllocations removes all allocations of the given username,
+    /// closing their relay sockets.
+    DeleteUserAllocations(String),
+
+    /// RefreshAllocation sets the remaining lifetime of every allocation to
+    /// the given duration, as if each client had sent a Refresh request.
+    RefreshAllocation(Duration),
+}
+
+/// KickReason tells why a user is kicked off the server with
+/// [`Server::kick_user`](super::Server::kick_user). It is only logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KickReason {
+    AdminRequest,
+    QuotaExceeded,
+    PolicyViolation,
+    ServerShutdown,
+}
+
+impl fmt::Display for KickReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match *self {
+            KickReason::AdminRequest => "admin request",
+            KickReason::QuotaExceeded => "quota exceeded",
+            KickReason::PolicyViolation => "policy violation",
+            KickReason::ServerShutdown => "server shutdown",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Commander sends [`Command`]s to a running server. It is cheap to clone and
+/// may be handed to external management integrations (e.g. a sidecar control
+/// API) that have no access to the `Server` itself.
+///
+/// Commands are delivered asynchronously: `send` returns once the command is
+/// queued, not once it has been applied. Every listener has its own queue of
+/// `COMMAND_CHANNEL_CAPACITY` commands; `send` waits while a queue is full, so
+/// commands are never dropped.
+#[derive(Clone)]
+pub struct Commander {
+    txs: Vec<mpsc::Sender<Command>>,
+}
+
+impl Commander {
+    pub(crate) fn new(txs: Vec<mpsc::Sender<Command>>) -> Self {
+        Commander { txs }
+    }
+
+    /// send queues cmd for every listener of the server and returns how many
+    /// listeners will receive it. Fails with `Error::ErrClosed` once the server
+    /// has shut down.
+    pub async fn send(&self, cmd: Command) -> Result<usize> {
+        let mut n = 0;
+        for tx in &self.txs {
+            // a listener whose read loop has exited is skipped
+            if tx.send(cmd.clone()).await.is_ok() {
+                n += 1;
+            }
+        }
+
+        if n == 0 {
+            Err(Error::ErrClosed)
+        } else {
+            Ok(n)
+        }
+    }
+}
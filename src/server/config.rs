@@ -1,12 +1,40 @@
+use super::middleware::RequestMiddleware;
+use crate::allocation::allocation_manager::LifetimeStrategy;
+use crate::allocation::channel_bind::ChannelRefreshPolicy;
+use crate::allocation::AllocationInfo;
 use crate::auth::*;
 use crate::error::*;
+use crate::proto::nonce::NonceGenerator;
 use crate::relay::*;
 
 use util::Conn;
 
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::runtime::Handle;
 use tokio::time::Duration;
 
+// AllocationEventFn is called with the allocation an event is about
+pub type AllocationEventFn = Box<dyn Fn(AllocationInfo) + Send + Sync>;
+
+// ErrorFn is called with every error a listener runs into, see ConnConfig::on_error
+pub type ErrorFn = Box<dyn Fn(TurnError) + Send + Sync>;
+
+// TurnError is an error a listener ran into while handling a packet or
+// receiving from its conn
+#[derive(Debug)]
+pub struct TurnError {
+    // src_addr is the client the packet came from, None for errors of the conn itself
+    pub src_addr: Option<SocketAddr>,
+    pub error: Error,
+    pub timestamp: SystemTime,
+}
+
+// PreAuthFn decides whether a packet from the given source address is processed at all
+pub type PreAuthFn = Box<dyn (Fn(SocketAddr, &[u8]) -> bool) + Send + Sync>;
+
 // ConnConfig is used for UDP listeners
 pub struct ConnConfig {
     pub conn: Arc<dyn Conn + Send + Sync>,
@@ -14,15 +42,81 @@ pub struct ConnConfig {
     // When an allocation is generated the RelayAddressGenerator
     // creates the net.PacketConn and returns the IP/Port it is available at
     pub relay_addr_generator: Box<dyn RelayAddressGenerator + Send + Sync>,
+
+    // max_connections limits how many unique source addresses may hold an
+    // allocation on this listener at once. Allocate requests from new
+    // addresses beyond the limit are rejected with 508 (Insufficient Capacity).
+    // None means unlimited.
+    pub max_connections: Option<usize>,
+
+    // relay_keepalive_interval, when set, sends a STUN Binding Request from each
+    // relay socket at this interval to keep NAT mappings for idle relays alive.
+    pub relay_keepalive_interval: Option<Duration>,
+
+    // relay_keepalive_server is the STUN server keepalives are sent to.
+    // When None they are looped back to the relay address.
+    pub relay_keepalive_server: Option<SocketAddr>,
+
+    // realm, when set, overrides the server realm for this listener, e.g. to use
+    // a different realm on a private interface than on the public one.
+    pub realm: Option<String>,
+
+    // pre_auth, when set, is called with the source address and payload of every
+    // packet received on this listener before any parsing or authentication.
+    // Returning false drops the packet silently, e.g. for IP allowlists. It runs
+    // on every packet, so it must be cheap and must not block.
+    pub pre_auth: Option<PreAuthFn>,
+
+    // max_packet_size is the largest datagram accepted on this listener. Larger
+    // ones are dropped with a warning instead of being handled truncated.
+    // Zero means the default of 1500 bytes.
+    pub max_packet_size: usize,
+
+    // inbound_worker_threads, when greater than 1, handles the packets received
+    // on this listener on a dedicated multi-threaded runtime with that many
+    // workers instead of on the read loop. Packets from one source address
+    // always go to the same worker, so they are still handled in order.
+    // 0 and 1 both mean the packets are handled on the read loop.
+    pub inbound_worker_threads: usize,
+
+    // on_error, when set, is called with every error this listener runs into,
+    // e.g. malformed or unauthenticated requests or a failing conn, on top of
+    // it being logged. It is meant to feed error reporting, so it must not block.
+    pub on_error: Option<ErrorFn>,
+
+    // forward_icmp_errors, when set, forwards ICMP errors for datagrams relayed
+    // to peers to the client as Data indications carrying an ICMP attribute
+    // (RFC 8656 Section 11.5), so it can stop sending to them. The kernel only
+    // reports them for relay sockets that ask for them, like the RelaySocket of
+    // the static and range generators on Linux. A Send indication with
+    // DONT-FRAGMENT whose data is too big for the DF bit is answered the same
+    // way, with an ICMP packet too big.
+    pub forward_icmp_errors: bool,
+
+    // allocation_lifetime_strategy decides the lifetime granted to Allocate and
+    // Refresh requests on this listener, see LifetimeStrategy
+    pub allocation_lifetime_strategy: LifetimeStrategy,
+
+    // idle_timeout, when set, closes the listener once no packet was received
+    // on it for that long, along with its allocations. It is meant for conns
+    // carrying a single client, e.g. a TCP connection wrapped in a TcpFramer,
+    // so connections of clients that went away without closing them don't
+    // leak. It is rejected on conns without a remote address, e.g. a shared
+    // UDP socket, where it would stop the whole listener.
+    pub idle_timeout: Option<Duration>,
 }
 
 impl ConnConfig {
-    pub fn validate(&self) -> Result<()> {
+    pub async fn validate(&self) -> Result<()> {
+        if self.idle_timeout.is_some() && self.conn.remote_addr().await.is_none() {
+            return Err(Error::ErrIdleTimeoutWithoutRemoteAddr);
+        }
+
         self.relay_addr_generator.validate()
     }
 }
 
-// ServerConfig configures the Pion TURN Server
+// ServerConfig configures the TURN Server
 pub struct ServerConfig {
     // conn_configs are a list of all the turn listeners
     // Each listener can have custom behavior around the creation of Relays
@@ -31,22 +125,157 @@ pub struct ServerConfig {
     // realm sets the realm for this server
     pub realm: String,
 
-    // auth_handler is a callback used to handle incoming auth requests, allowing users to customize Pion TURN with custom behavior
+    // enforce_realm rejects requests whose REALM doesn't match realm with
+    // 401 (Unauthorized) before the auth_handler is consulted. Should normally be true.
+    pub enforce_realm: bool,
+
+    // auth_handler is a callback used to handle incoming auth requests, allowing users to customize the server with custom behavior
     pub auth_handler: Arc<dyn AuthHandler + Send + Sync>,
 
     // channel_bind_timeout sets the lifetime of channel binding. Defaults to 10 minutes.
     pub channel_bind_timeout: Duration,
+
+    // channel_bind_refresh_policy decides what a ChannelBind request for a
+    // channel already bound to the same peer does. Defaults to Reset.
+    pub channel_bind_refresh_policy: ChannelRefreshPolicy,
+
+    // min_allocation_lifetime is the shortest LIFETIME accepted in an Allocate
+    // request, shorter ones are rejected with 400 (Bad Request). Defaults to
+    // 1 second, RFC 5766 Section 6.2 suggests 10 minutes.
+    pub min_allocation_lifetime: Duration,
+
+    // software_name, when set, is sent as a SOFTWARE attribute in every response.
+    // Defaults to None so the server does not advertise its implementation.
+    pub software_name: Option<String>,
+
+    // nonce_cleanup_interval sets how often expired nonces are purged. Defaults to 60 seconds.
+    pub nonce_cleanup_interval: Duration,
+
+    // nonce_prefix, when set, is prepended to every nonce the server issues,
+    // e.g. a server identifier, so nonces presented to the wrong server of a
+    // deployment can be told apart from forged ones. See NonceGenerator.
+    pub nonce_prefix: Option<String>,
+
+    // allow_app_id accepts the APP-ID attribute in Allocate requests and tags
+    // the allocation with it, see AllocationInfo::app_id. When false, APP-ID
+    // is ignored.
+    pub allow_app_id: bool,
+
+    // debug_nonces makes Server::list_nonces include the nonce values, e.g. to
+    // correlate them with client-side logs during development. Defaults to
+    // false, which only reveals how many nonces there are and when they expire.
+    pub debug_nonces: bool,
+
+    // state_dump_path, when set, is the file a snapshot of all allocations is
+    // written to as JSON every dump_interval, for postmortem analysis of e.g.
+    // allocation leaks. The file is replaced atomically. Requires the
+    // `state-dump` feature and is ignored without it.
+    pub state_dump_path: Option<PathBuf>,
+
+    // dump_interval sets how often the state dump is written. Defaults to 60 seconds.
+    pub dump_interval: Duration,
+
+    // middlewares are run around the handling of every request on all listeners,
+    // see RequestMiddleware
+    pub middlewares: Vec<Arc<dyn RequestMiddleware + Send + Sync>>,
+
+    // on_allocation_created, when set, is called whenever an allocation is
+    // created on any listener, after it is ready to relay. It runs on the
+    // listener read loop, so it must not block.
+    pub on_allocation_created: Option<AllocationEventFn>,
+
+    // on_allocation_closed, when set, is called whenever an allocation is closed
+    // on any listener, whether it expired, was deleted or the server shut down.
+    pub on_allocation_closed: Option<AllocationEventFn>,
+
+    // runtime, when set, is the runtime the read loops and background tasks of
+    // the server are spawned on, e.g. to keep them apart from CPU-bound work of
+    // the embedding application. Defaults to the runtime Server::new is called on.
+    pub runtime: Option<Handle>,
+}
+
+// PartialServerConfig holds the settings Server::reconfigure can change on a
+// running server. None leaves a setting untouched. The auth handler can't be
+// replaced this way.
+#[derive(Debug, Clone, Default)]
+pub struct PartialServerConfig {
+    // realm replaces the server realm, see Server::update_realm
+    pub realm: Option<String>,
+
+    // channel_bind_timeout replaces the lifetime of new channel bindings.
+    // Zero means the default of 10 minutes.
+    pub channel_bind_timeout: Option<Duration>,
+
+    // nonce_lifetime replaces how long a nonce is accepted. Zero means the default of one hour.
+    pub nonce_lifetime: Option<Duration>,
+
+    // reauth_grace_period, together with realm, cuts the remaining lifetime of the
+    // allocations on listeners using the server realm to at most this. Their
+    // clients then have to refresh, and so re-authenticate against the new realm,
+    // within it or lose the allocation. Ignored when realm isn't set.
+    pub reauth_grace_period: Option<Duration>,
 }
 
 impl ServerConfig {
-    pub fn validate(&self) -> Result<()> {
+    pub async fn validate(&self) -> Result<()> {
         if self.conn_configs.is_empty() {
             return Err(Error::ErrNoAvailableConns);
         }
 
         for cc in &self.conn_configs {
-            cc.validate()?;
+            cc.validate().await?;
+        }
+
+        if let Some(prefix) = &self.nonce_prefix {
+            NonceGenerator::with_prefix(prefix).validate()?;
         }
         Ok(())
     }
 }
+
+// ListenerConfigExport is the exported configuration of one listener, see ServerConfigExport
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ListenerConfigExport {
+    pub local_addr: Option<SocketAddr>,
+    pub relay_addr_generator_type: String,
+    pub max_connections: Option<usize>,
+    pub relay_keepalive_interval: Option<Duration>,
+    pub relay_keepalive_server: Option<SocketAddr>,
+    pub realm: Option<String>,
+    pub pre_auth: bool,
+    pub max_packet_size: usize,
+    pub inbound_worker_threads: usize,
+    pub on_error: bool,
+    pub forward_icmp_errors: bool,
+    pub allocation_lifetime_strategy: String,
+    pub idle_timeout: Option<Duration>,
+}
+
+// ServerConfigExport is a view of the configuration of a running server, as
+// returned by Server::export_config. Callbacks and handlers are represented by
+// their type name or whether they are set, so it holds no secrets. It is
+// Serialize with the `serde` feature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ServerConfigExport {
+    pub listeners: Vec<ListenerConfigExport>,
+    pub realm: String,
+    pub enforce_realm: bool,
+    pub auth_handler_type: String,
+    pub channel_bind_timeout: Duration,
+    pub channel_bind_refresh_policy: ChannelRefreshPolicy,
+    pub nonce_lifetime: Duration,
+    pub min_allocation_lifetime: Duration,
+    pub software_name: Option<String>,
+    pub nonce_cleanup_interval: Duration,
+    pub nonce_prefix: Option<String>,
+    pub allow_app_id: bool,
+    pub debug_nonces: bool,
+    pub state_dump_path: Option<PathBuf>,
+    pub dump_interval: Duration,
+    pub middleware_count: usize,
+    pub on_allocation_created: bool,
+    pub on_allocation_closed: bool,
+    pub runtime: bool,
+}
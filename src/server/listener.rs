@@ -0,0 +1,104 @@
+use crate::proto::Protocol;
+
+use async_trait::async_trait;
+use util::Conn;
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// ListenerInfo describes one listener of a running server, as returned by
+// Server::list_listeners
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenerInfo {
+    pub addr: SocketAddr,
+    pub protocol: Protocol,
+    pub allocation_count: usize,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+}
+
+// ListenerStats counts the bytes a listener received from and sent to clients,
+// and whether its conn is an open connection, see Server::connection_count
+pub(crate) struct ListenerStats {
+    pub(crate) addr: SocketAddr,
+    pub(crate) protocol: Protocol,
+    pub(crate) bytes_received: AtomicU64,
+    pub(crate) bytes_sent: AtomicU64,
+    pub(crate) connections: AtomicUsize,
+}
+
+impl ListenerStats {
+    pub(crate) fn new(addr: SocketAddr, protocol: Protocol) -> Self {
+        ListenerStats {
+            addr,
+            protocol,
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            connections: AtomicUsize::new(0),
+        }
+    }
+}
+
+// CountingConn wraps the conn of a listener and counts the bytes moved through
+// it in ListenerStats. Responses and relayed data are all sent through the
+// listener conn, so wrapping it once in Server::new covers every send.
+pub(crate) struct CountingConn {
+    pub(crate) conn: Arc<dyn Conn + Send + Sync>,
+    pub(crate) stats: Arc<ListenerStats>,
+}
+
+impl CountingConn {
+    fn received(&self, n: usize) {
+        self.stats
+            .bytes_received
+            .fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    fn sent(&self, n: usize) {
+        self.stats.bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl Conn for CountingConn {
+    async fn connect(&self, addr: SocketAddr) -> util::Result<()> {
+        self.conn.connect(addr).await
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> util::Result<usize> {
+        let n = self.conn.recv(buf).await?;
+        self.received(n);
+        Ok(n)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> util::Result<(usize, SocketAddr)> {
+        let (n, addr) = self.conn.recv_from(buf).await?;
+        self.received(n);
+        Ok((n, addr))
+    }
+
+    async fn send(&self, buf: &[u8]) -> util::Result<usize> {
+        let n = self.conn.send(buf).await?;
+        self.sent(n);
+        Ok(n)
+    }
+
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> util::Result<usize> {
+        let n = self.conn.send_to(buf, target).await?;
+        self.sent(n);
+        Ok(n)
+    }
+
+    async fn local_addr(&self) -> util::Result<SocketAddr> {
+        self.conn.local_addr().await
+    }
+
+    async fn remote_addr(&self) -> Option<SocketAddr> {
+        self.conn.remote_addr().await
+    }
+
+    async fn close(&self) -> util::Result<()> {
+        self.conn.close().await
+    }
+}
@@ -0,0 +1,22 @@
+use super::request::Request;
+use crate::error::*;
+
+/// RequestMiddleware hooks into the handling of every packet a server
+/// receives, e.g. for rate limiting by source address, tracing or custom
+/// logging.
+///
+/// Middlewares run in the order they are configured in
+/// `ServerConfig::middlewares` for `before`, and in reverse order for `after`.
+/// Both are called on the listener read loop, so they must be cheap and must
+/// not block.
+pub trait RequestMiddleware {
+    /// before is called ahead of handling req. Returning an error drops the
+    /// packet: neither the remaining middlewares nor the request handler see it,
+    /// and no `after` is called for it.
+    fn before(&self, _req: &Request) -> Result<()> {
+        Ok(())
+    }
+
+    /// after is called once req has been handled, with the result of handling it.
+    fn after(&self, _req: &Request, _result: &Result<()>) {}
+}
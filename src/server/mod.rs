@@ -1,90 +1,451 @@
+//! The TURN server. A [`Server`] serves one or more listeners, each described
+//! by a [`ConnConfig`], and relays between the clients on them and their peers.
+//!
+//! ```text
+//!  client                                                   peer
+//!    |  ^                                                   ^  |
+//!    |  |                                                   |  |
+//!    v  |                                                   |  v
+//!  listener conn <------ Data indication / ChannelData --+ relay socket
+//!    |                                                   |  ^  |
+//!    v                                                   |  |  |
+//!  read_loop <-- Command, shutdown                       |  |  |
+//!    |                                                   |  |  |
+//!    v                                                   |  |  |
+//!  Request --> AuthHandler, nonces                       |  |  |
+//!    |                                                   |  |  |
+//!    v                                                   |  |  |
+//!  Manager --> RelayAddressGenerator                     |  |  |
+//!    |                                                   |  |  |
+//!    v                                                   |  |  |
+//!  Allocation ---- Send indication / ChannelData --------|--+  |
+//!    |                                                   |     |
+//!    +-- relay receiver task ----------------------------+ <---+
+//! ```
+//!
+//! * [`Server::new`] creates one allocation [`Manager`] per listener and spawns
+//!   a `read_loop` for it. The listener conn is shared by everything that talks
+//!   to clients on that listener, so all responses and relayed data leave
+//!   through it.
+//! * `read_loop` receives datagrams, drops oversized ones and those rejected by
+//!   `ConnConfig::pre_auth`, and turns every other datagram into a [`Request`]
+//!   carrying a copy of the current settings. It handles the request itself,
+//!   or hands it to one of the inbound workers when
+//!   `ConnConfig::inbound_worker_threads` is set. Between datagrams it applies
+//!   the [`Command`]s sent by a [`Commander`] and watches for shutdown.
+//! * [`Request`] runs the middlewares, authenticates STUN requests against the
+//!   [`AuthHandler`] and the server nonces, and dispatches on the method:
+//!   Allocate, Refresh, CreatePermission and ChannelBind requests change
+//!   allocations through the `Manager`, Send indications and ChannelData are
+//!   sent to the peer from the relay socket of the client allocation.
+//! * The `Manager` keeps the allocations of its listener keyed by 5-tuple and
+//!   gets their relay sockets from the listener `RelayAddressGenerator`.
+//! * Each [`Allocation`](crate::allocation::Allocation) owns its relay sockets,
+//!   permissions, channel bindings and lifetime timer. A relay receiver task
+//!   per relay socket reads what peers send to it and forwards it to the
+//!   client through the listener conn, as ChannelData when the peer has a
+//!   channel bound and as a Data indication when it only has a permission.
+
 #[cfg(test)]
 mod server_test;
 
+pub mod command;
 pub mod config;
+pub mod listener;
+pub mod middleware;
+mod prometheus;
 pub mod request;
+#[cfg(feature = "state-dump")]
+pub mod snapshot;
+#[cfg(feature = "webrtc-stats")]
+pub mod stats;
 
 use crate::allocation::allocation_manager::*;
+use crate::allocation::channel_bind::ChannelRefreshPolicy;
+use crate::allocation::{AllocationCallback, AllocationInfo};
+use crate::auth::metrics::*;
 use crate::auth::AuthHandler;
 use crate::error::*;
 use crate::proto::lifetime::DEFAULT_LIFETIME;
+use crate::proto::nonce::NonceGenerator;
+use crate::proto::{PROTO_TCP, PROTO_UDP};
+use command::*;
 use config::*;
+use listener::*;
+use middleware::*;
 use request::*;
 
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::{watch, Mutex};
+use std::time::SystemTime;
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, watch, Mutex, RwLock};
 use tokio::time::{Duration, Instant};
 use util::Conn;
 
 const INBOUND_MTU: usize = 1500;
+const INBOUND_WORKER_QUEUE_SIZE: usize = 1024;
+const ALLOCATION_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_NONCE_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+#[cfg(feature = "state-dump")]
+const DEFAULT_DUMP_INTERVAL: Duration = Duration::from_secs(60);
+
+// ErrorCallback is the shared form of ConnConfig::on_error
+type ErrorCallback = Arc<dyn Fn(TurnError) + Send + Sync>;
 
 /// Server is an instance of the TURN Server
 pub struct Server {
     auth_handler: Arc<dyn AuthHandler + Send + Sync>,
-    realm: String,
-    channel_bind_timeout: Duration,
+    realm: Arc<RwLock<String>>,
+    enforce_realm: bool,
+    channel_bind_timeout: Arc<RwLock<Duration>>,
+    channel_bind_refresh_policy: ChannelRefreshPolicy,
+    nonce_lifetime: Arc<RwLock<Duration>>,
+    min_allocation_lifetime: Duration,
+    software_name: Option<String>,
+    allow_app_id: bool,
+    debug_nonces: bool,
+    middlewares: Vec<Arc<dyn RequestMiddleware + Send + Sync>>,
     pub(crate) nonces: Arc<Mutex<HashMap<String, Instant>>>,
+    auth_metrics: Arc<AuthMetrics>,
+    nonce_generator: Arc<NonceGenerator>,
+    // the static part of export_config, captured in new
+    config_export: ServerConfigExport,
     shutdown_tx: Mutex<Option<watch::Sender<bool>>>,
+    // set once close starts, so is_shutdown doesn't need the shutdown_tx lock
+    shutting_down: AtomicBool,
+    command_txs: Vec<mpsc::Sender<Command>>,
+    allocation_managers: Vec<Arc<Manager>>,
+    listener_stats: Vec<Arc<ListenerStats>>,
+    inbound_runtimes: Vec<InboundRuntime>,
+    started_at: Instant,
+}
+
+// InboundRuntime owns the worker runtime of a listener with more than one
+// inbound worker thread. Dropping a runtime blocks, which panics in async
+// code, so it is shut down in the background instead.
+struct InboundRuntime(Option<tokio::runtime::Runtime>);
+
+impl Drop for InboundRuntime {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
 }
 
 impl Server {
     /// creates the TURN server
     pub async fn new(config: ServerConfig) -> Result<Self> {
-        config.validate()?;
+        config.validate().await?;
 
         let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
+        let mut channel_bind_timeout = config.channel_bind_timeout;
+        if channel_bind_timeout == Duration::from_secs(0) {
+            channel_bind_timeout = DEFAULT_LIFETIME;
+        }
+
+        let mut min_allocation_lifetime = config.min_allocation_lifetime;
+        if min_allocation_lifetime == Duration::from_secs(0) {
+            min_allocation_lifetime = DEFAULT_MIN_ALLOCATION_LIFETIME;
+        }
+
         let mut s = Server {
             auth_handler: config.auth_handler,
-            realm: config.realm,
-            channel_bind_timeout: config.channel_bind_timeout,
+            realm: Arc::new(RwLock::new(config.realm)),
+            enforce_realm: config.enforce_realm,
+            channel_bind_timeout: Arc::new(RwLock::new(channel_bind_timeout)),
+            channel_bind_refresh_policy: config.channel_bind_refresh_policy,
+            nonce_lifetime: Arc::new(RwLock::new(NONCE_LIFETIME)),
+            min_allocation_lifetime,
+            software_name: config.software_name,
+            allow_app_id: config.allow_app_id,
+            debug_nonces: config.debug_nonces,
+            middlewares: config.middlewares,
             nonces: Arc::new(Mutex::new(HashMap::new())),
+            auth_metrics: Arc::new(AuthMetrics::default()),
+            nonce_generator: Arc::new(match &config.nonce_prefix {
+                Some(prefix) => NonceGenerator::with_prefix(prefix),
+                None => NonceGenerator::new(),
+            }),
+            config_export: ServerConfigExport {
+                listeners: vec![],
+                realm: String::new(),
+                enforce_realm: config.enforce_realm,
+                auth_handler_type: String::new(),
+                channel_bind_timeout: Duration::from_secs(0),
+                channel_bind_refresh_policy: config.channel_bind_refresh_policy,
+                nonce_lifetime: Duration::from_secs(0),
+                min_allocation_lifetime,
+                software_name: None,
+                nonce_cleanup_interval: config.nonce_cleanup_interval,
+                nonce_prefix: config.nonce_prefix.clone(),
+                allow_app_id: config.allow_app_id,
+                debug_nonces: config.debug_nonces,
+                state_dump_path: config.state_dump_path.clone(),
+                dump_interval: config.dump_interval,
+                middleware_count: 0,
+                on_allocation_created: config.on_allocation_created.is_some(),
+                on_allocation_closed: config.on_allocation_closed.is_some(),
+                runtime: config.runtime.is_some(),
+            },
             shutdown_tx: Mutex::new(Some(shutdown_tx)),
+            shutting_down: AtomicBool::new(false),
+            command_txs: vec![],
+            allocation_managers: vec![],
+            listener_stats: vec![],
+            inbound_runtimes: vec![],
+            started_at: Instant::now(),
         };
 
-        if s.channel_bind_timeout == Duration::from_secs(0) {
-            s.channel_bind_timeout = DEFAULT_LIFETIME;
+        let runtime = config.runtime.unwrap_or_else(Handle::current);
+
+        let mut nonce_cleanup_interval = config.nonce_cleanup_interval;
+        if nonce_cleanup_interval == Duration::from_secs(0) {
+            nonce_cleanup_interval = DEFAULT_NONCE_CLEANUP_INTERVAL;
         }
 
-        for p in config.conn_configs.into_iter() {
+        {
             let nonces = Arc::clone(&s.nonces);
+            let nonce_lifetime = Arc::clone(&s.nonce_lifetime);
+            let shutdown_rx = shutdown_rx.clone();
+            runtime.spawn(async move {
+                Server::nonce_cleanup_loop(
+                    nonces,
+                    nonce_lifetime,
+                    nonce_cleanup_interval,
+                    shutdown_rx,
+                )
+                .await;
+            });
+        }
+
+        let on_allocation_created: Option<AllocationCallback> =
+            config.on_allocation_created.map(Arc::from);
+        let on_allocation_closed: Option<AllocationCallback> =
+            config.on_allocation_closed.map(Arc::from);
+
+        s.config_export.auth_handler_type = s.auth_handler.type_name().to_owned();
+        s.config_export.software_name = s.software_name.clone();
+        s.config_export.middleware_count = s.middlewares.len();
+
+        for mut p in config.conn_configs.into_iter() {
+            p.relay_addr_generator.init().await?;
+
+            let local_addr = p.conn.local_addr().await?;
+            s.config_export.listeners.push(ListenerConfigExport {
+                local_addr: Some(local_addr),
+                relay_addr_generator_type: p.relay_addr_generator.type_name().to_owned(),
+                max_connections: p.max_connections,
+                relay_keepalive_interval: p.relay_keepalive_interval,
+                relay_keepalive_server: p.relay_keepalive_server,
+                realm: p.realm.clone(),
+                pre_auth: p.pre_auth.is_some(),
+                on_error: p.on_error.is_some(),
+                forward_icmp_errors: p.forward_icmp_errors,
+                allocation_lifetime_strategy: format!("{:?}", p.allocation_lifetime_strategy),
+                idle_timeout: p.idle_timeout,
+                max_packet_size: if p.max_packet_size == 0 {
+                    INBOUND_MTU
+                } else {
+                    p.max_packet_size
+                },
+                inbound_worker_threads: p.inbound_worker_threads.max(1),
+            });
+
+            let nonces = Arc::clone(&s.nonces);
+            let auth_metrics = Arc::clone(&s.auth_metrics);
+            let nonce_generator = Arc::clone(&s.nonce_generator);
             let auth_handler = Arc::clone(&s.auth_handler);
-            let realm = s.realm.clone();
-            let channel_bind_timeout = s.channel_bind_timeout;
+            let realm = match p.realm {
+                Some(realm) => Arc::new(RwLock::new(realm)),
+                None => Arc::clone(&s.realm),
+            };
+            let enforce_realm = s.enforce_realm;
+            let channel_bind_timeout = Arc::clone(&s.channel_bind_timeout);
+            let channel_bind_refresh_policy = s.channel_bind_refresh_policy;
+            let nonce_lifetime = Arc::clone(&s.nonce_lifetime);
+            let min_allocation_lifetime = s.min_allocation_lifetime;
+            let software_name = s.software_name.clone();
+            let allow_app_id = s.allow_app_id;
+            let middlewares = s.middlewares.clone();
             let shutdown_rx = shutdown_rx.clone();
+            let (command_tx, command_rx) = mpsc::channel(COMMAND_CHANNEL_CAPACITY);
+            s.command_txs.push(command_tx);
+            let on_error: Option<ErrorCallback> = p.on_error.map(Arc::from);
 
-            tokio::spawn(async move {
-                let allocation_manager = Arc::new(Manager::new(ManagerConfig {
-                    relay_addr_generator: p.relay_addr_generator,
-                }));
+            let mut inbound_workers = vec![];
+            if p.inbound_worker_threads > 1 {
+                let runtime = tokio::runtime::Builder::new_multi_thread()
+                    .worker_threads(p.inbound_worker_threads)
+                    .thread_name("turn-inbound-worker")
+                    .enable_all()
+                    .build()?;
+                for _ in 0..p.inbound_worker_threads {
+                    let (tx, rx) = mpsc::channel(INBOUND_WORKER_QUEUE_SIZE);
+                    runtime.spawn(Server::inbound_worker(
+                        rx,
+                        middlewares.clone(),
+                        on_error.clone(),
+                    ));
+                    inbound_workers.push(tx);
+                }
+                s.inbound_runtimes.push(InboundRuntime(Some(runtime)));
+            }
 
+            let allocation_manager = Arc::new(Manager::new(ManagerConfig {
+                relay_addr_generator: p.relay_addr_generator,
+                max_connections: p.max_connections,
+                relay_keepalive_interval: p.relay_keepalive_interval,
+                relay_keepalive_server: p.relay_keepalive_server,
+                on_allocation_created: on_allocation_created.clone(),
+                on_allocation_closed: on_allocation_closed.clone(),
+                forward_icmp_errors: p.forward_icmp_errors,
+                allocation_lifetime_strategy: p.allocation_lifetime_strategy,
+            }));
+            s.allocation_managers.push(Arc::clone(&allocation_manager));
+            // a connection-oriented conn, e.g. a TcpFramer, has a remote address
+            // and carries one client connection until its read loop exits
+            let connected = p.conn.remote_addr().await.is_some();
+            let protocol = if connected { PROTO_TCP } else { PROTO_UDP };
+            let stats = Arc::new(ListenerStats::new(local_addr, protocol));
+            s.listener_stats.push(Arc::clone(&stats));
+            if connected {
+                stats.connections.fetch_add(1, Ordering::Relaxed);
+            }
+            let conn: Arc<dyn Conn + Send + Sync> = Arc::new(CountingConn {
+                conn: p.conn,
+                stats: Arc::clone(&stats),
+            });
+            let pre_auth = p.pre_auth;
+            let idle_timeout = p.idle_timeout;
+            let max_packet_size = if p.max_packet_size == 0 {
+                INBOUND_MTU
+            } else {
+                p.max_packet_size
+            };
+
+            runtime.spawn(async move {
                 Server::read_loop(
-                    p.conn,
+                    conn,
+                    pre_auth,
+                    on_error,
+                    max_packet_size,
+                    idle_timeout,
                     allocation_manager,
                     nonces,
+                    auth_metrics,
+                    nonce_generator,
                     auth_handler,
                     realm,
+                    enforce_realm,
                     channel_bind_timeout,
+                    channel_bind_refresh_policy,
+                    nonce_lifetime,
+                    min_allocation_lifetime,
+                    software_name,
+                    allow_app_id,
+                    middlewares,
+                    inbound_workers,
                     shutdown_rx,
+                    command_rx,
                 )
                 .await;
+                if connected {
+                    stats.connections.fetch_sub(1, Ordering::Relaxed);
+                }
+            });
+        }
+
+        #[cfg(feature = "state-dump")]
+        if let Some(path) = config.state_dump_path {
+            let mut dump_interval = config.dump_interval;
+            if dump_interval == Duration::from_secs(0) {
+                dump_interval = DEFAULT_DUMP_INTERVAL;
+            }
+
+            let allocation_managers = s.allocation_managers.clone();
+            let shutdown_rx = shutdown_rx.clone();
+            runtime.spawn(async move {
+                Server::state_dump_loop(allocation_managers, path, dump_interval, shutdown_rx)
+                    .await;
             });
         }
 
         Ok(s)
     }
 
+    /// creates the TURN server like new, but panics if the config is invalid or
+    /// the server fails to start. Meant for test harnesses with a known-good config.
+    pub async fn new_unchecked(config: ServerConfig) -> Self {
+        match Server::new(config).await {
+            Ok(s) => s,
+            Err(err) => panic!("failed to create TURN server: {}", err),
+        }
+    }
+
+    // nonce_cleanup_loop periodically drops nonces older than nonce_lifetime, so
+    // they don't pile up when no requests arrive to expire them.
+    async fn nonce_cleanup_loop(
+        nonces: Arc<Mutex<HashMap<String, Instant>>>,
+        nonce_lifetime: Arc<RwLock<Duration>>,
+        interval: Duration,
+        mut shutdown_rx: watch::Receiver<bool>,
+    ) {
+        let mut ticker = tokio::time::interval_at(Instant::now() + interval, interval);
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let nonce_lifetime = *nonce_lifetime.read().await;
+                    let mut nonces = nonces.lock().await;
+                    nonces.retain(|_, created| created.elapsed() < nonce_lifetime);
+                },
+                did_change = shutdown_rx.changed() => {
+                    if did_change.is_err() || *shutdown_rx.borrow() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     async fn read_loop(
         conn: Arc<dyn Conn + Send + Sync>,
+        pre_auth: Option<PreAuthFn>,
+        on_error: Option<ErrorCallback>,
+        max_packet_size: usize,
+        idle_timeout: Option<Duration>,
         allocation_manager: Arc<Manager>,
         nonces: Arc<Mutex<HashMap<String, Instant>>>,
+        auth_metrics: Arc<AuthMetrics>,
+        nonce_generator: Arc<NonceGenerator>,
         auth_handler: Arc<dyn AuthHandler + Send + Sync>,
-        realm: String,
-        channel_bind_timeout: Duration,
+        realm: Arc<RwLock<String>>,
+        enforce_realm: bool,
+        channel_bind_timeout: Arc<RwLock<Duration>>,
+        channel_bind_refresh_policy: ChannelRefreshPolicy,
+        nonce_lifetime: Arc<RwLock<Duration>>,
+        min_allocation_lifetime: Duration,
+        software_name: Option<String>,
+        allow_app_id: bool,
+        middlewares: Vec<Arc<dyn RequestMiddleware + Send + Sync>>,
+        inbound_workers: Vec<mpsc::Sender<Request>>,
         mut shutdown_rx: watch::Receiver<bool>,
+        mut command_rx: mpsc::Receiver<Command>,
     ) {
-        let mut buf = vec![0u8; INBOUND_MTU];
+        // one spare byte tells datagrams of exactly max_packet_size from
+        // larger ones, which recv_from truncates
+        let mut buf = vec![0u8; max_packet_size + 1];
+        // responses are cached per listener, a client talks to one listener
+        let transaction_cache = Arc::new(TransactionCache::new());
+        let mut idle_deadline = idle_timeout.map(|t| Instant::now() + t);
 
         loop {
             let (n, addr) = tokio::select! {
@@ -93,10 +454,19 @@ impl Server {
                         Ok(v) => v,
                         Err(err) => {
                             log::debug!("exit read loop on error: {}", err);
+                            Server::report_error(&on_error, None, err.into());
                             break;
                         }
                     }
                 },
+                _ = Server::sleep_until_idle(idle_deadline) => {
+                    log::debug!(
+                        "closing conn to {:?}, no packets for {:?}",
+                        conn.remote_addr().await,
+                        idle_timeout
+                    );
+                    break;
+                }
                 did_change = shutdown_rx.changed() => {
                     if did_change.is_err() || *shutdown_rx.borrow() {
                         // if did_change.is_err, sender was dropped, or if
@@ -106,30 +476,392 @@ impl Server {
                         continue;
                     }
                 }
+                cmd = command_rx.recv() => {
+                    match cmd {
+                        Some(cmd) => {
+                            Server::handle_command(&allocation_manager, cmd).await;
+                            continue;
+                        }
+                        None => break,
+                    }
+                }
             };
 
-            let mut r = Request {
-                conn: Arc::clone(&conn),
-                src_addr: addr,
-                buff: buf[..n].to_vec(),
-                allocation_manager: Arc::clone(&allocation_manager),
-                nonces: Arc::clone(&nonces),
-                auth_handler: Arc::clone(&auth_handler),
-                realm: realm.clone(),
-                channel_bind_timeout,
+            if let Some(idle_timeout) = idle_timeout {
+                idle_deadline = Some(Instant::now() + idle_timeout);
+            }
+
+            if n > max_packet_size {
+                log::warn!(
+                    "dropping datagram from {} larger than {} bytes",
+                    addr,
+                    max_packet_size
+                );
+                continue;
+            }
+
+            if let Some(pre_auth) = &pre_auth {
+                if !pre_auth(addr, &buf[..n]) {
+                    log::trace!("pre_auth dropped {} bytes from {}", n, addr);
+                    continue;
+                }
+            }
+
+            // the request works on a copy of the settings, so reconfigure
+            // doesn't change them while it is handled
+            let r = {
+                let realm = realm.read().await;
+                let channel_bind_timeout = channel_bind_timeout.read().await;
+                let nonce_lifetime = nonce_lifetime.read().await;
+                Request {
+                    request_id: next_request_id(),
+                    conn: Arc::clone(&conn),
+                    src_addr: addr,
+                    buff: buf[..n].to_vec(),
+                    origin: None,
+                    allocation_manager: Arc::clone(&allocation_manager),
+                    nonces: Arc::clone(&nonces),
+                    auth_metrics: Arc::clone(&auth_metrics),
+                    nonce_generator: Arc::clone(&nonce_generator),
+                    auth_handler: Arc::clone(&auth_handler),
+                    realm: realm.clone(),
+                    enforce_realm,
+                    channel_bind_timeout: *channel_bind_timeout,
+                    channel_bind_refresh_policy,
+                    nonce_lifetime: *nonce_lifetime,
+                    min_allocation_lifetime,
+                    software_name: software_name.clone(),
+                    allow_app_id,
+                    transaction_cache: Arc::clone(&transaction_cache),
+                }
             };
 
-            if let Err(err) = r.handle_request().await {
-                log::error!("error when handling datagram: {}", err);
+            if inbound_workers.is_empty() {
+                Server::handle_packet(r, &middlewares, &on_error).await;
+            } else {
+                // pick the worker by source address, so the packets of one
+                // client are handled in order
+                let mut hasher = DefaultHasher::new();
+                addr.hash(&mut hasher);
+                let worker = hasher.finish() as usize % inbound_workers.len();
+                if inbound_workers[worker].send(r).await.is_err() {
+                    log::debug!("exit read loop, inbound worker {} is gone", worker);
+                    break;
+                }
             }
         }
 
-        let _ = allocation_manager.close().await;
+        drop(inbound_workers);
+        let _ = allocation_manager.close(ALLOCATION_CLOSE_TIMEOUT).await;
         let _ = conn.close().await;
     }
 
+    // sleep_until_idle completes at deadline, or never without one
+    async fn sleep_until_idle(deadline: Option<Instant>) {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn inbound_worker(
+        mut rx: mpsc::Receiver<Request>,
+        middlewares: Vec<Arc<dyn RequestMiddleware + Send + Sync>>,
+        on_error: Option<ErrorCallback>,
+    ) {
+        while let Some(r) = rx.recv().await {
+            Server::handle_packet(r, &middlewares, &on_error).await;
+        }
+    }
+
+    async fn handle_packet(
+        mut r: Request,
+        middlewares: &[Arc<dyn RequestMiddleware + Send + Sync>],
+        on_error: &Option<ErrorCallback>,
+    ) {
+        if let Err(err) = middlewares.iter().try_for_each(|m| m.before(&r)) {
+            log::debug!(
+                "request {}: middleware dropped packet from {}: {}",
+                r.request_id,
+                r.src_addr,
+                err
+            );
+            return;
+        }
+
+        let result = r.handle_request().await;
+        for m in middlewares.iter().rev() {
+            m.after(&r, &result);
+        }
+
+        if let Err(err) = result {
+            log::error!(
+                "request {}: error when handling datagram: {}",
+                r.request_id,
+                err
+            );
+            Server::report_error(on_error, Some(r.src_addr), err);
+        }
+    }
+
+    fn report_error(on_error: &Option<ErrorCallback>, src_addr: Option<SocketAddr>, error: Error) {
+        if let Some(on_error) = on_error {
+            on_error(TurnError {
+                src_addr,
+                error,
+                timestamp: SystemTime::now(),
+            });
+        }
+    }
+
+    async fn handle_command(allocation_manager: &Manager, cmd: Command) {
+        match cmd {
+            Command::DeleteAllocation(five_tuple) => {
+                allocation_manager.delete_allocation(&five_tuple).await;
+            }
+            Command::DeleteAllocationByRelayAddr(relay_addr) => {
+                allocation_manager
+                    .delete_allocation_by_relay_addr(&relay_addr)
+                    .await;
+            }
+            Command::DeleteUserAllocations(username) => {
+                allocation_manager
+                    .delete_allocations_by_username(&username)
+                    .await;
+            }
+            Command::RefreshAllocation(lifetime) => {
+                allocation_manager.refresh_all_allocations(lifetime).await;
+            }
+        }
+    }
+
+    /// commander returns a handle that sends management commands to every
+    /// listener of this server. It stays usable from other tasks after the
+    /// `Server` has been moved, and starts failing once the server is closed.
+    pub fn commander(&self) -> Commander {
+        Commander::new(self.command_txs.clone())
+    }
+
+    /// delete_allocation_by_relay_addr asks every listener to remove the
+    /// allocation owning relay_addr. Deletion happens asynchronously.
+    pub async fn delete_allocation_by_relay_addr(&self, relay_addr: SocketAddr) -> Result<()> {
+        self.commander()
+            .send(Command::DeleteAllocationByRelayAddr(relay_addr))
+            .await?;
+        Ok(())
+    }
+
+    /// kick_user asks every listener to remove all allocations of username,
+    /// logging reason. Deletion happens asynchronously.
+    pub async fn kick_user(&self, username: &str, reason: KickReason) -> Result<()> {
+        log::info!("kicking user {}: {}", username, reason);
+        self.commander()
+            .send(Command::DeleteUserAllocations(username.to_owned()))
+            .await?;
+        Ok(())
+    }
+
+    /// force_refresh_all_allocations asks every listener to reset the lifetime of
+    /// all its allocations to new_lifetime, e.g. to keep them alive through a
+    /// maintenance window. Refreshing happens asynchronously.
+    pub async fn force_refresh_all_allocations(&self, new_lifetime: Duration) -> Result<()> {
+        self.commander()
+            .send(Command::RefreshAllocation(new_lifetime))
+            .await?;
+        Ok(())
+    }
+
+    /// channel_bind_count returns the number of active channel bindings on all listeners
+    pub fn channel_bind_count(&self) -> usize {
+        self.allocation_managers
+            .iter()
+            .map(|m| m.channel_bind_count())
+            .sum()
+    }
+
+    /// connection_count returns the number of open client connections on all
+    /// connection-oriented listeners, e.g. TURN over TCP through a `TcpFramer`,
+    /// with or without an allocation. A listener conn counts while its read
+    /// loop runs. UDP listeners never count.
+    pub fn connection_count(&self) -> usize {
+        self.listener_stats
+            .iter()
+            .map(|stats| stats.connections.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// permission_count returns the number of active permissions on all listeners
+    pub fn permission_count(&self) -> usize {
+        self.allocation_managers
+            .iter()
+            .map(|m| m.permission_count())
+            .sum()
+    }
+
+    /// allocations_by_username_prefix returns the allocations of all users whose
+    /// name starts with prefix on all listeners, see
+    /// `Manager::allocation_by_username_prefix`
+    pub async fn allocations_by_username_prefix(&self, prefix: &str) -> Vec<AllocationInfo> {
+        let mut infos = vec![];
+        for manager in &self.allocation_managers {
+            infos.extend(manager.allocation_by_username_prefix(prefix).await);
+        }
+        infos
+    }
+
+    /// list_listeners returns the address, protocol, allocation count and traffic
+    /// of every listener, in the order of `ServerConfig::conn_configs`. A listener
+    /// with a connection-oriented conn, e.g. a `TcpFramer`, reports TCP.
+    pub async fn list_listeners(&self) -> Vec<ListenerInfo> {
+        let mut listeners = vec![];
+        for (manager, stats) in self.allocation_managers.iter().zip(&self.listener_stats) {
+            listeners.push(ListenerInfo {
+                addr: stats.addr,
+                protocol: stats.protocol,
+                allocation_count: manager.allocations().await.len(),
+                bytes_received: stats.bytes_received.load(Ordering::Relaxed),
+                bytes_sent: stats.bytes_sent.load(Ordering::Relaxed),
+            });
+        }
+        listeners
+    }
+
+    /// manager_for_listener returns the allocation `Manager` of the listener
+    /// bound to addr, for tools that need direct access to its allocations,
+    /// e.g. a custom management protocol. Changes made through it bypass the
+    /// `Command` channel, so they are not ordered with the packets handled by
+    /// the listener read loop and can race with requests of the same clients.
+    pub fn manager_for_listener(&self, addr: SocketAddr) -> Option<Arc<Manager>> {
+        self.allocation_managers
+            .iter()
+            .zip(&self.listener_stats)
+            .find(|(_, stats)| stats.addr == addr)
+            .map(|(manager, _)| Arc::clone(manager))
+    }
+
+    /// uptime returns how long the server has been running, e.g. for health checks
+    pub fn uptime(&self) -> Duration {
+        Instant::now() - self.started_at
+    }
+
+    /// list_nonces returns the outstanding nonces with the time they expire,
+    /// soonest first. The nonce values are only included with
+    /// `ServerConfig::debug_nonces` and are empty otherwise, so by default the
+    /// list only tells how many nonces there are and when they expire.
+    pub async fn list_nonces(&self) -> Vec<(String, Instant)> {
+        let nonce_lifetime = *self.nonce_lifetime.read().await;
+        let mut list: Vec<(String, Instant)> = {
+            let nonces = self.nonces.lock().await;
+            nonces
+                .iter()
+                .map(|(nonce, created)| {
+                    let nonce = if self.debug_nonces {
+                        nonce.clone()
+                    } else {
+                        String::new()
+                    };
+                    (nonce, *created + nonce_lifetime)
+                })
+                .collect()
+        };
+        list.sort_by_key(|(_, expires_at)| *expires_at);
+        list
+    }
+
+    /// auth_metrics returns the number, failures and latencies of the calls made
+    /// to the auth handler on all listeners
+    pub fn auth_metrics(&self) -> AuthMetricsSnapshot {
+        self.auth_metrics.snapshot()
+    }
+
+    /// update_realm switches the server to new_realm. All outstanding nonces are
+    /// flushed, so every client is answered with 438 (Stale Nonce) on its next
+    /// request and has to re-authenticate against the new realm. Listeners with
+    /// their own `ConnConfig::realm` keep it. Active allocations are kept until
+    /// they expire; set `PartialServerConfig::reauth_grace_period` in reconfigure
+    /// to force their clients to re-authenticate sooner.
+    pub async fn update_realm(&self, new_realm: String) {
+        self.reconfigure(PartialServerConfig {
+            realm: Some(new_realm),
+            ..Default::default()
+        })
+        .await;
+    }
+
+    /// reconfigure applies the settings of new_config that are set, all at once.
+    /// Requests already being handled keep the settings they started with.
+    /// Changing the realm flushes all nonces like update_realm, and with
+    /// reauth_grace_period also cuts the lifetime of the affected allocations.
+    pub async fn reconfigure(&self, new_config: PartialServerConfig) {
+        let reauth_grace_period = new_config
+            .realm
+            .as_ref()
+            .and(new_config.reauth_grace_period);
+        self.apply_config(new_config).await;
+
+        // done once the locks are released, refreshing takes the allocation locks
+        if let Some(grace_period) = reauth_grace_period {
+            for (manager, listener) in self
+                .allocation_managers
+                .iter()
+                .zip(&self.config_export.listeners)
+            {
+                if listener.realm.is_none() {
+                    manager.cap_allocation_lifetimes(grace_period).await;
+                }
+            }
+        }
+    }
+
+    async fn apply_config(&self, new_config: PartialServerConfig) {
+        // the locks are taken in the order read_loop takes them
+        let mut realm = self.realm.write().await;
+        let mut channel_bind_timeout = self.channel_bind_timeout.write().await;
+        let mut nonce_lifetime = self.nonce_lifetime.write().await;
+
+        if let Some(new_realm) = new_config.realm {
+            let mut nonces = self.nonces.lock().await;
+            *realm = new_realm;
+            nonces.clear();
+        }
+
+        if let Some(timeout) = new_config.channel_bind_timeout {
+            *channel_bind_timeout = if timeout == Duration::from_secs(0) {
+                DEFAULT_LIFETIME
+            } else {
+                timeout
+            };
+        }
+
+        if let Some(lifetime) = new_config.nonce_lifetime {
+            *nonce_lifetime = if lifetime == Duration::from_secs(0) {
+                NONCE_LIFETIME
+            } else {
+                lifetime
+            };
+        }
+    }
+
+    /// export_config returns the configuration the server runs with, including
+    /// changes made by reconfigure, without secrets such as credentials.
+    pub async fn export_config(&self) -> ServerConfigExport {
+        let mut export = self.config_export.clone();
+        export.realm = self.realm.read().await.clone();
+        export.channel_bind_timeout = *self.channel_bind_timeout.read().await;
+        export.nonce_lifetime = *self.nonce_lifetime.read().await;
+        export
+    }
+
+    /// is_shutdown reports whether close has been called, e.g. for management
+    /// endpoints to reject requests once the server is going away. The listeners
+    /// may still be winding down when it first returns true.
+    pub fn is_shutdown(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
     /// Close stops the TURN Server. It cleans up any associated state and closes all connections it is managing
     pub async fn close(&self) -> Result<()> {
+        self.shutting_down.store(true, Ordering::SeqCst);
         let mut shutdown_tx = self.shutdown_tx.lock().await;
         if let Some(tx) = shutdown_tx.take() {
             // errors if there are no receivers, but that's irrelevant.
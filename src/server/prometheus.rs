@@ -0,0 +1,150 @@
+use super::*;
+
+use std::fmt::Write;
+
+// write_header writes the HELP and TYPE lines of metric name
+fn write_header(out: &mut String, name: &str, metric_type: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, metric_type);
+}
+
+// sum_listeners adds up the listeners that share an address and a protocol, so
+// that every series of a listener metric is unique, keeping the order in which
+// each address first appears
+fn sum_listeners(listeners: Vec<ListenerInfo>) -> Vec<ListenerInfo> {
+    let mut summed: Vec<ListenerInfo> = vec![];
+    for l in listeners {
+        match summed
+            .iter_mut()
+            .find(|s| s.addr == l.addr && s.protocol == l.protocol)
+        {
+            Some(s) => {
+                s.allocation_count += l.allocation_count;
+                s.bytes_received += l.bytes_received;
+                s.bytes_sent += l.bytes_sent;
+            }
+            None => summed.push(l),
+        }
+    }
+    summed
+}
+
+impl Server {
+    /// metrics_text returns the current metrics of the server in the Prometheus
+    /// text exposition format, for any HTTP server or sidecar to serve to a
+    /// scraper. Listener metrics carry the listener address in the `listener`
+    /// label and its protocol in the `protocol` label; the listeners that share
+    /// both, e.g. the `TcpFramer` conns accepted on one TCP port, are added up
+    /// into one series.
+    pub async fn metrics_text(&self) -> String {
+        let mut out = String::new();
+
+        write_header(
+            &mut out,
+            "turn_uptime_seconds",
+            "gauge",
+            "Time since the server was started.",
+        );
+        let _ = writeln!(out, "turn_uptime_seconds {}", self.uptime().as_secs_f64());
+
+        let listeners = sum_listeners(self.list_listeners().await);
+        write_header(
+            &mut out,
+            "turn_allocations",
+            "gauge",
+            "Active allocations per listener.",
+        );
+        for l in &listeners {
+            let _ = writeln!(
+                out,
+                "turn_allocations{{listener=\"{}\",protocol=\"{}\"}} {}",
+                l.addr, l.protocol, l.allocation_count
+            );
+        }
+        write_header(
+            &mut out,
+            "turn_received_bytes_total",
+            "counter",
+            "Bytes received from clients per listener.",
+        );
+        for l in &listeners {
+            let _ = writeln!(
+                out,
+                "turn_received_bytes_total{{listener=\"{}\",protocol=\"{}\"}} {}",
+                l.addr, l.protocol, l.bytes_received
+            );
+        }
+        write_header(
+            &mut out,
+            "turn_sent_bytes_total",
+            "counter",
+            "Bytes sent to clients per listener.",
+        );
+        for l in &listeners {
+            let _ = writeln!(
+                out,
+                "turn_sent_bytes_total{{listener=\"{}\",protocol=\"{}\"}} {}",
+                l.addr, l.protocol, l.bytes_sent
+            );
+        }
+
+        for (name, help, value) in [
+            (
+                "turn_permissions",
+                "Active permissions on all listeners.",
+                self.permission_count(),
+            ),
+            (
+                "turn_channel_bindings",
+                "Active channel bindings on all listeners.",
+                self.channel_bind_count(),
+            ),
+            (
+                "turn_connections",
+                "Open client connections on connection-oriented listeners.",
+                self.connection_count(),
+            ),
+        ] {
+            write_header(&mut out, name, "gauge", help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        let auth = self.auth_metrics();
+        write_header(
+            &mut out,
+            "turn_auth_failures_total",
+            "counter",
+            "Calls to the auth handler that failed.",
+        );
+        let _ = writeln!(out, "turn_auth_failures_total {}", auth.failures);
+        write_header(
+            &mut out,
+            "turn_auth_duration_seconds",
+            "histogram",
+            "Latency of the calls to the auth handler.",
+        );
+        let mut cumulative = 0;
+        for (bound, count) in AUTH_LATENCY_BUCKETS.iter().zip(&auth.latency_buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "turn_auth_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound.as_secs_f64(),
+                cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "turn_auth_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            auth.calls
+        );
+        let _ = writeln!(
+            out,
+            "turn_auth_duration_seconds_sum {}",
+            auth.total_latency.as_secs_f64()
+        );
+        let _ = writeln!(out, "turn_auth_duration_seconds_count {}", auth.calls);
+
+        out
+    }
+}
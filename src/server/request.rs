@@ -2,62 +2,176 @@
 mod request_test;
 
 use crate::allocation::allocation_manager::*;
-use crate::allocation::channel_bind::ChannelBind;
+use crate::allocation::channel_bind::{ChannelBind, ChannelRefreshPolicy};
 use crate::allocation::five_tuple::*;
 use crate::allocation::permission::Permission;
+use crate::auth::metrics::AuthMetrics;
 use crate::auth::*;
 use crate::error::*;
+use crate::proto::addfamily::*;
+use crate::proto::addrerror::AddressErrorCode;
+use crate::proto::appid::*;
+use crate::proto::attrs::ResponseBuilder;
 use crate::proto::chandata::ChannelData;
 use crate::proto::channum::ChannelNumber;
 use crate::proto::data::Data;
+use crate::proto::error_code::ErrorCode;
 use crate::proto::evenport::EvenPort;
 use crate::proto::lifetime::*;
+use crate::proto::msgext::MessageExt;
+use crate::proto::nonce::{get_nonce, NonceGenerator};
+use crate::proto::origin::Origin;
 use crate::proto::peeraddr::PeerAddress;
-use crate::proto::relayaddr::RelayedAddress;
+use crate::proto::reqfamily::*;
 use crate::proto::reqtrans::RequestedTransport;
 use crate::proto::rsrvtoken::ReservationToken;
+use crate::proto::trcounter::TransactionTransmitCounter;
 use crate::proto::*;
 
 use stun::agent::*;
 use stun::attributes::*;
-use stun::error_code::*;
-use stun::fingerprint::*;
 use stun::integrity::*;
 use stun::message::*;
 use stun::textattrs::*;
 use stun::uattrs::*;
-use stun::xoraddr::*;
 
 use util::Conn;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::marker::{Send, Sync};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::SystemTime;
 use tokio::sync::Mutex;
 use tokio::time::{Duration, Instant};
 
-use md5::{Digest, Md5};
-
 pub(crate) const MAXIMUM_ALLOCATION_LIFETIME: Duration = Duration::from_secs(3600); // https://tools.ietf.org/html/rfc5766#section-6.2 defines 3600 seconds recommendation
 pub(crate) const NONCE_LIFETIME: Duration = Duration::from_secs(3600); // https://tools.ietf.org/html/rfc5766#section-4
+pub(crate) const DEFAULT_MIN_ALLOCATION_LIFETIME: Duration = Duration::from_secs(1);
+// TRANSACTION_CACHE_TIMEOUT is how long a response is kept for retransmissions
+// of its request. A client gives up on a transaction after 39.5 seconds with
+// the default RTO, see RFC 5389 Section 7.2.1.
+pub(crate) const TRANSACTION_CACHE_TIMEOUT: Duration = Duration::from_secs(40);
+// TRANSACTION_CACHE_MAX_ENTRIES is the number of responses a listener keeps at
+// most. Beyond it the oldest response is dropped, so a flood of requests can't
+// grow the cache without bound.
+pub(crate) const TRANSACTION_CACHE_MAX_ENTRIES: usize = 4096;
+
+type TransactionKey = (SocketAddr, TransactionId);
+
+// TransactionCache remembers the responses sent on a listener by client and
+// transaction ID. A request retransmitted because its response was lost gets
+// the same response again instead of being handled twice, which would e.g.
+// fail a retransmitted Allocate with 437 (Allocation Mismatch), see RFC 5766
+// Section 6.2 and RFC 5389 Section 7.3.1.
+//
+// The response is resent byte for byte, so its TRANSACTION-TRANSMIT-COUNTER
+// still carries the counts of the first transmission. This is intended:
+// changing it would invalidate the MESSAGE-INTEGRITY computed over it. A
+// client matching the response to its first transmission overestimates the
+// RTT, which only delays its retransmissions.
+#[derive(Default)]
+pub struct TransactionCache {
+    responses: std::sync::Mutex<TransactionCacheState>,
+}
+
+#[derive(Default)]
+struct TransactionCacheState {
+    entries: HashMap<TransactionKey, (Vec<u8>, Instant)>,
+    // order holds the keys by the time their response was inserted, oldest
+    // first, so expired and evicted responses are removed from the front
+    order: VecDeque<(TransactionKey, Instant)>,
+}
+
+impl TransactionCache {
+    pub fn new() -> Self {
+        TransactionCache::default()
+    }
+
+    // get returns the response sent to src_addr for transaction_id, unless it
+    // is older than TRANSACTION_CACHE_TIMEOUT
+    pub fn get(&self, src_addr: SocketAddr, transaction_id: &TransactionId) -> Option<Vec<u8>> {
+        let state = self.responses.lock().unwrap_or_else(|e| e.into_inner());
+        match state.entries.get(&(src_addr, *transaction_id)) {
+            Some((response, sent_at)) if sent_at.elapsed() < TRANSACTION_CACHE_TIMEOUT => {
+                Some(response.clone())
+            }
+            _ => None,
+        }
+    }
+
+    // insert keeps the response sent to src_addr for transaction_id, dropping
+    // expired responses and, above TRANSACTION_CACHE_MAX_ENTRIES, the oldest one
+    pub fn insert(&self, src_addr: SocketAddr, transaction_id: &TransactionId, response: Vec<u8>) {
+        let now = Instant::now();
+        let mut state = self.responses.lock().unwrap_or_else(|e| e.into_inner());
+        while let Some(&(key, sent_at)) = state.order.front() {
+            if now - sent_at < TRANSACTION_CACHE_TIMEOUT
+                && state.order.len() < TRANSACTION_CACHE_MAX_ENTRIES
+            {
+                break;
+            }
+            state.order.pop_front();
+            // the key may have been inserted again since, keep the newer response
+            if matches!(state.entries.get(&key), Some((_, at)) if *at == sent_at) {
+                state.entries.remove(&key);
+            }
+        }
+
+        let key = (src_addr, *transaction_id);
+        state.entries.insert(key, (response, now));
+        state.order.push_back((key, now));
+    }
+
+    // len returns the number of cached responses, including expired ones not
+    // removed yet
+    pub fn len(&self) -> usize {
+        let state = self.responses.lock().unwrap_or_else(|e| e.into_inner());
+        state.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+// next_request_id returns a process-wide unique, increasing id for a Request
+pub(crate) fn next_request_id() -> u64 {
+    NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
 
 // Request contains all the state needed to process a single incoming datagram
 pub struct Request {
     // Current Request State
+    // request_id tells the log lines of one request apart from those of others
+    pub request_id: u64,
     pub conn: Arc<dyn Conn + Send + Sync>,
     pub src_addr: SocketAddr,
     pub buff: Vec<u8>,
+    // origin is the ORIGIN attribute of the STUN message being handled, if any
+    pub origin: Option<String>,
 
     // Server State
     pub allocation_manager: Arc<Manager>,
     pub nonces: Arc<Mutex<HashMap<String, Instant>>>,
+    pub auth_metrics: Arc<AuthMetrics>,
+    pub nonce_generator: Arc<NonceGenerator>,
 
     // User Configuration
     pub auth_handler: Arc<dyn AuthHandler + Send + Sync>,
     pub realm: String,
+    pub enforce_realm: bool,
     pub channel_bind_timeout: Duration,
+    pub channel_bind_refresh_policy: ChannelRefreshPolicy,
+    pub nonce_lifetime: Duration,
+    pub min_allocation_lifetime: Duration,
+    pub software_name: Option<String>,
+    pub allow_app_id: bool,
+    // transaction_cache holds the responses sent on this listener, so
+    // retransmitted requests are answered without being handled again
+    pub transaction_cache: Arc<TransactionCache>,
 }
 
 impl Request {
@@ -68,18 +182,30 @@ impl Request {
         auth_handler: Arc<dyn AuthHandler + Send + Sync>,
     ) -> Self {
         Request {
+            request_id: next_request_id(),
             conn,
             src_addr,
             buff: vec![],
+            origin: None,
             allocation_manager,
             nonces: Arc::new(Mutex::new(HashMap::new())),
+            auth_metrics: Arc::new(AuthMetrics::default()),
+            nonce_generator: Arc::new(NonceGenerator::new()),
             auth_handler,
             realm: String::new(),
+            // no realm is configured yet, so there is nothing to enforce
+            enforce_realm: false,
             channel_bind_timeout: Duration::from_secs(0),
+            channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+            nonce_lifetime: NONCE_LIFETIME,
+            min_allocation_lifetime: DEFAULT_MIN_ALLOCATION_LIFETIME,
+            software_name: None,
+            allow_app_id: false,
+            transaction_cache: Arc::new(TransactionCache::new()),
         }
     }
 
-    // handle_request processes the give Request
+    // handle_request processes the given Request
     pub async fn handle_request(&mut self) -> Result<()> {
         /*log::debug!(
             "received {} bytes of udp from {} on {}",
@@ -96,7 +222,11 @@ impl Request {
     }
 
     async fn handle_data_packet(&mut self) -> Result<()> {
-        log::debug!("received DataPacket from {}", self.src_addr);
+        log::debug!(
+            "request {}: received DataPacket from {}",
+            self.request_id,
+            self.src_addr
+        );
         let mut c = ChannelData {
             raw: self.buff.clone(),
             ..Default::default()
@@ -106,13 +236,30 @@ impl Request {
     }
 
     async fn handle_turn_packet(&mut self) -> Result<()> {
-        log::debug!("handle_turn_packet");
+        log::debug!("request {}: handle_turn_packet", self.request_id);
         let mut m = Message {
             raw: self.buff.clone(),
             ..Default::default()
         };
         m.decode()?;
 
+        // ORIGIN is comprehension-optional, clients sending it work with
+        // servers that don't know it, so a malformed one is just ignored.
+        let mut origin = Origin::default();
+        self.origin = origin.get_from(&m).ok().map(|_| origin.0);
+
+        if m.typ.class == CLASS_REQUEST {
+            if let Some(response) = self.transaction_cache.get(self.src_addr, &m.transaction_id) {
+                log::debug!(
+                    "request {}: retransmission from {}, resending response",
+                    self.request_id,
+                    self.src_addr
+                );
+                self.conn.send_to(&response, self.src_addr).await?;
+                return Ok(());
+            }
+        }
+
         self.process_message_handler(&m).await
     }
 
@@ -140,29 +287,27 @@ impl Request {
         &mut self,
         m: &Message,
         calling_method: Method,
-    ) -> Result<Option<MessageIntegrity>> {
+    ) -> Result<Option<(Username, MessageIntegrity)>> {
         if !m.contains(ATTR_MESSAGE_INTEGRITY) {
-            self.respond_with_nonce(m, calling_method, CODE_UNAUTHORIZED)
+            self.respond_with_nonce(m, calling_method, ErrorCode::Unauthorized)
                 .await?;
             return Ok(None);
         }
 
-        let mut nonce_attr = Nonce::new(ATTR_NONCE, String::new());
         let mut username_attr = Username::new(ATTR_USERNAME, String::new());
         let mut realm_attr = Realm::new(ATTR_REALM, String::new());
-        let bad_request_msg = build_msg(
-            m.transaction_id,
-            MessageType::new(calling_method, CLASS_ERROR_RESPONSE),
-            vec![Box::new(ErrorCodeAttribute {
-                code: CODE_BAD_REQUEST,
-                reason: vec![],
-            })],
-        )?;
-
-        if let Err(err) = nonce_attr.get_from(m) {
-            build_and_send_err(&self.conn, self.src_addr, bad_request_msg, err.into()).await?;
-            return Ok(None);
-        }
+        let bad_request_msg = self
+            .response_builder(m, MessageType::new(calling_method, CLASS_ERROR_RESPONSE))
+            .with_error_code(ErrorCode::BadRequest, "")
+            .build()?;
+
+        let nonce_attr = match get_nonce(m) {
+            Ok(nonce_attr) => nonce_attr,
+            Err(err) => {
+                self.send_err_response(bad_request_msg, err).await?;
+                return Ok(None);
+            }
+        };
 
         let to_be_deleted = {
             // Assert Nonce exists and is not expired
@@ -172,7 +317,7 @@ impl Request {
                 Instant::now()
                     .checked_duration_since(*nonce_creation_time)
                     .unwrap_or_else(|| Duration::from_secs(0))
-                    >= NONCE_LIFETIME
+                    >= self.nonce_lifetime
             } else {
                 true
             };
@@ -184,45 +329,101 @@ impl Request {
         };
 
         if to_be_deleted {
-            self.respond_with_nonce(m, calling_method, CODE_STALE_NONCE)
+            if !self.nonce_generator.is_own(&nonce_attr.text) {
+                log::debug!(
+                    "request {}: {} presented a nonce issued by another server: {}",
+                    self.request_id,
+                    self.src_addr,
+                    nonce_attr.text
+                );
+            }
+            self.respond_with_nonce(m, calling_method, ErrorCode::StaleNonce)
                 .await?;
             return Ok(None);
         }
 
         if let Err(err) = realm_attr.get_from(m) {
-            build_and_send_err(&self.conn, self.src_addr, bad_request_msg, err.into()).await?;
+            self.send_err_response(bad_request_msg, err.into()).await?;
+            return Ok(None);
+        }
+
+        // Reject a foreign realm up front, there is no point asking the
+        // auth_handler for credentials that can't be valid for this server.
+        if self.enforce_realm && realm_attr.text != self.realm {
+            log::debug!(
+                "request {}: realm mismatch from {}: got {}, expected {}",
+                self.request_id,
+                self.src_addr,
+                realm_attr,
+                self.realm
+            );
+            self.respond_with_nonce(m, calling_method, ErrorCode::Unauthorized)
+                .await?;
             return Ok(None);
         }
         if let Err(err) = username_attr.get_from(m) {
-            build_and_send_err(&self.conn, self.src_addr, bad_request_msg, err.into()).await?;
+            self.send_err_response(bad_request_msg, err.into()).await?;
             return Ok(None);
         }
 
-        let our_key = match self.auth_handler.auth_handle(
+        let start = Instant::now();
+        let result = self.auth_handler.auth_handle_with_origin(
             &username_attr.to_string(),
             &realm_attr.to_string(),
             self.src_addr,
-        ) {
+            self.origin.as_deref(),
+        );
+        self.auth_metrics.record(start.elapsed(), result.is_ok());
+
+        let our_key = match result {
             Ok(key) => key,
-            Err(_) => {
-                build_and_send_err(
-                    &self.conn,
+            Err(err @ Error::ErrInvalidAccessToken)
+            | Err(err @ Error::ErrAccessTokenExpired)
+            | Err(err @ Error::ErrAuthRateLimited) => {
+                // RFC 7635 Section 6.2: an invalid or expired access token gets a
+                // 401, so the client can fetch a new one and retry. A rate limited
+                // client gets a 401 as well, so it can retry once the limit allows.
+                log::debug!(
+                    "request {}: auth_handler rejected {}: {}",
+                    self.request_id,
                     self.src_addr,
-                    bad_request_msg,
-                    Error::ErrNoSuchUser,
-                )
-                .await?;
+                    err
+                );
+                self.respond_with_nonce(m, calling_method, ErrorCode::Unauthorized)
+                    .await?;
+                return Ok(None);
+            }
+            Err(_) => {
+                self.send_err_response(bad_request_msg, Error::ErrNoSuchUser)
+                    .await?;
                 return Ok(None);
             }
         };
 
         let mi = MessageIntegrity(our_key);
         if let Err(err) = mi.check(&mut m.clone()) {
-            build_and_send_err(&self.conn, self.src_addr, bad_request_msg, err.into()).await?;
+            self.send_err_response(bad_request_msg, err.into()).await?;
             Ok(None)
         } else {
-            Ok(Some(mi))
+            Ok(Some((username_attr, mi)))
+        }
+    }
+
+    // send_response sends the response msg to the client and caches it for
+    // retransmissions of the request
+    async fn send_response(&self, msg: Message) -> Result<()> {
+        if msg.typ.class == CLASS_SUCCESS_RESPONSE || msg.typ.class == CLASS_ERROR_RESPONSE {
+            self.transaction_cache
+                .insert(self.src_addr, &msg.transaction_id, msg.raw.clone());
         }
+        build_and_send(&self.conn, self.src_addr, msg).await
+    }
+
+    // send_err_response is send_response that returns the original error to
+    // the caller
+    async fn send_err_response(&self, msg: Message, err: Error) -> Result<()> {
+        self.send_response(msg).await?;
+        Err(err)
     }
 
     async fn respond_with_nonce(
@@ -231,7 +432,7 @@ impl Request {
         calling_method: Method,
         response_code: ErrorCode,
     ) -> Result<()> {
-        let nonce = build_nonce()?;
+        let nonce = self.nonce_generator.generate()?;
 
         {
             // Nonce has already been taken
@@ -242,53 +443,102 @@ impl Request {
             nonces.insert(nonce.clone(), Instant::now());
         }
 
-        let msg = build_msg(
-            m.transaction_id,
-            MessageType::new(calling_method, CLASS_ERROR_RESPONSE),
-            vec![
-                Box::new(ErrorCodeAttribute {
-                    code: response_code,
-                    reason: vec![],
-                }),
-                Box::new(Nonce::new(ATTR_NONCE, nonce)),
-                Box::new(Realm::new(ATTR_REALM, self.realm.clone())),
-            ],
-        )?;
+        let msg = self
+            .response_builder(m, MessageType::new(calling_method, CLASS_ERROR_RESPONSE))
+            .with_error_code(response_code, "")
+            .with_attr(Box::new(Nonce::new(ATTR_NONCE, nonce)))
+            .with_attr(Box::new(Realm::new(ATTR_REALM, self.realm.clone())))
+            .build()?;
 
-        build_and_send(&self.conn, self.src_addr, msg).await
+        self.send_response(msg).await
     }
 
-    pub(crate) async fn handle_binding_request(&mut self, m: &Message) -> Result<()> {
-        log::debug!("received BindingRequest from {}", self.src_addr);
+    // response_builder starts a response to the request m, echoing back any
+    // attributes the client expects to see reflected in the response and
+    // adding the server-wide ones such as SOFTWARE.
+    fn response_builder(&self, m: &Message, msg_type: MessageType) -> ResponseBuilder {
+        let mut builder = ResponseBuilder::new(m.transaction_id, msg_type);
+
+        // https://tools.ietf.org/html/rfc7982#section-3.2
+        // The server copies the req value and sets resp to the number of
+        // responses sent for this transaction, which is always one here.
+        // Retransmissions get the cached response, see TransactionCache.
+        if let Some(mut transmit_counter) = m.get_attr::<TransactionTransmitCounter>() {
+            transmit_counter.resp = 1;
+            builder = builder.with_attr(Box::new(transmit_counter));
+        }
 
-        let (ip, port) = (self.src_addr.ip(), self.src_addr.port());
+        if let Some(software_name) = &self.software_name {
+            builder = builder.with_attr(Box::new(Software::new(
+                ATTR_SOFTWARE,
+                software_name.clone(),
+            )));
+        }
 
-        let msg = build_msg(
-            m.transaction_id,
-            BINDING_SUCCESS,
-            vec![
-                Box::new(XorMappedAddress { ip, port }),
-                Box::new(FINGERPRINT),
-            ],
-        )?;
+        builder
+    }
 
-        build_and_send(&self.conn, self.src_addr, msg).await
+    pub(crate) async fn handle_binding_request(&mut self, m: &Message) -> Result<()> {
+        log::debug!(
+            "request {}: received BindingRequest from {}",
+            self.request_id,
+            self.src_addr
+        );
+
+        let msg = self
+            .response_builder(m, BINDING_SUCCESS)
+            .with_xor_mapped_address(self.src_addr)
+            .with_fingerprint()
+            .build()?;
+
+        self.send_response(msg).await
     }
 
     // // https://tools.ietf.org/html/rfc5766#section-6.2
     pub(crate) async fn handle_allocate_request(&mut self, m: &Message) -> Result<()> {
-        log::debug!("received AllocateRequest from {}", self.src_addr);
+        log::debug!(
+            "request {}: received AllocateRequest from {}",
+            self.request_id,
+            self.src_addr
+        );
+
+        // REQUESTED-TRANSPORT (step 3 below) is checked first. It needs no
+        // state, and rejecting a bad request before the credentials are looked
+        // up doesn't tell the client anything about them through the timing.
+        //
+        // 3. The server checks if the request contains a REQUESTED-TRANSPORT
+        //    attribute.  If the REQUESTED-TRANSPORT attribute is not included
+        //    or is malformed, the server rejects the request with a 400 (Bad
+        //    Request) error.  Otherwise, if the attribute is included but
+        //    specifies a protocol other that UDP, the server rejects the
+        //    request with a 442 (Unsupported Transport Protocol) error.
+        let mut requested_transport = RequestedTransport::default();
+        if let Err(err) = requested_transport.get_from(m) {
+            let bad_request_msg = self
+                .response_builder(m, MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE))
+                .with_error_code(ErrorCode::BadRequest, "")
+                .build()?;
+            return self.send_err_response(bad_request_msg, err.into()).await;
+        } else if requested_transport.protocol != PROTO_UDP {
+            let msg = self
+                .response_builder(m, MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE))
+                .with_error_code(ErrorCode::UnsupportedTransportProtocol, "")
+                .build()?;
+            return self
+                .send_err_response(msg, Error::ErrRequestedTransportMustBeUdp)
+                .await;
+        }
 
         // 1. The server MUST require that the request be authenticated.  This
         //    authentication MUST be done using the long-term credential
         //    mechanism of [https://tools.ietf.org/html/rfc5389#section-10.2.2]
         //    unless the client and server agree to use another mechanism through
         //    some procedure outside the scope of this document.
-        let message_integrity =
+        let (username, message_integrity) =
             if let Some(mi) = self.authenticate_request(m, METHOD_ALLOCATE).await? {
                 mi
             } else {
-                log::debug!("no MessageIntegrity");
+                log::debug!("request {}: no MessageIntegrity", self.request_id);
                 return Ok(());
             };
 
@@ -309,57 +559,13 @@ impl Request {
             .await
             .is_some()
         {
-            let msg = build_msg(
-                m.transaction_id,
-                MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE),
-                vec![Box::new(ErrorCodeAttribute {
-                    code: CODE_ALLOC_MISMATCH,
-                    reason: vec![],
-                })],
-            )?;
-            return build_and_send_err(
-                &self.conn,
-                self.src_addr,
-                msg,
-                Error::ErrRelayAlreadyAllocatedForFiveTuple,
-            )
-            .await;
-        }
-
-        // 3. The server checks if the request contains a REQUESTED-TRANSPORT
-        //    attribute.  If the REQUESTED-TRANSPORT attribute is not included
-        //    or is malformed, the server rejects the request with a 400 (Bad
-        //    Request) error.  Otherwise, if the attribute is included but
-        //    specifies a protocol other that UDP, the server rejects the
-        //    request with a 442 (Unsupported Transport Protocol) error.
-        let mut requested_transport = RequestedTransport::default();
-        if let Err(err) = requested_transport.get_from(m) {
-            let bad_request_msg = build_msg(
-                m.transaction_id,
-                MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE),
-                vec![Box::new(ErrorCodeAttribute {
-                    code: CODE_BAD_REQUEST,
-                    reason: vec![],
-                })],
-            )?;
-            return build_and_send_err(&self.conn, self.src_addr, bad_request_msg, err.into())
+            let msg = self
+                .response_builder(m, MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE))
+                .with_error_code(ErrorCode::AllocationMismatch, "")
+                .build()?;
+            return self
+                .send_err_response(msg, Error::ErrRelayAlreadyAllocatedForFiveTuple)
                 .await;
-        } else if requested_transport.protocol != PROTO_UDP {
-            let msg = build_msg(
-                m.transaction_id,
-                MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE),
-                vec![Box::new(ErrorCodeAttribute {
-                    code: CODE_UNSUPPORTED_TRANS_PROTO,
-                    reason: vec![],
-                })],
-            )?;
-            return build_and_send_err(
-                &self.conn,
-                self.src_addr,
-                msg,
-                Error::ErrRequestedTransportMustBeUdp,
-            )
-            .await;
         }
 
         // 4. The request may contain a DONT-FRAGMENT attribute.  If it does,
@@ -367,25 +573,15 @@ impl Request {
         //    bit set to 1 (see Section 12), then the server treats the DONT-
         //    FRAGMENT attribute in the Allocate request as an unknown
         //    comprehension-required attribute.
-        if m.contains(ATTR_DONT_FRAGMENT) {
-            let msg = build_msg(
-                m.transaction_id,
-                MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE),
-                vec![
-                    Box::new(ErrorCodeAttribute {
-                        code: CODE_UNKNOWN_ATTRIBUTE,
-                        reason: vec![],
-                    }),
-                    Box::new(UnknownAttributes(vec![ATTR_DONT_FRAGMENT])),
-                ],
-            )?;
-            return build_and_send_err(
-                &self.conn,
-                self.src_addr,
-                msg,
-                Error::ErrNoDontFragmentSupport,
-            )
-            .await;
+        if m.contains(ATTR_DONT_FRAGMENT) && !self.allocation_manager.supports_dont_fragment() {
+            let msg = self
+                .response_builder(m, MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE))
+                .with_error_code(ErrorCode::UnknownAttribute, "")
+                .with_attr(Box::new(UnknownAttributes(vec![ATTR_DONT_FRAGMENT])))
+                .build()?;
+            return self
+                .send_err_response(msg, Error::ErrNoDontFragmentSupport)
+                .await;
         }
 
         // 5.  The server checks if the request contains a RESERVATION-TOKEN
@@ -396,25 +592,31 @@ impl Request {
         //     corresponding relayed transport address is still available).  If
         //     the token is not valid for some reason, the server rejects the
         //     request with a 508 (Insufficient Capacity) error.
-        let mut reservation_token_attr = ReservationToken::default();
-        if reservation_token_attr.get_from(m).is_ok() {
-            let mut even_port = EvenPort::default();
-            if even_port.get_from(m).is_ok() {
-                let bad_request_msg = build_msg(
-                    m.transaction_id,
-                    MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE),
-                    vec![Box::new(ErrorCodeAttribute {
-                        code: CODE_BAD_REQUEST,
-                        reason: vec![],
-                    })],
-                )?;
-                return build_and_send_err(
-                    &self.conn,
-                    self.src_addr,
-                    bad_request_msg,
-                    Error::ErrRequestWithReservationTokenAndEvenPort,
-                )
-                .await;
+        if let Some(reservation_token_attr) = m.get_attr::<ReservationToken>() {
+            if m.get_attr::<EvenPort>().is_some() {
+                let bad_request_msg = self
+                    .response_builder(m, MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE))
+                    .with_error_code(ErrorCode::BadRequest, "")
+                    .build()?;
+                return self
+                    .send_err_response(
+                        bad_request_msg,
+                        Error::ErrRequestWithReservationTokenAndEvenPort,
+                    )
+                    .await;
+            }
+
+            let token = String::from_utf8_lossy(&reservation_token_attr.0);
+            if let Some(port) = self.allocation_manager.get_reservation(&token).await {
+                requested_port = port;
+            } else {
+                let insufficent_capacity_msg = self
+                    .response_builder(m, MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE))
+                    .with_error_code(ErrorCode::InsufficientCapacity, "")
+                    .build()?;
+                return self
+                    .send_err_response(insufficent_capacity_msg, Error::ErrReservationNotFound)
+                    .await;
             }
         }
 
@@ -424,35 +626,50 @@ impl Request {
         //    below).  If the server cannot satisfy the request, then the
         //    server rejects the request with a 508 (Insufficient Capacity)
         //    error.
-        let mut even_port = EvenPort::default();
-        if even_port.get_from(m).is_ok() {
-            let mut random_port = 1;
-
-            while random_port % 2 != 0 {
-                random_port = match self.allocation_manager.get_random_even_port().await {
-                    Ok(port) => port,
-                    Err(err) => {
-                        let insufficent_capacity_msg = build_msg(
-                            m.transaction_id,
+        if let Some(even_port) = m.get_attr::<EvenPort>() {
+            requested_port = match self.allocation_manager.get_random_even_port().await {
+                Ok(port) => port,
+                Err(err) => {
+                    let insufficent_capacity_msg = self
+                        .response_builder(
+                            m,
                             MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE),
-                            vec![Box::new(ErrorCodeAttribute {
-                                code: CODE_INSUFFICIENT_CAPACITY,
-                                reason: vec![],
-                            })],
-                        )?;
-                        return build_and_send_err(
-                            &self.conn,
-                            self.src_addr,
-                            insufficent_capacity_msg,
-                            err,
                         )
-                        .await;
-                    }
-                };
+                        .with_error_code(ErrorCode::InsufficientCapacity, "")
+                        .build()?;
+                    return self.send_err_response(insufficent_capacity_msg, err).await;
+                }
+            };
+            // only the R bit asks to hold the next-higher port in reserve
+            if even_port.reserve_port {
+                reservation_token = rand_seq(8);
             }
+        }
+
+        // https://tools.ietf.org/html/rfc8656#section-7.2
+        // If the request contains an ADDITIONAL-ADDRESS-FAMILY attribute together
+        // with a REQUESTED-ADDRESS-FAMILY attribute, or asks for anything other
+        // than IPv6, the server rejects the request with a 400 (Bad Request) error.
+        let mut additional_family = AdditionalAddressFamily::default();
+        let has_additional_family = m.contains(ATTR_ADDITIONAL_ADDRESS_FAMILY);
+        if has_additional_family {
+            let err = if let Err(err) = additional_family.get_from(m) {
+                Some(err.into())
+            } else if RequestedAddressFamily::default().get_from(m).is_ok() {
+                Some(Error::ErrRequestWithRequestedAndAdditionalFamily)
+            } else if additional_family != ADDITIONAL_FAMILY_IPV6 {
+                Some(Error::ErrAdditionalAddressFamilyMustBeIpv6)
+            } else {
+                None
+            };
 
-            requested_port = random_port;
-            reservation_token = rand_seq(8);
+            if let Some(err) = err {
+                let bad_request_msg = self
+                    .response_builder(m, MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE))
+                    .with_error_code(ErrorCode::BadRequest, "")
+                    .build()?;
+                return self.send_err_response(bad_request_msg, err).await;
+            }
         }
 
         // 7. At any point, the server MAY choose to reject the request with a
@@ -466,34 +683,78 @@ impl Request {
         //    with a 300 (Try Alternate) error if it wishes to redirect the
         //    client to a different server.  The use of this error code and
         //    attribute follow the specification in [RFC5389].
-        let lifetime_duration = allocation_lifetime(m);
-        let a = match self
+
+        // A LIFETIME of 0 only makes sense in a Refresh, where it deletes the
+        // allocation. Lifetimes below min_allocation_lifetime are rejected too,
+        // the allocation would expire before it could be used.
+        let mut requested_lifetime = Lifetime::default();
+        if requested_lifetime.get_from(m).is_ok() {
+            let err = if requested_lifetime.0 == Duration::from_secs(0) {
+                Some(Error::ErrLifetimeZero)
+            } else if requested_lifetime.0 < self.min_allocation_lifetime {
+                Some(Error::ErrLifetimeTooShort)
+            } else {
+                None
+            };
+
+            if let Some(err) = err {
+                let bad_request_msg = self
+                    .response_builder(m, MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE))
+                    .with_error_code(ErrorCode::BadRequest, "")
+                    .build()?;
+                return self.send_err_response(bad_request_msg, err).await;
+            }
+        }
+
+        // APP-ID is comprehension-optional, so it is ignored unless the server
+        // accepts it. An APP-ID that can't be decoded is a 400 (Bad Request).
+        let mut app_id = None;
+        if m.contains(ATTR_APP_ID) {
+            if self.allow_app_id {
+                let mut app_id_attr = AppId::default();
+                if let Err(err) = app_id_attr.get_from(m) {
+                    let bad_request_msg = self
+                        .response_builder(
+                            m,
+                            MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE),
+                        )
+                        .with_error_code(ErrorCode::BadRequest, "")
+                        .build()?;
+                    return self.send_err_response(bad_request_msg, err.into()).await;
+                }
+                app_id = Some(app_id_attr.0);
+            } else {
+                log::debug!(
+                    "request {}: ignoring APP-ID from {}, it is not allowed",
+                    self.request_id,
+                    self.src_addr
+                );
+            }
+        }
+
+        let lifetime_duration = self
+            .allocation_manager
+            .allocation_lifetime(&username.text, allocation_lifetime(m));
+        let result = self
             .allocation_manager
-            .create_allocation(
+            .create_allocation_internal(
                 five_tuple,
                 Arc::clone(&self.conn),
                 requested_port,
                 lifetime_duration,
+                username,
+                app_id,
+                has_additional_family,
             )
-            .await
-        {
+            .await;
+        let a = match result {
             Ok(a) => a,
             Err(err) => {
-                let insufficent_capacity_msg = build_msg(
-                    m.transaction_id,
-                    MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE),
-                    vec![Box::new(ErrorCodeAttribute {
-                        code: CODE_INSUFFICIENT_CAPACITY,
-                        reason: vec![],
-                    })],
-                )?;
-                return build_and_send_err(
-                    &self.conn,
-                    self.src_addr,
-                    insufficent_capacity_msg,
-                    err,
-                )
-                .await;
+                let insufficent_capacity_msg = self
+                    .response_builder(m, MessageType::new(METHOD_ALLOCATE, CLASS_ERROR_RESPONSE))
+                    .with_error_code(ErrorCode::InsufficientCapacity, "")
+                    .build()?;
+                return self.send_err_response(insufficent_capacity_msg, err).await;
             }
         };
 
@@ -508,60 +769,75 @@ impl Request {
         //   * An XOR-MAPPED-ADDRESS attribute containing the client's IP address
         //     and port (from the 5-tuple).
 
-        let (src_ip, src_port) = (self.src_addr.ip(), self.src_addr.port());
-        let (relay_ip, relay_port) = {
+        let (relay_addr, additional_relay_addr) = {
             let a = a.lock().await;
-            (a.relay_addr.ip(), a.relay_addr.port())
+            (a.relay_addr, a.additional_relay_addr)
         };
 
         let msg = {
             if !reservation_token.is_empty() {
+                // the reserved port is the next-higher one, RFC 5766 Section 6.2
                 self.allocation_manager
-                    .create_reservation(reservation_token.clone(), relay_port)
+                    .create_reservation(
+                        reservation_token.clone(),
+                        relay_addr.port().saturating_add(1),
+                    )
                     .await;
             }
 
-            let mut response_attrs: Vec<Box<dyn Setter>> = vec![
-                Box::new(RelayedAddress {
-                    ip: relay_ip,
-                    port: relay_port,
-                }),
-                Box::new(Lifetime(lifetime_duration)),
-                Box::new(XorMappedAddress {
-                    ip: src_ip,
-                    port: src_port,
-                }),
-            ];
+            let mut builder = self
+                .response_builder(m, MessageType::new(METHOD_ALLOCATE, CLASS_SUCCESS_RESPONSE))
+                .with_relay_address(relay_addr);
+
+            if let Some(addr) = additional_relay_addr {
+                builder = builder.with_relay_address(addr);
+            } else if has_additional_family {
+                // https://tools.ietf.org/html/rfc8656#section-7.2
+                // The allocation succeeds with the IPv4 relayed address alone,
+                // ADDRESS-ERROR-CODE tells the client why there is no IPv6 one.
+                builder = builder.with_attr(Box::new(AddressErrorCode::new(
+                    REQUESTED_FAMILY_IPV6,
+                    ErrorCode::AddressFamilyNotSupported,
+                )));
+            }
+
+            builder = builder
+                .with_lifetime(lifetime_duration)
+                .with_xor_mapped_address(self.src_addr);
 
             if !reservation_token.is_empty() {
-                response_attrs.push(Box::new(ReservationToken(
+                builder = builder.with_attr(Box::new(ReservationToken(
                     reservation_token.as_bytes().to_vec(),
                 )));
             }
 
-            response_attrs.push(Box::new(message_integrity));
-            build_msg(
-                m.transaction_id,
-                MessageType::new(METHOD_ALLOCATE, CLASS_SUCCESS_RESPONSE),
-                response_attrs,
-            )?
+            builder.with_message_integrity(message_integrity).build()?
         };
 
-        build_and_send(&self.conn, self.src_addr, msg).await
+        self.send_response(msg).await
     }
 
     pub(crate) async fn handle_refresh_request(&mut self, m: &Message) -> Result<()> {
-        log::debug!("received RefreshRequest from {}", self.src_addr);
+        log::debug!(
+            "request {}: received RefreshRequest from {}",
+            self.request_id,
+            self.src_addr
+        );
 
-        let message_integrity =
+        let (username, message_integrity) =
             if let Some(mi) = self.authenticate_request(m, METHOD_REFRESH).await? {
                 mi
             } else {
-                log::debug!("no MessageIntegrity");
+                log::debug!("request {}: no MessageIntegrity", self.request_id);
                 return Ok(());
             };
 
-        let lifetime_duration = allocation_lifetime(m);
+        let mut lifetime_duration = allocation_lifetime(m);
+        if lifetime_duration != Duration::from_secs(0) {
+            lifetime_duration = self
+                .allocation_manager
+                .allocation_lifetime(&username.text, lifetime_duration);
+        }
         let five_tuple = FiveTuple {
             src_addr: self.src_addr,
             dst_addr: self.conn.local_addr().await?,
@@ -580,20 +856,21 @@ impl Request {
             self.allocation_manager.delete_allocation(&five_tuple).await;
         }
 
-        let msg = build_msg(
-            m.transaction_id,
-            MessageType::new(METHOD_REFRESH, CLASS_SUCCESS_RESPONSE),
-            vec![
-                Box::new(Lifetime(lifetime_duration)),
-                Box::new(message_integrity),
-            ],
-        )?;
+        let msg = self
+            .response_builder(m, MessageType::new(METHOD_REFRESH, CLASS_SUCCESS_RESPONSE))
+            .with_lifetime(lifetime_duration)
+            .with_message_integrity(message_integrity)
+            .build()?;
 
-        build_and_send(&self.conn, self.src_addr, msg).await
+        self.send_response(msg).await
     }
 
     pub(crate) async fn handle_create_permission_request(&mut self, m: &Message) -> Result<()> {
-        log::debug!("received CreatePermission from {}", self.src_addr);
+        log::debug!(
+            "request {}: received CreatePermission from {}",
+            self.request_id,
+            self.src_addr
+        );
 
         let a = self
             .allocation_manager
@@ -605,16 +882,17 @@ impl Request {
             .await;
 
         if let Some(a) = a {
-            let message_integrity = if let Some(mi) = self
+            let message_integrity = if let Some((_, mi)) = self
                 .authenticate_request(m, METHOD_CREATE_PERMISSION)
                 .await?
             {
                 mi
             } else {
-                log::debug!("no MessageIntegrity");
+                log::debug!("request {}: no MessageIntegrity", self.request_id);
                 return Ok(());
             };
             let mut add_count = 0;
+            let mut failed_families = vec![];
 
             {
                 let a = a.lock().await;
@@ -624,44 +902,84 @@ impl Request {
                     }
 
                     let mut peer_address = PeerAddress::default();
-                    if peer_address.get_from(m).is_err() {
+                    if peer_address.get_from_raw(m, &attr.value).is_err() {
                         add_count = 0;
+                        failed_families.clear();
                         break;
                     }
 
+                    let addr = SocketAddr::new(peer_address.ip, peer_address.port);
+
+                    // RFC 6156 Section 4.2: peers the allocation has no relayed
+                    // address for are reported with ADDRESS-ERROR-CODE instead
+                    // of failing the other permissions of the request.
+                    if !a.relays_family(&addr) {
+                        log::debug!(
+                            "request {}: no relayed address for the family of peer {}",
+                            self.request_id,
+                            addr
+                        );
+                        let family = if addr.is_ipv4() {
+                            REQUESTED_FAMILY_IPV4
+                        } else {
+                            REQUESTED_FAMILY_IPV6
+                        };
+                        if !failed_families.contains(&family) {
+                            failed_families.push(family);
+                        }
+                        continue;
+                    }
+
                     log::debug!(
-                        "adding permission for {}",
-                        format!("{}:{}", peer_address.ip, peer_address.port)
+                        "request {}: adding permission for {}:{}",
+                        self.request_id,
+                        peer_address.ip,
+                        peer_address.port
                     );
 
-                    a.add_permission(Permission::new(SocketAddr::new(
-                        peer_address.ip,
-                        peer_address.port,
-                    )))
-                    .await;
+                    a.add_permission(Permission::new(addr)).await;
                     add_count += 1;
                 }
             }
 
-            let mut resp_class = CLASS_SUCCESS_RESPONSE;
-            if add_count == 0 {
-                resp_class = CLASS_ERROR_RESPONSE;
-            }
+            let msg = if add_count == 0 && !failed_families.is_empty() {
+                self.response_builder(
+                    m,
+                    MessageType::new(METHOD_CREATE_PERMISSION, CLASS_ERROR_RESPONSE),
+                )
+                .with_error_code(ErrorCode::PeerAddressFamilyMismatch, "")
+                .with_message_integrity(message_integrity)
+                .build()?
+            } else {
+                let mut resp_class = CLASS_SUCCESS_RESPONSE;
+                if add_count == 0 {
+                    resp_class = CLASS_ERROR_RESPONSE;
+                }
+
+                let mut builder = self
+                    .response_builder(m, MessageType::new(METHOD_CREATE_PERMISSION, resp_class));
+                for family in failed_families {
+                    builder = builder.with_attr(Box::new(AddressErrorCode::new(
+                        family,
+                        ErrorCode::PeerAddressFamilyMismatch,
+                    )));
+                }
 
-            let msg = build_msg(
-                m.transaction_id,
-                MessageType::new(METHOD_CREATE_PERMISSION, resp_class),
-                vec![Box::new(message_integrity)],
-            )?;
+                builder.with_message_integrity(message_integrity).build()?
+            };
 
-            build_and_send(&self.conn, self.src_addr, msg).await
+            self.send_response(msg).await
         } else {
             Err(Error::ErrNoAllocationFound)
         }
     }
 
     pub(crate) async fn handle_send_indication(&mut self, m: &Message) -> Result<()> {
-        log::debug!("received SendIndication from {}", self.src_addr);
+        log::debug!(
+            "request {}: received SendIndication from {}",
+            self.request_id,
+            self.src_addr
+        );
 
         let a = self
             .allocation_manager
@@ -690,7 +1008,49 @@ impl Request {
             }
 
             let a = a.lock().await;
-            let l = a.relay_socket.send_to(&data_attr.0, msg_dst).await?;
+            let relay_socket = a.relay_socket_for(&msg_dst);
+            let l = if m.contains(ATTR_DONT_FRAGMENT) {
+                // RFC 5766 Section 10.2: DONT-FRAGMENT asks for the DF bit, e.g.
+                // for path MTU probing, where a rejected probe is expected
+                match self
+                    .allocation_manager
+                    .send_to_dont_fragment(relay_socket.as_ref(), &data_attr.0, msg_dst)
+                    .await
+                {
+                    Err(err @ Error::ErrPacketTooBig) => {
+                        log::debug!(
+                            "request {}: {} bytes from {} to {} too big to send unfragmented",
+                            self.request_id,
+                            data_attr.0.len(),
+                            self.src_addr,
+                            msg_dst
+                        );
+                        // tell the client like an ICMP packet too big on the
+                        // way to the peer would
+                        a.forward_icmp_error(&msg_dst, &err).await;
+                        return Ok(());
+                    }
+                    Err(err) => {
+                        a.log_relay_send_error(&msg_dst, None, &err);
+                        a.forward_icmp_error(&msg_dst, &err).await;
+                        return Err(err);
+                    }
+                    Ok(l) => l,
+                }
+            } else {
+                match relay_socket.send_to(&data_attr.0, msg_dst).await {
+                    Ok(l) => l,
+                    Err(err) => {
+                        let err = err.into();
+                        a.log_relay_send_error(&msg_dst, None, &err);
+                        a.forward_icmp_error(&msg_dst, &err).await;
+                        return Err(err);
+                    }
+                }
+            };
+            a.counters
+                .bytes_relayed_out
+                .fetch_add(l as u64, Ordering::Relaxed);
             if l != data_attr.0.len() {
                 Err(Error::ErrShortWrite)
             } else {
@@ -702,7 +1062,11 @@ impl Request {
     }
 
     pub(crate) async fn handle_channel_bind_request(&mut self, m: &Message) -> Result<()> {
-        log::debug!("received ChannelBindRequest from {}", self.src_addr);
+        log::debug!(
+            "request {}: received ChannelBindRequest from {}",
+            self.request_id,
+            self.src_addr
+        );
 
         let a = self
             .allocation_manager
@@ -714,65 +1078,78 @@ impl Request {
             .await;
 
         if let Some(a) = a {
-            let bad_request_msg = build_msg(
-                m.transaction_id,
-                MessageType::new(METHOD_CHANNEL_BIND, CLASS_ERROR_RESPONSE),
-                vec![Box::new(ErrorCodeAttribute {
-                    code: CODE_BAD_REQUEST,
-                    reason: vec![],
-                })],
-            )?;
+            let bad_request_msg = self
+                .response_builder(
+                    m,
+                    MessageType::new(METHOD_CHANNEL_BIND, CLASS_ERROR_RESPONSE),
+                )
+                .with_error_code(ErrorCode::BadRequest, "")
+                .build()?;
 
             let message_integrity =
-                if let Some(mi) = self.authenticate_request(m, METHOD_CHANNEL_BIND).await? {
+                if let Some((_, mi)) = self.authenticate_request(m, METHOD_CHANNEL_BIND).await? {
                     mi
                 } else {
-                    log::debug!("no MessageIntegrity");
+                    log::debug!("request {}: no MessageIntegrity", self.request_id);
                     return Ok(());
                 };
             let mut channel = ChannelNumber::default();
             if let Err(err) = channel.get_from(m) {
-                return build_and_send_err(&self.conn, self.src_addr, bad_request_msg, err.into())
+                return self.send_err_response(bad_request_msg, err.into()).await;
+            }
+            // https://tools.ietf.org/html/rfc5766#section-11.2
+            // The channel number must be in the range 0x4000 through 0x7FFF.
+            if !channel.valid() {
+                return self
+                    .send_err_response(bad_request_msg, Error::ErrInvalidChannelNumber)
                     .await;
             }
 
             let mut peer_addr = PeerAddress::default();
             if let Err(err) = peer_addr.get_from(m) {
-                return build_and_send_err(&self.conn, self.src_addr, bad_request_msg, err.into())
-                    .await;
+                return self.send_err_response(bad_request_msg, err.into()).await;
             }
 
             log::debug!(
-                "binding channel {} to {}",
+                "request {}: binding channel {} to {}:{}",
+                self.request_id,
                 channel,
-                format!("{}:{}", peer_addr.ip, peer_addr.port)
+                peer_addr.ip,
+                peer_addr.port
             );
 
             let result = {
                 let a = a.lock().await;
-                a.add_channel_bind(
+                a.add_channel_bind_with_policy(
                     ChannelBind::new(channel, SocketAddr::new(peer_addr.ip, peer_addr.port)),
                     self.channel_bind_timeout,
+                    self.channel_bind_refresh_policy,
                 )
                 .await
             };
             if let Err(err) = result {
-                return build_and_send_err(&self.conn, self.src_addr, bad_request_msg, err).await;
+                return self.send_err_response(bad_request_msg, err).await;
             }
 
-            let msg = build_msg(
-                m.transaction_id,
-                MessageType::new(METHOD_CHANNEL_BIND, CLASS_SUCCESS_RESPONSE),
-                vec![Box::new(message_integrity)],
-            )?;
-            build_and_send(&self.conn, self.src_addr, msg).await
+            let msg = self
+                .response_builder(
+                    m,
+                    MessageType::new(METHOD_CHANNEL_BIND, CLASS_SUCCESS_RESPONSE),
+                )
+                .with_message_integrity(message_integrity)
+                .build()?;
+            self.send_response(msg).await
         } else {
             Err(Error::ErrNoAllocationFound)
         }
     }
 
     pub(crate) async fn handle_channel_data(&mut self, c: &ChannelData) -> Result<()> {
-        log::debug!("received ChannelData from {}", self.src_addr);
+        log::debug!(
+            "request {}: received ChannelData from {}",
+            self.request_id,
+            self.src_addr
+        );
 
         let a = self
             .allocation_manager
@@ -787,7 +1164,18 @@ impl Request {
             let a = a.lock().await;
             let channel = a.get_channel_addr(&c.number).await;
             if let Some(peer) = channel {
-                let l = a.relay_socket.send_to(&c.data, peer).await?;
+                let l = match a.relay_socket_for(&peer).send_to(&c.data, peer).await {
+                    Ok(l) => l,
+                    Err(err) => {
+                        let err = err.into();
+                        a.log_relay_send_error(&peer, Some(c.number), &err);
+                        a.forward_icmp_error(&peer, &err).await;
+                        return Err(err);
+                    }
+                };
+                a.counters
+                    .bytes_relayed_out
+                    .fetch_add(l as u64, Ordering::Relaxed);
                 if l != c.data.len() {
                     Err(Error::ErrShortWrite)
                 } else {
@@ -808,30 +1196,7 @@ pub(crate) fn rand_seq(n: usize) -> String {
     for b in &mut buf {
         *b = letters[rand::random::<usize>() % letters.len()];
     }
-    if let Ok(s) = String::from_utf8(buf) {
-        s
-    } else {
-        String::new()
-    }
-}
-
-pub(crate) fn build_nonce() -> Result<String> {
-    /* #nosec */
-    let mut s = String::new();
-    s.push_str(
-        format!(
-            "{}",
-            SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)?
-                .as_nanos()
-        )
-        .as_str(),
-    );
-    s.push_str(format!("{}", rand::random::<u64>()).as_str());
-
-    let mut h = Md5::new();
-    h.update(s.as_bytes());
-    Ok(format!("{:x}", h.finalize()))
+    String::from_utf8(buf).unwrap_or_default()
 }
 
 pub(crate) async fn build_and_send(
@@ -843,47 +1208,9 @@ pub(crate) async fn build_and_send(
     Ok(())
 }
 
-// Send a STUN packet and return the original error to the caller
-pub(crate) async fn build_and_send_err(
-    conn: &Arc<dyn Conn + Send + Sync>,
-    dst: SocketAddr,
-    msg: Message,
-    err: Error,
-) -> Result<()> {
-    if let Err(send_err) = build_and_send(conn, dst, msg).await {
-        Err(send_err)
-    } else {
-        Err(err)
-    }
-}
-
-pub(crate) fn build_msg(
-    transaction_id: TransactionId,
-    msg_type: MessageType,
-    mut additional: Vec<Box<dyn Setter>>,
-) -> Result<Message> {
-    let mut attrs: Vec<Box<dyn Setter>> = vec![
-        Box::new(Message {
-            transaction_id,
-            ..Default::default()
-        }),
-        Box::new(msg_type),
-    ];
-
-    attrs.append(&mut additional);
-
-    let mut msg = Message::new();
-    msg.build(&attrs)?;
-    Ok(msg)
-}
-
 pub(crate) fn allocation_lifetime(m: &Message) -> Duration {
-    let mut lifetime_duration = DEFAULT_LIFETIME;
-
-    let mut lifetime = Lifetime::default();
-    if lifetime.get_from(m).is_ok() && lifetime.0 < MAXIMUM_ALLOCATION_LIFETIME {
-        lifetime_duration = lifetime.0;
+    match m.get_attr::<Lifetime>() {
+        Some(lifetime) if lifetime.0 < MAXIMUM_ALLOCATION_LIFETIME => lifetime.0,
+        _ => DEFAULT_LIFETIME,
     }
-
-    lifetime_duration
 }
@@ -1,10 +1,20 @@
 use super::*;
+use crate::auth::r#static::StaticAuthHandler;
+use crate::proto::channum::MIN_CHANNEL_NUMBER;
+use crate::proto::dontfrag::DontFragmentAttr;
+use crate::proto::icmp::Icmp;
+use crate::proto::relayaddr::RelayedAddress;
 use crate::relay::relay_none::*;
+use crate::relay::RelayAddressGenerator;
+use async_trait::async_trait;
+use stun::error_code::ErrorCodeAttribute;
+use stun::xoraddr::XorMappedAddress;
 
 use util::vnet::net::*;
 
 use std::net::IpAddr;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use tokio::net::UdpSocket;
 use tokio::time::{Duration, Instant};
@@ -70,6 +80,13 @@ async fn test_allocation_lifetime_deletion_zero_lifetime() -> Result<()> {
             address: "0.0.0.0".to_owned(),
             net: Arc::new(Net::new(None)),
         }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
     }));
 
     let socket = SocketAddr::new(IpAddr::from_str("127.0.0.1")?, 5000);
@@ -93,6 +110,7 @@ async fn test_allocation_lifetime_deletion_zero_lifetime() -> Result<()> {
             Arc::clone(&r.conn),
             0,
             Duration::from_secs(3600),
+            Username::new(ATTR_USERNAME, "user".to_owned()),
         )
         .await?;
     assert!(r
@@ -117,3 +135,1743 @@ async fn test_allocation_lifetime_deletion_zero_lifetime() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_binding_request_xor_mapped_address() -> Result<()> {
+    let l = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let allocation_manager = Arc::new(Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(RelayAddressGeneratorNone {
+            address: "0.0.0.0".to_owned(),
+            net: Arc::new(Net::new(None)),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    }));
+
+    let mut r = Request::new(
+        Arc::clone(&l) as Arc<dyn Conn + Send + Sync>,
+        client.local_addr()?,
+        allocation_manager,
+        Arc::new(TestAuthHandler {}),
+    );
+
+    // Binding requests go through handle_request like TURN requests do,
+    // and need no authentication.
+    let mut m = Message::new();
+    m.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])?;
+    r.buff = m.raw.clone();
+    r.handle_request().await?;
+
+    let mut buf = vec![0u8; 1500];
+    let (n, from) = client.recv_from(&mut buf).await?;
+    assert_eq!(from, l.local_addr()?, "should be answered by the listener");
+
+    let mut resp = Message {
+        raw: buf[..n].to_vec(),
+        ..Default::default()
+    };
+    resp.decode()?;
+    assert_eq!(resp.typ, BINDING_SUCCESS, "should be a success response");
+
+    let mut reflexive = XorMappedAddress::default();
+    reflexive.get_from(&resp)?;
+    let client_addr = client.local_addr()?;
+    assert_eq!(reflexive.ip, client_addr.ip(), "should map the source ip");
+    assert_eq!(
+        reflexive.port,
+        client_addr.port(),
+        "should map the source port"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_response_echoes_transaction_transmit_counter() -> Result<()> {
+    let l = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let allocation_manager = Arc::new(Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(RelayAddressGeneratorNone {
+            address: "0.0.0.0".to_owned(),
+            net: Arc::new(Net::new(None)),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    }));
+
+    let mut r = Request::new(
+        l,
+        client.local_addr()?,
+        allocation_manager,
+        Arc::new(TestAuthHandler {}),
+    );
+
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(BINDING_REQUEST),
+        Box::new(TransactionTransmitCounter { req: 2, resp: 0 }),
+    ])?;
+
+    r.handle_binding_request(&m).await?;
+
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = client.recv_from(&mut buf).await?;
+
+    let mut resp = Message {
+        raw: buf[..n].to_vec(),
+        ..Default::default()
+    };
+    resp.decode()?;
+    assert_eq!(resp.transaction_id, m.transaction_id, "should match");
+
+    let mut transmit_counter = TransactionTransmitCounter::default();
+    transmit_counter.get_from(&resp)?;
+    assert_eq!(
+        transmit_counter,
+        TransactionTransmitCounter { req: 2, resp: 1 },
+        "counter should be echoed back"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_response_software_attribute() -> Result<()> {
+    let l = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let allocation_manager = Arc::new(Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(RelayAddressGeneratorNone {
+            address: "0.0.0.0".to_owned(),
+            net: Arc::new(Net::new(None)),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    }));
+
+    let mut r = Request::new(
+        l,
+        client.local_addr()?,
+        allocation_manager,
+        Arc::new(TestAuthHandler {}),
+    );
+
+    let mut m = Message::new();
+    m.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])?;
+
+    let mut buf = vec![0u8; 1500];
+
+    // no SOFTWARE attribute unless configured
+    r.handle_binding_request(&m).await?;
+    let (n, _) = client.recv_from(&mut buf).await?;
+    let mut resp = Message {
+        raw: buf[..n].to_vec(),
+        ..Default::default()
+    };
+    resp.decode()?;
+    assert!(!resp.contains(ATTR_SOFTWARE), "should not contain SOFTWARE");
+
+    r.software_name = Some("webrtc-rs/turn".to_owned());
+    r.handle_binding_request(&m).await?;
+    let (n, _) = client.recv_from(&mut buf).await?;
+    let mut resp = Message {
+        raw: buf[..n].to_vec(),
+        ..Default::default()
+    };
+    resp.decode()?;
+
+    let mut software = Software::new(ATTR_SOFTWARE, String::new());
+    software.get_from(&resp)?;
+    assert_eq!(software.to_string(), "webrtc-rs/turn", "should match");
+
+    Ok(())
+}
+
+struct CountingAuthHandler {
+    calls: AtomicUsize,
+}
+
+impl AuthHandler for CountingAuthHandler {
+    fn auth_handle(&self, _username: &str, _realm: &str, _src_addr: SocketAddr) -> Result<Vec<u8>> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        Ok(STATIC_KEY.as_bytes().to_vec())
+    }
+}
+
+async fn authenticate_with_realm(
+    enforce_realm: bool,
+    realm: &str,
+) -> Result<(bool, Option<Message>, usize)> {
+    let l = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let allocation_manager = Arc::new(Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(RelayAddressGeneratorNone {
+            address: "0.0.0.0".to_owned(),
+            net: Arc::new(Net::new(None)),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    }));
+
+    let auth_handler = Arc::new(CountingAuthHandler {
+        calls: AtomicUsize::new(0),
+    });
+    let mut r = Request::new(
+        l,
+        client.local_addr()?,
+        allocation_manager,
+        Arc::clone(&auth_handler) as Arc<dyn AuthHandler + Send + Sync>,
+    );
+    r.realm = "webrtc.rs".to_owned();
+    r.enforce_realm = enforce_realm;
+
+    {
+        let mut nonces = r.nonces.lock().await;
+        nonces.insert(STATIC_KEY.to_owned(), Instant::now());
+    }
+
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_REFRESH, CLASS_REQUEST)),
+    ])?;
+    Nonce::new(ATTR_NONCE, STATIC_KEY.to_owned()).add_to(&mut m)?;
+    Realm::new(ATTR_REALM, realm.to_owned()).add_to(&mut m)?;
+    Username::new(ATTR_USERNAME, STATIC_KEY.to_owned()).add_to(&mut m)?;
+    MessageIntegrity(STATIC_KEY.as_bytes().to_vec()).add_to(&mut m)?;
+
+    let authenticated = r.authenticate_request(&m, METHOD_REFRESH).await?.is_some();
+
+    let resp = if authenticated {
+        None
+    } else {
+        let mut buf = vec![0u8; 1500];
+        let (n, _) = client.recv_from(&mut buf).await?;
+        let mut resp = Message {
+            raw: buf[..n].to_vec(),
+            ..Default::default()
+        };
+        resp.decode()?;
+        Some(resp)
+    };
+
+    Ok((
+        authenticated,
+        resp,
+        auth_handler.calls.load(Ordering::SeqCst),
+    ))
+}
+
+#[tokio::test]
+async fn test_authenticate_request_enforce_realm() -> Result<()> {
+    // matching realm is accepted
+    let (authenticated, _, calls) = authenticate_with_realm(true, "webrtc.rs").await?;
+    assert!(authenticated, "matching realm should be accepted");
+    assert_eq!(calls, 1, "auth handler should be consulted");
+
+    // foreign realm is rejected with 401 before the auth handler is called
+    let (authenticated, resp, calls) = authenticate_with_realm(true, "other.realm").await?;
+    assert!(!authenticated, "foreign realm should be rejected");
+    assert_eq!(calls, 0, "auth handler should not be consulted");
+
+    let resp = resp.ok_or_else(|| Error::Other("no response".to_owned()))?;
+    let mut code = ErrorCodeAttribute::default();
+    code.get_from(&resp)?;
+    assert_eq!(code.code.0, ErrorCode::Unauthorized.code(), "should be 401");
+
+    let mut realm = Realm::new(ATTR_REALM, String::new());
+    realm.get_from(&resp)?;
+    assert_eq!(realm.to_string(), "webrtc.rs", "should carry server realm");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_authenticate_request_no_enforce_realm() -> Result<()> {
+    let (authenticated, _, calls) = authenticate_with_realm(false, "other.realm").await?;
+    assert!(authenticated, "foreign realm should be accepted");
+    assert_eq!(calls, 1, "auth handler should be consulted");
+
+    Ok(())
+}
+
+async fn create_permission_for_peers(peers: &[&str]) -> Result<(Message, Vec<bool>)> {
+    let l = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let allocation_manager = Arc::new(Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(RelayAddressGeneratorNone {
+            address: "127.0.0.1".to_owned(),
+            net: Arc::new(Net::new(None)),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    }));
+
+    let mut r = Request::new(
+        l,
+        client.local_addr()?,
+        allocation_manager,
+        Arc::new(TestAuthHandler {}),
+    );
+
+    {
+        let mut nonces = r.nonces.lock().await;
+        nonces.insert(STATIC_KEY.to_owned(), Instant::now());
+    }
+
+    let five_tuple = FiveTuple {
+        src_addr: r.src_addr,
+        dst_addr: r.conn.local_addr().await?,
+        protocol: PROTO_UDP,
+    };
+    r.allocation_manager
+        .create_allocation(
+            five_tuple.clone(),
+            Arc::clone(&r.conn),
+            0,
+            Duration::from_secs(3600),
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+
+    let peers: Vec<SocketAddr> = peers
+        .iter()
+        .map(|p| SocketAddr::from_str(p))
+        .collect::<std::result::Result<_, _>>()?;
+
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_CREATE_PERMISSION, CLASS_REQUEST)),
+    ])?;
+    for peer in &peers {
+        PeerAddress {
+            ip: peer.ip(),
+            port: peer.port(),
+        }
+        .add_to(&mut m)?;
+    }
+    Nonce::new(ATTR_NONCE, STATIC_KEY.to_owned()).add_to(&mut m)?;
+    Realm::new(ATTR_REALM, STATIC_KEY.to_owned()).add_to(&mut m)?;
+    Username::new(ATTR_USERNAME, STATIC_KEY.to_owned()).add_to(&mut m)?;
+    MessageIntegrity(STATIC_KEY.as_bytes().to_vec()).add_to(&mut m)?;
+
+    r.handle_create_permission_request(&m).await?;
+
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = client.recv_from(&mut buf).await?;
+    let mut resp = Message {
+        raw: buf[..n].to_vec(),
+        ..Default::default()
+    };
+    resp.decode()?;
+
+    let a = r
+        .allocation_manager
+        .get_allocation(&five_tuple)
+        .await
+        .unwrap();
+    let a = a.lock().await;
+    let mut has_permission = vec![];
+    for peer in &peers {
+        has_permission.push(a.has_permission(peer).await);
+    }
+
+    Ok((resp, has_permission))
+}
+
+#[tokio::test]
+async fn test_create_permission_mixed_family_peers() -> Result<()> {
+    let (resp, has_permission) =
+        create_permission_for_peers(&["127.0.0.2:5000", "[::2]:5000", "127.0.0.3:5000"]).await?;
+
+    assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE);
+    assert_eq!(has_permission, vec![true, false, true]);
+
+    let mut address_error = AddressErrorCode::default();
+    address_error.get_from(&resp)?;
+    assert_eq!(address_error.family, REQUESTED_FAMILY_IPV6);
+    assert_eq!(
+        address_error.code.0,
+        ErrorCode::PeerAddressFamilyMismatch.code()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_create_permission_peer_address_family_mismatch() -> Result<()> {
+    let (resp, has_permission) = create_permission_for_peers(&["[::2]:5000"]).await?;
+
+    assert_eq!(resp.typ.class, CLASS_ERROR_RESPONSE);
+    assert_eq!(has_permission, vec![false]);
+
+    let mut code = ErrorCodeAttribute::default();
+    code.get_from(&resp)?;
+    assert_eq!(code.code.0, ErrorCode::PeerAddressFamilyMismatch.code());
+    assert!(AddressErrorCode::default().get_from(&resp).is_err());
+
+    Ok(())
+}
+
+struct OriginAuthHandler {
+    origin: std::sync::Mutex<Option<Option<String>>>,
+}
+
+impl AuthHandler for OriginAuthHandler {
+    fn auth_handle(&self, _username: &str, _realm: &str, _src_addr: SocketAddr) -> Result<Vec<u8>> {
+        Err(Error::ErrFakeErr)
+    }
+
+    fn auth_handle_with_origin(
+        &self,
+        _username: &str,
+        _realm: &str,
+        _src_addr: SocketAddr,
+        origin: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        *self.origin.lock().unwrap() = Some(origin.map(str::to_owned));
+        Ok(STATIC_KEY.as_bytes().to_vec())
+    }
+}
+
+#[tokio::test]
+async fn test_origin_passed_to_auth_handler() -> Result<()> {
+    let l = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let allocation_manager = Arc::new(Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(RelayAddressGeneratorNone {
+            address: "0.0.0.0".to_owned(),
+            net: Arc::new(Net::new(None)),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    }));
+
+    let auth_handler = Arc::new(OriginAuthHandler {
+        origin: std::sync::Mutex::new(None),
+    });
+    let mut r = Request::new(
+        l,
+        client.local_addr()?,
+        allocation_manager,
+        Arc::clone(&auth_handler) as Arc<dyn AuthHandler + Send + Sync>,
+    );
+
+    {
+        let mut nonces = r.nonces.lock().await;
+        nonces.insert(STATIC_KEY.to_owned(), Instant::now());
+    }
+
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_REFRESH, CLASS_REQUEST)),
+    ])?;
+    Origin("https://webrtc.rs".to_owned()).add_to(&mut m)?;
+    Lifetime(Duration::from_secs(0)).add_to(&mut m)?;
+    Nonce::new(ATTR_NONCE, STATIC_KEY.to_owned()).add_to(&mut m)?;
+    Realm::new(ATTR_REALM, STATIC_KEY.to_owned()).add_to(&mut m)?;
+    Username::new(ATTR_USERNAME, STATIC_KEY.to_owned()).add_to(&mut m)?;
+    MessageIntegrity(STATIC_KEY.as_bytes().to_vec()).add_to(&mut m)?;
+
+    r.buff = m.raw.clone();
+    r.handle_request().await?;
+
+    assert_eq!(r.origin.as_deref(), Some("https://webrtc.rs"));
+    assert_eq!(
+        *auth_handler.origin.lock().unwrap(),
+        Some(Some("https://webrtc.rs".to_owned())),
+        "auth handler should get the origin"
+    );
+
+    Ok(())
+}
+
+// RejectingAuthHandler rejects every user, as an unknown user or, with
+// access_token set, as the holder of an invalid access token
+struct RejectingAuthHandler {
+    access_token: bool,
+}
+
+impl AuthHandler for RejectingAuthHandler {
+    fn auth_handle(&self, _username: &str, _realm: &str, _src_addr: SocketAddr) -> Result<Vec<u8>> {
+        if self.access_token {
+            Err(Error::ErrInvalidAccessToken)
+        } else {
+            Err(Error::ErrNoSuchUser)
+        }
+    }
+}
+
+async fn authenticate_rejected(
+    access_token: bool,
+) -> Result<(Result<Option<(Username, MessageIntegrity)>>, Message)> {
+    let l = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let allocation_manager = Arc::new(Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(RelayAddressGeneratorNone {
+            address: "0.0.0.0".to_owned(),
+            net: Arc::new(Net::new(None)),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    }));
+
+    let mut r = Request::new(
+        l,
+        client.local_addr()?,
+        allocation_manager,
+        Arc::new(RejectingAuthHandler { access_token }),
+    );
+
+    {
+        let mut nonces = r.nonces.lock().await;
+        nonces.insert(STATIC_KEY.to_owned(), Instant::now());
+    }
+
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_REFRESH, CLASS_REQUEST)),
+    ])?;
+    Nonce::new(ATTR_NONCE, STATIC_KEY.to_owned()).add_to(&mut m)?;
+    Realm::new(ATTR_REALM, STATIC_KEY.to_owned()).add_to(&mut m)?;
+    Username::new(ATTR_USERNAME, STATIC_KEY.to_owned()).add_to(&mut m)?;
+    MessageIntegrity(STATIC_KEY.as_bytes().to_vec()).add_to(&mut m)?;
+
+    let result = r.authenticate_request(&m, METHOD_REFRESH).await;
+
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = client.recv_from(&mut buf).await?;
+    let mut resp = Message {
+        raw: buf[..n].to_vec(),
+        ..Default::default()
+    };
+    resp.decode()?;
+
+    Ok((result, resp))
+}
+
+#[tokio::test]
+async fn test_authenticate_request_rejected_credentials() -> Result<()> {
+    let (result, resp) = authenticate_rejected(false).await?;
+    assert_eq!(result.err(), Some(Error::ErrNoSuchUser));
+
+    let mut code = ErrorCodeAttribute::default();
+    code.get_from(&resp)?;
+    assert_eq!(code.code.0, ErrorCode::BadRequest.code());
+    assert!(!resp.contains(ATTR_NONCE), "should not offer a nonce");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_authenticate_request_rejected_access_token() -> Result<()> {
+    let (result, resp) = authenticate_rejected(true).await?;
+    assert!(result?.is_none());
+
+    let mut code = ErrorCodeAttribute::default();
+    code.get_from(&resp)?;
+    assert_eq!(code.code.0, ErrorCode::Unauthorized.code());
+    assert!(resp.contains(ATTR_NONCE), "should offer a fresh nonce");
+
+    Ok(())
+}
+
+async fn allocate_with_lifetime(lifetime: Duration, min_lifetime: Duration) -> Result<Message> {
+    allocate_with_lifetime_strategy(lifetime, min_lifetime, LifetimeStrategy::Negotiated).await
+}
+
+async fn allocate_with_lifetime_strategy(
+    lifetime: Duration,
+    min_lifetime: Duration,
+    strategy: LifetimeStrategy,
+) -> Result<Message> {
+    let l = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let allocation_manager = Arc::new(Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(RelayAddressGeneratorNone {
+            address: "127.0.0.1".to_owned(),
+            net: Arc::new(Net::new(None)),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: strategy,
+    }));
+
+    let mut r = Request::new(
+        l,
+        client.local_addr()?,
+        allocation_manager,
+        Arc::new(TestAuthHandler {}),
+    );
+    r.min_allocation_lifetime = min_lifetime;
+
+    {
+        let mut nonces = r.nonces.lock().await;
+        nonces.insert(STATIC_KEY.to_owned(), Instant::now());
+    }
+
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_ALLOCATE, CLASS_REQUEST)),
+    ])?;
+    RequestedTransport {
+        protocol: PROTO_UDP,
+    }
+    .add_to(&mut m)?;
+    Lifetime(lifetime).add_to(&mut m)?;
+    Nonce::new(ATTR_NONCE, STATIC_KEY.to_owned()).add_to(&mut m)?;
+    Realm::new(ATTR_REALM, STATIC_KEY.to_owned()).add_to(&mut m)?;
+    Username::new(ATTR_USERNAME, STATIC_KEY.to_owned()).add_to(&mut m)?;
+    MessageIntegrity(STATIC_KEY.as_bytes().to_vec()).add_to(&mut m)?;
+
+    let _ = r.handle_allocate_request(&m).await;
+
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = client.recv_from(&mut buf).await?;
+    let mut resp = Message {
+        raw: buf[..n].to_vec(),
+        ..Default::default()
+    };
+    resp.decode()?;
+
+    Ok(resp)
+}
+
+#[tokio::test]
+async fn test_allocate_request_zero_lifetime() -> Result<()> {
+    let resp = allocate_with_lifetime(Duration::from_secs(0), Duration::from_secs(1)).await?;
+
+    assert_eq!(resp.typ.class, CLASS_ERROR_RESPONSE);
+    let mut code = ErrorCodeAttribute::default();
+    code.get_from(&resp)?;
+    assert_eq!(code.code.0, ErrorCode::BadRequest.code());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_allocate_request_lifetime_below_minimum() -> Result<()> {
+    let resp = allocate_with_lifetime(Duration::from_secs(30), Duration::from_secs(600)).await?;
+
+    assert_eq!(resp.typ.class, CLASS_ERROR_RESPONSE);
+    let mut code = ErrorCodeAttribute::default();
+    code.get_from(&resp)?;
+    assert_eq!(code.code.0, ErrorCode::BadRequest.code());
+
+    let resp = allocate_with_lifetime(Duration::from_secs(600), Duration::from_secs(600)).await?;
+    assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_allocate_request_fixed_lifetime_strategy() -> Result<()> {
+    let resp = allocate_with_lifetime_strategy(
+        Duration::from_secs(300),
+        Duration::from_secs(0),
+        LifetimeStrategy::Fixed(Duration::from_secs(60)),
+    )
+    .await?;
+    assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE);
+
+    let mut lifetime = Lifetime::default();
+    lifetime.get_from(&resp)?;
+    assert_eq!(lifetime.0, Duration::from_secs(60));
+
+    Ok(())
+}
+
+// DontFragmentRelayAddressGenerator binds relays on loopback and pretends the
+// path MTU is max_size for datagrams sent with DONT-FRAGMENT
+struct DontFragmentRelayAddressGenerator {
+    max_size: usize,
+    dont_fragment_sends: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl RelayAddressGenerator for DontFragmentRelayAddressGenerator {
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn allocate_conn(
+        &self,
+        _use_ipv4: bool,
+        requested_port: u16,
+    ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr)> {
+        let conn = UdpSocket::bind(("127.0.0.1", requested_port)).await?;
+        let relay_addr = conn.local_addr()?;
+        Ok((Arc::new(conn), relay_addr))
+    }
+
+    fn supports_dont_fragment(&self) -> bool {
+        true
+    }
+
+    async fn send_to_dont_fragment(
+        &self,
+        relay_socket: &(dyn Conn + Send + Sync),
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Result<usize> {
+        self.dont_fragment_sends.fetch_add(1, Ordering::SeqCst);
+        if buf.len() > self.max_size {
+            return Err(Error::ErrPacketTooBig);
+        }
+        Ok(relay_socket.send_to(buf, target).await?)
+    }
+}
+
+#[tokio::test]
+async fn test_send_indication_dont_fragment() -> Result<()> {
+    let l = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let peer = UdpSocket::bind("127.0.0.1:0").await?;
+    let peer_addr = peer.local_addr()?;
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let dont_fragment_sends = Arc::new(AtomicUsize::new(0));
+
+    let allocation_manager = Arc::new(Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(DontFragmentRelayAddressGenerator {
+            max_size: 1000,
+            dont_fragment_sends: Arc::clone(&dont_fragment_sends),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: true,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    }));
+
+    let src_addr = client.local_addr()?;
+    let mut r = Request::new(
+        l,
+        src_addr,
+        allocation_manager,
+        Arc::new(TestAuthHandler {}),
+    );
+
+    let five_tuple = FiveTuple {
+        src_addr: r.src_addr,
+        dst_addr: r.conn.local_addr().await?,
+        protocol: PROTO_UDP,
+    };
+    let a = r
+        .allocation_manager
+        .create_allocation(
+            five_tuple,
+            Arc::clone(&r.conn),
+            0,
+            Duration::from_secs(3600),
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+    a.lock()
+        .await
+        .add_permission(Permission::new(peer_addr))
+        .await;
+
+    let send_indication = |size: usize| -> Result<Message> {
+        let mut m = Message::new();
+        m.build(&[
+            Box::new(TransactionId::new()),
+            Box::new(MessageType::new(METHOD_SEND, CLASS_INDICATION)),
+            Box::new(PeerAddress {
+                ip: peer_addr.ip(),
+                port: peer_addr.port(),
+            }),
+            Box::new(Data(vec![0u8; size])),
+            Box::new(DontFragmentAttr),
+        ])?;
+        Ok(m)
+    };
+
+    r.handle_send_indication(&send_indication(500)?).await?;
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = tokio::time::timeout(Duration::from_secs(1), peer.recv_from(&mut buf))
+        .await
+        .map_err(|_| Error::Other("peer got no data".to_owned()))??;
+    assert_eq!(n, 500);
+
+    // a rejected probe is dropped, it is no error of the server, and the
+    // client hears about it like from an ICMP packet too big
+    r.handle_send_indication(&send_indication(1200)?).await?;
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), peer.recv_from(&mut buf))
+            .await
+            .is_err(),
+        "too big probe should not reach the peer"
+    );
+
+    let (n, _) = tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buf))
+        .await
+        .map_err(|_| Error::Other("client got no ICMP Data indication".to_owned()))??;
+    let mut msg = Message::new();
+    msg.write(&buf[..n])?;
+    assert_eq!(msg.typ, MessageType::new(METHOD_DATA, CLASS_INDICATION));
+    let mut icmp = Icmp::default();
+    icmp.get_from(&msg)?;
+    assert_eq!(icmp, Icmp::packet_too_big(&peer_addr));
+    assert_eq!(dont_fragment_sends.load(Ordering::SeqCst), 2);
+    assert_eq!(
+        r.allocation_manager.statistics().total_bytes_relayed_out,
+        500,
+        "only data sent to the peer counts"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_send_indication_dont_fragment_unsupported() -> Result<()> {
+    let l = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let peer_addr = SocketAddr::from_str("127.0.0.1:5001")?;
+
+    let allocation_manager = Arc::new(Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(RelayAddressGeneratorNone {
+            address: "127.0.0.1".to_owned(),
+            net: Arc::new(Net::new(None)),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    }));
+
+    let src_addr = SocketAddr::new(IpAddr::from_str("127.0.0.1")?, 5000);
+    let mut r = Request::new(
+        l,
+        src_addr,
+        allocation_manager,
+        Arc::new(TestAuthHandler {}),
+    );
+
+    let five_tuple = FiveTuple {
+        src_addr: r.src_addr,
+        dst_addr: r.conn.local_addr().await?,
+        protocol: PROTO_UDP,
+    };
+    let a = r
+        .allocation_manager
+        .create_allocation(
+            five_tuple,
+            Arc::clone(&r.conn),
+            0,
+            Duration::from_secs(3600),
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+    a.lock()
+        .await
+        .add_permission(Permission::new(peer_addr))
+        .await;
+
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_SEND, CLASS_INDICATION)),
+        Box::new(PeerAddress {
+            ip: peer_addr.ip(),
+            port: peer_addr.port(),
+        }),
+        Box::new(Data(vec![0u8; 100])),
+        Box::new(DontFragmentAttr),
+    ])?;
+
+    let result = r.handle_send_indication(&m).await;
+    assert!(
+        matches!(result, Err(Error::ErrNoDontFragmentSupport)),
+        "should fail without DF support, got {:?}",
+        result
+    );
+
+    Ok(())
+}
+
+const HANDLER_NONCE: &str = "nonce";
+
+// new_handler_request returns a Request from client on a fresh listener,
+// authenticating against a StaticAuthHandler that knows user/pass, with
+// HANDLER_NONCE issued already
+async fn new_handler_request(client: &UdpSocket) -> Result<Request> {
+    let l = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+
+    let allocation_manager = Arc::new(Manager::new(ManagerConfig {
+        relay_addr_generator: Box::new(RelayAddressGeneratorNone {
+            address: "127.0.0.1".to_owned(),
+            net: Arc::new(Net::new(None)),
+        }),
+        max_connections: None,
+        relay_keepalive_interval: None,
+        relay_keepalive_server: None,
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        forward_icmp_errors: false,
+        allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+    }));
+
+    let mut credentials = HashMap::new();
+    credentials.insert("user".to_owned(), "pass".to_owned());
+    let mut r = Request::new(
+        l,
+        client.local_addr()?,
+        allocation_manager,
+        Arc::new(StaticAuthHandler::new(credentials)),
+    );
+    r.realm = "webrtc.rs".to_owned();
+    r.enforce_realm = true;
+    r.nonces
+        .lock()
+        .await
+        .insert(HANDLER_NONCE.to_owned(), Instant::now());
+
+    Ok(r)
+}
+
+// authenticated_request builds a request of method with attrs, authenticated
+// as user/pass in the realm of new_handler_request
+fn authenticated_request(method: Method, attrs: Vec<Box<dyn Setter>>) -> Result<Message> {
+    let mut setters: Vec<Box<dyn Setter>> = vec![
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(method, CLASS_REQUEST)),
+    ];
+    setters.extend(attrs);
+    setters.push(Box::new(Nonce::new(ATTR_NONCE, HANDLER_NONCE.to_owned())));
+    setters.push(Box::new(Realm::new(ATTR_REALM, "webrtc.rs".to_owned())));
+    setters.push(Box::new(Username::new(ATTR_USERNAME, "user".to_owned())));
+    setters.push(Box::new(MessageIntegrity::new_long_term_integrity(
+        "user".to_owned(),
+        "webrtc.rs".to_owned(),
+        "pass".to_owned(),
+    )));
+
+    let mut m = Message::new();
+    m.build(&setters)?;
+    Ok(m)
+}
+
+async fn recv_response(client: &UdpSocket) -> Result<Message> {
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buf))
+        .await
+        .map_err(|_| Error::Other("no response".to_owned()))??;
+    let mut resp = Message {
+        raw: buf[..n].to_vec(),
+        ..Default::default()
+    };
+    resp.decode()?;
+    Ok(resp)
+}
+
+fn assert_error_code(resp: &Message, code: ErrorCode) -> Result<()> {
+    assert_eq!(resp.typ.class, CLASS_ERROR_RESPONSE);
+    let mut attr = ErrorCodeAttribute::default();
+    attr.get_from(resp)?;
+    assert_eq!(attr.code.0, code.code());
+    Ok(())
+}
+
+fn allocate_request() -> Result<Message> {
+    authenticated_request(
+        METHOD_ALLOCATE,
+        vec![Box::new(RequestedTransport {
+            protocol: PROTO_UDP,
+        })],
+    )
+}
+
+async fn allocate(r: &mut Request, client: &UdpSocket) -> Result<Message> {
+    r.handle_allocate_request(&allocate_request()?).await?;
+    recv_response(client).await
+}
+
+#[tokio::test]
+async fn test_authenticate_request_invalid_nonce() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_ALLOCATE, CLASS_REQUEST)),
+        Box::new(RequestedTransport {
+            protocol: PROTO_UDP,
+        }),
+        Box::new(Nonce::new(ATTR_NONCE, "bad\"nonce".to_owned())),
+        Box::new(Realm::new(ATTR_REALM, "webrtc.rs".to_owned())),
+        Box::new(Username::new(ATTR_USERNAME, "user".to_owned())),
+        Box::new(MessageIntegrity::new_long_term_integrity(
+            "user".to_owned(),
+            "webrtc.rs".to_owned(),
+            "pass".to_owned(),
+        )),
+    ])?;
+    let result = r.handle_allocate_request(&m).await;
+    assert_eq!(result, Err(Error::ErrInvalidNonce));
+
+    let resp = recv_response(&client).await?;
+    assert_error_code(&resp, ErrorCode::BadRequest)?;
+    assert_eq!(r.allocation_manager.statistics().total_created, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_allocate_success() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+
+    let resp = allocate(&mut r, &client).await?;
+    assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE);
+
+    let mut relayed_addr = RelayedAddress::default();
+    relayed_addr.get_from(&resp)?;
+    let mut mapped_addr = XorMappedAddress::default();
+    mapped_addr.get_from(&resp)?;
+    assert_eq!(
+        SocketAddr::new(mapped_addr.ip, mapped_addr.port),
+        r.src_addr
+    );
+    let mut lifetime = Lifetime::default();
+    lifetime.get_from(&resp)?;
+    assert_eq!(lifetime.0, DEFAULT_LIFETIME);
+
+    let a = r
+        .allocation_manager
+        .get_allocation_by_relay_addr(&SocketAddr::new(relayed_addr.ip, relayed_addr.port))
+        .await;
+    assert!(
+        a.is_some(),
+        "allocation should exist at the relayed address"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_allocate_additional_family_unavailable() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    // the relay address generator of the handler only binds IPv4 relays
+    let mut r = new_handler_request(&client).await?;
+
+    let m = authenticated_request(
+        METHOD_ALLOCATE,
+        vec![
+            Box::new(RequestedTransport {
+                protocol: PROTO_UDP,
+            }),
+            Box::new(ADDITIONAL_FAMILY_IPV6),
+        ],
+    )?;
+    r.handle_allocate_request(&m).await?;
+
+    // the allocation succeeds with the IPv4 relayed address only, and
+    // ADDRESS-ERROR-CODE reports why there is no IPv6 one
+    let resp = recv_response(&client).await?;
+    assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE);
+    let mut relayed_addr = RelayedAddress::default();
+    relayed_addr.get_from(&resp)?;
+    assert!(relayed_addr.ip.is_ipv4());
+    let mut address_error = AddressErrorCode::default();
+    address_error.get_from(&resp)?;
+    assert_eq!(address_error.family, REQUESTED_FAMILY_IPV6);
+    assert_eq!(
+        address_error.code.0,
+        ErrorCode::AddressFamilyNotSupported.code()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_allocate_unauthenticated() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_ALLOCATE, CLASS_REQUEST)),
+        Box::new(RequestedTransport {
+            protocol: PROTO_UDP,
+        }),
+    ])?;
+    r.handle_allocate_request(&m).await?;
+
+    let resp = recv_response(&client).await?;
+    assert_error_code(&resp, ErrorCode::Unauthorized)?;
+    let mut nonce = Nonce::new(ATTR_NONCE, String::new());
+    nonce.get_from(&resp)?;
+    assert!(!nonce.text.is_empty(), "should offer a nonce");
+    assert_eq!(r.allocation_manager.allocations().await.len(), 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_allocate_unsupported_transport_before_auth() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_ALLOCATE, CLASS_REQUEST)),
+        Box::new(RequestedTransport {
+            protocol: PROTO_TCP,
+        }),
+    ])?;
+    let result = r.handle_allocate_request(&m).await;
+    assert_eq!(result, Err(Error::ErrRequestedTransportMustBeUdp));
+
+    // 442, not 401: the transport is checked before the credentials
+    let resp = recv_response(&client).await?;
+    assert_error_code(&resp, ErrorCode::UnsupportedTransportProtocol)?;
+    assert!(!resp.contains(ATTR_NONCE), "should not offer a nonce");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_request_id() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let r1 = new_handler_request(&client).await?;
+    let r2 = new_handler_request(&client).await?;
+    assert!(
+        r2.request_id > r1.request_id,
+        "request ids should increase, got {} then {}",
+        r1.request_id,
+        r2.request_id
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_allocate_nonce_prefix() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+    r.nonce_generator = Arc::new(NonceGenerator::with_prefix("turn-1"));
+    // the nonce of allocate_request was not issued by this server
+    r.nonces.lock().await.clear();
+
+    let resp = allocate(&mut r, &client).await?;
+    assert_error_code(&resp, ErrorCode::StaleNonce)?;
+    let mut nonce = Nonce::new(ATTR_NONCE, String::new());
+    nonce.get_from(&resp)?;
+    assert!(nonce.text.starts_with("turn-1."), "got {}", nonce.text);
+    assert!(r.nonces.lock().await.contains_key(&nonce.text));
+
+    Ok(())
+}
+
+async fn allocate_with_app_id(allow_app_id: bool, app_id: &str) -> Result<(Request, Message)> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+    r.allow_app_id = allow_app_id;
+
+    let m = authenticated_request(
+        METHOD_ALLOCATE,
+        vec![
+            Box::new(RequestedTransport {
+                protocol: PROTO_UDP,
+            }),
+            Box::new(AppId(app_id.to_owned())),
+        ],
+    )?;
+    let _ = r.handle_allocate_request(&m).await;
+    let resp = recv_response(&client).await?;
+    Ok((r, resp))
+}
+
+#[tokio::test]
+async fn test_handle_allocate_app_id() -> Result<()> {
+    let (r, resp) = allocate_with_app_id(true, "meetings").await?;
+    assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE);
+    let infos = r.allocation_manager.allocation_by_username_prefix("").await;
+    assert_eq!(infos.len(), 1);
+    assert_eq!(infos[0].app_id.as_deref(), Some("meetings"));
+
+    // APP-ID is ignored unless allowed
+    let (r, resp) = allocate_with_app_id(false, "meetings").await?;
+    assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE);
+    let infos = r.allocation_manager.allocation_by_username_prefix("").await;
+    assert_eq!(infos.len(), 1);
+    assert_eq!(infos[0].app_id, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_allocate_retransmission() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+
+    // the response to the first transmission is lost, the retransmission must
+    // get the same response instead of 437 (Allocation Mismatch)
+    let m = allocate_request()?;
+    r.buff = m.raw.clone();
+    r.handle_request().await?;
+    let first = recv_response(&client).await?;
+    assert_eq!(first.typ.class, CLASS_SUCCESS_RESPONSE);
+
+    r.handle_request().await?;
+    let second = recv_response(&client).await?;
+    assert_eq!(second.raw, first.raw);
+    assert_eq!(
+        r.allocation_manager
+            .allocation_by_username_prefix("")
+            .await
+            .len(),
+        1
+    );
+
+    // a new transaction is handled again
+    let result = r.handle_allocate_request(&allocate_request()?).await;
+    assert_eq!(result, Err(Error::ErrRelayAlreadyAllocatedForFiveTuple));
+    let resp = recv_response(&client).await?;
+    assert_error_code(&resp, ErrorCode::AllocationMismatch)?;
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_transaction_cache() -> Result<()> {
+    let cache = TransactionCache::new();
+    let client = SocketAddr::from_str("127.0.0.1:5000")?;
+    let other = SocketAddr::from_str("127.0.0.1:5001")?;
+    let tid = TransactionId::new();
+
+    assert!(cache.is_empty());
+    cache.insert(client, &tid, vec![1, 2, 3]);
+    assert_eq!(cache.get(client, &tid), Some(vec![1, 2, 3]));
+    assert_eq!(cache.get(other, &tid), None);
+    assert_eq!(cache.get(client, &TransactionId::new()), None);
+
+    tokio::time::advance(TRANSACTION_CACHE_TIMEOUT).await;
+    assert_eq!(cache.get(client, &tid), None, "response should expire");
+
+    // expired responses are removed on a later insert
+    cache.insert(other, &tid, vec![4]);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.get(other, &tid), Some(vec![4]));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_transaction_cache_max_entries() -> Result<()> {
+    let cache = TransactionCache::new();
+    let client = SocketAddr::from_str("127.0.0.1:5000")?;
+    let tids: Vec<TransactionId> = (0..=TRANSACTION_CACHE_MAX_ENTRIES)
+        .map(|_| TransactionId::new())
+        .collect();
+
+    for tid in &tids {
+        cache.insert(client, tid, vec![1]);
+    }
+    assert_eq!(cache.len(), TRANSACTION_CACHE_MAX_ENTRIES);
+    assert_eq!(
+        cache.get(client, &tids[0]),
+        None,
+        "oldest response should be evicted"
+    );
+    assert_eq!(cache.get(client, &tids[1]), Some(vec![1]));
+    assert_eq!(
+        cache.get(client, &tids[TRANSACTION_CACHE_MAX_ENTRIES]),
+        Some(vec![1])
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_allocate_duplicate() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+
+    let resp = allocate(&mut r, &client).await?;
+    assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE);
+
+    let result = r.handle_allocate_request(&allocate_request()?).await;
+    assert_eq!(result, Err(Error::ErrRelayAlreadyAllocatedForFiveTuple));
+    let resp = recv_response(&client).await?;
+    assert_error_code(&resp, ErrorCode::AllocationMismatch)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_allocate_same_username_other_five_tuple() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let other_client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+
+    let resp = allocate(&mut r, &client).await?;
+    assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE);
+    let mut relayed_addr = RelayedAddress::default();
+    relayed_addr.get_from(&resp)?;
+
+    // allocations are per 5-tuple, so the same user gets another one from
+    // another source address, see RFC 5766 Section 6.2
+    r.src_addr = other_client.local_addr()?;
+    let resp = allocate(&mut r, &other_client).await?;
+    assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE);
+    let mut other_relayed_addr = RelayedAddress::default();
+    other_relayed_addr.get_from(&resp)?;
+    assert_ne!(other_relayed_addr.port, relayed_addr.port);
+
+    // the first allocation is left as it was
+    let a = r
+        .allocation_manager
+        .get_allocation(&FiveTuple {
+            src_addr: client.local_addr()?,
+            dst_addr: r.conn.local_addr().await?,
+            protocol: PROTO_UDP,
+        })
+        .await
+        .expect("first allocation should remain");
+    assert_eq!(a.lock().await.relay_addr.port(), relayed_addr.port);
+    assert_eq!(r.allocation_manager.statistics().current_count, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_allocate_missing_requested_transport() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+
+    let m = authenticated_request(METHOD_ALLOCATE, vec![])?;
+    assert!(r.handle_allocate_request(&m).await.is_err());
+    let resp = recv_response(&client).await?;
+    assert_error_code(&resp, ErrorCode::BadRequest)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_refresh_success() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+    allocate(&mut r, &client).await?;
+
+    let m = authenticated_request(
+        METHOD_REFRESH,
+        vec![Box::new(Lifetime(Duration::from_secs(300)))],
+    )?;
+    r.handle_refresh_request(&m).await?;
+
+    let resp = recv_response(&client).await?;
+    assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE);
+    let mut lifetime = Lifetime::default();
+    lifetime.get_from(&resp)?;
+    assert_eq!(lifetime.0, Duration::from_secs(300));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_refresh_expired() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+
+    let five_tuple = FiveTuple {
+        src_addr: r.src_addr,
+        dst_addr: r.conn.local_addr().await?,
+        protocol: PROTO_UDP,
+    };
+    r.allocation_manager
+        .create_allocation(
+            five_tuple.clone(),
+            Arc::clone(&r.conn),
+            0,
+            Duration::from_millis(50),
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(r
+        .allocation_manager
+        .get_allocation(&five_tuple)
+        .await
+        .is_none());
+
+    let m = authenticated_request(
+        METHOD_REFRESH,
+        vec![Box::new(Lifetime(Duration::from_secs(300)))],
+    )?;
+    let result = r.handle_refresh_request(&m).await;
+    assert_eq!(result, Err(Error::ErrNoAllocationFound));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_refresh_zero_lifetime() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let peer = UdpSocket::bind("127.0.0.1:0").await?;
+    let peer_addr = peer.local_addr()?;
+    let mut r = new_handler_request(&client).await?;
+
+    let resp = allocate(&mut r, &client).await?;
+    let mut relayed_addr = RelayedAddress::default();
+    relayed_addr.get_from(&resp)?;
+    let m = authenticated_request(
+        METHOD_CREATE_PERMISSION,
+        vec![Box::new(PeerAddress {
+            ip: peer_addr.ip(),
+            port: peer_addr.port(),
+        })],
+    )?;
+    r.handle_create_permission_request(&m).await?;
+    recv_response(&client).await?;
+
+    // a LIFETIME of zero deletes the allocation, see RFC 5766 Section 7.2
+    let m = authenticated_request(
+        METHOD_REFRESH,
+        vec![Box::new(Lifetime(Duration::from_secs(0)))],
+    )?;
+    r.handle_refresh_request(&m).await?;
+
+    let resp = recv_response(&client).await?;
+    assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE);
+    let mut lifetime = Lifetime::default();
+    lifetime.get_from(&resp)?;
+    assert_eq!(lifetime.0, Duration::from_secs(0));
+
+    let five_tuple = FiveTuple {
+        src_addr: r.src_addr,
+        dst_addr: r.conn.local_addr().await?,
+        protocol: PROTO_UDP,
+    };
+    assert!(r
+        .allocation_manager
+        .get_allocation(&five_tuple)
+        .await
+        .is_none());
+    let stats = r.allocation_manager.statistics();
+    assert_eq!((stats.total_closed, stats.current_count), (1, 0));
+    assert_eq!(r.allocation_manager.permission_count(), 0);
+
+    // the allocation can't be used anymore, in either direction
+    let m = authenticated_request(
+        METHOD_CREATE_PERMISSION,
+        vec![Box::new(PeerAddress {
+            ip: peer_addr.ip(),
+            port: peer_addr.port(),
+        })],
+    )?;
+    let result = r.handle_create_permission_request(&m).await;
+    assert_eq!(result, Err(Error::ErrNoAllocationFound));
+
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_SEND, CLASS_INDICATION)),
+        Box::new(PeerAddress {
+            ip: peer_addr.ip(),
+            port: peer_addr.port(),
+        }),
+        Box::new(Data(vec![1, 2, 3])),
+    ])?;
+    let result = r.handle_send_indication(&m).await;
+    assert_eq!(result, Err(Error::ErrNoAllocationFound));
+
+    peer.send_to(
+        &[1, 2, 3],
+        SocketAddr::new(IpAddr::from_str("127.0.0.1")?, relayed_addr.port),
+    )
+    .await?;
+    let mut buf = vec![0u8; 1500];
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), client.recv_from(&mut buf))
+            .await
+            .is_err(),
+        "peer data should not be relayed after the deletion"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_create_permission_without_allocation() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+
+    let m = authenticated_request(
+        METHOD_CREATE_PERMISSION,
+        vec![Box::new(PeerAddress {
+            ip: IpAddr::from_str("127.0.0.2")?,
+            port: 5000,
+        })],
+    )?;
+    let result = r.handle_create_permission_request(&m).await;
+    assert_eq!(result, Err(Error::ErrNoAllocationFound));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_channel_bind_success() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+    allocate(&mut r, &client).await?;
+
+    let peer = SocketAddr::from_str("127.0.0.2:5000")?;
+    let m = authenticated_request(
+        METHOD_CHANNEL_BIND,
+        vec![
+            Box::new(ChannelNumber(MIN_CHANNEL_NUMBER)),
+            Box::new(PeerAddress {
+                ip: peer.ip(),
+                port: peer.port(),
+            }),
+        ],
+    )?;
+    r.handle_channel_bind_request(&m).await?;
+
+    let resp = recv_response(&client).await?;
+    assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE);
+    assert_eq!(r.allocation_manager.channel_bind_count(), 1);
+    assert_eq!(r.allocation_manager.permission_count(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_channel_bind_invalid_channel_number() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+    allocate(&mut r, &client).await?;
+
+    let m = authenticated_request(
+        METHOD_CHANNEL_BIND,
+        vec![
+            Box::new(ChannelNumber(MIN_CHANNEL_NUMBER - 1)),
+            Box::new(PeerAddress {
+                ip: IpAddr::from_str("127.0.0.2")?,
+                port: 5000,
+            }),
+        ],
+    )?;
+    let result = r.handle_channel_bind_request(&m).await;
+    assert_eq!(result, Err(Error::ErrInvalidChannelNumber));
+
+    let resp = recv_response(&client).await?;
+    assert_error_code(&resp, ErrorCode::BadRequest)?;
+    assert_eq!(r.allocation_manager.channel_bind_count(), 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_channel_bind_same_channel_different_peer() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+    allocate(&mut r, &client).await?;
+
+    for (port, class) in [(5000, CLASS_SUCCESS_RESPONSE), (5001, CLASS_ERROR_RESPONSE)] {
+        let m = authenticated_request(
+            METHOD_CHANNEL_BIND,
+            vec![
+                Box::new(ChannelNumber(MIN_CHANNEL_NUMBER)),
+                Box::new(PeerAddress {
+                    ip: IpAddr::from_str("127.0.0.2")?,
+                    port,
+                }),
+            ],
+        )?;
+        let _ = r.handle_channel_bind_request(&m).await;
+        let resp = recv_response(&client).await?;
+        assert_eq!(resp.typ.class, class);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_send_indication_without_permission() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+    allocate(&mut r, &client).await?;
+
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_SEND, CLASS_INDICATION)),
+        Box::new(PeerAddress {
+            ip: IpAddr::from_str("127.0.0.2")?,
+            port: 5000,
+        }),
+        Box::new(Data(vec![0u8; 10])),
+    ])?;
+    let result = r.handle_send_indication(&m).await;
+    assert_eq!(result, Err(Error::ErrNoPermission));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_channel_data_unbound_channel() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+    allocate(&mut r, &client).await?;
+
+    let c = ChannelData {
+        number: ChannelNumber(MIN_CHANNEL_NUMBER),
+        data: vec![0u8; 10],
+        ..Default::default()
+    };
+    let result = r.handle_channel_data(&c).await;
+    assert_eq!(result, Err(Error::ErrNoSuchChannelBind));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_allocate_even_port_reservation() -> Result<()> {
+    for reserve_port in [false, true] {
+        let client = UdpSocket::bind("127.0.0.1:0").await?;
+        let mut r = new_handler_request(&client).await?;
+
+        let m = authenticated_request(
+            METHOD_ALLOCATE,
+            vec![
+                Box::new(RequestedTransport {
+                    protocol: PROTO_UDP,
+                }),
+                Box::new(EvenPort { reserve_port }),
+            ],
+        )?;
+        r.handle_allocate_request(&m).await?;
+        let resp = recv_response(&client).await?;
+        assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE);
+
+        let mut token = ReservationToken::default();
+        if !reserve_port {
+            assert!(
+                token.get_from(&resp).is_err(),
+                "no RESERVATION-TOKEN without the R bit"
+            );
+            continue;
+        }
+        token.get_from(&resp)?;
+
+        let mut relayed_addr = RelayedAddress::default();
+        relayed_addr.get_from(&resp)?;
+        let reserved_port = r
+            .allocation_manager
+            .get_reservation(&String::from_utf8_lossy(&token.0))
+            .await;
+        assert_eq!(reserved_port, Some(relayed_addr.port + 1));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_allocate_unknown_reservation_token() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+
+    let m = authenticated_request(
+        METHOD_ALLOCATE,
+        vec![
+            Box::new(RequestedTransport {
+                protocol: PROTO_UDP,
+            }),
+            Box::new(ReservationToken(b"unknown!".to_vec())),
+        ],
+    )?;
+    let result = r.handle_allocate_request(&m).await;
+    assert_eq!(result, Err(Error::ErrReservationNotFound));
+
+    let resp = recv_response(&client).await?;
+    assert_error_code(&resp, ErrorCode::InsufficientCapacity)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_channel_bind_refresh_rejected() -> Result<()> {
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut r = new_handler_request(&client).await?;
+    r.channel_bind_refresh_policy = ChannelRefreshPolicy::Reject;
+    allocate(&mut r, &client).await?;
+
+    for class in [CLASS_SUCCESS_RESPONSE, CLASS_ERROR_RESPONSE] {
+        let m = authenticated_request(
+            METHOD_CHANNEL_BIND,
+            vec![
+                Box::new(ChannelNumber(MIN_CHANNEL_NUMBER)),
+                Box::new(PeerAddress {
+                    ip: IpAddr::from_str("127.0.0.2")?,
+                    port: 5000,
+                }),
+            ],
+        )?;
+        let _ = r.handle_channel_bind_request(&m).await;
+        let resp = recv_response(&client).await?;
+        assert_eq!(resp.typ.class, class);
+    }
+    assert_eq!(r.allocation_manager.channel_bind_count(), 1);
+
+    Ok(())
+}
@@ -1,13 +1,24 @@
 use super::config::*;
 use super::*;
+use crate::allocation::five_tuple::FiveTuple;
 use crate::auth::generate_auth_key;
+use crate::auth::rate_limited::RateLimitedAuthHandler;
 use crate::client::*;
 use crate::error::*;
+use crate::proto::reqtrans::RequestedTransport;
+use crate::proto::{PROTO_TCP, PROTO_UDP};
 use crate::relay::relay_static::*;
+use crate::relay::RelayAddressGenerator;
 
 use crate::relay::relay_none::RelayAddressGeneratorNone;
+use async_trait::async_trait;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use stun::agent::TransactionId;
+use stun::attributes::{ATTR_REALM, ATTR_SOFTWARE, ATTR_USERNAME};
+use stun::message::*;
+use stun::textattrs::{Realm, Software, Username};
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
 use util::{vnet::router::Nic, vnet::*};
@@ -54,15 +65,41 @@ async fn test_server_simple() -> Result<()> {
                 address: "0.0.0.0".to_owned(),
                 net: Arc::new(net::Net::new(None)),
             }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
         }],
         realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
         auth_handler: Arc::new(TestAuthHandler::new()),
         channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
     })
     .await?;
 
     assert_eq!(
-        DEFAULT_LIFETIME, server.channel_bind_timeout,
+        DEFAULT_LIFETIME,
+        *server.channel_bind_timeout.read().await,
         "should match"
     );
 
@@ -93,6 +130,2251 @@ async fn test_server_simple() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_server_export_config() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let local_addr = conn.local_addr()?;
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let export = server.export_config().await;
+    assert_eq!(export.realm, "webrtc.rs");
+    assert!(export.enforce_realm);
+    assert!(
+        export.auth_handler_type.ends_with("TestAuthHandler"),
+        "{} should name the auth handler",
+        export.auth_handler_type
+    );
+    assert_eq!(export.channel_bind_timeout, DEFAULT_LIFETIME);
+    assert_eq!(export.listeners.len(), 1);
+    assert_eq!(export.listeners[0].local_addr, Some(local_addr));
+    assert!(export.listeners[0]
+        .relay_addr_generator_type
+        .ends_with("RelayAddressGeneratorStatic"));
+    assert_eq!(export.listeners[0].max_packet_size, INBOUND_MTU);
+    assert!(!export.listeners[0].pre_auth);
+
+    server
+        .reconfigure(PartialServerConfig {
+            realm: Some("new.webrtc.rs".to_owned()),
+            channel_bind_timeout: Some(Duration::from_secs(60)),
+            ..Default::default()
+        })
+        .await;
+
+    let export = server.export_config().await;
+    assert_eq!(export.realm, "new.webrtc.rs");
+    assert_eq!(export.channel_bind_timeout, Duration::from_secs(60));
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_unknown_user_error_response() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let server_port = conn.local_addr()?.port();
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let conn = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let client = Client::new(ClientConfig {
+        stun_serv_addr: format!("0.0.0.0:{}", server_port),
+        turn_serv_addr: format!("0.0.0.0:{}", server_port),
+        username: "nobody".to_owned(),
+        password: "pass".to_owned(),
+        realm: "webrtc.rs".to_owned(),
+        software: String::new(),
+        rto_in_ms: 0,
+        conn,
+        vnet: None,
+    })
+    .await?;
+    client.listen().await?;
+
+    match client.allocate().await {
+        Err(Error::ErrErrorResponse { code, reason, .. }) => {
+            assert_eq!(code, 400);
+            assert_eq!(reason, "Bad Request");
+        }
+        Err(err) => panic!("expected an error response, got {}", err),
+        Ok(_) => panic!("unknown user should not get an allocation"),
+    }
+
+    client.close().await?;
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_rate_limited_error_response() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let server_port = conn.local_addr()?.port();
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(RateLimitedAuthHandler::new(
+            Arc::new(TestAuthHandler::new()),
+            1,
+            10,
+            Duration::from_secs(60),
+        )),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    // the first allocation takes the only call user may make per window
+    let mut clients = vec![];
+    for _ in 0..2 {
+        let conn = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+        let client = Client::new(ClientConfig {
+            stun_serv_addr: format!("0.0.0.0:{}", server_port),
+            turn_serv_addr: format!("0.0.0.0:{}", server_port),
+            username: "user".to_owned(),
+            password: "pass".to_owned(),
+            realm: "webrtc.rs".to_owned(),
+            software: String::new(),
+            rto_in_ms: 0,
+            conn,
+            vnet: None,
+        })
+        .await?;
+        client.listen().await?;
+        clients.push(client);
+    }
+
+    clients[0].allocate().await?;
+    match clients[1].allocate().await {
+        Err(Error::ErrErrorResponse { code, reason, .. }) => {
+            assert_eq!(code, 401);
+            assert_eq!(reason, "Unauthorized");
+        }
+        Err(err) => panic!("expected an error response, got {}", err),
+        Ok(_) => panic!("rate limited user should not get an allocation"),
+    }
+
+    for client in clients {
+        client.close().await?;
+    }
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_update_realm() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    {
+        let mut nonces = server.nonces.lock().await;
+        nonces.insert("nonce".to_owned(), Instant::now());
+    }
+
+    server.update_realm("new.webrtc.rs".to_owned()).await;
+
+    assert_eq!(*server.realm.read().await, "new.webrtc.rs", "should match");
+    assert!(
+        server.nonces.lock().await.is_empty(),
+        "nonces should be flushed"
+    );
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_reconfigure_reauth_grace_period() -> Result<()> {
+    let public_conn = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let private_conn = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![
+            ConnConfig {
+                conn: Arc::clone(&public_conn) as Arc<dyn Conn + Send + Sync>,
+                relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                    relay_address: IpAddr::from_str("127.0.0.1")?,
+                    address: "0.0.0.0".to_owned(),
+                    net: Arc::new(net::Net::new(None)),
+                }),
+                max_connections: None,
+                relay_keepalive_interval: None,
+                relay_keepalive_server: None,
+                realm: None,
+                pre_auth: None,
+                max_packet_size: 0,
+                inbound_worker_threads: 0,
+                on_error: None,
+                forward_icmp_errors: false,
+                allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+                idle_timeout: None,
+            },
+            ConnConfig {
+                conn: Arc::clone(&private_conn) as Arc<dyn Conn + Send + Sync>,
+                relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                    relay_address: IpAddr::from_str("127.0.0.1")?,
+                    address: "0.0.0.0".to_owned(),
+                    net: Arc::new(net::Net::new(None)),
+                }),
+                max_connections: None,
+                relay_keepalive_interval: None,
+                relay_keepalive_server: None,
+                realm: Some("private.webrtc.rs".to_owned()),
+                pre_auth: None,
+                max_packet_size: 0,
+                inbound_worker_threads: 0,
+                on_error: None,
+                forward_icmp_errors: false,
+                allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+                idle_timeout: None,
+            },
+        ],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let mut allocations = vec![];
+    for (manager, conn) in server
+        .allocation_managers
+        .iter()
+        .zip(vec![public_conn, private_conn])
+    {
+        let five_tuple = FiveTuple {
+            src_addr: SocketAddr::from_str("127.0.0.1:5000")?,
+            dst_addr: conn.local_addr()?,
+            ..Default::default()
+        };
+        allocations.push(
+            manager
+                .create_allocation(
+                    five_tuple,
+                    conn,
+                    0,
+                    Duration::from_secs(600),
+                    Username::new(ATTR_USERNAME, "user".to_owned()),
+                )
+                .await?,
+        );
+    }
+
+    server
+        .reconfigure(PartialServerConfig {
+            realm: Some("new.webrtc.rs".to_owned()),
+            reauth_grace_period: Some(Duration::from_secs(10)),
+            ..Default::default()
+        })
+        .await;
+
+    let public = allocations[0].lock().await.remaining_lifetime().await;
+    assert!(
+        public <= Duration::from_secs(10),
+        "allocation on the server realm should expire within the grace period, got {:?}",
+        public
+    );
+    let private = allocations[1].lock().await.remaining_lifetime().await;
+    assert!(
+        private > Duration::from_secs(10),
+        "allocation on a listener realm should keep its lifetime, got {:?}",
+        private
+    );
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_reconfigure() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    assert_eq!(*server.channel_bind_timeout.read().await, DEFAULT_LIFETIME);
+    assert_eq!(*server.nonce_lifetime.read().await, NONCE_LIFETIME);
+
+    let server = Arc::new(server);
+
+    // a request being set up holds the settings it copies
+    let in_flight = server.realm.read().await;
+    let reconfigure = {
+        let server = Arc::clone(&server);
+        tokio::spawn(async move {
+            server
+                .reconfigure(PartialServerConfig {
+                    realm: Some("new.webrtc.rs".to_owned()),
+                    channel_bind_timeout: Some(Duration::from_secs(60)),
+                    nonce_lifetime: Some(Duration::from_secs(300)),
+                    reauth_grace_period: None,
+                })
+                .await;
+        })
+    };
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(
+        *in_flight, "webrtc.rs",
+        "in-flight request should see the old realm"
+    );
+    assert_eq!(
+        *server.channel_bind_timeout.read().await,
+        DEFAULT_LIFETIME,
+        "in-flight request should see the old channel bind timeout"
+    );
+    drop(in_flight);
+
+    reconfigure.await.unwrap();
+
+    assert_eq!(*server.realm.read().await, "new.webrtc.rs");
+    assert_eq!(
+        *server.channel_bind_timeout.read().await,
+        Duration::from_secs(60)
+    );
+    assert_eq!(
+        *server.nonce_lifetime.read().await,
+        Duration::from_secs(300)
+    );
+
+    // unset fields are left untouched
+    server
+        .reconfigure(PartialServerConfig {
+            channel_bind_timeout: Some(Duration::from_secs(0)),
+            ..Default::default()
+        })
+        .await;
+    assert_eq!(*server.realm.read().await, "new.webrtc.rs");
+    assert_eq!(*server.channel_bind_timeout.read().await, DEFAULT_LIFETIME);
+    assert_eq!(
+        *server.nonce_lifetime.read().await,
+        Duration::from_secs(300)
+    );
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_server_nonce_cleanup() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(10),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    server
+        .nonces
+        .lock()
+        .await
+        .insert("old".to_owned(), Instant::now());
+    tokio::time::advance(NONCE_LIFETIME).await;
+    server
+        .nonces
+        .lock()
+        .await
+        .insert("fresh".to_owned(), Instant::now());
+    tokio::time::advance(Duration::from_secs(10)).await;
+    tokio::time::sleep(Duration::from_millis(1)).await;
+
+    {
+        let nonces = server.nonces.lock().await;
+        assert!(
+            !nonces.contains_key("old"),
+            "expired nonce should be purged"
+        );
+        assert!(nonces.contains_key("fresh"), "live nonce should be kept");
+    }
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_server_list_nonces() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let mut server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let first = Instant::now();
+    server.nonces.lock().await.insert("first".to_owned(), first);
+    tokio::time::advance(Duration::from_secs(10)).await;
+    let second = Instant::now();
+    server
+        .nonces
+        .lock()
+        .await
+        .insert("second".to_owned(), second);
+
+    let nonces = server.list_nonces().await;
+    assert_eq!(
+        nonces,
+        vec![
+            (String::new(), first + NONCE_LIFETIME),
+            (String::new(), second + NONCE_LIFETIME),
+        ],
+        "values should be hidden by default"
+    );
+
+    server.debug_nonces = true;
+    let nonces = server.list_nonces().await;
+    assert_eq!(
+        nonces,
+        vec![
+            ("first".to_owned(), first + NONCE_LIFETIME),
+            ("second".to_owned(), second + NONCE_LIFETIME),
+        ]
+    );
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_server_uptime() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    assert_eq!(server.uptime(), Duration::from_secs(0));
+    tokio::time::advance(Duration::from_secs(90)).await;
+    assert_eq!(server.uptime(), Duration::from_secs(90));
+
+    assert!(!server.is_shutdown());
+    server.close().await?;
+    assert!(server.is_shutdown());
+    server.close().await?;
+    assert!(
+        server.is_shutdown(),
+        "closing again should keep it shut down"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_listener_realm() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let server_addr = conn.local_addr()?;
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: Some("private.webrtc.rs".to_owned()),
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    // the override must survive a server-wide realm change
+    server.update_realm("new.webrtc.rs".to_owned()).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_ALLOCATE, CLASS_REQUEST)),
+        Box::new(RequestedTransport {
+            protocol: PROTO_UDP,
+        }),
+    ])?;
+    client.send_to(&m.raw, server_addr).await?;
+
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = client.recv_from(&mut buf).await?;
+    let mut resp = Message::new();
+    resp.unmarshal_binary(&buf[..n])?;
+
+    let mut realm = Realm::new(ATTR_REALM, String::new());
+    realm.get_from(&resp)?;
+    assert_eq!(
+        realm.text, "private.webrtc.rs",
+        "should use the listener realm"
+    );
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_list_listeners() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let server_addr = conn.local_addr()?;
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let listeners = server.list_listeners().await;
+    assert_eq!(listeners.len(), 1);
+    assert_eq!(listeners[0].addr, server_addr);
+    assert_eq!(listeners[0].protocol, PROTO_UDP);
+    assert_eq!(listeners[0].bytes_received, 0);
+    assert_eq!(listeners[0].bytes_sent, 0);
+
+    let manager = server
+        .manager_for_listener(server_addr)
+        .expect("should find the listener manager");
+    assert!(Arc::ptr_eq(&manager, &server.allocation_managers[0]));
+    assert!(server
+        .manager_for_listener(SocketAddr::from_str("127.0.0.1:1")?)
+        .is_none());
+
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_ALLOCATE, CLASS_REQUEST)),
+    ])?;
+    client.send_to(&m.raw, server_addr).await?;
+
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = client.recv_from(&mut buf).await?;
+
+    let listeners = server.list_listeners().await;
+    assert_eq!(
+        listeners[0].allocation_count, 0,
+        "request was unauthenticated"
+    );
+    assert_eq!(listeners[0].bytes_received, m.raw.len() as u64);
+    assert_eq!(listeners[0].bytes_sent, n as u64);
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_list_listeners_tcp() -> Result<()> {
+    use crate::proto::framer::TcpFramer;
+    use tokio::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let server_addr = listener.local_addr()?;
+    let _client = TcpStream::connect(server_addr).await?;
+    let (stream, _) = listener.accept().await?;
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn: Arc::new(TcpFramer::new(stream)?),
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let listeners = server.list_listeners().await;
+    assert_eq!(listeners.len(), 1);
+    assert_eq!(listeners[0].addr, server_addr);
+    assert_eq!(listeners[0].protocol, PROTO_TCP);
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_metrics_text() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let server_addr = conn.local_addr()?;
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut m = Message::new();
+    m.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])?;
+    client.send_to(&m.raw, server_addr).await?;
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = client.recv_from(&mut buf).await?;
+
+    server.auth_metrics.record(Duration::from_millis(3), true);
+    server.auth_metrics.record(Duration::from_secs(2), false);
+
+    let text = server.metrics_text().await;
+    let lines: Vec<&str> = text.lines().collect();
+    for expected in [
+        "# TYPE turn_allocations gauge".to_owned(),
+        format!(
+            "turn_allocations{{listener=\"{}\",protocol=\"UDP\"}} 0",
+            server_addr
+        ),
+        format!(
+            "turn_received_bytes_total{{listener=\"{}\",protocol=\"UDP\"}} {}",
+            server_addr,
+            m.raw.len()
+        ),
+        format!(
+            "turn_sent_bytes_total{{listener=\"{}\",protocol=\"UDP\"}} {}",
+            server_addr, n
+        ),
+        "turn_permissions 0".to_owned(),
+        "turn_channel_bindings 0".to_owned(),
+        "turn_connections 0".to_owned(),
+        "turn_auth_failures_total 1".to_owned(),
+        "# TYPE turn_auth_duration_seconds histogram".to_owned(),
+        "turn_auth_duration_seconds_bucket{le=\"0.001\"} 0".to_owned(),
+        "turn_auth_duration_seconds_bucket{le=\"0.005\"} 1".to_owned(),
+        "turn_auth_duration_seconds_bucket{le=\"1\"} 1".to_owned(),
+        "turn_auth_duration_seconds_bucket{le=\"+Inf\"} 2".to_owned(),
+        "turn_auth_duration_seconds_sum 2.003".to_owned(),
+        "turn_auth_duration_seconds_count 2".to_owned(),
+    ] {
+        assert!(lines.contains(&expected.as_str()), "missing {}", expected);
+    }
+    assert!(lines.iter().any(|l| l.starts_with("turn_uptime_seconds ")));
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_metrics_text_shared_listener_addr() -> Result<()> {
+    use crate::proto::framer::TcpFramer;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let server_addr = listener.local_addr()?;
+    let mut clients = vec![];
+    let mut conn_configs = vec![];
+    for _ in 0..2 {
+        clients.push(TcpStream::connect(server_addr).await?);
+        let (stream, _) = listener.accept().await?;
+        conn_configs.push(ConnConfig {
+            conn: Arc::new(TcpFramer::new(stream)?),
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        });
+    }
+
+    let server = Server::new(ServerConfig {
+        conn_configs,
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let mut m = Message::new();
+    m.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])?;
+    let mut received = 0;
+    for client in &mut clients {
+        client.write_all(&m.raw).await?;
+        let mut buf = vec![0u8; 1500];
+        received += client.read(&mut buf).await?;
+    }
+
+    let text = server.metrics_text().await;
+    let lines: Vec<&str> = text.lines().collect();
+    for (name, value) in [
+        ("turn_allocations", 0),
+        ("turn_received_bytes_total", 2 * m.raw.len()),
+        ("turn_sent_bytes_total", received),
+    ] {
+        let series: Vec<&&str> = lines
+            .iter()
+            .filter(|l| l.starts_with(&format!("{}{{", name)))
+            .collect();
+        assert_eq!(
+            series,
+            vec![&format!(
+                "{}{{listener=\"{}\",protocol=\"TCP\"}} {}",
+                name, server_addr, value
+            )
+            .as_str()],
+            "listeners sharing an address must add up into one series"
+        );
+    }
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "webrtc-stats")]
+#[tokio::test]
+async fn test_server_rtc_stats() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let server_port = conn.local_addr()?.port();
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    assert!(server.rtc_stats().await.is_empty(), "no allocations yet");
+
+    let conn = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let client = Client::new(ClientConfig {
+        stun_serv_addr: format!("0.0.0.0:{}", server_port),
+        turn_serv_addr: format!("0.0.0.0:{}", server_port),
+        username: "user".to_owned(),
+        password: "pass".to_owned(),
+        realm: "webrtc.rs".to_owned(),
+        software: String::new(),
+        rto_in_ms: 0,
+        conn,
+        vnet: None,
+    })
+    .await?;
+    client.listen().await?;
+    let relay_conn = client.allocate().await?;
+    let relay_addr = relay_conn.local_addr().await?;
+
+    let stats = server.rtc_stats().await;
+    assert_eq!(stats.len(), 1, "should report the allocation");
+    assert_eq!(stats[0].stats_type, "local-candidate");
+    assert_eq!(stats[0].candidate_type, "relay");
+    assert_eq!(
+        stats[0].port,
+        relay_addr.port(),
+        "should report the relay port"
+    );
+    assert_eq!(stats[0].username, "user");
+    assert!(stats[0].lifetime_remaining > 0.0, "should not be expired");
+
+    client.close().await?;
+    server.close().await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "state-dump")]
+#[tokio::test]
+async fn test_server_state_dump() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let server_port = conn.local_addr()?.port();
+    let path = std::env::temp_dir().join(format!("turn-state-dump-{}.json", server_port));
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: Some(path.clone()),
+        dump_interval: Duration::from_millis(50),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let conn = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let client = Client::new(ClientConfig {
+        stun_serv_addr: format!("0.0.0.0:{}", server_port),
+        turn_serv_addr: format!("0.0.0.0:{}", server_port),
+        username: "user".to_owned(),
+        password: "pass".to_owned(),
+        realm: "webrtc.rs".to_owned(),
+        software: String::new(),
+        rto_in_ms: 0,
+        conn,
+        vnet: None,
+    })
+    .await?;
+    client.listen().await?;
+    let relay_conn = client.allocate().await?;
+    let relay_addr = relay_conn.local_addr().await?;
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let dump: snapshot::ServerSnapshot = serde_json::from_slice(&std::fs::read(&path)?)
+        .map_err(|err| Error::Other(err.to_string()))?;
+    assert_eq!(dump.allocations.len(), 1, "should dump the allocation");
+    assert_eq!(dump.allocations[0].username, "user");
+    assert_eq!(dump.allocations[0].relay_addr.port(), relay_addr.port());
+
+    client.close().await?;
+    server.close().await?;
+    let _ = std::fs::remove_file(&path);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_idle_timeout() -> Result<()> {
+    use crate::proto::framer::TcpFramer;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let mut client = TcpStream::connect(listener.local_addr()?).await?;
+    let (stream, _) = listener.accept().await?;
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn: Arc::new(TcpFramer::new(stream)?),
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: Some(Duration::from_millis(300)),
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let mut m = Message::new();
+    m.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])?;
+    let mut header = [0u8; MESSAGE_HEADER_SIZE];
+
+    // every packet resets the idle timer
+    for _ in 0..2 {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        client.write_all(&m.raw).await?;
+        client.read_exact(&mut header).await?;
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        client.read_exact(&mut vec![0u8; len]).await?;
+    }
+    let last_packet = Instant::now();
+
+    let mut buf = [0u8; 1];
+    let n = tokio::time::timeout(Duration::from_secs(2), client.read(&mut buf))
+        .await
+        .expect("idle conn should be closed in time")?;
+    assert_eq!(n, 0, "idle conn should be closed");
+    assert!(
+        last_packet.elapsed() >= Duration::from_millis(250),
+        "conn closed before it was idle"
+    );
+
+    server.close().await?;
+
+    Ok(())
+}
+
+// tcp_round_trip sends m over client and reads back one STUN message
+async fn tcp_round_trip(client: &mut tokio::net::TcpStream, m: &Message) -> Result<Message> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    client.write_all(&m.raw).await?;
+    let mut raw = vec![0u8; MESSAGE_HEADER_SIZE];
+    client.read_exact(&mut raw).await?;
+    let len = u16::from_be_bytes([raw[2], raw[3]]) as usize;
+    raw.resize(MESSAGE_HEADER_SIZE + len, 0);
+    client.read_exact(&mut raw[MESSAGE_HEADER_SIZE..]).await?;
+
+    let mut resp = Message::new();
+    resp.unmarshal_binary(&raw)?;
+    Ok(resp)
+}
+
+#[tokio::test]
+async fn test_server_tcp_refresh_zero_lifetime() -> Result<()> {
+    use crate::proto::framer::TcpFramer;
+    use crate::proto::lifetime::Lifetime;
+    use stun::attributes::ATTR_NONCE;
+    use stun::integrity::MessageIntegrity;
+    use stun::textattrs::Nonce;
+    use tokio::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let mut client = TcpStream::connect(listener.local_addr()?).await?;
+    let (stream, _) = listener.accept().await?;
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn: Arc::new(TcpFramer::new(stream)?),
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_ALLOCATE, CLASS_REQUEST)),
+        Box::new(RequestedTransport {
+            protocol: PROTO_UDP,
+        }),
+    ])?;
+    let resp = tcp_round_trip(&mut client, &m).await?;
+    let mut nonce = Nonce::new(ATTR_NONCE, String::new());
+    nonce.get_from(&resp)?;
+
+    let integrity = MessageIntegrity::new_long_term_integrity(
+        "user".to_owned(),
+        "webrtc.rs".to_owned(),
+        "pass".to_owned(),
+    );
+    let credentials = |m: &mut Message| -> Result<()> {
+        Username::new(ATTR_USERNAME, "user".to_owned()).add_to(m)?;
+        Realm::new(ATTR_REALM, "webrtc.rs".to_owned()).add_to(m)?;
+        nonce.add_to(m)?;
+        integrity.add_to(m)?;
+        Ok(())
+    };
+
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_ALLOCATE, CLASS_REQUEST)),
+        Box::new(RequestedTransport {
+            protocol: PROTO_UDP,
+        }),
+    ])?;
+    credentials(&mut m)?;
+    let resp = tcp_round_trip(&mut client, &m).await?;
+    assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE, "{}", resp);
+
+    // deleting the allocation must not shut down the connection it was made on
+    let mut m = Message::new();
+    m.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(MessageType::new(METHOD_REFRESH, CLASS_REQUEST)),
+        Box::new(Lifetime(Duration::from_secs(0))),
+    ])?;
+    credentials(&mut m)?;
+    let resp = tokio::time::timeout(Duration::from_secs(2), tcp_round_trip(&mut client, &m))
+        .await
+        .expect("Refresh response should arrive")?;
+    assert_eq!(resp.typ.class, CLASS_SUCCESS_RESPONSE, "{}", resp);
+    assert_eq!(server.allocation_managers[0].allocations().await.len(), 0);
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_connection_count() -> Result<()> {
+    use crate::proto::framer::TcpFramer;
+    use tokio::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let client = TcpStream::connect(listener.local_addr()?).await?;
+    let (stream, _) = listener.accept().await?;
+
+    let new_conn_config = |conn: Arc<dyn Conn + Send + Sync>| -> Result<ConnConfig> {
+        Ok(ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        })
+    };
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![
+            new_conn_config(Arc::new(UdpSocket::bind("127.0.0.1:0").await?))?,
+            new_conn_config(Arc::new(TcpFramer::new(stream)?))?,
+        ],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    // the UDP listener doesn't count, the TCP connection does without an allocation
+    assert_eq!(server.connection_count(), 1);
+
+    drop(client);
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while server.connection_count() != 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("closed connection should not count");
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_idle_timeout_without_remote_addr() -> Result<()> {
+    let result = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn: Arc::new(UdpSocket::bind("127.0.0.1:0").await?),
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: Some(Duration::from_secs(30)),
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await;
+    assert_eq!(
+        result.err(),
+        Some(Error::ErrIdleTimeoutWithoutRemoteAddr),
+        "a shared UDP socket must not have an idle timeout"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_pre_auth() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let server_addr = conn.local_addr()?;
+
+    let allowed = UdpSocket::bind("127.0.0.1:0").await?;
+    let blocked = UdpSocket::bind("127.0.0.1:0").await?;
+    let allowed_addr = allowed.local_addr()?;
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: Some(Box::new(move |src_addr, _| src_addr == allowed_addr)),
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let mut m = Message::new();
+    m.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])?;
+
+    let mut buf = vec![0u8; 1500];
+
+    blocked.send_to(&m.raw, server_addr).await?;
+    let result =
+        tokio::time::timeout(Duration::from_millis(200), blocked.recv_from(&mut buf)).await;
+    assert!(result.is_err(), "blocked source should get no response");
+
+    allowed.send_to(&m.raw, server_addr).await?;
+    let result = tokio::time::timeout(Duration::from_secs(1), allowed.recv_from(&mut buf)).await;
+    assert!(result.is_ok(), "allowed source should get a response");
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_on_error() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let server_addr = conn.local_addr()?;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let client_addr = client.local_addr()?;
+
+    let (error_tx, mut error_rx) = mpsc::unbounded_channel();
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: Some(Box::new(move |err| {
+                let _ = error_tx.send(err);
+            })),
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    assert!(server.export_config().await.listeners[0].on_error);
+
+    // not a STUN message, nor ChannelData
+    client
+        .send_to(&[0xde, 0xad, 0xbe, 0xef], server_addr)
+        .await?;
+
+    let err = tokio::time::timeout(Duration::from_secs(1), error_rx.recv())
+        .await
+        .expect("should report the malformed packet")
+        .expect("callback should be alive");
+    assert_eq!(err.src_addr, Some(client_addr));
+    assert!(err.timestamp <= std::time::SystemTime::now());
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_runtime() -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("turn-test-runtime")
+        .enable_all()
+        .build()?;
+
+    let conn = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let server_addr = conn.local_addr()?;
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let thread_name = Arc::new(std::sync::Mutex::new(None));
+    let pre_auth_thread_name = Arc::clone(&thread_name);
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: Some(Box::new(move |_, _| {
+                *pre_auth_thread_name.lock().unwrap() =
+                    std::thread::current().name().map(str::to_owned);
+                true
+            })),
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: Some(runtime.handle().clone()),
+    })
+    .await?;
+
+    assert!(server.export_config().await.runtime);
+
+    let mut m = Message::new();
+    m.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])?;
+    client.send_to(&m.raw, server_addr).await?;
+
+    let mut buf = vec![0u8; 1500];
+    let result = tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buf)).await;
+    assert!(result.is_ok(), "should get a response");
+    assert_eq!(
+        thread_name.lock().unwrap().as_deref(),
+        Some("turn-test-runtime"),
+        "packets should be handled on the configured runtime"
+    );
+
+    server.close().await?;
+    runtime.shutdown_background();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_max_packet_size() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let server_addr = conn.local_addr()?;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 100,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let mut small = Message::new();
+    small.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])?;
+
+    let mut large = Message::new();
+    large.build(&[
+        Box::new(TransactionId::new()),
+        Box::new(BINDING_REQUEST),
+        Box::new(Software::new(ATTR_SOFTWARE, "x".repeat(100))),
+    ])?;
+    assert!(large.raw.len() > 100);
+
+    let mut buf = vec![0u8; 1500];
+
+    client.send_to(&large.raw, server_addr).await?;
+    let result = tokio::time::timeout(Duration::from_millis(200), client.recv_from(&mut buf)).await;
+    assert!(result.is_err(), "oversized datagram should be dropped");
+
+    client.send_to(&small.raw, server_addr).await?;
+    let result = tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buf)).await;
+    assert!(
+        result.is_ok(),
+        "datagram within the limit should be handled"
+    );
+
+    server.close().await?;
+
+    Ok(())
+}
+
+// BlockingMiddleware drops packets from blocked and counts the rest
+struct BlockingMiddleware {
+    blocked: SocketAddr,
+    before: AtomicUsize,
+    after: AtomicUsize,
+}
+
+impl RequestMiddleware for BlockingMiddleware {
+    fn before(&self, req: &Request) -> Result<()> {
+        if req.src_addr == self.blocked {
+            return Err(Error::Other("blocked".to_owned()));
+        }
+        self.before.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn after(&self, _req: &Request, result: &Result<()>) {
+        assert!(result.is_ok(), "binding request should be handled");
+        self.after.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[tokio::test]
+async fn test_server_middleware() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let server_addr = conn.local_addr()?;
+
+    let allowed = UdpSocket::bind("127.0.0.1:0").await?;
+    let blocked = UdpSocket::bind("127.0.0.1:0").await?;
+
+    let middleware = Arc::new(BlockingMiddleware {
+        blocked: blocked.local_addr()?,
+        before: AtomicUsize::new(0),
+        after: AtomicUsize::new(0),
+    });
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![Arc::clone(&middleware) as Arc<dyn RequestMiddleware + Send + Sync>],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let mut m = Message::new();
+    m.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])?;
+
+    let mut buf = vec![0u8; 1500];
+
+    blocked.send_to(&m.raw, server_addr).await?;
+    let result =
+        tokio::time::timeout(Duration::from_millis(200), blocked.recv_from(&mut buf)).await;
+    assert!(result.is_err(), "blocked source should get no response");
+
+    allowed.send_to(&m.raw, server_addr).await?;
+    let result = tokio::time::timeout(Duration::from_secs(1), allowed.recv_from(&mut buf)).await;
+    assert!(result.is_ok(), "allowed source should get a response");
+
+    // after runs once the response has been sent
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(middleware.before.load(Ordering::SeqCst), 1);
+    assert_eq!(middleware.after.load(Ordering::SeqCst), 1);
+
+    server.close().await?;
+
+    Ok(())
+}
+
+// MetadataRelayAddressGenerator learns its relay address in init, like a
+// generator asking a cloud metadata service would.
+struct MetadataRelayAddressGenerator {
+    metadata: Option<IpAddr>,
+    relay_address: Option<IpAddr>,
+}
+
+#[async_trait]
+impl RelayAddressGenerator for MetadataRelayAddressGenerator {
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn init(&mut self) -> Result<()> {
+        self.relay_address = Some(self.metadata.ok_or(Error::ErrFakeErr)?);
+        Ok(())
+    }
+
+    async fn allocate_conn(
+        &self,
+        _use_ipv4: bool,
+        requested_port: u16,
+    ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr)> {
+        let relay_address = self.relay_address.ok_or(Error::ErrFakeErr)?;
+        let conn = UdpSocket::bind(format!("0.0.0.0:{}", requested_port)).await?;
+        let mut relay_addr = conn.local_addr()?;
+        relay_addr.set_ip(relay_address);
+        Ok((Arc::new(conn), relay_addr))
+    }
+}
+
+async fn new_metadata_server(metadata: Option<IpAddr>) -> Result<(Server, u16)> {
+    let conn = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let server_port = conn.local_addr()?.port();
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(MetadataRelayAddressGenerator {
+                metadata,
+                relay_address: None,
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    Ok((server, server_port))
+}
+
+#[tokio::test]
+async fn test_server_relay_addr_generator_init() -> Result<()> {
+    let result = new_metadata_server(None).await;
+    assert_eq!(
+        result.err(),
+        Some(Error::ErrFakeErr),
+        "init errors should fail Server::new"
+    );
+
+    let relay_ip = IpAddr::from_str("127.0.0.2")?;
+    let (server, server_port) = new_metadata_server(Some(relay_ip)).await?;
+
+    let conn = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let client = Client::new(ClientConfig {
+        stun_serv_addr: format!("0.0.0.0:{}", server_port),
+        turn_serv_addr: format!("0.0.0.0:{}", server_port),
+        username: "user".to_owned(),
+        password: "pass".to_owned(),
+        realm: "webrtc.rs".to_owned(),
+        software: String::new(),
+        rto_in_ms: 0,
+        conn,
+        vnet: None,
+    })
+    .await?;
+    client.listen().await?;
+
+    let relay_conn = client.allocate().await?;
+    assert_eq!(
+        relay_conn.local_addr().await?.ip(),
+        relay_ip,
+        "relay address should come from init"
+    );
+
+    client.close().await?;
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_commander() -> Result<()> {
+    let conn: Arc<dyn Conn + Send + Sync> = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn: Arc::clone(&conn),
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    let five_tuple = FiveTuple {
+        src_addr: SocketAddr::from_str("127.0.0.1:5000")?,
+        dst_addr: conn.local_addr().await?,
+        ..Default::default()
+    };
+    server.allocation_managers[0]
+        .create_allocation(
+            five_tuple.clone(),
+            conn,
+            0,
+            Duration::from_secs(600),
+            Username::new(ATTR_USERNAME, "user".to_owned()),
+        )
+        .await?;
+
+    // queue far more commands than the channel holds without letting the read
+    // loop run in between, the first one must not be dropped
+    let commander = server.commander();
+    let n = commander
+        .send(Command::DeleteAllocation(five_tuple.clone()))
+        .await?;
+    assert_eq!(n, 1, "every listener should receive the command");
+    for _ in 0..COMMAND_CHANNEL_CAPACITY * 2 {
+        commander
+            .send(Command::DeleteAllocation(FiveTuple::default()))
+            .await?;
+    }
+    server.kick_user("other", KickReason::AdminRequest).await?;
+
+    let deleted = async {
+        while server.allocation_managers[0]
+            .get_allocation(&five_tuple)
+            .await
+            .is_some()
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), deleted)
+        .await
+        .expect("the allocation should be deleted");
+
+    server.close().await?;
+
+    let result = commander
+        .send(Command::DeleteAllocation(FiveTuple::default()))
+        .await;
+    assert_eq!(
+        result,
+        Err(Error::ErrClosed),
+        "commands should fail after close"
+    );
+
+    Ok(())
+}
+
 struct VNet {
     wan: Arc<Mutex<router::Router>>,
     net0: Arc<net::Net>,
@@ -188,10 +2470,35 @@ async fn build_vnet() -> Result<VNet> {
                 address: "1.2.3.4".to_owned(),
                 net: Arc::clone(&net0),
             }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
         }],
         realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
         auth_handler: Arc::new(TestAuthHandler::new()),
         channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
     })
     .await?;
 
@@ -332,3 +2639,221 @@ async fn test_server_vnet_echo_via_relay() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_server_inbound_worker_threads() -> Result<()> {
+    let conn = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let server_addr = conn.local_addr()?;
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 4,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await?;
+
+    assert_eq!(
+        server.export_config().await.listeners[0].inbound_worker_threads,
+        4
+    );
+
+    let mut clients = vec![];
+    for _ in 0..8 {
+        clients.push(UdpSocket::bind("127.0.0.1:0").await?);
+    }
+
+    let mut buf = vec![0u8; 1500];
+    for client in &clients {
+        let mut m = Message::new();
+        m.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])?;
+        client.send_to(&m.raw, server_addr).await?;
+
+        let (n, _) = tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buf))
+            .await
+            .expect("each client should get a binding response")?;
+        let mut resp = Message {
+            raw: buf[..n].to_vec(),
+            ..Default::default()
+        };
+        resp.decode()?;
+        assert_eq!(resp.transaction_id, m.transaction_id);
+    }
+
+    server.close().await?;
+
+    Ok(())
+}
+
+// assert_send_sync only compiles if T can be shared across threads
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_server_send_sync() -> Result<()> {
+    assert_send_sync::<Server>();
+
+    let server = Arc::new(
+        Server::new_unchecked(ServerConfig {
+            conn_configs: vec![ConnConfig {
+                conn: Arc::new(UdpSocket::bind("127.0.0.1:0").await?),
+                relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                    relay_address: IpAddr::from_str("127.0.0.1")?,
+                    address: "0.0.0.0".to_owned(),
+                    net: Arc::new(net::Net::new(None)),
+                }),
+                max_connections: None,
+                relay_keepalive_interval: None,
+                relay_keepalive_server: None,
+                realm: None,
+                pre_auth: None,
+                max_packet_size: 0,
+                inbound_worker_threads: 0,
+                on_error: None,
+                forward_icmp_errors: false,
+                allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+                idle_timeout: None,
+            }],
+            realm: "webrtc.rs".to_owned(),
+            enforce_realm: true,
+            auth_handler: Arc::new(TestAuthHandler::new()),
+            channel_bind_timeout: Duration::from_secs(0),
+            channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+            min_allocation_lifetime: Duration::from_secs(0),
+            software_name: None,
+            nonce_cleanup_interval: Duration::from_secs(0),
+            nonce_prefix: None,
+            allow_app_id: false,
+            debug_nonces: false,
+            state_dump_path: None,
+            dump_interval: Duration::from_secs(0),
+            middlewares: vec![],
+            on_allocation_created: None,
+            on_allocation_closed: None,
+            runtime: None,
+        })
+        .await,
+    );
+
+    // the server is shared by tasks on several worker threads
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let server = Arc::clone(&server);
+            tokio::spawn(async move {
+                server.update_realm("webrtc.rs".to_owned()).await;
+                server.list_listeners().await.len()
+            })
+        })
+        .collect();
+    for handle in handles {
+        assert_eq!(handle.await.unwrap(), 1);
+    }
+
+    server.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_invalid_nonce_prefix() -> Result<()> {
+    let result = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn: Arc::new(UdpSocket::bind("127.0.0.1:0").await?),
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::from_str("127.0.0.1")?,
+                address: "0.0.0.0".to_owned(),
+                net: Arc::new(net::Net::new(None)),
+            }),
+            max_connections: None,
+            relay_keepalive_interval: None,
+            relay_keepalive_server: None,
+            realm: None,
+            pre_auth: None,
+            max_packet_size: 0,
+            inbound_worker_threads: 0,
+            on_error: None,
+            forward_icmp_errors: false,
+            allocation_lifetime_strategy: LifetimeStrategy::Negotiated,
+            idle_timeout: None,
+        }],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        // nonces are quoted strings, so they can't hold a double quote
+        nonce_prefix: Some("turn\"1".to_owned()),
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await;
+    assert_eq!(result.err(), Some(Error::ErrInvalidNonce));
+
+    Ok(())
+}
+
+#[tokio::test]
+#[should_panic(expected = "failed to create TURN server")]
+async fn test_server_new_unchecked_panics() {
+    Server::new_unchecked(ServerConfig {
+        conn_configs: vec![],
+        realm: "webrtc.rs".to_owned(),
+        enforce_realm: true,
+        auth_handler: Arc::new(TestAuthHandler::new()),
+        channel_bind_timeout: Duration::from_secs(0),
+        channel_bind_refresh_policy: ChannelRefreshPolicy::Reset,
+        min_allocation_lifetime: Duration::from_secs(0),
+        software_name: None,
+        nonce_cleanup_interval: Duration::from_secs(0),
+        nonce_prefix: None,
+        allow_app_id: false,
+        debug_nonces: false,
+        state_dump_path: None,
+        dump_interval: Duration::from_secs(0),
+        middlewares: vec![],
+        on_allocation_created: None,
+        on_allocation_closed: None,
+        runtime: None,
+    })
+    .await;
+}
@@ -0,0 +1,94 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// AllocationSnapshot is the state of one allocation at the time the
+/// snapshot was taken.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllocationSnapshot {
+    pub username: String,
+    /// app_id is the APP-ID the allocation was tagged with, if any
+    pub app_id: Option<String>,
+    pub relay_addr: SocketAddr,
+    pub src_addr: SocketAddr,
+    /// permissions are the peer IPs the allocation has a permission for
+    pub permissions: Vec<IpAddr>,
+    /// channels are the bound channel numbers with their peers
+    pub channels: Vec<(u16, SocketAddr)>,
+    pub expires_at: SystemTime,
+}
+
+/// ServerSnapshot is the state of all allocations on all listeners.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerSnapshot {
+    pub timestamp: SystemTime,
+    pub allocations: Vec<AllocationSnapshot>,
+}
+
+impl ServerSnapshot {
+    pub(crate) async fn take(allocation_managers: &[Arc<Manager>]) -> Self {
+        let timestamp = SystemTime::now();
+
+        let mut allocations = vec![];
+        for m in allocation_managers {
+            allocations.extend(m.export_snapshots().await);
+        }
+
+        ServerSnapshot {
+            timestamp,
+            allocations,
+        }
+    }
+
+    // write_to replaces the file at path with the snapshot as JSON. It writes a
+    // temporary file next to path first and renames it over path, so a reader
+    // never sees a partially written dump.
+    pub(crate) async fn write_to(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(|err| Error::Other(err.to_string()))?;
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+}
+
+impl Server {
+    /// snapshot returns the current state of all allocations on all listeners.
+    pub async fn snapshot(&self) -> ServerSnapshot {
+        ServerSnapshot::take(&self.allocation_managers).await
+    }
+
+    // state_dump_loop writes a ServerSnapshot to path every interval until the
+    // server shuts down.
+    pub(crate) async fn state_dump_loop(
+        allocation_managers: Vec<Arc<Manager>>,
+        path: PathBuf,
+        interval: Duration,
+        mut shutdown_rx: watch::Receiver<bool>,
+    ) {
+        let mut ticker = tokio::time::interval_at(Instant::now() + interval, interval);
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let snapshot = ServerSnapshot::take(&allocation_managers).await;
+                    if let Err(err) = snapshot.write_to(&path).await {
+                        log::warn!("failed to dump server state to {}: {}", path.display(), err);
+                    }
+                },
+                did_change = shutdown_rx.changed() => {
+                    if did_change.is_err() || *shutdown_rx.borrow() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use super::*;
+
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// RtcRelayStats describes one allocation in the shape of a WebRTC stats
+/// dictionary entry. It follows the `RTCIceCandidateStats` layout used for
+/// relay candidates (`type` is `"local-candidate"`, `candidateType` is
+/// `"relay"`), so dashboards built on the WebRTC stats types can show TURN
+/// allocations next to the candidates that use them. The TURN specific
+/// members are extensions.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RtcRelayStats {
+    /// id is unique per allocation, derived from its 5-tuple
+    pub id: String,
+    /// timestamp is when the stats were taken, in milliseconds since the UNIX epoch
+    pub timestamp: f64,
+    #[serde(rename = "type")]
+    pub stats_type: String,
+    pub candidate_type: String,
+    /// address and port are the relayed transport address
+    pub address: String,
+    pub port: u16,
+    pub protocol: String,
+    pub relay_protocol: String,
+
+    pub client_address: String,
+    pub client_port: u16,
+    pub username: String,
+    pub permission_count: usize,
+    pub channel_count: usize,
+    /// lifetime_remaining is the number of seconds until the allocation expires
+    pub lifetime_remaining: f64,
+}
+
+impl Server {
+    /// rtc_stats returns a stats entry for every active allocation on every listener.
+    pub async fn rtc_stats(&self) -> Vec<RtcRelayStats> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64() * 1000.0)
+            .unwrap_or_default();
+
+        let mut stats = vec![];
+        for m in &self.allocation_managers {
+            for a in m.allocations().await {
+                let a = a.lock().await;
+                let permission_count = a.permissions.lock().await.len();
+                let channel_count = a.channel_bindings.lock().await.len();
+
+                stats.push(RtcRelayStats {
+                    id: format!("RTCRelay_{}", a.five_tuple.fingerprint()),
+                    timestamp,
+                    stats_type: "local-candidate".to_owned(),
+                    candidate_type: "relay".to_owned(),
+                    address: a.relay_addr.ip().to_string(),
+                    port: a.relay_addr.port(),
+                    protocol: "udp".to_owned(),
+                    relay_protocol: "udp".to_owned(),
+                    client_address: a.five_tuple.src_addr.ip().to_string(),
+                    client_port: a.five_tuple.src_addr.port(),
+                    username: a.username.text.clone(),
+                    permission_count,
+                    channel_count,
+                    lifetime_remaining: a.remaining_lifetime().await.as_secs_f64(),
+                });
+            }
+        }
+        stats
+    }
+}